@@ -7,6 +7,9 @@ use std::io::{self, BufRead};
 use std::path::Path;
 use std::string::String;
 
+mod svcdb;
+use svcdb::*;
+
 // The output is wrapped in a Result to allow matching on errors
 // Returns an Iterator to the Reader of the lines of the file.
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
@@ -15,149 +18,460 @@ where P: AsRef<Path>, {
     Ok(io::BufReader::new(file).lines())
 }
 
-fn remove_whitespace(s: &mut String) {
-    s.retain(|c| !c.is_whitespace());
+fn ipcdb_arg_info(kind: &str) -> Option<(&'static str, u16)> {
+    // (IpcArgKind variant, raw size or 0 if not raw data)
+    match kind {
+        "u8" => Some(("U8", 1)),
+        "u16" => Some(("U16", 2)),
+        "u32" => Some(("U32", 4)),
+        "u64" => Some(("U64", 8)),
+        "u128" => Some(("U128", 16)),
+        "bool" => Some(("Bool", 1)),
+        "pid" => Some(("Pid", 8)),
+        "copy" => Some(("CopyHandle", 0)),
+        "move" => Some(("MoveHandle", 0)),
+        "obj" => Some(("Object", 0)),
+        "static" => Some(("Static", 0)),
+        "send" => Some(("Send", 0)),
+        "recv" => Some(("Recv", 0)),
+        "exch" => Some(("Exch", 0)),
+        "ptr" => Some(("Pointer", 0)),
+        _ => None
+    }
 }
 
-fn main() {
-    let out_dir = env::var_os("OUT_DIR").unwrap();
-    let dest_path = Path::new(&out_dir).join("vsvc_gen.rs");
-    
+fn ipcdb_end_interface(output: &mut String, services: &Vec<String>, in_cmds: bool) {
+    if in_cmds {
+        *output += "        ],\n";
+    }
+    else {
+        *output += "        cmds: &[],\n";
+    }
+    *output += "        services: &[";
+    for service in services {
+        *output += &format!("\"{}\", ", service);
+    }
+    *output += "],\n";
+    *output += "    },\n";
+}
+
+fn gen_ipcdb() -> String {
     let mut output = String::new();
+    let mut in_interface = false;
+    let mut in_cmds = false;
+    let mut services: Vec<String> = Vec::new();
     
-    let mut handlers: Vec<String> = Vec::new();
-    let mut svcs: Vec<(String, String)> = Vec::new();
-    
-    let mut ignore_commented = false;
-    if let Ok(lines) = read_lines("src/vm/vsvc.rs") {
-        for line_try in lines {
-            if let Ok(line) = line_try {
-                if line.contains("/*") {
-                    ignore_commented = true;
+    output += "pub static IPCDB_INTERFACES: &[IpcInterfaceDesc] = &[\n";
+    
+    let lines = read_lines("src/hos/ipcdb.txt").expect("failed to open src/hos/ipcdb.txt");
+    for (line_num, line_try) in lines.enumerate() {
+        let line = line_try.unwrap();
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") { continue; }
+        
+        let split: Vec<&str> = line.split_whitespace().collect();
+        match split[0] {
+            "interface" => {
+                if split.len() != 3 {
+                    panic!("ipcdb.txt:{}: expected `interface <full name> <short name>`", line_num + 1);
                 }
-                if line.contains("*/") {
-                    ignore_commented = false;
+                if in_interface {
+                    ipcdb_end_interface(&mut output, &services, in_cmds);
+                }
+                in_interface = true;
+                in_cmds = false;
+                services.clear();
+                output += &format!("    IpcInterfaceDesc {{\n        name: \"{}\",\n        short_name: \"{}\",\n", split[1], split[2]);
+            },
+            "service" => {
+                if !in_interface || split.len() != 2 {
+                    panic!("ipcdb.txt:{}: expected `service <name>` after an interface", line_num + 1);
+                }
+                services.push(String::from(split[1]));
+            },
+            "cmd" => {
+                if !in_interface || split.len() < 3 {
+                    panic!("ipcdb.txt:{}: expected `cmd <id> <name> [args]` after an interface", line_num + 1);
+                }
+                let id: u32 = split[1].parse().unwrap_or_else(|_| panic!("ipcdb.txt:{}: bad command id `{}`", line_num + 1, split[1]));
+                
+                if !in_cmds {
+                    output += "        cmds: &[\n";
+                    in_cmds = true;
                 }
-                if ignore_commented { continue; }
                 
-                if line.contains("impl") {
-                    let split: Vec<&str> = line.split(' ').collect();
+                let mut in_offs: u16 = 0;
+                let mut out_offs: u16 = 0;
+                let mut args = String::new();
+                for arg in &split[3..] {
+                    let arg_split: Vec<&str> = arg.split(':').collect();
+                    if arg_split.len() < 2 || arg_split.len() > 3 {
+                        panic!("ipcdb.txt:{}: bad argument `{}`", line_num + 1, arg);
+                    }
                     
-                    if split[1] != "SvcHandler" { continue; }
+                    let dir = match arg_split[0] {
+                        "in" => "In",
+                        "out" => "Out",
+                        _ => panic!("ipcdb.txt:{}: bad argument direction `{}`", line_num + 1, arg_split[0])
+                    };
+                    let (kind, size) = ipcdb_arg_info(arg_split[1]).unwrap_or_else(|| panic!("ipcdb.txt:{}: unknown argument type `{}`", line_num + 1, arg_split[1]));
+                    let name = if arg_split.len() == 3 { arg_split[2] } else { arg_split[1] };
                     
-                    handlers.push(String::from(split[3]));
+                    let mut offset: u16 = 0;
+                    if size != 0 {
+                        let offs = if dir == "In" { &mut in_offs } else { &mut out_offs };
+                        *offs = (*offs + size - 1) & !(size - 1);
+                        offset = *offs;
+                        *offs += size;
+                    }
+                    
+                    args += &format!("IpcArgDesc {{ dir: IpcArgDir::{}, kind: IpcArgKind::{}, name: \"{}\", offset: {} }}, ", dir, kind, name, offset);
                 }
+                
+                output += &format!("            IpcCmdDesc {{ id: {}, name: \"{}\", args: &[{}] }},\n", id, split[2], args);
+            },
+            _ => {
+                panic!("ipcdb.txt:{}: unknown directive `{}`", line_num + 1, split[0]);
             }
         }
     }
     
-    let mut found_enum = false;
-    if let Ok(lines) = read_lines("src/hos/svc.rs") {
-        for line_try in lines {
-            if let Ok(line) = line_try {
-                if line.contains("/*") {
-                    ignore_commented = true;
-                }
-                if line.contains("*/") {
-                    ignore_commented = false;
-                }
-                if ignore_commented { continue; }
-                
-                if line.contains("enum HorizonSvc") {
-                    found_enum = true;
+    if in_interface {
+        ipcdb_end_interface(&mut output, &services, in_cmds);
+    }
+    output += "];\n";
+    
+    return output;
+}
+
+// Finds every `impl SvcHandler for <name>` in a source file. Comments and
+// literals are skipped properly so neither can hide or fake a handler, and
+// any handler that isn't Svc<name of an SVC in svcdb.txt> fails the build
+fn svcdb_scan_handlers(path: &str, svcs: &Vec<SvcDbEntry>) -> Vec<String> {
+    let src = fs::read_to_string(path).unwrap_or_else(|_| panic!("failed to open {}", path));
+    let chars: Vec<char> = src.chars().collect();
+    let at = |i: usize| -> char { if i < chars.len() { chars[i] } else { '\0' } };
+    
+    let mut tokens: Vec<String> = Vec::new();
+    let mut cur = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        
+        if c.is_alphanumeric() || c == '_' {
+            // Raw strings, r"..." and r#"..."#
+            if c == 'r' && cur.is_empty() && (at(i+1) == '"' || at(i+1) == '#') {
+                let mut hashes = 0;
+                while at(i + 1 + hashes) == '#' { hashes += 1; }
+                if at(i + 1 + hashes) == '"' {
+                    i += 2 + hashes;
+                    while i < chars.len() && !(chars[i] == '"' && (0..hashes).all(|j| at(i + 1 + j) == '#')) { i += 1; }
+                    i += 1 + hashes;
+                    continue;
                 }
-                if !found_enum { continue; }
-                
-                if line.contains("}") {
-                    found_enum = false;
+            }
+            cur.push(c);
+            i += 1;
+            continue;
+        }
+        
+        if !cur.is_empty() {
+            tokens.push(cur.clone());
+            cur.clear();
+        }
+        
+        if c == '/' && at(i+1) == '/' {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+        }
+        else if c == '/' && at(i+1) == '*' {
+            let mut depth = 0;
+            while i < chars.len() {
+                if chars[i] == '/' && at(i+1) == '*' { depth += 1; i += 2; }
+                else if chars[i] == '*' && at(i+1) == '/' {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 { break; }
                 }
-                
-                let split: Vec<&str> = line.split('(').collect();
-                
-                if split.len() < 2 { continue; }
-                
-                let name = split[0];
-                
-                let split2: Vec<&str> = split[1].split(')').collect();
-                let handler = split2[0];
-                
-                let mut name_str = String::from(name);
-                remove_whitespace(&mut name_str);
-                
-                if name_str.contains("//") || name_str.contains("/*") { continue; }
-                
-                svcs.push((name_str, String::from(handler)));
+                else { i += 1; }
+            }
+        }
+        else if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
             }
+            i += 1;
+        }
+        else if c == '\'' && at(i+1) == '\\' {
+            i += 3;
+            while i < chars.len() && chars[i] != '\'' { i += 1; }
+            i += 1;
+        }
+        else if c == '\'' && at(i+2) == '\'' {
+            i += 3;
+        }
+        else {
+            // Lifetimes and punctuation just split tokens
+            i += 1;
+        }
+    }
+    if !cur.is_empty() {
+        tokens.push(cur);
+    }
+    
+    let mut handlers: Vec<String> = Vec::new();
+    for i in 0..tokens.len() {
+        if tokens[i] != "SvcHandler" || i + 2 >= tokens.len() || tokens[i+1] != "for" { continue; }
+        
+        let handler = &tokens[i+2];
+        let known = handler == "SvcInvalid" || handler == "SvcDefaultHandler"
+                    || svcs.iter().any(|svc| handler.len() == svc.name.len() + 3 && handler.starts_with("Svc") && handler[3..] == svc.name);
+        if !known {
+            panic!("{}: handler `{}` doesn't match any SVC in src/hos/svcdb.txt", path, handler);
+        }
+        if handlers.contains(handler) {
+            panic!("{}: `{}` is implemented twice", path, handler);
         }
+        handlers.push(handler.clone());
+    }
+    
+    for required in ["SvcInvalid", "SvcDefaultHandler"].iter() {
+        if !handlers.iter().any(|handler| handler == required) {
+            panic!("{}: missing `impl SvcHandler for {}`", path, required);
+        }
+    }
+    
+    return handlers;
+}
+
+fn svcdb_handler(svc: &SvcDbEntry, handlers: &Vec<String>) -> String {
+    let handler = format!("Svc{}", svc.name);
+    if handlers.contains(&handler) {
+        return handler;
+    }
+    return String::from("SvcDefaultHandler");
+}
+
+fn svcdb_decode(arg: &SvcDbArg, field_u32: bool, is_a32: bool) -> String {
+    if arg.regs.len() == 2 {
+        return format!("(ctx[{}] & 0xFFFFFFFF) | (ctx[{}] << 32)", arg.regs[0], arg.regs[1]);
+    }
+    if field_u32 {
+        return format!("(ctx[{}] & 0xFFFFFFFF) as u32", arg.regs[0]);
+    }
+    if is_a32 {
+        return format!("ctx[{}] & 0xFFFFFFFF", arg.regs[0]);
+    }
+    return format!("ctx[{}]", arg.regs[0]);
+}
+
+fn svcdb_encode(arg: &SvcDbArg, field_u32: bool, is_a32: bool) -> String {
+    if arg.regs.len() == 2 {
+        return format!("ctx[{}] = self.{} & 0xFFFFFFFF; ctx[{}] = self.{} >> 32;", arg.regs[0], arg.name, arg.regs[1], arg.name);
+    }
+    if field_u32 {
+        return format!("ctx[{}] = self.{} as u64;", arg.regs[0], arg.name);
     }
+    if is_a32 {
+        return format!("ctx[{}] = self.{} & 0xFFFFFFFF;", arg.regs[0], arg.name);
+    }
+    return format!("ctx[{}] = self.{};", arg.regs[0], arg.name);
+}
+
+// Svc<Name>Args/Svc<Name>Out, normalizing either ABI's registers into
+// named fields and back
+fn svcdb_gen_args(svc: &SvcDbEntry, is_out: bool) -> String {
+    let args: Vec<&SvcDbArg> = svc.args.iter().filter(|arg| arg.is_out == is_out).collect();
+    if args.is_empty() {
+        return String::new();
+    }
+    
+    let struct_name = format!("Svc{}{}", svc.name, if is_out { "Out" } else { "Args" });
+    let args_a32: Vec<&SvcDbArg> = match &svc.args_a32 {
+        Some(args_a32) => args.iter().map(|arg| args_a32.iter().find(|other| other.name == arg.name && other.is_out == is_out).unwrap()).collect(),
+        None => args.clone()
+    };
+    
+    let mut output = String::new();
+    output += "#[derive(Copy, Clone, Default)]\n";
+    output += &format!("pub struct {}\n{{\n", struct_name);
+    for arg in &args {
+        output += &format!("    pub {}: {},\n", arg.name, if svcdb_is_u32(&arg.kind) { "u32" } else { "u64" });
+    }
+    output += "}\n\n";
+    
+    output += &format!("impl {}\n{{\n", struct_name);
+    output += "    pub fn from_ctx(ctx: &[u64], is_a32: bool) -> Self\n    {\n";
+    output += &format!("        if is_a32 {{\n            return {} {{\n", struct_name);
+    for (arg, arg_a32) in args.iter().zip(args_a32.iter()) {
+        output += &format!("                {}: {},\n", arg.name, svcdb_decode(arg_a32, svcdb_is_u32(&arg.kind), true));
+    }
+    output += &format!("            }};\n        }}\n        return {} {{\n", struct_name);
+    for arg in &args {
+        output += &format!("            {}: {},\n", arg.name, svcdb_decode(arg, svcdb_is_u32(&arg.kind), false));
+    }
+    output += "        };\n    }\n\n";
+    
+    output += "    pub fn to_ctx(&self, ctx: &mut [u64], is_a32: bool)\n    {\n";
+    output += "        if is_a32 {\n";
+    for (arg, arg_a32) in args.iter().zip(args_a32.iter()) {
+        output += &format!("            {}\n", svcdb_encode(arg_a32, svcdb_is_u32(&arg.kind), true));
+    }
+    output += "            return;\n        }\n";
+    for arg in &args {
+        output += &format!("        {}\n", svcdb_encode(arg, svcdb_is_u32(&arg.kind), false));
+    }
+    output += "    }\n}\n\n";
+    
+    return output;
+}
+
+fn gen_svc(svcs: &Vec<SvcDbEntry>, handlers: &Vec<String>) -> String {
+    let mut output = String::new();
+    
+    for handler in handlers {
+        output += &format!("#[derive(Copy, Clone)]\npub struct {};\n\n", handler);
+    }
+    
+    output += "#[derive(Copy, Clone)]\npub enum HorizonSvc {\n    Invalid(SvcInvalid),\n";
+    for svc in svcs {
+        output += &format!("    {}({}),\n", svc.name, svcdb_handler(svc, handlers));
+    }
+    output += "}\n\n";
+    
+    // Every SVC svcdb.txt knows, firmware profiles knock out what their
+    // kernel doesn't implement
+    output += "pub const SVC_MAP_ALL: [HorizonSvc; 0x80] = {\n";
+    output += "    let mut map = [HorizonSvc::Invalid(SvcInvalid); 0x80];\n";
+    for svc in svcs {
+        output += &format!("    map[0x{:02X}] = HorizonSvc::{}({});\n", svc.id, svc.name, svcdb_handler(svc, handlers));
+    }
+    output += "    map\n};\n\n";
+    
+    output += "impl HorizonSvc\n{\n    pub fn from_iss(iss: u32) -> HorizonSvc {\n";
+    output += "        let svc_u8 = (iss & 0xFF) as u8;\n";
+    output += "        match firmware_get_svc_map().get(svc_u8 as usize) {\n";
+    output += "            Some(svc) => *svc,\n            None => HorizonSvc::Invalid(SvcInvalid),\n        }\n    }\n}\n\n";
+    
+    for svc in svcs {
+        output += &svcdb_gen_args(svc, false);
+        output += &svcdb_gen_args(svc, true);
+    }
+    
+    output += &svcdb_gen_convert(svcs, "svc_args_from_a32", "Args", "true", "false");
+    output += &svcdb_gen_convert(svcs, "svc_args_to_a32", "Args", "false", "true");
+    output += &svcdb_gen_convert(svcs, "svc_out_from_a32", "Out", "true", "false");
+    output += &svcdb_gen_convert(svcs, "svc_out_to_a32", "Out", "false", "true");
+    
+    return output;
+}
+
+// Moves an SVC's args (or outputs) between the two register layouts in place
+fn svcdb_gen_convert(svcs: &Vec<SvcDbEntry>, fn_name: &str, suffix: &str, from_a32: &str, to_a32: &str) -> String {
+    let is_out = suffix == "Out";
+    
+    let mut output = String::new();
+    output += &format!("pub fn {}(svc_id: u8, ctx: &mut [u64])\n{{\n    match svc_id {{\n", fn_name);
+    for svc in svcs {
+        if !svc.args.iter().any(|arg| arg.is_out == is_out) { continue; }
+        output += &format!("        0x{:02X} => Svc{}{}::from_ctx(ctx, {}).to_ctx(ctx, {}),\n", svc.id, svc.name, suffix, from_a32, to_a32);
+    }
+    output += "        _ => {},\n    }\n}\n\n";
+    return output;
+}
+
+fn gen_vsvc(svcs: &Vec<SvcDbEntry>, handlers: &Vec<String>) -> String {
+    let mut output = String::new();
     
-    for _handler in &handlers
-    {
-        let handler = &_handler;
-        output += "#[allow(non_snake_case)]\n";
-        output += "async fn _svc_shim_";
-        output += handler;
-        output += "(ctx: [u64; 32]) -> [u64; 32] {
-                   let handler = ";
-        output += handler;
-        output += ";
-                   return handler.handle(ctx).await;
-               }
-            ";
-    }
-    
-    output += "fn _svc_gen_pre(iss: u32, thread_ctx: u64, ctx: [u64; 32]) -> bool {
-                   let svc = HorizonSvc::from_iss(iss);
-                   let id = match svc {\n";
-
-    for _svc in &svcs
-    {
-        let svc = &_svc.0;
-        let handler = &_svc.1;
-        output += "                       HorizonSvc::";
-        output += svc;
-        output += "(_) => {";
-        if handler != "SvcDefaultHandler" {
-            output += "task_run_svc(thread_ctx, _svc_shim_";
-            output += handler;
-            output += "(ctx))";
+    for handler in handlers {
+        output += &format!("#[allow(non_snake_case)]\nasync fn _svc_shim_{}(ctx: [u64; 32]) -> [u64; 32] {{\n", handler);
+        output += &format!("    let handler = {};\n    return handler.handle(ctx).await;\n}}\n\n", handler);
+    }
+    
+    // true if nothing needs to run for this SVC
+    output += "fn _svc_gen_pre(iss: u32, thread_ctx: u64, ctx: [u64; 32]) -> bool {\n";
+    output += "    match HorizonSvc::from_iss(iss) {\n";
+    for svc in svcs {
+        let handler = svcdb_handler(svc, handlers);
+        if handler == "SvcDefaultHandler" {
+            output += &format!("        HorizonSvc::{}(_) => {{ return true; }},\n", svc.name);
         }
-        else
-        {
-            output += "return true;";
+        else {
+            output += &format!("        HorizonSvc::{}(_) => {{ task_run_svc(thread_ctx, _svc_shim_{}(ctx)); }},\n", svc.name, handler);
         }
-        output += "},\n";
     }
+    output += "        HorizonSvc::Invalid(_) => { task_run_svc(thread_ctx, _svc_shim_SvcInvalid(ctx)); },\n";
+    output += "    };\n    return false;\n}\n";
+    
+    return output;
+}
 
-    output += "    _ => {task_run_svc(thread_ctx, _svc_shim_SvcInvalid(ctx))},
-                   };
-                   return false;
-               }
-    ";
+// Same FNV-1a 64 as firmware_hash
+fn firmware_hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+// The kernel main.rs bundles gets a profile entry of its own, so the default
+// build always boots. HTB_FIRMWARE says which profile it is
+fn gen_firmware(kern_path: &str) -> String {
+    let profile = env::var("HTB_FIRMWARE").unwrap_or(String::from("8.0.1-9.0.1"));
+    let hash = match fs::read(kern_path) {
+        Ok(data) => format!("Some(0x{:016x})", firmware_hash(&data)),
+        Err(_) => String::from("None")
+    };
+    
+    let mut output = String::new();
+    output += &format!("pub const FIRMWARE_BUNDLED_HASH: Option<u64> = {};\n", hash);
+    output += &format!("pub const FIRMWARE_BUNDLED_PROFILE: &'static str = {:?};\n", profile);
+    return output;
+}
+
+fn main() {
+    let out_dir = env::var_os("OUT_DIR").unwrap();
     
+    let svcs = svcdb_read("src/hos/svcdb.txt");
+    let handlers = svcdb_scan_handlers("src/vm/vsvc.rs", &svcs);
     
+    let dest_path = Path::new(&out_dir).join("vsvc_gen.rs");
     fs::write(
         &dest_path,
-        output
+        gen_vsvc(&svcs, &handlers)
     ).unwrap();
     
-    
-    output = String::new();
     let dest_path = Path::new(&out_dir).join("svc_gen.rs");
-
-    for _handler in &handlers
-    {
-        let handler = &_handler;
-        output += "#[derive(Copy, Clone)]\n";
-        output += "pub struct ";
-        output += handler;
-        output += ";\n\n";
-    }
     fs::write(
         &dest_path,
-        output
+        gen_svc(&svcs, &handlers)
+    ).unwrap();
+    
+    let dest_path = Path::new(&out_dir).join("svcsig_gen.rs");
+    fs::write(
+        &dest_path,
+        gen_svcsig(&svcs)
+    ).unwrap();
+    
+    let dest_path = Path::new(&out_dir).join("ipcdb_gen.rs");
+    fs::write(
+        &dest_path,
+        gen_ipcdb()
+    ).unwrap();
+    
+    let dest_path = Path::new(&out_dir).join("firmware_gen.rs");
+    fs::write(
+        &dest_path,
+        gen_firmware("data/0_kernel_80060000.bin")
     ).unwrap();
     
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=svcdb.rs");
     println!("cargo:rerun-if-changed=src/vm/vsvc.rs");
+    println!("cargo:rerun-if-changed=src/hos/ipcdb.txt");
+    println!("cargo:rerun-if-changed=src/hos/svcdb.txt");
+    println!("cargo:rerun-if-changed=data/0_kernel_80060000.bin");
+    println!("cargo:rerun-if-env-changed=HTB_FIRMWARE");
 }
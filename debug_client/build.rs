@@ -0,0 +1,26 @@
+// build.rs
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Same svcdb.txt parsing as the hypervisor's build.rs, for the svcsig.rs
+// pulled in by path
+#[path = "../svcdb.rs"]
+mod svcdb;
+use svcdb::*;
+
+fn main() {
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+
+    let svcs = svcdb_read("../src/hos/svcdb.txt");
+    let dest_path = Path::new(&out_dir).join("svcsig_gen.rs");
+    fs::write(
+        &dest_path,
+        gen_svcsig(&svcs)
+    ).unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=../svcdb.rs");
+    println!("cargo:rerun-if-changed=../src/hos/svcdb.txt");
+}
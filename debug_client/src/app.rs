@@ -1,5 +1,7 @@
 use crate::util::{StaticSignal};
 use crate::send_cmd;
+use crate::ipc_cap::ipc_cap_host_cmd;
+use crate::svc_prof::SvcProfSort;
 
 pub struct Signal<S: Iterator> {
     source: S,
@@ -29,6 +31,7 @@ pub struct App<'a> {
     pub cmdbuf: String,
     pub enhanced_graphics: bool,
     pub scroll_up: i32,
+    pub svc_prof_sort: SvcProfSort,
 }
 
 impl<'a> App<'a> {
@@ -49,6 +52,7 @@ impl<'a> App<'a> {
             cmdbuf: String::new(),
             enhanced_graphics: enhanced_graphics,
             scroll_up: 0,
+            svc_prof_sort: SvcProfSort::Total,
         }
     }
 
@@ -80,6 +84,11 @@ impl<'a> App<'a> {
         }
     }
 
+    // Cycles the SVC profile table's sort column
+    pub fn on_tab(&mut self) {
+        self.svc_prof_sort = self.svc_prof_sort.next();
+    }
+
     pub fn on_up(&mut self) {
         
     }
@@ -114,7 +123,10 @@ impl<'a> App<'a> {
                 self.show_chart = !self.show_chart;
             }*/
             '\n' => {
-                send_cmd(&format!("{}\n", self.cmdbuf));
+                match ipc_cap_host_cmd(&self.cmdbuf) {
+                    Some(cmds) => send_cmd(&cmds),
+                    None => send_cmd(&format!("{}\n", self.cmdbuf))
+                };
                 self.cmdbuf = format!("");
                 self.cursor_idx = 0;
             },
@@ -0,0 +1,169 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use std::io::{Read, Write, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::{thread, time};
+use crate::{UsbCmdPacket, UsbCtx, find_device, run_device, get_log_buf, clear_log_buf};
+use crate::ipc_trace::{CHUNK_FIRST, CHUNK_LAST};
+
+pub const CMD_GDB: u8 = 0x16;
+
+// [1, len, CMD_GDB, data...] has to fit in one USB packet
+const GDB_USB_CHUNK: usize = 64 - 3;
+
+static mut CHUNK_BUF: Vec<u8> = Vec::new();
+static mut TCP_OUT: Vec<u8> = Vec::new();
+
+pub fn gdb_bridge_handle(_ctx: &mut UsbCtx, pkt: &UsbCmdPacket)
+{
+    if pkt.data.len() < 2 {
+        return;
+    }
+
+    let flags = pkt.data[1];
+    unsafe
+    {
+        // Single-chunk replies (acks, stop replies) can land between the
+        // chunks of a bigger one sent from another core, don't mix them in
+        if (flags & CHUNK_FIRST) != 0 && (flags & CHUNK_LAST) != 0 {
+            TCP_OUT.extend_from_slice(&pkt.data[2..]);
+            return;
+        }
+
+        if (flags & CHUNK_FIRST) != 0 {
+            CHUNK_BUF.clear();
+        }
+        CHUNK_BUF.extend_from_slice(&pkt.data[2..]);
+
+        if (flags & CHUNK_LAST) == 0 {
+            return;
+        }
+
+        TCP_OUT.extend_from_slice(&CHUNK_BUF);
+        CHUNK_BUF.clear();
+    }
+}
+
+fn gdb_bridge_send(ctx: &mut UsbCtx, data: &[u8]) -> bool
+{
+    for chunk in data.chunks(GDB_USB_CHUNK)
+    {
+        let mut pkt: Vec<u8> = vec![1, (chunk.len() + 1) as u8, CMD_GDB];
+        pkt.extend_from_slice(chunk);
+
+        if let Err(e) = ctx.handle.write_bulk(ctx.ep_out_num, &pkt, time::Duration::from_millis(100)) {
+            std::println!("[gdb] USB write err {}", e);
+            return false;
+        }
+    }
+    return true;
+}
+
+fn gdb_bridge_flush_log()
+{
+    let log = get_log_buf();
+    if !log.is_empty() {
+        std::print!("{}", log);
+        clear_log_buf();
+    }
+}
+
+fn gdb_bridge_connect() -> UsbCtx
+{
+    std::println!("[gdb] Searching for device...");
+    loop
+    {
+        let (mut handle, iface_num, ep_in_num, ep_out_num) = match find_device() {
+            Some(dev) => dev,
+            None => {
+                thread::sleep(time::Duration::from_millis(100));
+                continue;
+            }
+        };
+
+        if handle.reset().is_err() || handle.claim_interface(iface_num).is_err() {
+            std::println!("[gdb] Failed to claim device, retrying...");
+            thread::sleep(time::Duration::from_millis(500));
+            continue;
+        }
+
+        let magic_data: [u8; 4] = [0x0f, 0xf0, 0x0f, 0xf0];
+        if handle.write_bulk(ep_out_num, &magic_data, time::Duration::from_millis(100)).is_err() {
+            continue;
+        }
+
+        std::println!("[gdb] Connected to device");
+        return UsbCtx {
+            handle: handle,
+            ep_in_num: ep_in_num,
+            ep_out_num: ep_out_num,
+            log_buf: String::new()
+        };
+    }
+}
+
+// Pumps one gdb client until either side goes away. Returns false if the
+// device was lost
+fn gdb_bridge_serve(ctx: &mut UsbCtx, client: &mut TcpStream) -> bool
+{
+    let mut tcp_buf: [u8; 0x400] = [0; 0x400];
+    loop
+    {
+        if !run_device(ctx) {
+            std::println!("[gdb] Lost connection with device");
+            return false;
+        }
+        gdb_bridge_flush_log();
+
+        let pending: Vec<u8> = unsafe { TCP_OUT.drain(..).collect() };
+        if !pending.is_empty() && client.write_all(&pending).is_err() {
+            break;
+        }
+
+        match client.read(&mut tcp_buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if !gdb_bridge_send(ctx, &tcp_buf[..n]) {
+                    return false;
+                }
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {},
+            Err(_) => break,
+        };
+    }
+
+    // Don't leave the process stopped behind a dead client
+    std::println!("[gdb] Client disconnected, detaching");
+    return gdb_bridge_send(ctx, b"$D#44");
+}
+
+// Relays gdb's remote protocol between a TCP port and the stub on the
+// device, for `target extended-remote localhost:<port>`
+pub fn gdb_bridge_run(port: u16) -> std::io::Result<()>
+{
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    std::println!("[gdb] Listening on 127.0.0.1:{}", port);
+
+    let mut ctx = gdb_bridge_connect();
+    loop
+    {
+        let (mut client, addr) = listener.accept()?;
+        client.set_nonblocking(true)?;
+        client.set_nodelay(true)?;
+        std::println!("[gdb] Client connected from {}", addr);
+
+        unsafe
+        {
+            CHUNK_BUF.clear();
+            TCP_OUT.clear();
+        }
+
+        if !gdb_bridge_serve(&mut ctx, &mut client) {
+            ctx = gdb_bridge_connect();
+        }
+    }
+}
@@ -0,0 +1,187 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use std::fs;
+use std::string::String;
+use crate::{UsbCmdPacket, UsbCtx, log_push};
+use crate::ipcdb::ipcdb_cmd_name;
+use crate::ipc_trace::{read_u32, read_u64, CHUNK_FIRST, CHUNK_LAST};
+use crate::result::result_format;
+
+pub const CMD_IPCCAP: u8 = 0x12;
+
+pub const IPCCAP_PKT_CAPTURE: u8 = 0;
+pub const IPCCAP_PKT_REPLAY: u8 = 1;
+
+const HIPC_MAX_SIZE: usize = 0x100;
+
+// Hex bytes per `ipccap data` line, keeps lines well under a USB packet or two
+const UPLOAD_LINE_BYTES: usize = 48;
+
+static mut CHUNK_BUF: Vec<u8> = Vec::new();
+
+fn capture_path(slot: u32) -> String
+{
+    format!("ipc_cap_{}.bin", slot)
+}
+
+fn hexdump(data: &[u8]) -> String
+{
+    // Trailing zeroes are just the unused part of the command buffer
+    let len = data.iter().rposition(|b| *b != 0).map(|i| i + 1).unwrap_or(0);
+
+    let mut out = String::new();
+    for (i, chunk) in data[..len].chunks(16).enumerate()
+    {
+        out += &format!("    {:04x}:", i * 16);
+        for b in chunk
+        {
+            out += &format!(" {:02x}", b);
+        }
+        out += "\n";
+    }
+    return out;
+}
+
+// Mirrors IpcCapture::serialize, only the header is needed for the summary
+fn capture_summary(data: &[u8]) -> Option<(u32, String)>
+{
+    if data.len() < 22 {
+        return None;
+    }
+
+    let slot = read_u32(data, 1);
+    let pid = read_u32(data, 5);
+    let handle = read_u32(data, 9);
+    let tls = read_u64(data, 13);
+
+    let service_len = data[21] as usize;
+    let interface_offs = 22 + service_len + 1;
+    if data.len() < interface_offs {
+        return None;
+    }
+    let interface_len = data[interface_offs - 1] as usize;
+    let cmd_buf_offs = interface_offs + interface_len;
+    if data.len() < cmd_buf_offs + HIPC_MAX_SIZE {
+        return None;
+    }
+
+    let service = String::from_utf8_lossy(&data[22..22+service_len]).to_string();
+    let interface = String::from_utf8_lossy(&data[interface_offs..interface_offs+interface_len]).to_string();
+    let cmd_buf = &data[cmd_buf_offs..cmd_buf_offs+HIPC_MAX_SIZE];
+
+    // CMIF command IDs sit after the SFCI magic, TIPC packs them in the type
+    let mut cmd_id = (read_u32(cmd_buf, 0) & 0xFFFF).saturating_sub(16);
+    let mut offs = 0;
+    while offs + 16 <= HIPC_MAX_SIZE
+    {
+        if &cmd_buf[offs..offs+4] == b"SFCI" {
+            cmd_id = read_u32(cmd_buf, offs + 8);
+            break;
+        }
+        offs += 16;
+    }
+
+    let summary = format!("[IPC cap] slot {}: pid {} tls {:x} handle {:x} {}\n{}",
+                          slot, pid, tls, handle, ipcdb_cmd_name(&service, &interface, cmd_id), hexdump(cmd_buf));
+    return Some((slot, summary));
+}
+
+fn replay_summary(data: &[u8]) -> Option<String>
+{
+    if data.len() < 21 + HIPC_MAX_SIZE {
+        return None;
+    }
+
+    let slot = read_u32(data, 1);
+    let pid = read_u32(data, 5);
+    let handle = read_u32(data, 9);
+    let svc_result = read_u32(data, 13);
+    let ipc_result = read_u32(data, 17);
+
+    Some(format!("[IPC cap] replay of slot {} in pid {} (handle {:x}) -> {} / {}\n{}",
+                 slot, pid, handle, result_format(svc_result), result_format(ipc_result),
+                 hexdump(&data[21..21+HIPC_MAX_SIZE])))
+}
+
+// `ipccap upload <slot> <file>` is handled here and turned into the
+// begin/data/end commands the hypervisor understands
+pub fn ipc_cap_host_cmd(line: &str) -> Option<String>
+{
+    let args: Vec<&str> = line.split_whitespace().collect();
+    if args.len() < 2 || args[0] != "ipccap" || args[1] != "upload" {
+        return None;
+    }
+
+    if args.len() < 4 {
+        log_push("[Host] Usage: ipccap upload <slot> <file>\n");
+        return Some(String::new());
+    }
+
+    let data = match fs::read(args[3]) {
+        Ok(data) => data,
+        Err(e) => {
+            log_push(&format!("[Host] Failed to read `{}`: {}\n", args[3], e));
+            return Some(String::new());
+        }
+    };
+
+    let mut cmds = format!("ipccap begin {}\n", args[2]);
+    for chunk in data.chunks(UPLOAD_LINE_BYTES)
+    {
+        cmds += "ipccap data ";
+        for b in chunk
+        {
+            cmds += &format!("{:02x}", b);
+        }
+        cmds += "\n";
+    }
+    cmds += "ipccap end\n";
+
+    return Some(cmds);
+}
+
+pub fn ipc_cap_handle(_ctx: &mut UsbCtx, pkt: &UsbCmdPacket)
+{
+    if pkt.data.len() < 2 {
+        return;
+    }
+
+    let flags = pkt.data[1];
+    unsafe
+    {
+        if (flags & CHUNK_FIRST) != 0 {
+            CHUNK_BUF.clear();
+        }
+        CHUNK_BUF.extend_from_slice(&pkt.data[2..]);
+
+        if (flags & CHUNK_LAST) == 0 || CHUNK_BUF.is_empty() {
+            return;
+        }
+
+        match CHUNK_BUF[0] {
+            IPCCAP_PKT_CAPTURE => {
+                if let Some((slot, summary)) = capture_summary(&CHUNK_BUF) {
+                    log_push(&summary);
+
+                    // Saved as-is, so it can be edited and sent back with `ipccap upload`
+                    let path = capture_path(slot);
+                    match fs::write(&path, &CHUNK_BUF) {
+                        Ok(_) => log_push(&format!("[Host] Saved slot {} to `{}`\n", slot, path)),
+                        Err(e) => log_push(&format!("[Host] Failed to write `{}`: {}\n", path, e))
+                    };
+                }
+            },
+            IPCCAP_PKT_REPLAY => {
+                if let Some(summary) = replay_summary(&CHUNK_BUF) {
+                    log_push(&summary);
+                }
+            },
+            _ => {}
+        }
+        CHUNK_BUF.clear();
+    }
+}
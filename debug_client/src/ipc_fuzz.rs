@@ -0,0 +1,202 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::string::String;
+use crate::{UsbCmdPacket, UsbCtx, log_push};
+use crate::ipcdb::ipcdb_cmd_name;
+use crate::ipc_trace::{read_u16, read_u32, read_u64, CHUNK_FIRST, CHUNK_LAST};
+use crate::result::result_format;
+
+pub const CMD_IPCFUZZ: u8 = 0x11;
+
+const CRASHERS_PATH: &str = "ipc_fuzz_crashers.txt";
+
+static mut CHUNK_BUF: Vec<u8> = Vec::new();
+
+pub struct IpcFuzzMutation {
+    pub kind: u8,
+    pub index: u16,
+    pub old: u64,
+    pub new: u64,
+}
+
+pub struct IpcFuzzCrash {
+    pub seq: u32,
+    pub seed: u64,
+    pub client_pid: u32,
+    pub server_pid: u32,
+    pub cmd_id: u32,
+    pub svc_result: u32,
+    pub ipc_result: u32,
+    pub reason: u8,
+    pub service: String,
+    pub interface: String,
+    pub mutations: Vec<IpcFuzzMutation>,
+    pub payload: Vec<u8>,
+}
+
+impl IpcFuzzMutation {
+    // Matches HIPCMutation::describe on the hypervisor side
+    pub fn describe(&self) -> String
+    {
+        match self.kind
+        {
+            0 => format!("raw[{:#x}] {:#x} -> {:#x}", self.index, self.old, self.new),
+            1 => format!("X[{}].size {:#x} -> {:#x}", self.index, self.old, self.new),
+            2 => format!("A[{}].size {:#x} -> {:#x}", self.index, self.old, self.new),
+            3 => format!("B[{}].size {:#x} -> {:#x}", self.index, self.old, self.new),
+            4 => format!("W[{}].size {:#x} -> {:#x}", self.index, self.old, self.new),
+            5 => format!("handles {} -> {} (+{:x})", self.old, self.old + 1, self.new),
+            6 => format!("handles {} -> {} (-{:x})", self.old, self.old.wrapping_sub(1), self.new),
+            7 => format!("raw size {:#x} -> {:#x}", self.old, self.new),
+            _ => format!("unknown mutation {}", self.kind),
+        }
+    }
+}
+
+impl IpcFuzzCrash {
+    pub fn parse(data: &[u8]) -> Option<IpcFuzzCrash>
+    {
+        if data.len() < 34 {
+            return None;
+        }
+
+        let service_len = data[33] as usize;
+        let mut offs = 34;
+        if data.len() < offs + service_len + 1 {
+            return None;
+        }
+        let service = String::from_utf8_lossy(&data[offs..offs+service_len]).to_string();
+        offs += service_len;
+
+        let interface_len = data[offs] as usize;
+        offs += 1;
+        if data.len() < offs + interface_len + 1 {
+            return None;
+        }
+        let interface = String::from_utf8_lossy(&data[offs..offs+interface_len]).to_string();
+        offs += interface_len;
+
+        let num_mutations = data[offs] as usize;
+        offs += 1;
+        let mut mutations: Vec<IpcFuzzMutation> = Vec::new();
+        for _ in 0..num_mutations
+        {
+            if data.len() < offs + 19 {
+                return None;
+            }
+            mutations.push(IpcFuzzMutation {
+                kind: data[offs],
+                index: read_u16(data, offs + 1),
+                old: read_u64(data, offs + 3),
+                new: read_u64(data, offs + 11),
+            });
+            offs += 19;
+        }
+
+        if data.len() < offs + 2 {
+            return None;
+        }
+        let payload_len = read_u16(data, offs) as usize;
+        offs += 2;
+        if data.len() < offs + payload_len {
+            return None;
+        }
+
+        Some(IpcFuzzCrash {
+            seq: read_u32(data, 0),
+            seed: read_u64(data, 4),
+            client_pid: read_u32(data, 12),
+            server_pid: read_u32(data, 16),
+            cmd_id: read_u32(data, 20),
+            svc_result: read_u32(data, 24),
+            ipc_result: read_u32(data, 28),
+            reason: data[32],
+            service: service,
+            interface: interface,
+            mutations: mutations,
+            payload: data[offs..offs+payload_len].to_vec(),
+        })
+    }
+
+    pub fn reason_str(&self) -> &'static str
+    {
+        match self.reason
+        {
+            1 => "fatal",
+            2 => "svcBreak",
+            _ => "session closed",
+        }
+    }
+
+    pub fn summary(&self) -> String
+    {
+        let mut out = format!("[IPC fuzz] pid {} crashed ({}) on case #{} (seed {:#x}): {} from pid {} -> {} / {}\n",
+                              self.server_pid, self.reason_str(), self.seq, self.seed,
+                              ipcdb_cmd_name(&self.service, &self.interface, self.cmd_id), self.client_pid,
+                              result_format(self.svc_result), result_format(self.ipc_result));
+        for mutation in &self.mutations
+        {
+            out += &format!("    {}\n", mutation.describe());
+        }
+        return out;
+    }
+}
+
+// Plain text so crashers can be diffed and pasted into bug reports
+fn crashers_write(crash: &IpcFuzzCrash)
+{
+    let file = OpenOptions::new().create(true).append(true).open(CRASHERS_PATH);
+    let mut f = match file {
+        Ok(f) => f,
+        Err(e) => {
+            log_push(&format!("[Host] Failed to open `{}`: {}\n", CRASHERS_PATH, e));
+            return;
+        }
+    };
+
+    let mut out = crash.summary();
+    for (i, chunk) in crash.payload.chunks(16).enumerate()
+    {
+        out += &format!("    {:04x}:", i * 16);
+        for b in chunk
+        {
+            out += &format!(" {:02x}", b);
+        }
+        out += "\n";
+    }
+    out += "\n";
+
+    let _ = f.write_all(out.as_bytes());
+}
+
+pub fn ipc_fuzz_handle(_ctx: &mut UsbCtx, pkt: &UsbCmdPacket)
+{
+    if pkt.data.len() < 2 {
+        return;
+    }
+
+    let flags = pkt.data[1];
+    unsafe
+    {
+        if (flags & CHUNK_FIRST) != 0 {
+            CHUNK_BUF.clear();
+        }
+        CHUNK_BUF.extend_from_slice(&pkt.data[2..]);
+
+        if (flags & CHUNK_LAST) == 0 {
+            return;
+        }
+
+        if let Some(crash) = IpcFuzzCrash::parse(&CHUNK_BUF) {
+            log_push(&crash.summary());
+            crashers_write(&crash);
+        }
+        CHUNK_BUF.clear();
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use std::string::String;
+use crate::{UsbCmdPacket, UsbCtx};
+use crate::ipc_trace::{read_u32, CHUNK_FIRST, CHUNK_LAST};
+
+pub const CMD_IPCSTAT: u8 = 0x13;
+
+static mut CHUNK_BUF: Vec<u8> = Vec::new();
+static mut IPC_STAT_LATEST: Vec<IpcStatEntry> = Vec::new();
+
+#[derive(Clone)]
+pub struct IpcStatEntry {
+    pub service: String,
+    pub errors: u32,
+    pub avg_ns: u32,
+    pub max_ns: u32,
+    pub per_sec: u64,
+}
+
+// Mirrors ipcstat_send_telemetry on the hypervisor side
+fn ipc_stat_parse(data: &[u8]) -> Option<Vec<IpcStatEntry>>
+{
+    if data.len() < 5 {
+        return None;
+    }
+
+    let interval_ms = std::cmp::max(read_u32(data, 0), 1) as u64;
+    let count = data[4] as usize;
+    let mut offs = 5;
+    let mut entries: Vec<IpcStatEntry> = Vec::new();
+    for _ in 0..count
+    {
+        if data.len() < offs + 1 {
+            return None;
+        }
+        let service_len = data[offs] as usize;
+        offs += 1;
+        if data.len() < offs + service_len + 16 {
+            return None;
+        }
+        let service = String::from_utf8_lossy(&data[offs..offs+service_len]).to_string();
+        offs += service_len;
+
+        let requests = read_u32(data, offs);
+        entries.push(IpcStatEntry {
+            service: service,
+            errors: read_u32(data, offs + 4),
+            avg_ns: read_u32(data, offs + 8),
+            max_ns: read_u32(data, offs + 12),
+            per_sec: (requests as u64) * 1000 / interval_ms,
+        });
+        offs += 16;
+    }
+
+    return Some(entries);
+}
+
+// Busiest services from the last telemetry interval
+pub fn get_ipc_stats() -> Vec<IpcStatEntry>
+{
+    unsafe { IPC_STAT_LATEST.clone() }
+}
+
+pub fn ipc_stat_handle(_ctx: &mut UsbCtx, pkt: &UsbCmdPacket)
+{
+    if pkt.data.len() < 2 {
+        return;
+    }
+
+    let flags = pkt.data[1];
+    unsafe
+    {
+        if (flags & CHUNK_FIRST) != 0 {
+            CHUNK_BUF.clear();
+        }
+        CHUNK_BUF.extend_from_slice(&pkt.data[2..]);
+
+        if (flags & CHUNK_LAST) == 0 {
+            return;
+        }
+
+        if let Some(entries) = ipc_stat_parse(&CHUNK_BUF) {
+            IPC_STAT_LATEST = entries;
+        }
+        CHUNK_BUF.clear();
+    }
+}
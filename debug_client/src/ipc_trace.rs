@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::string::String;
+use crate::{UsbCmdPacket, UsbCtx, log_push};
+use crate::ipcdb::*;
+use crate::result::result_format;
+
+pub const CMD_IPCTRACE: u8 = 0x10;
+
+pub const CHUNK_FIRST: u8 = 1 << 0;
+pub const CHUNK_LAST: u8 = 1 << 1;
+
+pub const IPCTRACE_FLAG_DOMAIN: u8 = 1 << 0;
+pub const IPCTRACE_FLAG_TIPC: u8 = 1 << 1;
+
+// Capture files are the magic followed by [u32 len][record] entries,
+// records being exactly what the hypervisor sent
+const CAPTURE_MAGIC: &[u8; 8] = b"HTBIPC\x00\x01";
+const CAPTURE_DEFAULT_PATH: &str = "ipc_trace.htbcap";
+
+static mut CHUNK_BUF: Vec<u8> = Vec::new();
+static mut CAPTURE_FILE: Option<File> = None;
+
+pub struct IpcTraceRecord {
+    pub pid: u32,
+    pub handle: u32,
+    pub domain_obj: u32,
+    pub cmd_id: u32,
+    pub pkt_type: u16,
+    pub flags: u8,
+    pub svc_result: u32,
+    pub ipc_result: u32,
+    pub latency: u64,
+    pub service: String,
+    pub interface: String,
+    pub payload: Vec<u8>,
+}
+
+pub fn read_u16(data: &[u8], offs: usize) -> u16
+{
+    u16::from_le_bytes([data[offs], data[offs+1]])
+}
+
+pub fn read_u32(data: &[u8], offs: usize) -> u32
+{
+    u32::from_le_bytes([data[offs], data[offs+1], data[offs+2], data[offs+3]])
+}
+
+pub fn read_u64(data: &[u8], offs: usize) -> u64
+{
+    (read_u32(data, offs) as u64) | ((read_u32(data, offs+4) as u64) << 32)
+}
+
+impl IpcTraceRecord {
+    pub fn parse(data: &[u8]) -> Option<IpcTraceRecord>
+    {
+        if data.len() < 36 {
+            return None;
+        }
+
+        let service_len = data[19] as usize;
+        let interface_offs = 36 + service_len + 1;
+        if data.len() < interface_offs {
+            return None;
+        }
+
+        let interface_len = data[interface_offs - 1] as usize;
+        let payload_len_offs = interface_offs + interface_len;
+        if data.len() < payload_len_offs + 2 {
+            return None;
+        }
+
+        let payload_len = read_u16(data, payload_len_offs) as usize;
+        let payload_offs = payload_len_offs + 2;
+        if data.len() < payload_offs + payload_len {
+            return None;
+        }
+
+        Some(IpcTraceRecord {
+            pid: read_u32(data, 0),
+            handle: read_u32(data, 4),
+            domain_obj: read_u32(data, 8),
+            cmd_id: read_u32(data, 12),
+            pkt_type: read_u16(data, 16),
+            flags: data[18],
+            svc_result: read_u32(data, 20),
+            ipc_result: read_u32(data, 24),
+            latency: read_u64(data, 28),
+            service: String::from_utf8_lossy(&data[36..36+service_len]).to_string(),
+            interface: String::from_utf8_lossy(&data[interface_offs..interface_offs+interface_len]).to_string(),
+            payload: data[payload_offs..payload_offs+payload_len].to_vec(),
+        })
+    }
+
+    // CMIF raw data starts after the SFCI header, which is 16-byte aligned
+    // somewhere after the descriptors
+    fn raw_data(&self) -> Option<&[u8]>
+    {
+        if (self.flags & IPCTRACE_FLAG_TIPC) != 0 {
+            return None;
+        }
+
+        let mut offs = 0;
+        while offs + 16 <= self.payload.len()
+        {
+            if &self.payload[offs..offs+4] == b"SFCI" {
+                return Some(&self.payload[offs+16..]);
+            }
+            offs += 16;
+        }
+        return None;
+    }
+
+    pub fn summary(&self) -> String
+    {
+        let mut name = ipcdb_cmd_name(&self.service, &self.interface, self.cmd_id);
+        if self.service.is_empty() && self.interface.is_empty() {
+            name = format!("handle {:x}::Cmd{}", self.handle, self.cmd_id);
+        }
+        if (self.flags & IPCTRACE_FLAG_DOMAIN) != 0 {
+            name = format!("{}[{}]", name, self.domain_obj);
+        }
+        let args = ipcdb_format_args(&self.service, &self.interface, self.cmd_id, self.raw_data());
+
+        let proto = if (self.flags & IPCTRACE_FLAG_TIPC) != 0 { "tipc" } else { "cmif" };
+        let result = if self.svc_result != 0 { self.svc_result } else { self.ipc_result };
+
+        // Ticks are 19.2MHz
+        format!("[IPC] pid {:3} {}{} ({}, type {}) -> {} in {}us, {} bytes",
+                self.pid, name, args, proto, self.pkt_type, result_format(result),
+                (self.latency * 625) / 12000, self.payload.len())
+    }
+}
+
+fn capture_write(record: &[u8])
+{
+    unsafe
+    {
+        if CAPTURE_FILE.is_none() {
+            let exists = std::path::Path::new(CAPTURE_DEFAULT_PATH).exists();
+            let file = OpenOptions::new().create(true).append(true).open(CAPTURE_DEFAULT_PATH);
+            match file {
+                Ok(mut f) => {
+                    if !exists {
+                        let _ = f.write_all(CAPTURE_MAGIC);
+                    }
+                    CAPTURE_FILE = Some(f);
+                },
+                Err(e) => {
+                    log_push(&format!("[Host] Failed to open IPC capture `{}`: {}\n", CAPTURE_DEFAULT_PATH, e));
+                    return;
+                }
+            }
+        }
+
+        if let Some(f) = CAPTURE_FILE.as_mut() {
+            let _ = f.write_all(&(record.len() as u32).to_le_bytes());
+            let _ = f.write_all(record);
+        }
+    }
+}
+
+pub fn ipc_trace_handle(_ctx: &mut UsbCtx, pkt: &UsbCmdPacket)
+{
+    if pkt.data.len() < 2 {
+        return;
+    }
+
+    let flags = pkt.data[1];
+    unsafe
+    {
+        if (flags & CHUNK_FIRST) != 0 {
+            CHUNK_BUF.clear();
+        }
+        CHUNK_BUF.extend_from_slice(&pkt.data[2..]);
+
+        if (flags & CHUNK_LAST) == 0 {
+            return;
+        }
+
+        capture_write(&CHUNK_BUF);
+        if let Some(record) = IpcTraceRecord::parse(&CHUNK_BUF) {
+            log_push(&format!("{}\n", record.summary()));
+        }
+        CHUNK_BUF.clear();
+    }
+}
+
+pub fn ipc_trace_load(path: &str) -> std::io::Result<Vec<IpcTraceRecord>>
+{
+    let mut data: Vec<u8> = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    if data.len() < CAPTURE_MAGIC.len() || &data[..CAPTURE_MAGIC.len()] != CAPTURE_MAGIC {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not an IPC capture file"));
+    }
+
+    let mut records: Vec<IpcTraceRecord> = Vec::new();
+    let mut offs = CAPTURE_MAGIC.len();
+    while offs + 4 <= data.len()
+    {
+        let len = read_u32(&data, offs) as usize;
+        offs += 4;
+        if offs + len > data.len() {
+            break;
+        }
+
+        if let Some(record) = IpcTraceRecord::parse(&data[offs..offs+len]) {
+            records.push(record);
+        }
+        offs += len;
+    }
+
+    return Ok(records);
+}
@@ -0,0 +1,190 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use std::string::String;
+use std::sync::Once;
+
+// Same file the hypervisor's build.rs generates its tables from
+const IPCDB_TXT: &str = include_str!("../../src/hos/ipcdb.txt");
+
+pub struct IpcArg {
+    pub is_in: bool,
+    pub kind: String,
+    pub name: String,
+    pub offset: usize,
+}
+
+pub struct IpcCmd {
+    pub id: u32,
+    pub name: String,
+    pub args: Vec<IpcArg>,
+}
+
+pub struct IpcInterface {
+    pub name: String,
+    pub short_name: String,
+    pub services: Vec<String>,
+    pub cmds: Vec<IpcCmd>,
+}
+
+static mut IPCDB: Vec<IpcInterface> = Vec::new();
+static IPCDB_INIT: Once = Once::new();
+
+fn raw_size(kind: &str) -> usize
+{
+    match kind {
+        "u8" | "bool" => 1,
+        "u16" => 2,
+        "u32" => 4,
+        "u64" | "pid" => 8,
+        "u128" => 16,
+        _ => 0
+    }
+}
+
+fn ipcdb_parse() -> Vec<IpcInterface>
+{
+    let mut ifaces: Vec<IpcInterface> = Vec::new();
+
+    for line in IPCDB_TXT.lines()
+    {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let split: Vec<&str> = line.split_whitespace().collect();
+        match split[0] {
+            "interface" if split.len() == 3 => {
+                ifaces.push(IpcInterface {
+                    name: String::from(split[1]),
+                    short_name: String::from(split[2]),
+                    services: Vec::new(),
+                    cmds: Vec::new(),
+                });
+            },
+            "service" if split.len() == 2 => {
+                if let Some(iface) = ifaces.last_mut() {
+                    iface.services.push(String::from(split[1]));
+                }
+            },
+            "cmd" if split.len() >= 3 => {
+                let mut args: Vec<IpcArg> = Vec::new();
+                let mut in_offs = 0;
+                let mut out_offs = 0;
+                for arg in &split[3..]
+                {
+                    let arg_split: Vec<&str> = arg.split(':').collect();
+                    if arg_split.len() < 2 {
+                        continue;
+                    }
+
+                    let is_in = arg_split[0] == "in";
+                    let size = raw_size(arg_split[1]);
+                    let mut offset = 0;
+                    if size != 0 {
+                        let offs = if is_in { &mut in_offs } else { &mut out_offs };
+                        *offs = (*offs + size - 1) & !(size - 1);
+                        offset = *offs;
+                        *offs += size;
+                    }
+
+                    args.push(IpcArg {
+                        is_in: is_in,
+                        kind: String::from(arg_split[1]),
+                        name: String::from(*arg_split.get(2).unwrap_or(&arg_split[1])),
+                        offset: offset,
+                    });
+                }
+
+                if let (Some(iface), Ok(id)) = (ifaces.last_mut(), split[1].parse::<u32>()) {
+                    iface.cmds.push(IpcCmd {
+                        id: id,
+                        name: String::from(split[2]),
+                        args: args,
+                    });
+                }
+            },
+            _ => {}
+        }
+    }
+
+    return ifaces;
+}
+
+fn ipcdb_get() -> &'static Vec<IpcInterface>
+{
+    unsafe
+    {
+        IPCDB_INIT.call_once(|| { IPCDB = ipcdb_parse(); });
+        &IPCDB
+    }
+}
+
+pub fn ipcdb_lookup(service: &str, interface: &str) -> Option<&'static IpcInterface>
+{
+    let db = ipcdb_get();
+    if !interface.is_empty() {
+        if let Some(iface) = db.iter().find(|i| i.name == interface || i.short_name == interface) {
+            return Some(iface);
+        }
+    }
+    return db.iter().find(|i| i.services.iter().any(|s| s == service));
+}
+
+pub fn ipcdb_cmd_name(service: &str, interface: &str, cmd_id: u32) -> String
+{
+    let iface_opt = ipcdb_lookup(service, interface);
+    let prefix = match iface_opt {
+        Some(iface) => iface.short_name.clone(),
+        None if !interface.is_empty() => String::from(interface),
+        None if !service.is_empty() => String::from(service),
+        None => String::from("unk")
+    };
+
+    if let Some(cmd) = iface_opt.and_then(|iface| iface.cmds.iter().find(|c| c.id == cmd_id)) {
+        return format!("{}::{}", prefix, cmd.name);
+    }
+    return format!("{}::Cmd{}", prefix, cmd_id);
+}
+
+// `raw` is the request's raw data (after the SFCI header), if it could be found
+pub fn ipcdb_format_args(service: &str, interface: &str, cmd_id: u32, raw: Option<&[u8]>) -> String
+{
+    let cmd = match ipcdb_lookup(service, interface).and_then(|iface| iface.cmds.iter().find(|c| c.id == cmd_id)) {
+        Some(cmd) => cmd,
+        None => return String::from("(...)")
+    };
+
+    let mut out = String::from("(");
+    let mut first = true;
+    for arg in &cmd.args
+    {
+        if !arg.is_in {
+            continue;
+        }
+
+        if !first {
+            out += ", ";
+        }
+        first = false;
+
+        let size = raw_size(&arg.kind);
+        let val = match raw {
+            Some(data) if size != 0 && arg.offset + size <= data.len() => {
+                let mut bytes = [0u8; 16];
+                bytes[..size].copy_from_slice(&data[arg.offset..arg.offset+size]);
+                let val = u128::from_le_bytes(bytes);
+                if arg.kind == "bool" { format!("{}", val != 0) } else { format!("{:#x}", val) }
+            },
+            _ => arg.kind.clone()
+        };
+        out += &format!("{}={}", arg.name, val);
+    }
+    out += ")";
+
+    return out;
+}
@@ -6,9 +6,26 @@
 
 #![feature(assoc_char_funcs)]
 
+extern crate alloc;
 extern crate rusb;
 
 mod file_cmd;
+mod ipc_trace;
+mod ipc_fuzz;
+mod ipc_cap;
+mod ipc_stat;
+mod svc_trace;
+mod svc_prof;
+mod gdb_bridge;
+mod ipcdb;
+// Shared with the hypervisor, the client only formats results
+#[path = "../../src/hos/result.rs"]
+#[allow(dead_code)]
+mod result;
+// Same, the client only decodes traced calls by ID
+#[path = "../../src/hos/svcsig.rs"]
+#[allow(dead_code)]
+mod svcsig;
 mod app;
 mod ui;
 mod util;
@@ -26,6 +43,13 @@ use signal_hook::consts::TERM_SIGNALS;
 use signal_hook::flag;
 use binread::{BinRead, io::Cursor};
 use crate::file_cmd::file_cmd_handle;
+use crate::ipc_trace::{ipc_trace_handle, ipc_trace_load, CMD_IPCTRACE};
+use crate::ipc_fuzz::{ipc_fuzz_handle, CMD_IPCFUZZ};
+use crate::ipc_cap::{ipc_cap_handle, CMD_IPCCAP};
+use crate::ipc_stat::{ipc_stat_handle, CMD_IPCSTAT};
+use crate::svc_trace::{svc_trace_handle, CMD_SVCTRACE};
+use crate::svc_prof::{svc_prof_handle, CMD_SVCPROF};
+use crate::gdb_bridge::{gdb_bridge_handle, gdb_bridge_run, CMD_GDB};
 use crate::app::App;
 use std::string::String;
 use crossterm::{
@@ -119,6 +143,11 @@ pub fn get_log_buf() -> &'static String
     unsafe { &LOG_BUF }
 }
 
+pub fn log_push(text: &str)
+{
+    unsafe { LOG_BUF.push_str(text) }
+}
+
 pub fn clear_log_buf()
 {
     send_cmd(&String::new());
@@ -251,6 +280,27 @@ fn process_input(ctx: &mut UsbCtx, input_buf: &[u8; 64], n: usize)
             else if pkt.data[0] == 1 {
                 file_cmd_handle(ctx, &pkt);
             }
+            else if pkt.data[0] == CMD_IPCTRACE {
+                ipc_trace_handle(ctx, &pkt);
+            }
+            else if pkt.data[0] == CMD_IPCFUZZ {
+                ipc_fuzz_handle(ctx, &pkt);
+            }
+            else if pkt.data[0] == CMD_IPCCAP {
+                ipc_cap_handle(ctx, &pkt);
+            }
+            else if pkt.data[0] == CMD_IPCSTAT {
+                ipc_stat_handle(ctx, &pkt);
+            }
+            else if pkt.data[0] == CMD_SVCTRACE {
+                svc_trace_handle(ctx, &pkt);
+            }
+            else if pkt.data[0] == CMD_SVCPROF {
+                svc_prof_handle(ctx, &pkt);
+            }
+            else if pkt.data[0] == CMD_GDB {
+                gdb_bridge_handle(ctx, &pkt);
+            }
         }
         else
         {
@@ -347,6 +397,21 @@ fn run_device(ctx: &mut UsbCtx) -> bool
 
 fn main() -> Result<(), Box<dyn Error>>
 {
+    // Dump a previously saved IPC capture instead of connecting
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() >= 3 && args[1] == "--open-capture" {
+        for record in ipc_trace_load(&args[2])? {
+            std::println!("{}", record.summary());
+        }
+        return Ok(());
+    }
+
+    // Headless relay for gdb instead of the TUI
+    if args.len() >= 3 && args[1] == "--gdb-bridge" {
+        gdb_bridge_run(args[2].parse()?)?;
+        return Ok(());
+    }
+
     let term_now = Arc::new(AtomicBool::new(false));
 
     for sig in TERM_SIGNALS {
@@ -425,6 +490,7 @@ fn main() -> Result<(), Box<dyn Error>>
                     KeyCode::Down => app.on_down(),
                     KeyCode::PageUp => app.on_pageup(),
                     KeyCode::PageDown => app.on_pagedown(),
+                    KeyCode::Tab => app.on_tab(),
                     _ => {}
                 }
             },
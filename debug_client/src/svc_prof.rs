@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use std::string::String;
+use crate::{UsbCmdPacket, UsbCtx};
+use crate::ipc_trace::{read_u32, CHUNK_FIRST, CHUNK_LAST};
+use crate::svcsig::svcsig_lookup;
+
+pub const CMD_SVCPROF: u8 = 0x15;
+
+static mut CHUNK_BUF: Vec<u8> = Vec::new();
+static mut SVC_PROF_LATEST: Vec<SvcProfEntry> = Vec::new();
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SvcProfSort {
+    Total,
+    Calls,
+    Avg,
+    Max,
+    Process,
+}
+
+impl SvcProfSort {
+    pub fn next(self) -> SvcProfSort
+    {
+        match self {
+            SvcProfSort::Total => SvcProfSort::Calls,
+            SvcProfSort::Calls => SvcProfSort::Avg,
+            SvcProfSort::Avg => SvcProfSort::Max,
+            SvcProfSort::Max => SvcProfSort::Process,
+            SvcProfSort::Process => SvcProfSort::Total,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SvcProfEntry {
+    pub pid: u8,
+    pub svc_id: u8,
+    pub process: String,
+    pub calls: u32,
+    pub total_us: u32,
+    pub avg_ns: u32,
+    pub max_ns: u32,
+}
+
+impl SvcProfEntry {
+    pub fn svc_name(&self) -> String
+    {
+        match svcsig_lookup(self.svc_id) {
+            Some(sig) => sig.name.to_string(),
+            None => format!("{:#x}", self.svc_id),
+        }
+    }
+}
+
+// Mirrors svcprof_send_telemetry on the hypervisor side
+fn svc_prof_parse(data: &[u8]) -> Option<Vec<SvcProfEntry>>
+{
+    if data.len() < 1 {
+        return None;
+    }
+
+    let count = data[0] as usize;
+    let mut offs = 1;
+    let mut entries: Vec<SvcProfEntry> = Vec::new();
+    for _ in 0..count
+    {
+        if data.len() < offs + 3 {
+            return None;
+        }
+        let pid = data[offs];
+        let svc_id = data[offs + 1];
+        let name_len = data[offs + 2] as usize;
+        offs += 3;
+        if data.len() < offs + name_len + 16 {
+            return None;
+        }
+        let process = String::from_utf8_lossy(&data[offs..offs+name_len]).to_string();
+        offs += name_len;
+
+        entries.push(SvcProfEntry {
+            pid: pid,
+            svc_id: svc_id,
+            process: process,
+            calls: read_u32(data, offs),
+            total_us: read_u32(data, offs + 4),
+            avg_ns: read_u32(data, offs + 8),
+            max_ns: read_u32(data, offs + 12),
+        });
+        offs += 16;
+    }
+
+    return Some(entries);
+}
+
+// Busiest (pid, SVC) pairs since the last reset, in the requested order
+pub fn get_svc_prof(sort: SvcProfSort) -> Vec<SvcProfEntry>
+{
+    let mut entries = unsafe { SVC_PROF_LATEST.clone() };
+    match sort {
+        SvcProfSort::Total => entries.sort_by(|a, b| b.total_us.cmp(&a.total_us)),
+        SvcProfSort::Calls => entries.sort_by(|a, b| b.calls.cmp(&a.calls)),
+        SvcProfSort::Avg => entries.sort_by(|a, b| b.avg_ns.cmp(&a.avg_ns)),
+        SvcProfSort::Max => entries.sort_by(|a, b| b.max_ns.cmp(&a.max_ns)),
+        SvcProfSort::Process => entries.sort_by(|a, b| (a.pid, b.total_us).cmp(&(b.pid, a.total_us))),
+    };
+    return entries;
+}
+
+pub fn svc_prof_handle(_ctx: &mut UsbCtx, pkt: &UsbCmdPacket)
+{
+    if pkt.data.len() < 2 {
+        return;
+    }
+
+    let flags = pkt.data[1];
+    unsafe
+    {
+        if (flags & CHUNK_FIRST) != 0 {
+            CHUNK_BUF.clear();
+        }
+        CHUNK_BUF.extend_from_slice(&pkt.data[2..]);
+
+        if (flags & CHUNK_LAST) == 0 {
+            return;
+        }
+
+        if let Some(entries) = svc_prof_parse(&CHUNK_BUF) {
+            SVC_PROF_LATEST = entries;
+        }
+        CHUNK_BUF.clear();
+    }
+}
@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use std::string::String;
+use crate::{UsbCmdPacket, UsbCtx, log_push};
+use crate::ipc_trace::{read_u32, read_u64, CHUNK_FIRST, CHUNK_LAST};
+use crate::svcsig::svcsig_lookup;
+
+pub const CMD_SVCTRACE: u8 = 0x14;
+
+static mut CHUNK_BUF: Vec<u8> = Vec::new();
+
+pub struct SvcTraceRecord {
+    pub pid: u32,
+    pub svc_id: u8,
+    pub result: u32,
+    pub latency: u64,
+    pub ins: Vec<u64>,
+    pub outs: Vec<u64>,
+    pub text: String,
+}
+
+impl SvcTraceRecord {
+    pub fn parse(data: &[u8]) -> Option<SvcTraceRecord>
+    {
+        if data.len() < 20 {
+            return None;
+        }
+
+        let num_in = data[5] as usize;
+        let num_out = data[6] as usize;
+        let text_len = data[7] as usize;
+        let text_offs = 20 + (num_in + num_out) * 8;
+        if data.len() < text_offs + text_len {
+            return None;
+        }
+
+        let regs: Vec<u64> = (0..num_in + num_out).map(|i| read_u64(data, 20 + i * 8)).collect();
+
+        Some(SvcTraceRecord {
+            pid: read_u32(data, 0),
+            svc_id: data[4],
+            result: read_u32(data, 8),
+            latency: read_u64(data, 12),
+            ins: regs[..num_in].to_vec(),
+            outs: regs[num_in..].to_vec(),
+            text: String::from_utf8_lossy(&data[text_offs..text_offs+text_len]).to_string(),
+        })
+    }
+
+    pub fn summary(&self) -> String
+    {
+        let call = match svcsig_lookup(self.svc_id) {
+            Some(sig) => sig.format_call(&self.ins, &self.outs, self.result, if self.text.is_empty() { None } else { Some(&self.text) }),
+            None => format!("Svc{:#x}{:x?}", self.svc_id, self.ins),
+        };
+
+        // Ticks are 19.2MHz
+        format!("[SVC] pid {:3} {} in {}us", self.pid, call, (self.latency * 625) / 12000)
+    }
+}
+
+pub fn svc_trace_handle(_ctx: &mut UsbCtx, pkt: &UsbCmdPacket)
+{
+    if pkt.data.len() < 2 {
+        return;
+    }
+
+    let flags = pkt.data[1];
+    unsafe
+    {
+        if (flags & CHUNK_FIRST) != 0 {
+            CHUNK_BUF.clear();
+        }
+        CHUNK_BUF.extend_from_slice(&pkt.data[2..]);
+
+        if (flags & CHUNK_LAST) == 0 {
+            return;
+        }
+
+        if let Some(record) = SvcTraceRecord::parse(&CHUNK_BUF) {
+            log_push(&format!("{}\n", record.summary()));
+        }
+        CHUNK_BUF.clear();
+    }
+}
@@ -7,11 +7,13 @@ use tui::{
     text::{Span, Spans},
     widgets::{
         Block, Borders,
-        Paragraph, Sparkline, Wrap,
+        BarChart, Cell, Paragraph, Row, Sparkline, Table, Wrap,
     },
     Frame,
 };
 use crate::{get_log_buf, get_sparkline_max, get_sparkline};
+use crate::ipc_stat::get_ipc_stats;
+use crate::svc_prof::{get_svc_prof, SvcProfSort};
 
 pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     let chunks = Layout::default()
@@ -29,6 +31,7 @@ where
         .constraints(
             [
                 Constraint::Length(7),
+                Constraint::Length(10),
                 Constraint::Min(8),
                 Constraint::Length(3),
             ]
@@ -36,14 +39,22 @@ where
         )
         .split(area);
     draw_gauges(f, app, chunks[0]);
-    draw_charts(f, app, chunks[1]);
-    draw_text(f, app, chunks[2]);
+    draw_svc_prof(f, app, chunks[1]);
+    draw_charts(f, app, chunks[2]);
+    draw_text(f, app, chunks[3]);
 }
 
 fn draw_gauges<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
+    let halves = Layout::default()
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .direction(Direction::Horizontal)
+        .split(area);
+    let area = halves[0];
+    draw_ipc_stats(f, app, halves[1]);
+
     let chunks = Layout::default()
         .constraints(
             [
@@ -81,6 +92,74 @@ where
     f.render_widget(line_gauge, chunks[1]);*/
 }
 
+fn draw_ipc_stats<B>(f: &mut Frame<B>, _app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let stats = get_ipc_stats();
+    let title = match stats.first() {
+        Some(busiest) => format!("IPC req/s (`{}` avg {}ns max {}ns, {} errs):", busiest.service, busiest.avg_ns, busiest.max_ns, busiest.errors),
+        None => format!("IPC req/s (ipcstat off):"),
+    };
+
+    let data: Vec<(&str, u64)> = stats.iter().map(|e| (e.service.as_str(), e.per_sec)).collect();
+    let barchart = BarChart::default()
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .data(&data)
+        .bar_width(9)
+        .bar_gap(1)
+        .bar_style(Style::default().fg(Color::Yellow))
+        .value_style(Style::default().fg(Color::Black).bg(Color::Yellow));
+    f.render_widget(barchart, area);
+}
+
+fn draw_svc_prof<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let sort = app.svc_prof_sort;
+    let columns = [
+        ("Process", Some(SvcProfSort::Process)),
+        ("SVC", None),
+        ("Calls", Some(SvcProfSort::Calls)),
+        ("Total us", Some(SvcProfSort::Total)),
+        ("Avg ns", Some(SvcProfSort::Avg)),
+        ("Max ns", Some(SvcProfSort::Max)),
+    ];
+    let header_cells = columns.iter().map(|(name, col_sort)| {
+        if *col_sort == Some(sort) {
+            Cell::from(format!("{} v", name)).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        } else {
+            Cell::from(*name).style(Style::default().add_modifier(Modifier::BOLD))
+        }
+    });
+    let header = Row::new(header_cells).bottom_margin(0);
+
+    let rows = get_svc_prof(sort).into_iter().map(|e| {
+        Row::new(vec![
+            Cell::from(format!("{} ({})", e.pid, e.process)),
+            Cell::from(e.svc_name()),
+            Cell::from(format!("{}", e.calls)),
+            Cell::from(format!("{}", e.total_us)),
+            Cell::from(format!("{}", e.avg_ns)),
+            Cell::from(format!("{}", e.max_ns)),
+        ])
+    });
+
+    let table = Table::new(rows)
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title("SVC profile (Tab to change sort, svcprof reset to clear):"))
+        .widths(&[
+            Constraint::Percentage(25),
+            Constraint::Percentage(25),
+            Constraint::Percentage(12),
+            Constraint::Percentage(13),
+            Constraint::Percentage(12),
+            Constraint::Percentage(13),
+        ]);
+    f.render_widget(table, area);
+}
+
 fn draw_charts<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
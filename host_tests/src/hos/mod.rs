@@ -0,0 +1,38 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+#[path = "../../../src/hos/hipc.rs"]
+pub mod hipc;
+#[path = "../../../src/hos/hipcmem.rs"]
+pub mod hipcmem;
+#[path = "../../../src/hos/hipcstat.rs"]
+pub mod hipcstat;
+#[path = "../../../src/hos/hhandle.rs"]
+pub mod hhandle;
+#[path = "../../../src/hos/hport.rs"]
+pub mod hport;
+#[path = "../../../src/hos/hclientsession.rs"]
+pub mod hclientsession;
+#[path = "../../../src/hos/hserversession.rs"]
+pub mod hserversession;
+#[path = "../../../src/hos/hdomainobj.rs"]
+pub mod hdomainobj;
+#[path = "../../../src/hos/hdomainsession.rs"]
+pub mod hdomainsession;
+#[path = "../../../src/hos/hipcfuzz.rs"]
+pub mod hipcfuzz;
+#[path = "../../../src/hos/hrules.rs"]
+pub mod hrules;
+#[path = "../../../src/hos/result.rs"]
+pub mod result;
+#[path = "../../../src/hos/svcsig.rs"]
+pub mod svcsig;
+#[path = "../../../src/hos/hsvcinject.rs"]
+pub mod hsvcinject;
+#[path = "../../../src/hos/hmempolicy.rs"]
+pub mod hmempolicy;
+#[path = "../../../src/hos/hgdb.rs"]
+pub mod hgdb;
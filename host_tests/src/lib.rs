@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// Builds the hypervisor's target-independent modules for the host so their
+// #[cfg(test)] tests run under a plain `cargo test`. The modules are pulled
+// in by path and everything they reach outside of src/hos/ is stubbed below
+
+#![allow(unused_parens)]
+#![allow(unused)]
+#![allow(static_mut_refs)]
+// Explicit returns and `field: field` are house style over in src/
+#![allow(clippy::all)]
+
+#[macro_use]
+extern crate alloc;
+
+macro_rules! bit {
+    ($a:expr) => {
+        (1 << $a)
+    }
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => { std::println!($($arg)*) }
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => { std::print!($($arg)*) }
+}
+
+macro_rules! println_core {
+    ($($arg:tt)*) => { std::println!($($arg)*) }
+}
+
+// Guest strings aren't reachable from here
+macro_rules! kstr_len {
+    ($a:expr, $b:expr) => {
+        ""
+    }
+}
+
+pub mod util
+{
+    // Only HIPCMemTarget goes through these, tests have to use HIPCMemSlice
+    pub fn peek8(addr: u64) -> u8 { panic!("peek8({:#x}) on the host", addr) }
+    pub fn peek32(addr: u64) -> u32 { panic!("peek32({:#x}) on the host", addr) }
+    pub fn peek64(addr: u64) -> u64 { panic!("peek64({:#x}) on the host", addr) }
+    pub fn poke8(addr: u64, _val: u8) { panic!("poke8({:#x}) on the host", addr) }
+    pub fn poke32(addr: u64, _val: u32) { panic!("poke32({:#x}) on the host", addr) }
+    pub fn poke64(addr: u64, _val: u64) { panic!("poke64({:#x}) on the host", addr) }
+
+    pub fn str_from_null_terminated_utf8_unchecked(s: &[u8]) -> &str
+    {
+        let len = s.iter().position(|c| *c == 0).unwrap_or(s.len());
+        return core::str::from_utf8(&s[..len]).unwrap_or("");
+    }
+
+    pub fn hexdump_vec(_label: &str, _data: &[u8]) {}
+}
+
+pub mod logger {}
+
+pub mod arm
+{
+    pub mod mmu
+    {
+        pub fn translate_el1_stage12(addr: u64) -> u64 { addr }
+    }
+}
+
+pub mod vm
+{
+    pub mod vsvc
+    {
+        pub fn vsvc_get_curpid() -> u32 { 0 }
+        pub fn vsvc_get_tls() -> u64 { 0 }
+    }
+}
+
+pub mod hos;
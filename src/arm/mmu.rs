@@ -27,6 +27,8 @@ pub const TTB_AP_URW_KRW: u64 = 1;
 pub const TTB_AP_UNO_KRO: u64 = 2;
 pub const TTB_AP_URO_KRO: u64 = 3;
 
+pub const PAR_EL1_F: u64 = (1 << 0);
+
 pub fn get_ttbr1_el1() -> u64
 {
     sysreg_read!("ttbr1_el1")
@@ -44,6 +46,22 @@ pub fn translate_el1_stage12(vaddr: u64) -> u64
     }
 }
 
+// None if the walk faulted, instead of whatever PAR_EL1 holds then
+pub fn try_translate_el1_stage12(vaddr: u64) -> Option<u64>
+{
+    unsafe
+    {
+    let mut taddr: u64 = 0;
+    asm!("AT S12E1R, {0}", in(reg) vaddr);
+    asm!("mrs {0}, PAR_EL1", out(reg) taddr);
+
+    if (taddr & PAR_EL1_F) != 0 {
+        return None;
+    }
+    return Some((taddr & 0xffffffffff000) | (vaddr & 0xFFF));
+    }
+}
+
 pub fn translate_el0_stage12(vaddr: u64) -> u64
 {
     unsafe
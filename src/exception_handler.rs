@@ -22,6 +22,9 @@ use crate::task::*;
 use crate::usbd::usbd::*;
 use crate::vm::virq::*;
 use crate::io::smmu::{smmu_print_err, smmu_active};
+use crate::arm::ticks::get_ticks;
+use crate::modules::svcprof::{svcprof_pre, svcprof_post};
+use crate::modules::gdbstub::{gdbstub_sync_pending, gdbstub_sync_core, gdbstub_handle_brk, gdbstub_handle_step, gdbstub_is_stepping, gdbstub_reflect};
 
 pub const EC_WFIWFE:        u8 = (0x01);
 pub const EC_ASIMD:         u8 = (0x07);
@@ -37,7 +40,11 @@ pub const EC_IABT_CUR_EL:   u8 = (0x21);
 pub const EC_PC_ALIGN:      u8 = (0x22);
 pub const EC_DABT_LOWER_EL: u8 = (0x24);
 pub const EC_DABT_CUR_EL:   u8 = (0x25);
+pub const EC_BKPT_LOWER_EL: u8 = (0x30);
 pub const EC_STEP_LOWER_EL: u8 = (0x32);
+pub const EC_WATCH_LOWER_EL: u8 = (0x34);
+pub const EC_BKPT_A32:      u8 = (0x38);
+pub const EC_BRK_A64:       u8 = (0x3C);
 
 static mut RET_ADDR_LAST: u64 = 0;
 static mut RET_ADDR_LAST_PRINT: u64 = 0;
@@ -446,6 +453,10 @@ pub fn handle_exception(which: i32, ctx: &mut [u64]) -> u64
     let elr_el2 = ctx[33];
     let mut ret_addr: u64 = elr_el2 + 4;
     
+    if gdbstub_sync_pending() {
+        gdbstub_sync_core();
+    }
+
     if (ec == EC_HVC_A64) // HVC
     {
         let hvc_num: u8 = (iss & 0xFF) as u8;
@@ -459,6 +470,8 @@ pub fn handle_exception(which: i32, ctx: &mut [u64]) -> u64
         }
         else if (hvc_num == 1)
         {
+            svcprof_pre(ctx, get_ticks());
+
             // emulate ff 42 03 d5     msr        DAIFClr,#0x2
             ctx[32] &= !0x80;
 
@@ -470,6 +483,8 @@ pub fn handle_exception(which: i32, ctx: &mut [u64]) -> u64
         }
         else if (hvc_num == 2) // SVC post-hook
         {
+            svcprof_post(iss, ctx, get_ticks());
+
             // emulate df 42 03 d5     msr        DAIFSet,#0x2
             ctx[32] |= 0x80;
 
@@ -481,18 +496,20 @@ pub fn handle_exception(which: i32, ctx: &mut [u64]) -> u64
         }
         else if (hvc_num == 3)
         {
+            svcprof_pre(ctx, get_ticks());
+
             // emulate ff 42 03 d5     msr        DAIFClr,#0x2
             ctx[32] &= !0x80;
 
-            //println!("(core {}) Unsupported SVC A32 hook!!", get_core());
             ret_addr = vsvc_pre_handle_32(iss, ctx);
         }
         else if (hvc_num == 4)
         {
+            svcprof_post(iss, ctx, get_ticks());
+
             // emulate df 42 03 d5     msr        DAIFSet,#0x2
             ctx[32] |= 0x80;
 
-            //println!("(core {}) Unsupported SVC A32 hook!!", get_core());
             ret_addr = vsvc_post_handle_32(iss, ctx);
         }
         else if (hvc_num == 5)
@@ -608,6 +625,22 @@ pub fn handle_exception(which: i32, ctx: &mut [u64]) -> u64
     {
         ret_addr = vmmio_handle_lowerel_dabt(iss, ctx);
     }
+    else if (ec == EC_BRK_A64)
+    {
+        ret_addr = match gdbstub_handle_brk(ctx) {
+            Some(addr) => addr,
+            None => gdbstub_reflect(ctx)
+        };
+    }
+    else if (ec == EC_STEP_LOWER_EL && gdbstub_is_stepping())
+    {
+        ret_addr = gdbstub_handle_step(ctx);
+    }
+    else if (ec == EC_BKPT_LOWER_EL || ec == EC_WATCH_LOWER_EL || ec == EC_BKPT_A32)
+    {
+        // Only routed here while gdbstub has TDE set, the guest gets them back
+        ret_addr = gdbstub_reflect(ctx);
+    }
     else if (ec == EC_STEP_LOWER_EL)
     {
         let mut re_enable = true;
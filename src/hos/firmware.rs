@@ -0,0 +1,180 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use crate::util::*;
+use crate::arm::mmu::translate_el1_stage12;
+use crate::arm::threading::get_sp_el1;
+use crate::logger::*;
+use super::svc::{HorizonSvc, SvcInvalid, SVC_MAP_ALL};
+
+// Hash of the kernel in data/ at build time and which profile HTB_FIRMWARE
+// says it is
+include!(concat!(env!("OUT_DIR"), "/firmware_gen.rs"));
+
+pub struct FirmwareProfile
+{
+    pub name: &'static str,
+    pub versions: &'static [&'static str], // what HTB_FIRMWARE can be set to
+    pub kernel_hashes: &'static [u64], // FNV-1a 64 of the whole kernel binary
+
+    // Fallback for hashes we don't know yet, any of these appearing in the
+    // kernel image picks the profile
+    pub version_strings: &'static [&'static [u8]],
+
+    // Exception vectors, relative to KERNEL_START
+    pub vbar_offset: u64,
+    pub irq_el1_offset: u64,
+    pub irq_el0_offset: u64,
+
+    // Saved x18 points at the current thread's context pointer
+    pub thread_ctx_reg: usize,
+    pub thread_ctx_offset: u64,
+
+    // User registers saved on SVC entry (KExceptionContext), relative to
+    // SP_EL1 at the SVC hook
+    pub svc_frame_offset: u64,
+
+    // SVC ID to handler, IDs this kernel doesn't implement are SvcInvalid
+    pub svc_map: &'static [HorizonSvc; 0x80],
+}
+
+// SVC_MAP_ALL with the IDs a kernel doesn't implement knocked out
+const fn firmware_svc_map(unavailable: &[u8]) -> [HorizonSvc; 0x80]
+{
+    let mut map = SVC_MAP_ALL;
+    let mut i = 0;
+    while i < unavailable.len()
+    {
+        map[unavailable[i] as usize] = HorizonSvc::Invalid(SvcInvalid);
+        i += 1;
+    }
+    return map;
+}
+
+static SVC_MAP_8_0_1: [HorizonSvc; 0x80] = firmware_svc_map(&[
+    0x37, // GetResourceLimitPeakValue, 11.0.0+
+]);
+
+// 8.0.1 and 9.0.1 don't differ in anything we patch or read, so they share
+// a profile. Firmware that moves any of this gets its own entry
+static FIRMWARE_PROFILES: &[FirmwareProfile] = &[
+    FirmwareProfile
+    {
+        name: "8.0.1-9.0.1",
+        versions: &["8.0.1", "9.0.1"],
+        kernel_hashes: &[],
+        version_strings: &[],
+        vbar_offset: 0x800,
+        irq_el1_offset: 0x280,
+        irq_el0_offset: 0x480,
+        thread_ctx_reg: 18,
+        thread_ctx_offset: 0,
+        svc_frame_offset: 0,
+        svc_map: &SVC_MAP_8_0_1,
+    },
+];
+
+static mut FIRMWARE_PROFILE: Option<&'static FirmwareProfile> = None;
+
+pub fn firmware_hash(data: &[u8]) -> u64
+{
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data
+    {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+pub fn firmware_lookup(name: &str) -> Option<&'static FirmwareProfile>
+{
+    FIRMWARE_PROFILES.iter().find(|profile| profile.name == name || profile.versions.iter().any(|version| *version == name))
+}
+
+fn firmware_contains(data: &[u8], needle: &[u8]) -> bool
+{
+    if needle.is_empty() || needle.len() > data.len() {
+        return false;
+    }
+    return data.windows(needle.len()).any(|window| window == needle);
+}
+
+fn firmware_find_version_string(kern_data: &[u8]) -> Option<(&'static FirmwareProfile, &'static [u8])>
+{
+    for profile in FIRMWARE_PROFILES.iter()
+    {
+        for version in profile.version_strings.iter()
+        {
+            if firmware_contains(kern_data, version) {
+                return Some((profile, version));
+            }
+        }
+    }
+    return None;
+}
+
+// Patching a kernel we don't know the layout of would just crash somewhere
+// much less obvious, so refuse to boot it
+pub fn firmware_init(kern_data: &[u8])
+{
+    let hash = firmware_hash(kern_data);
+
+    let mut profile = FIRMWARE_PROFILES.iter().find(|profile| profile.kernel_hashes.contains(&hash));
+    if profile.is_none() && FIRMWARE_BUNDLED_HASH == Some(hash)
+    {
+        profile = firmware_lookup(FIRMWARE_BUNDLED_PROFILE);
+        if profile.is_none() {
+            panic!("HTB_FIRMWARE={} doesn't match any firmware profile", FIRMWARE_BUNDLED_PROFILE);
+        }
+    }
+    if profile.is_none()
+    {
+        if let Some((found, version)) = firmware_find_version_string(kern_data)
+        {
+            println!("Kernel hash {:016x} isn't known, picked firmware profile {} from version string `{}`", hash, found.name, core::str::from_utf8(version).unwrap_or("?"));
+            profile = Some(found);
+        }
+    }
+
+    match profile
+    {
+        Some(profile) => {
+            println!("Kernel is {} ({:016x}, {:#x} bytes)", profile.name, hash, kern_data.len());
+            unsafe { FIRMWARE_PROFILE = Some(profile); }
+        },
+        None => {
+            panic!("Unknown kernel (FNV-1a {:016x}, {:#x} bytes)! Add its hash or version string to a profile in src/hos/firmware.rs, or bundle it in data/ and build with HTB_FIRMWARE=<version>", hash, kern_data.len());
+        }
+    }
+}
+
+pub fn firmware_get() -> &'static FirmwareProfile
+{
+    unsafe { FIRMWARE_PROFILE.expect("firmware_get before firmware_init") }
+}
+
+pub fn firmware_get_svc_map() -> &'static [HorizonSvc; 0x80]
+{
+    unsafe
+    {
+        match FIRMWARE_PROFILE {
+            Some(profile) => profile.svc_map,
+            None => &SVC_MAP_ALL
+        }
+    }
+}
+
+pub fn firmware_get_svc_frame() -> u64
+{
+    return get_sp_el1() + firmware_get().svc_frame_offset;
+}
+
+pub fn firmware_get_thread_ctx(ctx: &[u64]) -> u64
+{
+    let profile = firmware_get();
+    return peek64(translate_el1_stage12(ctx[profile.thread_ctx_reg] + profile.thread_ctx_offset));
+}
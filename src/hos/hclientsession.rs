@@ -5,10 +5,12 @@
  */
  
 use core::future::Future;
-use alloc::prelude::v1::Box;
+use alloc::boxed::Box;
 use core::pin::Pin;
+use alloc::string::String;
 use super::hdomainobj::HDomainObj;
 use super::hdomainsession::HDomainSession;
+use super::hipcstat::HIPCLatencyStats;
 use super::hipc::{HExtraNone, HObject, HObjectExtra};
 
 pub type HClientSessionHandler = fn(in_: [u64; 32], hobj: HObject) -> Pin<Box<dyn Future<Output = [u64; 32]> + Send>>;
@@ -18,7 +20,11 @@ pub struct HClientSession
     pub parent_port_pid: u8,
     pub client_pid: u8,
     pub handler: Option<HClientSessionHandler>,
-    pub extra: HObjectExtra
+    pub extra: HObjectExtra,
+    pub service: String,
+    pub interface: String,
+    pub pointer_buffer_size: Option<u16>, // from QueryPointerBufferSize, if it was asked
+    pub stats: HIPCLatencyStats
 }
 
 impl HClientSession
@@ -30,7 +36,11 @@ impl HClientSession
             parent_port_pid: parent_pid,
             client_pid: client_pid,
             handler: None,
-            extra: HObjectExtra::None(HExtraNone{})
+            extra: HObjectExtra::None(HExtraNone{}),
+            service: String::new(),
+            interface: String::new(),
+            pointer_buffer_size: None,
+            stats: HIPCLatencyStats::new()
         }
     }
     
@@ -41,7 +51,11 @@ impl HClientSession
             parent_port_pid: self.parent_port_pid,
             client_pid: self.client_pid,
             handler: None,
-            extra: self.extra.clone()
+            extra: self.extra.clone(),
+            service: self.service.clone(),
+            interface: String::new(),
+            pointer_buffer_size: self.pointer_buffer_size,
+            stats: HIPCLatencyStats::new()
         }
     }
     
@@ -65,10 +79,42 @@ impl HClientSession
         self.extra.clone()
     }
     
+    pub fn set_service(&mut self, service: &String)
+    {
+        self.service = service.clone();
+    }
+    
+    pub fn get_service(&self) -> String
+    {
+        self.service.clone()
+    }
+    
+    pub fn set_interface(&mut self, interface: &str)
+    {
+        self.interface = String::from(interface);
+    }
+    
+    pub fn get_interface(&self) -> String
+    {
+        self.interface.clone()
+    }
+    
+    pub fn set_pointer_buffer_size(&mut self, size: u16)
+    {
+        self.pointer_buffer_size = Some(size);
+    }
+    
+    pub fn get_pointer_buffer_size(&self) -> Option<u16>
+    {
+        self.pointer_buffer_size
+    }
+    
     pub fn convert_to_domain(&self, handle: u32, obj_id: u32) -> (HDomainObj, HDomainSession)
     {
         let mut obj = HDomainObj::from_curpid(handle, obj_id);
         let mut session = HDomainSession::new(self.parent_port_pid, self.client_pid);
+        session.set_service(&self.service);
+        session.set_interface(&self.interface);
         
         if let Some(handler) = self.handler
         {
@@ -84,7 +130,11 @@ impl HClientSession
             parent_port_pid: self.parent_port_pid,
             client_pid: self.client_pid,
             handler: self.handler,
-            extra: self.extra.clone()
+            extra: self.extra.clone(),
+            service: self.service.clone(),
+            interface: self.interface.clone(),
+            pointer_buffer_size: self.pointer_buffer_size,
+            stats: self.stats
         }
     }
 }
@@ -5,8 +5,9 @@
  */
  
 use core::future::Future;
-use alloc::prelude::v1::Box;
+use alloc::boxed::Box;
 use core::pin::Pin;
+use alloc::string::String;
 use super::hipc::{HExtraNone, HObject, HObjectExtra};
 
 pub type HDomainSessionHandler = fn(in_: [u64; 32], hobj: HObject) -> Pin<Box<dyn Future<Output = [u64; 32]> + Send>>;
@@ -16,7 +17,9 @@ pub struct HDomainSession
     pub parent_port_pid: u8,
     pub client_pid: u8,
     pub handler: Option<HDomainSessionHandler>,
-    pub extra: HObjectExtra
+    pub extra: HObjectExtra,
+    pub service: String,
+    pub interface: String
 }
 
 impl HDomainSession
@@ -28,7 +31,9 @@ impl HDomainSession
             parent_port_pid: parent_pid,
             client_pid: client_pid,
             handler: None,
-            extra: HObjectExtra::None(HExtraNone{})
+            extra: HObjectExtra::None(HExtraNone{}),
+            service: String::new(),
+            interface: String::new()
         }
     }
     
@@ -39,7 +44,9 @@ impl HDomainSession
             parent_port_pid: self.parent_port_pid,
             client_pid: self.client_pid,
             handler: None,
-            extra: self.extra.clone()
+            extra: self.extra.clone(),
+            service: self.service.clone(),
+            interface: String::new()
         }
     }
     
@@ -63,6 +70,26 @@ impl HDomainSession
         self.extra.clone()
     }
     
+    pub fn set_service(&mut self, service: &String)
+    {
+        self.service = service.clone();
+    }
+    
+    pub fn get_service(&self) -> String
+    {
+        self.service.clone()
+    }
+    
+    pub fn set_interface(&mut self, interface: &str)
+    {
+        self.interface = String::from(interface);
+    }
+    
+    pub fn get_interface(&self) -> String
+    {
+        self.interface.clone()
+    }
+    
     pub fn clone(&self) -> Self
     {
         HDomainSession
@@ -70,7 +97,9 @@ impl HDomainSession
             parent_port_pid: self.parent_port_pid,
             client_pid: self.client_pid,
             handler: self.handler,
-            extra: self.extra.clone()
+            extra: self.extra.clone(),
+            service: self.service.clone(),
+            interface: self.interface.clone()
         }
     }
 }
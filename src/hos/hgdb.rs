@@ -0,0 +1,1135 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// GDB remote serial protocol: packet framing, command parsing and the
+// session state. Everything that touches the guest goes through GdbTarget,
+// so this only needs alloc and builds on the host as well
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const GDB_NUM_REGS: usize = 34; // x0-x30, sp, pc, cpsr
+pub const GDB_REG_SP: usize = 31;
+pub const GDB_REG_PC: usize = 32;
+pub const GDB_REG_CPSR: usize = 33;
+
+pub const GDB_SIGINT: u8 = 2;
+pub const GDB_SIGTRAP: u8 = 5;
+pub const GDB_SIGSTOP: u8 = 0x13;
+
+// Biggest packet we accept, advertised in qSupported
+const GDB_PACKET_MAX: usize = 0x1000;
+
+pub trait GdbTarget
+{
+    fn get_processes(&mut self) -> Vec<(u32, String)>;
+    fn get_threads(&mut self, pid: u32) -> Vec<u32>;
+    fn attach(&mut self, pid: u32) -> bool;
+    fn detach(&mut self, pid: u32);
+
+    // Stops the process, returns the thread to report the stop on
+    fn halt(&mut self, pid: u32) -> Option<u32>;
+    // Lets the process run again, `step` being the one thread to stop again
+    // after a single instruction
+    fn resume(&mut self, pid: u32, step: Option<u32>);
+
+    fn read_regs(&mut self, pid: u32, tid: u32) -> Option<[u64; GDB_NUM_REGS]>;
+    fn write_regs(&mut self, pid: u32, tid: u32, regs: &[u64; GDB_NUM_REGS]) -> bool;
+
+    // Short reads are fine, gdb asks again for the rest
+    fn read_mem(&mut self, pid: u32, addr: u64, len: usize) -> Vec<u8>;
+    fn write_mem(&mut self, pid: u32, addr: u64, data: &[u8]) -> bool;
+
+    fn insert_breakpoint(&mut self, pid: u32, addr: u64) -> bool;
+    fn remove_breakpoint(&mut self, pid: u32, addr: u64) -> bool;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum GdbRecvState
+{
+    Idle,
+    Data,
+    Escape,
+    Checksum(u8), // hex digits seen
+}
+
+pub struct GdbSession
+{
+    state: GdbRecvState,
+    buf: Vec<u8>,
+    checksum: u8,
+    recv_checksum: u8,
+    last_sent: Vec<u8>,
+    no_ack: bool,
+    multiprocess: bool,
+
+    pid: Option<u32>,
+    tid_g: Option<u32>, // registers and memory, None for the first thread
+    tid_c: Option<u32>, // stepping, None for whatever tid_g is
+    running: bool,
+}
+
+fn hgdb_hex_digit(val: u8) -> Option<u8>
+{
+    match val {
+        b'0'..=b'9' => Some(val - b'0'),
+        b'a'..=b'f' => Some(val - b'a' + 10),
+        b'A'..=b'F' => Some(val - b'A' + 10),
+        _ => None
+    }
+}
+
+fn hgdb_parse_hex(val: &[u8]) -> Option<u64>
+{
+    if val.is_empty() || val.len() > 16 {
+        return None;
+    }
+
+    let mut out: u64 = 0;
+    for c in val.iter()
+    {
+        out = (out << 4) | (hgdb_hex_digit(*c)? as u64);
+    }
+    return Some(out);
+}
+
+fn hgdb_decode_hex(val: &[u8]) -> Option<Vec<u8>>
+{
+    if (val.len() % 2) != 0 {
+        return None;
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(val.len() / 2);
+    for pair in val.chunks(2)
+    {
+        out.push((hgdb_hex_digit(pair[0])? << 4) | hgdb_hex_digit(pair[1])?);
+    }
+    return Some(out);
+}
+
+fn hgdb_encode_hex(out: &mut Vec<u8>, data: &[u8])
+{
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for byte in data.iter()
+    {
+        out.push(DIGITS[(byte >> 4) as usize]);
+        out.push(DIGITS[(byte & 0xF) as usize]);
+    }
+}
+
+// `addr,len`
+fn hgdb_parse_addr_len(val: &[u8]) -> Option<(u64, u64)>
+{
+    let split = val.iter().position(|c| *c == b',')?;
+    return Some((hgdb_parse_hex(&val[..split])?, hgdb_parse_hex(&val[split+1..])?));
+}
+
+// Thread IDs are `p<pid>.<tid>` with multiprocess, otherwise just `<tid>`.
+// -1 and 0 (all/any) both come back as None
+fn hgdb_parse_thread_id(val: &[u8]) -> Option<(Option<u32>, Option<u32>)>
+{
+    fn parse_one(val: &[u8]) -> Option<Option<u32>>
+    {
+        if val == b"-1" {
+            return Some(None);
+        }
+        match hgdb_parse_hex(val)? {
+            0 => Some(None),
+            id => Some(Some(id as u32))
+        }
+    }
+
+    if val.first() != Some(&b'p') {
+        return Some((None, parse_one(val)?));
+    }
+
+    let val = &val[1..];
+    return match val.iter().position(|c| *c == b'.') {
+        Some(split) => Some((parse_one(&val[..split])?, parse_one(&val[split+1..])?)),
+        None => Some((parse_one(val)?, None))
+    };
+}
+
+// Frames `payload` as `$...#cs`, escaping anything gdb would choke on and
+// run-length encoding repeats (register dumps are mostly zeroes)
+pub fn hgdb_make_packet(payload: &[u8]) -> Vec<u8>
+{
+    let mut out: Vec<u8> = Vec::with_capacity(payload.len() + 4);
+
+    out.push(b'$');
+    let mut i = 0;
+    while i < payload.len()
+    {
+        let byte = payload[i];
+        if byte == b'$' || byte == b'#' || byte == b'}' || byte == b'*' {
+            out.push(b'}');
+            out.push(byte ^ 0x20);
+            i += 1;
+            continue;
+        }
+
+        // `x*<n+29>` is x and n more copies of it. The count has to stay
+        // printable and can't be '#' or '$', shorter runs aren't worth it
+        let mut repeat = payload[i+1..].iter().take(126 - 29).take_while(|c| **c == byte).count();
+        if repeat == 6 || repeat == 7 {
+            repeat = 5;
+        }
+
+        out.push(byte);
+        if repeat >= 3 {
+            out.push(b'*');
+            out.push(repeat as u8 + 29);
+            i += repeat;
+        }
+        i += 1;
+    }
+
+    let checksum = out[1..].iter().fold(0u8, |sum, c| sum.wrapping_add(*c));
+    out.push(b'#');
+    hgdb_encode_hex(&mut out, &[checksum]);
+    return out;
+}
+
+// The slice of an XML document one qXfer read asked for
+fn hgdb_xfer_slice(doc: &str, offset: u64, length: u64) -> Vec<u8>
+{
+    let doc = doc.as_bytes();
+    let offset = core::cmp::min(offset as usize, doc.len());
+    let end = core::cmp::min(offset.saturating_add(length as usize), doc.len());
+
+    let mut out: Vec<u8> = Vec::with_capacity(end - offset + 1);
+    out.push(if end < doc.len() { b'm' } else { b'l' });
+    out.extend_from_slice(&doc[offset..end]);
+    return out;
+}
+
+fn hgdb_target_xml() -> String
+{
+    let mut out = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<architecture>aarch64</architecture>\n<feature name=\"org.gnu.gdb.aarch64.core\">\n");
+    for i in 0..31
+    {
+        out += &format!("<reg name=\"x{}\" bitsize=\"64\"/>\n", i);
+    }
+    out += "<reg name=\"sp\" bitsize=\"64\" type=\"data_ptr\"/>\n";
+    out += "<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\"/>\n";
+    out += "<reg name=\"cpsr\" bitsize=\"32\"/>\n";
+    out += "</feature>\n</target>\n";
+    return out;
+}
+
+impl GdbSession
+{
+    pub const fn new() -> GdbSession
+    {
+        GdbSession
+        {
+            state: GdbRecvState::Idle,
+            buf: Vec::new(),
+            checksum: 0,
+            recv_checksum: 0,
+            last_sent: Vec::new(),
+            no_ack: false,
+            multiprocess: false,
+            pid: None,
+            tid_g: None,
+            tid_c: None,
+            running: false,
+        }
+    }
+
+    pub fn get_pid(&self) -> Option<u32>
+    {
+        self.pid
+    }
+
+    pub fn is_running(&self) -> bool
+    {
+        self.running
+    }
+
+    fn send(&mut self, out: &mut Vec<u8>, payload: &[u8])
+    {
+        self.last_sent = hgdb_make_packet(payload);
+        out.extend_from_slice(&self.last_sent);
+    }
+
+    fn format_thread_id(&self, pid: u32, tid: u32) -> String
+    {
+        if self.multiprocess {
+            return format!("p{:x}.{:x}", pid, tid);
+        }
+        return format!("{:x}", tid);
+    }
+
+    fn stop_reply(&self, pid: u32, tid: Option<u32>, signal: u8) -> Vec<u8>
+    {
+        match tid {
+            Some(tid) => format!("T{:02x}thread:{};", signal, self.format_thread_id(pid, tid)).into_bytes(),
+            None => format!("S{:02x}", signal).into_bytes()
+        }
+    }
+
+    // Thread g/G/m/M/Z work on
+    fn get_tid_g(&self, target: &mut dyn GdbTarget, pid: u32) -> Option<u32>
+    {
+        if self.tid_g.is_some() {
+            return self.tid_g;
+        }
+        return target.get_threads(pid).first().copied();
+    }
+
+    // Feeds bytes from gdb, returns whatever should go back
+    pub fn feed(&mut self, target: &mut dyn GdbTarget, data: &[u8]) -> Vec<u8>
+    {
+        let mut out: Vec<u8> = Vec::new();
+        for byte in data.iter()
+        {
+            let byte = *byte;
+            match self.state {
+                GdbRecvState::Idle => {
+                    match byte {
+                        b'$' => {
+                            self.buf.clear();
+                            self.checksum = 0;
+                            self.state = GdbRecvState::Data;
+                        },
+                        0x03 => self.handle_interrupt(target, &mut out),
+                        b'-' if !self.no_ack => out.extend_from_slice(&self.last_sent.clone()),
+                        _ => {} // acks and line noise
+                    };
+                },
+                GdbRecvState::Data => {
+                    match byte {
+                        b'#' => {
+                            self.recv_checksum = 0;
+                            self.state = GdbRecvState::Checksum(0);
+                            continue;
+                        },
+                        b'}' => self.state = GdbRecvState::Escape,
+                        _ => self.buf.push(byte)
+                    };
+                    self.checksum = self.checksum.wrapping_add(byte);
+                },
+                GdbRecvState::Escape => {
+                    self.buf.push(byte ^ 0x20);
+                    self.checksum = self.checksum.wrapping_add(byte);
+                    self.state = GdbRecvState::Data;
+                },
+                GdbRecvState::Checksum(digits) => {
+                    self.recv_checksum = (self.recv_checksum << 4) | hgdb_hex_digit(byte).unwrap_or(0xFF);
+                    if digits == 0 {
+                        self.state = GdbRecvState::Checksum(1);
+                        continue;
+                    }
+                    self.state = GdbRecvState::Idle;
+
+                    if !self.no_ack && self.recv_checksum != self.checksum {
+                        out.push(b'-');
+                        continue;
+                    }
+                    if !self.no_ack {
+                        out.push(b'+');
+                    }
+
+                    let packet = core::mem::replace(&mut self.buf, Vec::new());
+                    if packet.len() > GDB_PACKET_MAX {
+                        self.send(&mut out, b"E01");
+                        continue;
+                    }
+                    if let Some(reply) = self.handle_packet(target, &packet) {
+                        self.send(&mut out, &reply);
+                    }
+
+                    // Only takes effect once the OK went out acked
+                    if packet == b"QStartNoAckMode" {
+                        self.no_ack = true;
+                    }
+                },
+            };
+
+            if self.buf.len() > GDB_PACKET_MAX + 1 {
+                self.buf.clear();
+                self.state = GdbRecvState::Idle;
+            }
+        }
+        return out;
+    }
+
+    // The target stopped on its own (breakpoint, step). Only reported if gdb
+    // is waiting on a continue or step
+    pub fn notify_stop(&mut self, tid: u32, signal: u8) -> Vec<u8>
+    {
+        let mut out: Vec<u8> = Vec::new();
+        let pid = match self.pid {
+            Some(pid) if self.running => pid,
+            _ => return out
+        };
+
+        self.running = false;
+        self.tid_g = Some(tid);
+        let reply = self.stop_reply(pid, Some(tid), signal);
+        self.send(&mut out, &reply);
+        return out;
+    }
+
+    // The attached process went away
+    pub fn notify_exit(&mut self, pid: u32, status: u8) -> Vec<u8>
+    {
+        let mut out: Vec<u8> = Vec::new();
+        if self.pid != Some(pid) {
+            return out;
+        }
+
+        let was_running = self.running;
+        self.pid = None;
+        self.tid_g = None;
+        self.tid_c = None;
+        self.running = false;
+
+        if was_running {
+            let reply = if self.multiprocess { format!("W{:02x};process:{:x}", status, pid) } else { format!("W{:02x}", status) };
+            self.send(&mut out, reply.as_bytes());
+        }
+        return out;
+    }
+
+    fn handle_interrupt(&mut self, target: &mut dyn GdbTarget, out: &mut Vec<u8>)
+    {
+        let pid = match self.pid {
+            Some(pid) if self.running => pid,
+            _ => return
+        };
+
+        self.running = false;
+        let tid = target.halt(pid);
+        if tid.is_some() {
+            self.tid_g = tid;
+        }
+        let reply = self.stop_reply(pid, tid, GDB_SIGINT);
+        self.send(out, &reply);
+    }
+
+    fn handle_packet(&mut self, target: &mut dyn GdbTarget, packet: &[u8]) -> Option<Vec<u8>>
+    {
+        let cmd = match packet.first() {
+            Some(cmd) => *cmd,
+            None => return Some(Vec::new())
+        };
+        let args = &packet[1..];
+
+        if packet.starts_with(b"q") || packet.starts_with(b"Q") {
+            return Some(self.handle_query(target, packet));
+        }
+        if packet.starts_with(b"v") {
+            return self.handle_v(target, packet);
+        }
+
+        if cmd == b'!' {
+            return Some(b"OK".to_vec());
+        }
+        if cmd == b'?' {
+            return Some(match self.pid {
+                Some(pid) => {
+                    let tid = self.get_tid_g(target, pid);
+                    self.stop_reply(pid, tid, GDB_SIGTRAP)
+                },
+                None => b"W00".to_vec()
+            });
+        }
+        if cmd == b'D' {
+            // `D` or `D;<pid>`
+            if let Some(pid) = self.pid {
+                target.detach(pid);
+            }
+            self.pid = None;
+            self.tid_g = None;
+            self.tid_c = None;
+            return Some(b"OK".to_vec());
+        }
+        if cmd == b'k' {
+            // Horizon processes can't be killed from here, let it go instead.
+            // gdb doesn't wait for a reply
+            if let Some(pid) = self.pid {
+                target.detach(pid);
+            }
+            self.pid = None;
+            return None;
+        }
+
+        // Everything else needs a process
+        let pid = match self.pid {
+            Some(pid) => pid,
+            None => return Some(b"E01".to_vec())
+        };
+
+        match cmd {
+            b'H' if args.len() >= 2 => {
+                let (_, tid) = match hgdb_parse_thread_id(&args[1..]) {
+                    Some(id) => id,
+                    None => return Some(b"E01".to_vec())
+                };
+                match args[0] {
+                    b'g' => self.tid_g = tid,
+                    b'c' => self.tid_c = tid,
+                    _ => return Some(b"E01".to_vec())
+                };
+                return Some(b"OK".to_vec());
+            },
+            b'T' => {
+                let alive = match hgdb_parse_thread_id(args) {
+                    Some((_, Some(tid))) => target.get_threads(pid).contains(&tid),
+                    _ => false
+                };
+                return Some(if alive { b"OK".to_vec() } else { b"E01".to_vec() });
+            },
+            b'g' => {
+                let regs = match self.get_tid_g(target, pid).and_then(|tid| target.read_regs(pid, tid)) {
+                    Some(regs) => regs,
+                    None => return Some(b"E01".to_vec())
+                };
+
+                let mut out: Vec<u8> = Vec::new();
+                for i in 0..GDB_REG_CPSR
+                {
+                    hgdb_encode_hex(&mut out, &regs[i].to_le_bytes());
+                }
+                hgdb_encode_hex(&mut out, &(regs[GDB_REG_CPSR] as u32).to_le_bytes());
+                return Some(out);
+            },
+            b'G' => {
+                let data = match hgdb_decode_hex(args) {
+                    Some(data) if data.len() == (GDB_NUM_REGS - 1) * 8 + 4 => data,
+                    _ => return Some(b"E01".to_vec())
+                };
+
+                let mut regs: [u64; GDB_NUM_REGS] = [0; GDB_NUM_REGS];
+                for i in 0..GDB_REG_CPSR
+                {
+                    let mut val: [u8; 8] = [0; 8];
+                    val.copy_from_slice(&data[i*8..i*8+8]);
+                    regs[i] = u64::from_le_bytes(val);
+                }
+                let mut val: [u8; 4] = [0; 4];
+                val.copy_from_slice(&data[GDB_REG_CPSR*8..]);
+                regs[GDB_REG_CPSR] = u32::from_le_bytes(val) as u64;
+
+                let ok = match self.get_tid_g(target, pid) {
+                    Some(tid) => target.write_regs(pid, tid, &regs),
+                    None => false
+                };
+                return Some(if ok { b"OK".to_vec() } else { b"E01".to_vec() });
+            },
+            b'p' => {
+                let reg = match hgdb_parse_hex(args) {
+                    Some(reg) if (reg as usize) < GDB_NUM_REGS => reg as usize,
+                    _ => return Some(b"E01".to_vec())
+                };
+                let regs = match self.get_tid_g(target, pid).and_then(|tid| target.read_regs(pid, tid)) {
+                    Some(regs) => regs,
+                    None => return Some(b"E01".to_vec())
+                };
+
+                let mut out: Vec<u8> = Vec::new();
+                if reg == GDB_REG_CPSR {
+                    hgdb_encode_hex(&mut out, &(regs[reg] as u32).to_le_bytes());
+                }
+                else {
+                    hgdb_encode_hex(&mut out, &regs[reg].to_le_bytes());
+                }
+                return Some(out);
+            },
+            b'P' => {
+                let split = match args.iter().position(|c| *c == b'=') {
+                    Some(split) => split,
+                    None => return Some(b"E01".to_vec())
+                };
+                let reg = match hgdb_parse_hex(&args[..split]) {
+                    Some(reg) if (reg as usize) < GDB_NUM_REGS => reg as usize,
+                    _ => return Some(b"E01".to_vec())
+                };
+                let val = match hgdb_decode_hex(&args[split+1..]) {
+                    Some(val) if val.len() == 8 || (reg == GDB_REG_CPSR && val.len() == 4) => val,
+                    _ => return Some(b"E01".to_vec())
+                };
+
+                let tid = match self.get_tid_g(target, pid) {
+                    Some(tid) => tid,
+                    None => return Some(b"E01".to_vec())
+                };
+                let mut regs = match target.read_regs(pid, tid) {
+                    Some(regs) => regs,
+                    None => return Some(b"E01".to_vec())
+                };
+                let mut bytes: [u8; 8] = [0; 8];
+                bytes[..val.len()].copy_from_slice(&val);
+                regs[reg] = u64::from_le_bytes(bytes);
+
+                return Some(if target.write_regs(pid, tid, &regs) { b"OK".to_vec() } else { b"E01".to_vec() });
+            },
+            b'm' => {
+                let (addr, len) = match hgdb_parse_addr_len(args) {
+                    Some(val) => val,
+                    None => return Some(b"E01".to_vec())
+                };
+
+                let len = core::cmp::min(len as usize, (GDB_PACKET_MAX - 4) / 2);
+                let data = target.read_mem(pid, addr, len);
+                if data.is_empty() && len != 0 {
+                    return Some(b"E14".to_vec());
+                }
+
+                let mut out: Vec<u8> = Vec::with_capacity(data.len() * 2);
+                hgdb_encode_hex(&mut out, &data);
+                return Some(out);
+            },
+            b'M' => {
+                let split = match args.iter().position(|c| *c == b':') {
+                    Some(split) => split,
+                    None => return Some(b"E01".to_vec())
+                };
+                let (addr, len) = match hgdb_parse_addr_len(&args[..split]) {
+                    Some(val) => val,
+                    None => return Some(b"E01".to_vec())
+                };
+                let data = match hgdb_decode_hex(&args[split+1..]) {
+                    Some(data) if data.len() as u64 == len => data,
+                    _ => return Some(b"E01".to_vec())
+                };
+
+                return Some(if target.write_mem(pid, addr, &data) { b"OK".to_vec() } else { b"E14".to_vec() });
+            },
+            b'Z' | b'z' => {
+                // Only software breakpoints, `Z0,addr,kind`
+                if !args.starts_with(b"0,") {
+                    return Some(Vec::new());
+                }
+                let addr = match args[2..].iter().position(|c| *c == b',') {
+                    Some(split) => hgdb_parse_hex(&args[2..2+split]),
+                    None => hgdb_parse_hex(&args[2..])
+                };
+                let addr = match addr {
+                    Some(addr) => addr,
+                    None => return Some(b"E01".to_vec())
+                };
+
+                let ok = if cmd == b'Z' { target.insert_breakpoint(pid, addr) } else { target.remove_breakpoint(pid, addr) };
+                return Some(if ok { b"OK".to_vec() } else { b"E01".to_vec() });
+            },
+            b'c' | b's' => {
+                let tid = match self.tid_c.or(self.get_tid_g(target, pid)) {
+                    Some(tid) => tid,
+                    None => return Some(b"E01".to_vec())
+                };
+
+                // Optional address to resume at
+                if !args.is_empty() {
+                    let addr = match hgdb_parse_hex(args) {
+                        Some(addr) => addr,
+                        None => return Some(b"E01".to_vec())
+                    };
+                    let mut regs = match target.read_regs(pid, tid) {
+                        Some(regs) => regs,
+                        None => return Some(b"E01".to_vec())
+                    };
+                    regs[GDB_REG_PC] = addr;
+                    if !target.write_regs(pid, tid, &regs) {
+                        return Some(b"E01".to_vec());
+                    }
+                }
+
+                self.running = true;
+                target.resume(pid, if cmd == b's' { Some(tid) } else { None });
+                return None;
+            },
+            _ => return Some(Vec::new())
+        };
+    }
+
+    fn handle_query(&mut self, target: &mut dyn GdbTarget, packet: &[u8]) -> Vec<u8>
+    {
+        if packet.starts_with(b"qSupported") {
+            self.multiprocess = packet.windows(13).any(|val| val == b"multiprocess+");
+            return format!("PacketSize={:x};QStartNoAckMode+;multiprocess+;qXfer:features:read+;qXfer:threads:read+;qXfer:osdata:read+", GDB_PACKET_MAX).into_bytes();
+        }
+        if packet == b"QStartNoAckMode" {
+            return b"OK".to_vec();
+        }
+        if packet.starts_with(b"qAttached") {
+            return b"1".to_vec();
+        }
+
+        if packet.starts_with(b"qXfer:") {
+            // qXfer:<object>:read:<annex>:<offset>,<length>
+            let fields: Vec<&[u8]> = packet.splitn(5, |c| *c == b':').collect();
+            if fields.len() != 5 || fields[2] != b"read" {
+                return Vec::new();
+            }
+            let (offset, length) = match hgdb_parse_addr_len(fields[4]) {
+                Some(val) => val,
+                None => return b"E01".to_vec()
+            };
+
+            let doc = match (fields[1], fields[3]) {
+                (b"features", b"target.xml") => hgdb_target_xml(),
+                (b"threads", b"") => {
+                    let pid = match self.pid {
+                        Some(pid) => pid,
+                        None => return b"E01".to_vec()
+                    };
+                    let mut doc = String::from("<?xml version=\"1.0\"?>\n<threads>\n");
+                    for tid in target.get_threads(pid).iter()
+                    {
+                        doc += &format!("<thread id=\"{}\"/>\n", self.format_thread_id(pid, *tid));
+                    }
+                    doc += "</threads>\n";
+                    doc
+                },
+                (b"osdata", b"processes") => {
+                    let mut doc = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"osdata.dtd\">\n<osdata type=\"processes\">\n");
+                    for (pid, name) in target.get_processes().iter()
+                    {
+                        doc += &format!("<item>\n<column name=\"pid\">{}</column>\n<column name=\"user\">-</column>\n<column name=\"command\">{}</column>\n<column name=\"cores\">-</column>\n</item>\n", pid, name);
+                    }
+                    doc += "</osdata>\n";
+                    doc
+                },
+                _ => return b"E00".to_vec()
+            };
+            return hgdb_xfer_slice(&doc, offset, length);
+        }
+
+        let pid = match self.pid {
+            Some(pid) => pid,
+            None => return Vec::new()
+        };
+
+        if packet == b"qC" {
+            return match self.get_tid_g(target, pid) {
+                Some(tid) => format!("QC{}", self.format_thread_id(pid, tid)).into_bytes(),
+                None => Vec::new()
+            };
+        }
+        if packet == b"qfThreadInfo" {
+            let ids: Vec<String> = target.get_threads(pid).iter().map(|tid| self.format_thread_id(pid, *tid)).collect();
+            if ids.is_empty() {
+                return b"l".to_vec();
+            }
+            return format!("m{}", ids.join(",")).into_bytes();
+        }
+        if packet == b"qsThreadInfo" {
+            return b"l".to_vec();
+        }
+
+        return Vec::new();
+    }
+
+    fn handle_v(&mut self, target: &mut dyn GdbTarget, packet: &[u8]) -> Option<Vec<u8>>
+    {
+        if packet.starts_with(b"vAttach;") {
+            let pid = match hgdb_parse_hex(&packet[8..]) {
+                Some(pid) => pid as u32,
+                None => return Some(b"E01".to_vec())
+            };
+
+            if let Some(old_pid) = self.pid {
+                target.detach(old_pid);
+            }
+            self.pid = None;
+            self.tid_g = None;
+            self.tid_c = None;
+            if !target.attach(pid) {
+                return Some(b"E01".to_vec());
+            }
+
+            self.pid = Some(pid);
+            let tid = target.halt(pid);
+            self.tid_g = tid;
+            return Some(self.stop_reply(pid, tid, GDB_SIGSTOP));
+        }
+        if packet.starts_with(b"vKill") {
+            // Same as `k`, the process is only let go
+            if let Some(pid) = self.pid {
+                target.detach(pid);
+            }
+            self.pid = None;
+            return Some(b"OK".to_vec());
+        }
+        if packet.starts_with(b"vRun") {
+            return Some(b"E01".to_vec());
+        }
+
+        // vCont, vMustReplyEmpty, vFile...
+        return Some(Vec::new());
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const MEM_BASE: u64 = 0x1000;
+
+    struct MockTarget
+    {
+        attached: Option<u32>,
+        regs: [u64; GDB_NUM_REGS],
+        mem: Vec<u8>,
+        breakpoints: Vec<u64>,
+        resumes: Vec<Option<u32>>,
+    }
+
+    impl MockTarget
+    {
+        fn new() -> MockTarget
+        {
+            let mut regs: [u64; GDB_NUM_REGS] = [0; GDB_NUM_REGS];
+            regs[0] = 0x1122334455667788;
+            regs[GDB_REG_PC] = 0x7100000000;
+
+            MockTarget
+            {
+                attached: None,
+                regs: regs,
+                mem: vec![0xDE, 0xAD, 0xBE, 0xEF, 0, 0, 0, 0],
+                breakpoints: Vec::new(),
+                resumes: Vec::new(),
+            }
+        }
+    }
+
+    impl GdbTarget for MockTarget
+    {
+        fn get_processes(&mut self) -> Vec<(u32, String)>
+        {
+            vec![(0x51, String::from("sm"))]
+        }
+
+        fn get_threads(&mut self, pid: u32) -> Vec<u32>
+        {
+            if self.attached != Some(pid) {
+                return Vec::new();
+            }
+            vec![0x10, 0x11]
+        }
+
+        fn attach(&mut self, pid: u32) -> bool
+        {
+            if pid != 0x51 {
+                return false;
+            }
+            self.attached = Some(pid);
+            return true;
+        }
+
+        fn detach(&mut self, _pid: u32)
+        {
+            self.attached = None;
+        }
+
+        fn halt(&mut self, _pid: u32) -> Option<u32>
+        {
+            Some(0x10)
+        }
+
+        fn resume(&mut self, _pid: u32, step: Option<u32>)
+        {
+            self.resumes.push(step);
+        }
+
+        fn read_regs(&mut self, _pid: u32, _tid: u32) -> Option<[u64; GDB_NUM_REGS]>
+        {
+            Some(self.regs)
+        }
+
+        fn write_regs(&mut self, _pid: u32, _tid: u32, regs: &[u64; GDB_NUM_REGS]) -> bool
+        {
+            self.regs = *regs;
+            return true;
+        }
+
+        fn read_mem(&mut self, _pid: u32, addr: u64, len: usize) -> Vec<u8>
+        {
+            if addr < MEM_BASE || addr >= MEM_BASE + self.mem.len() as u64 {
+                return Vec::new();
+            }
+            let start = (addr - MEM_BASE) as usize;
+            let end = core::cmp::min(start + len, self.mem.len());
+            return self.mem[start..end].to_vec();
+        }
+
+        fn write_mem(&mut self, _pid: u32, addr: u64, data: &[u8]) -> bool
+        {
+            if addr < MEM_BASE || addr + data.len() as u64 > MEM_BASE + self.mem.len() as u64 {
+                return false;
+            }
+            let start = (addr - MEM_BASE) as usize;
+            self.mem[start..start + data.len()].copy_from_slice(data);
+            return true;
+        }
+
+        fn insert_breakpoint(&mut self, _pid: u32, addr: u64) -> bool
+        {
+            self.breakpoints.push(addr);
+            return true;
+        }
+
+        fn remove_breakpoint(&mut self, _pid: u32, addr: u64) -> bool
+        {
+            let len = self.breakpoints.len();
+            self.breakpoints.retain(|bp| *bp != addr);
+            return self.breakpoints.len() != len;
+        }
+    }
+
+    // What gdb sends: raw bytes, checksum over them as they are
+    fn packet(payload: &[u8]) -> Vec<u8>
+    {
+        let checksum = payload.iter().fold(0u8, |sum, c| sum.wrapping_add(*c));
+        let mut out: Vec<u8> = vec![b'$'];
+        out.extend_from_slice(payload);
+        out.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+        return out;
+    }
+
+    // Splits stub output into acks and decoded packets, the way gdb reads it
+    fn unpack(out: &[u8]) -> (String, Vec<Vec<u8>>)
+    {
+        let mut acks = String::new();
+        let mut packets: Vec<Vec<u8>> = Vec::new();
+
+        let mut i = 0;
+        while i < out.len()
+        {
+            if out[i] != b'$' {
+                acks.push(out[i] as char);
+                i += 1;
+                continue;
+            }
+
+            let end = i + out[i..].iter().position(|c| *c == b'#').unwrap();
+            let raw = &out[i+1..end];
+            let checksum = raw.iter().fold(0u8, |sum, c| sum.wrapping_add(*c));
+            assert_eq!(hgdb_parse_hex(&out[end+1..end+3]), Some(checksum as u64));
+
+            let mut data: Vec<u8> = Vec::new();
+            let mut j = 0;
+            while j < raw.len()
+            {
+                match raw[j] {
+                    b'}' => {
+                        data.push(raw[j+1] ^ 0x20);
+                        j += 2;
+                    },
+                    b'*' => {
+                        let last = *data.last().unwrap();
+                        for _ in 0..(raw[j+1] - 29)
+                        {
+                            data.push(last);
+                        }
+                        j += 2;
+                    },
+                    c => {
+                        data.push(c);
+                        j += 1;
+                    }
+                };
+            }
+            packets.push(data);
+            i = end + 3;
+        }
+        return (acks, packets);
+    }
+
+    fn exchange(session: &mut GdbSession, target: &mut MockTarget, payload: &[u8]) -> (String, Vec<Vec<u8>>)
+    {
+        let out = session.feed(target, &packet(payload));
+        return unpack(&out);
+    }
+
+    fn attached() -> (GdbSession, MockTarget)
+    {
+        let mut session = GdbSession::new();
+        let mut target = MockTarget::new();
+        let (_, replies) = exchange(&mut session, &mut target, b"vAttach;51");
+        assert_eq!(replies, vec![b"T13thread:10;".to_vec()]);
+        return (session, target);
+    }
+
+    #[test]
+    fn attach_and_stop_reason()
+    {
+        let mut session = GdbSession::new();
+        let mut target = MockTarget::new();
+
+        let (acks, replies) = exchange(&mut session, &mut target, b"qSupported:swbreak+");
+        assert_eq!(acks, "+");
+        assert!(String::from_utf8_lossy(&replies[0]).contains("QStartNoAckMode+"));
+
+        assert_eq!(exchange(&mut session, &mut target, b"?").1, vec![b"W00".to_vec()]);
+        assert_eq!(exchange(&mut session, &mut target, b"g").1, vec![b"E01".to_vec()]);
+        assert_eq!(exchange(&mut session, &mut target, b"vAttach;52").1, vec![b"E01".to_vec()]);
+
+        assert_eq!(exchange(&mut session, &mut target, b"vAttach;51").1, vec![b"T13thread:10;".to_vec()]);
+        assert_eq!(session.get_pid(), Some(0x51));
+        assert_eq!(exchange(&mut session, &mut target, b"?").1, vec![b"T05thread:10;".to_vec()]);
+    }
+
+    #[test]
+    fn registers()
+    {
+        let (mut session, mut target) = attached();
+
+        let mut expected = String::from("8877665544332211");
+        expected += &"0".repeat(16 * 31);
+        expected += "0000000071000000";
+        expected += "00000000";
+
+        // Mostly zeroes, so it has to come back run-length encoded
+        let out = session.feed(&mut target, &packet(b"g"));
+        assert!(out.contains(&b'*'));
+        assert!(out.len() < expected.len() / 4);
+        assert_eq!(unpack(&out).1, vec![expected.as_bytes().to_vec()]);
+
+        let mut written = String::from("G0100000000000000");
+        written += &"0".repeat(16 * 31);
+        written += "0010000000000000";
+        written += "05000060";
+        assert_eq!(exchange(&mut session, &mut target, written.as_bytes()).1, vec![b"OK".to_vec()]);
+        assert_eq!(target.regs[0], 1);
+        assert_eq!(target.regs[GDB_REG_PC], 0x1000);
+        assert_eq!(target.regs[GDB_REG_CPSR], 0x60000005);
+
+        assert_eq!(exchange(&mut session, &mut target, b"G00").1, vec![b"E01".to_vec()]);
+    }
+
+    #[test]
+    fn memory()
+    {
+        let (mut session, mut target) = attached();
+
+        assert_eq!(exchange(&mut session, &mut target, b"m1000,4").1, vec![b"deadbeef".to_vec()]);
+        assert_eq!(exchange(&mut session, &mut target, b"m1006,10").1, vec![b"0000".to_vec()]);
+        assert_eq!(exchange(&mut session, &mut target, b"m2000,4").1, vec![b"E14".to_vec()]);
+
+        assert_eq!(exchange(&mut session, &mut target, b"M1001,2:aabb").1, vec![b"OK".to_vec()]);
+        assert_eq!(&target.mem[..4], &[0xDE, 0xAA, 0xBB, 0xEF]);
+        assert_eq!(exchange(&mut session, &mut target, b"M1001,2:aa").1, vec![b"E01".to_vec()]);
+        assert_eq!(exchange(&mut session, &mut target, b"M1007,2:aabb").1, vec![b"E14".to_vec()]);
+
+        // Any byte can come escaped, `}` 0x11 is '1'
+        assert_eq!(exchange(&mut session, &mut target, b"m}\x11000,4").1, vec![b"deaabbef".to_vec()]);
+    }
+
+    #[test]
+    fn breakpoints()
+    {
+        let (mut session, mut target) = attached();
+
+        assert_eq!(exchange(&mut session, &mut target, b"Z0,1004,4").1, vec![b"OK".to_vec()]);
+        assert_eq!(target.breakpoints, vec![0x1004]);
+        assert_eq!(exchange(&mut session, &mut target, b"z0,1004,4").1, vec![b"OK".to_vec()]);
+        assert!(target.breakpoints.is_empty());
+        assert_eq!(exchange(&mut session, &mut target, b"z0,1004,4").1, vec![b"E01".to_vec()]);
+
+        // Hardware breakpoints and watchpoints aren't supported
+        assert_eq!(exchange(&mut session, &mut target, b"Z1,1004,4").1, vec![Vec::new()]);
+    }
+
+    #[test]
+    fn continue_and_step()
+    {
+        let (mut session, mut target) = attached();
+
+        // No reply until the target stops
+        let (acks, replies) = exchange(&mut session, &mut target, b"c");
+        assert_eq!(acks, "+");
+        assert!(replies.is_empty());
+        assert!(session.is_running());
+        assert_eq!(target.resumes, vec![None]);
+
+        let (_, replies) = unpack(&session.notify_stop(0x11, GDB_SIGTRAP));
+        assert_eq!(replies, vec![b"T05thread:11;".to_vec()]);
+        assert!(!session.is_running());
+        assert!(session.notify_stop(0x11, GDB_SIGTRAP).is_empty());
+
+        // Steps the thread that stopped
+        assert!(exchange(&mut session, &mut target, b"s").1.is_empty());
+        assert_eq!(target.resumes, vec![None, Some(0x11)]);
+
+        let (_, replies) = unpack(&session.feed(&mut target, &[0x03]));
+        assert_eq!(replies, vec![b"T02thread:10;".to_vec()]);
+
+        assert!(exchange(&mut session, &mut target, b"c1000").1.is_empty());
+        assert_eq!(target.regs[GDB_REG_PC], 0x1000);
+    }
+
+    #[test]
+    fn thread_list()
+    {
+        let (mut session, mut target) = attached();
+
+        let doc = "<?xml version=\"1.0\"?>\n<threads>\n<thread id=\"10\"/>\n<thread id=\"11\"/>\n</threads>\n";
+        let mut expected = b"l".to_vec();
+        expected.extend_from_slice(doc.as_bytes());
+        assert_eq!(exchange(&mut session, &mut target, b"qXfer:threads:read::0,fff").1, vec![expected]);
+
+        let mut expected = b"m".to_vec();
+        expected.extend_from_slice(&doc.as_bytes()[..0x10]);
+        assert_eq!(exchange(&mut session, &mut target, b"qXfer:threads:read::0,10").1, vec![expected]);
+
+        assert_eq!(exchange(&mut session, &mut target, b"qfThreadInfo").1, vec![b"m10,11".to_vec()]);
+    }
+
+    #[test]
+    fn bad_checksum()
+    {
+        let (mut session, mut target) = attached();
+
+        assert_eq!(session.feed(&mut target, b"$m1000,4#00"), b"-".to_vec());
+        assert_eq!(exchange(&mut session, &mut target, b"m1000,4").1, vec![b"deadbeef".to_vec()]);
+
+        // gdb didn't get it either, send it again
+        let (acks, replies) = unpack(&session.feed(&mut target, b"-"));
+        assert_eq!(acks, "");
+        assert_eq!(replies, vec![b"deadbeef".to_vec()]);
+    }
+
+    #[test]
+    fn no_ack_mode()
+    {
+        let (mut session, mut target) = attached();
+
+        let (acks, replies) = exchange(&mut session, &mut target, b"QStartNoAckMode");
+        assert_eq!(acks, "+");
+        assert_eq!(replies, vec![b"OK".to_vec()]);
+
+        // No acks either way, checksums aren't checked and `-` is ignored
+        let (acks, replies) = exchange(&mut session, &mut target, b"m1000,4");
+        assert_eq!(acks, "");
+        assert_eq!(replies, vec![b"deadbeef".to_vec()]);
+        assert_eq!(unpack(&session.feed(&mut target, b"$m1000,4#00")).1, vec![b"deadbeef".to_vec()]);
+        assert!(session.feed(&mut target, b"-").is_empty());
+    }
+
+    #[test]
+    fn packet_framing()
+    {
+        assert_eq!(hgdb_make_packet(b"OK"), b"$OK#9a".to_vec());
+        assert_eq!(hgdb_make_packet(b"a#b}"), b"$a}\x03b}]#1d".to_vec());
+        assert_eq!(hgdb_make_packet(b"000"), b"$000#90".to_vec());
+        assert_eq!(hgdb_make_packet(b"0000"), b"$0* #7a".to_vec());
+
+        // Runs that would need '#' or '$' as the count get split
+        for len in 1..300
+        {
+            let payload = "0".repeat(len).into_bytes();
+            let out = hgdb_make_packet(&payload);
+            assert!(!out[1..out.len()-3].contains(&b'#') && !out[1..].contains(&b'$'));
+            assert_eq!(unpack(&out).1, vec![payload]);
+        }
+    }
+}
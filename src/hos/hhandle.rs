@@ -7,6 +7,7 @@
 use core::cmp::{Ordering, Eq};
 use crate::vm::vsvc::vsvc_get_curpid;
 
+#[derive(Copy, Clone)]
 pub struct HHandle
 {
     pub pid: u8,
@@ -6,10 +6,9 @@
 
 use alloc::vec::Vec;
 use crate::util::*;
-use crate::arm::threading::get_tls_el0;
 use crate::arm::mmu::translate_el1_stage12;
 use crate::logger::*;
-use crate::vm::vsvc::vsvc_get_curpid;
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_tls};
 use alloc::{collections::BTreeMap, sync::Arc};
 use core::cmp::{Ordering, Eq};
 use super::hhandle::HHandle;
@@ -20,10 +19,14 @@ use spin::mutex::Mutex;
 use core::str;
 use super::hdomainobj::HDomainObj;
 use super::hdomainsession::HDomainSession;
+use super::hserversession::HServerSession;
+use super::hipcmem::{HIPCMem, HIPCMemTarget};
 
 pub const MAGIC_SFCI: u32 = 0x49434653;
 pub const MAGIC_SFCO: u32 = 0x4F434653;
 
+pub const HIPC_MAX_SIZE: usize = 0x100;
+
 pub const HIPC_MAX_BUFS: usize = 8;
 pub const HIPC_MAX_OBJS: usize = 8;
 
@@ -36,6 +39,10 @@ pub const PKT_TYPE_CONTROL:            u16 = 5;
 pub const PKT_TYPE_REQUESTWITHCONTEXT: u16 = 6;
 pub const PKT_TYPE_CONTROLWITHCONTEXT: u16 = 7;
 
+// TIPC (12.0.0+) reuses the type field, request command IDs start at 16
+pub const PKT_TYPE_TIPC_CLOSE:         u16 = 15;
+pub const PKT_TYPE_TIPC_CMD_BASE:      u16 = 16;
+
 pub const DOMAIN_CMD_SEND:     u8 = 1;
 pub const DOMAIN_CMD_CLOSEOBJ: u8 = 2;
 
@@ -67,6 +74,7 @@ pub enum HObject
     None(),
     ClientSession(Arc<Mutex<HClientSession>>),
     DomainSession(Arc<Mutex<HDomainSession>>),
+    ServerSession(Arc<Mutex<HServerSession>>),
     Port(Arc<Mutex<HPort>>)
 }
 
@@ -104,6 +112,40 @@ impl HObject
         }
     }
     
+    pub fn get_service(&self) -> String
+    {
+        match self
+        {
+            HObject::ClientSession(a) => { a.lock().get_service() },
+            HObject::DomainSession(a) => { a.lock().get_service() },
+            HObject::ServerSession(a) => { a.lock().get_service() },
+            HObject::Port(a) => { a.lock().name.clone().unwrap_or(String::new()) },
+            _ => { String::new() }
+        }
+    }
+    
+    pub fn get_interface(&self) -> String
+    {
+        match self
+        {
+            HObject::ClientSession(a) => { a.lock().get_interface() },
+            HObject::DomainSession(a) => { a.lock().get_interface() },
+            HObject::ServerSession(a) => { a.lock().get_interface() },
+            _ => { String::new() }
+        }
+    }
+    
+    pub fn set_interface(&self, interface: &str)
+    {
+        match self
+        {
+            HObject::ClientSession(a) => { a.lock().set_interface(interface); },
+            HObject::DomainSession(a) => { a.lock().set_interface(interface); },
+            HObject::ServerSession(a) => { a.lock().set_interface(interface); },
+            _ => {}
+        }
+    }
+    
     pub fn set_extra_str(&self, str: &String)
     {
         let extra = HExtraString { str: str.clone() };
@@ -136,48 +178,31 @@ pub fn hipc_remove_domain(obj: HDomainObj)
 pub fn hipc_remove_pid_handles(pid: u32)
 {
     let pid_u8 = (pid & 0xFF) as u8;
-    let mut handle_vec: Vec<&HHandle> = Vec::new();
-    let mut domain_vec: Vec<&HDomainObj> = Vec::new();
+    let mut port_vec: Vec<String> = Vec::new();
     
     unsafe
     {
-        for (key, val) in &HANDLE_TO_OBJ {
-            if key.pid == pid_u8 {
-                handle_vec.push(key);
-            }
-        }
+        HANDLE_TO_OBJ.retain(|key, _| key.pid != pid_u8);
+        DOMAINOBJ_TO_SESSION.retain(|key, _| key.pid != pid_u8);
         
-        for (key, val) in &DOMAINOBJ_TO_SESSION {
-            if key.pid == pid_u8 {
-                domain_vec.push(key);
+        // Named ports die with the process hosting them
+        for (name, port) in &NAME_TO_SERVERPORT {
+            if port.lock().pid == pid_u8 {
+                port_vec.push(name.clone());
             }
         }
         
-        for key in handle_vec {
-            HANDLE_TO_OBJ.remove(key);
-        }
-        
-        for key in domain_vec {
-            DOMAINOBJ_TO_SESSION.remove(key);
+        for name in port_vec {
+            NAME_TO_SERVERPORT.remove(&name);
         }
     }
 }
 
 pub fn hipc_remove_domains_from_handle(hhand: &HHandle)
 {
-    let mut domain_vec: Vec<&HDomainObj> = Vec::new();
-    
     unsafe
     {
-        for (key, val) in &DOMAINOBJ_TO_SESSION {
-            if key.handle == hhand.handle && key.pid == hhand.pid {
-                domain_vec.push(key);
-            }
-        }
-        
-        for key in domain_vec {
-            DOMAINOBJ_TO_SESSION.remove(key);
-        }
+        DOMAINOBJ_TO_SESSION.retain(|key, _| !(key.handle == hhand.handle && key.pid == hhand.pid));
     }
 }
 
@@ -202,6 +227,14 @@ pub fn hipc_register_handle_clientsession(handle: u32, session: Arc<Mutex<HClien
     }
 }
 
+pub fn hipc_register_handle_serversession(handle: u32, session: Arc<Mutex<HServerSession>>)
+{
+    unsafe
+    {
+        HANDLE_TO_OBJ.insert(HHandle::from_curpid(handle), HObject::ServerSession(session));
+    }
+}
+
 pub fn hipc_get_domain_session(obj: HDomainObj) -> Option<Arc<Mutex<HDomainSession>>>
 {
     unsafe
@@ -232,12 +265,113 @@ pub fn hipc_close_handle(handle: u32)
     unsafe
     {
         let hhandle = HHandle::from_curpid(handle);
-        if let Some(arc_res) = HANDLE_TO_OBJ.remove(&hhandle)
-        {
-            hipc_remove_domains_from_handle(&hhandle);
-            // TODO object delete hook?
+        HANDLE_TO_OBJ.remove(&hhandle);
+        
+        // Domain objects can outlive the session's entry (ie a session
+        // converted before we saw it), so always clear them
+        hipc_remove_domains_from_handle(&hhandle);
+    }
+}
+
+fn hipc_print_obj(label: &String, obj: &HObject)
+{
+    match obj
+    {
+        HObject::ClientSession(a) => {
+            let session = a.lock();
+            let pointer_buffer = match session.get_pointer_buffer_size() {
+                Some(size) => format!(", pointer buffer {:#x}", size),
+                None => String::new()
+            };
+            println!("  {:10} session `{}` {} -> pid {}{}{}", label, session.get_service(), session.get_interface(), session.parent_port_pid, if session.get_handler().is_some() { ", hooked" } else { "" }, pointer_buffer);
+        },
+        HObject::DomainSession(a) => {
+            let session = a.lock();
+            println!("  {:10} domain  `{}` {} -> pid {}{}", label, session.get_service(), session.get_interface(), session.parent_port_pid, if session.get_handler().is_some() { ", hooked" } else { "" });
+        },
+        HObject::ServerSession(a) => {
+            let session = a.lock();
+            println!("  {:10} server  `{}` {}{}", label, session.get_service(), session.get_interface(), if session.pending.is_some() { ", pending reply" } else { "" });
+        },
+        HObject::Port(a) => {
+            let port = a.lock();
+            println!("  {:10} port    `{}`", label, port.name.clone().unwrap_or(String::new()));
+        },
+        HObject::None() => {
+            println!("  {:10} none", label);
+        }
+    }
+}
+
+pub fn hipc_print_pid_handles(pid: u32)
+{
+    let pid_u8 = (pid & 0xFF) as u8;
+    let mut num_handles = 0;
+    let mut num_domains = 0;
+    
+    unsafe
+    {
+        for (key, val) in &HANDLE_TO_OBJ {
+            if key.pid == pid_u8 {
+                hipc_print_obj(&format!("{:08x}", key.handle), val);
+                num_handles += 1;
+            }
+        }
+        
+        for (key, val) in &DOMAINOBJ_TO_SESSION {
+            if key.pid == pid_u8 {
+                hipc_print_obj(&format!("{:08x}[{}]", key.handle, key.id), &HObject::DomainSession(val.clone()));
+                num_domains += 1;
+            }
+        }
+    }
+    
+    println!("  {} handles, {} domain objects", num_handles, num_domains);
+}
+
+// First session handle `pid` holds to `service`/`interface`
+pub fn hipc_find_pid_session(pid: u32, service: &String, interface: &String) -> Option<u32>
+{
+    let pid_u8 = (pid & 0xFF) as u8;
+    
+    unsafe
+    {
+        for (key, val) in &HANDLE_TO_OBJ {
+            if key.pid != pid_u8 {
+                continue;
+            }
+            
+            if let HObject::ClientSession(session) = val {
+                let session = session.lock();
+                if session.get_service() == *service && session.get_interface() == *interface {
+                    return Some(key.handle);
+                }
+            }
+        }
+    }
+    
+    return None;
+}
+
+pub fn hipc_get_pid_clientsessions(pid: u32) -> Vec<(u32, Arc<Mutex<HClientSession>>)>
+{
+    let pid_u8 = (pid & 0xFF) as u8;
+    let mut sessions: Vec<(u32, Arc<Mutex<HClientSession>>)> = Vec::new();
+    
+    unsafe
+    {
+        for (key, val) in &HANDLE_TO_OBJ {
+            if key.pid != pid_u8 {
+                continue;
+            }
+            
+            if let HObject::ClientSession(session) = val {
+                sessions.push((key.handle, session.clone()));
+            }
         }
     }
+    
+    return sessions;
 }
 
 pub fn hipc_get_handle_serverport(handle: u32) -> Option<Arc<Mutex<HPort>>>
@@ -286,10 +420,67 @@ pub fn hipc_get_handle_clientsession(handle: u32) -> Option<Arc<Mutex<HClientSes
     }
 }
 
+pub fn hipc_get_handle_serversession(handle: u32) -> Option<Arc<Mutex<HServerSession>>>
+{
+    unsafe
+    {
+        let hhandle = HHandle::from_curpid(handle);
+        if let Some(arc_handle) = HANDLE_TO_OBJ.get(&hhandle)
+        {
+            match arc_handle
+            {
+                HObject::ServerSession(session) => { return Some(session.clone()); },
+                _ => { return None; }
+            }
+        }
+        return None;
+    }
+}
+
+// Server end of a svcCreateSession pair, looked up by the client end
+pub fn hipc_find_serversession_by_client(client_handle: u32) -> Option<Arc<Mutex<HServerSession>>>
+{
+    let pid_u8 = (vsvc_get_curpid() & 0xFF) as u8;
+    
+    unsafe
+    {
+        for (key, val) in &HANDLE_TO_OBJ {
+            if key.pid != pid_u8 {
+                continue;
+            }
+            
+            if let HObject::ServerSession(session) = val {
+                if session.lock().client_handle == Some(client_handle) {
+                    return Some(session.clone());
+                }
+            }
+        }
+    }
+    
+    return None;
+}
+
 enum HIPCPayload
 {
+    None(),
     Domain(HIPCDomainPayload),
-    Session(HIPCDataPayload)
+    Session(HIPCDataPayload),
+    Tipc(HIPCDataPayload)
+}
+
+fn hipc_push_u32(out: &mut Vec<u8>, val: u32)
+{
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn hipc_peek_bytes<M: HIPCMem>(mem: &M, addr: u64, len: usize) -> Vec<u8>
+{
+    let mut bytes: Vec<u8> = Vec::with_capacity(len);
+    for i in 0..len
+    {
+        bytes.push(mem.read8(addr + i as u64));
+    }
+    return bytes;
 }
 
 pub struct HIPCDomainPayload
@@ -298,6 +489,7 @@ pub struct HIPCDomainPayload
     num_objs: u8,
     data_size: u16,
     obj_id: u32,
+    pad: u32,
     token: u32,
     data: HIPCDataPayload,
     objs: Vec<u32>,
@@ -305,29 +497,38 @@ pub struct HIPCDomainPayload
 
 impl HIPCDomainPayload
 {
-    pub fn unpack(buf: u64, buf_size: u16) -> HIPCDomainPayload
+    pub fn unpack<M: HIPCMem>(mem: &M, buf: u64, buf_size: u16) -> HIPCDomainPayload
     {
-        let word0 = peek32(buf);
+        let word0 = mem.read32(buf);
         
         let cmd = (word0 & 0xFF) as u8;
         let num_objs = ((word0 >> 8) & 0xFF) as u8;
         let data_size = ((word0 >> 16) & 0xFFFF) as u16;
         
-        // TODO verify buf_size
-        
-        let obj_id = peek32(buf + 4);
-        let pad = peek32(buf + 8);
-        let token = peek32(buf + 12);
+        let obj_id = mem.read32(buf + 4);
+        let pad = mem.read32(buf + 8);
+        let token = mem.read32(buf + 12);
         
-        let mut buf_objs = buf + 16 + (data_size as u64);
+        // Responses don't carry a data size, so the payload takes up
+        // the rest of the buffer
+        let mut data_len = buf_size - 0x10;
+        if data_size >= 0x10 && data_size <= buf_size - 0x10 {
+            data_len = data_size;
+        }
         
         let buf_data = buf + 16;
-        let data = HIPCDataPayload::unpack(buf_data, buf_size-0x10);
+        let data = HIPCDataPayload::unpack(mem, buf_data, data_len);
+        
+        let mut buf_objs = buf_data + (data_len as u64);
+        let buf_end = buf + (buf_size as u64);
         
         let mut objs: Vec<u32> = Vec::with_capacity(num_objs as usize);
         for i in 0..num_objs
         {
-            objs.push(peek32(buf_objs));
+            if buf_objs + 4 > buf_end {
+                break;
+            }
+            objs.push(mem.read32(buf_objs));
             buf_objs += 4;
         }
         
@@ -337,15 +538,34 @@ impl HIPCDomainPayload
             num_objs: num_objs,
             data_size: data_size,
             obj_id: obj_id,
+            pad: pad,
             token: token,
             data: data,
             objs: objs
         }
     }
     
+    pub fn pack_into(&self, out: &mut Vec<u8>)
+    {
+        let data_size = if self.data_size != 0 { self.data.packed_size() as u32 } else { 0 };
+        let word0 = (self.cmd as u32) | ((self.num_objs as u32) << 8) | ((data_size & 0xFFFF) << 16);
+        
+        hipc_push_u32(out, word0);
+        hipc_push_u32(out, self.obj_id);
+        hipc_push_u32(out, self.pad);
+        hipc_push_u32(out, self.token);
+        
+        self.data.pack_into(out);
+        
+        for obj in &self.objs
+        {
+            hipc_push_u32(out, *obj);
+        }
+    }
+    
     pub fn packed_size(&self) -> u64
     {
-        16 + self.data_size as u64 + ((self.num_objs as u64) * 4)
+        16 + self.data.packed_size() + ((self.objs.len() as u64) * 4)
     }
     
     pub fn get_cmd_id(&self) -> u32
@@ -365,7 +585,7 @@ impl HIPCDomainPayload
     
     pub fn get_domain_obj(&self, idx: usize) -> Option<u32>
     {
-        if idx >= self.num_objs as usize
+        if idx >= self.objs.len()
         {
             return None
         }
@@ -373,6 +593,24 @@ impl HIPCDomainPayload
         return Some(self.objs[idx]);
     }
     
+    pub fn push_domain_obj(&mut self, obj: u32)
+    {
+        self.objs.push(obj);
+        self.num_objs = self.objs.len() as u8;
+    }
+    
+    pub fn remove_domain_obj(&mut self, idx: usize) -> Option<u32>
+    {
+        if idx >= self.objs.len()
+        {
+            return None
+        }
+        
+        let obj = self.objs.remove(idx);
+        self.num_objs = self.objs.len() as u8;
+        return Some(obj);
+    }
+    
     pub fn read_u8(&self, offs: usize) -> u8
     {
         self.data.read_u8(offs)
@@ -398,11 +636,36 @@ impl HIPCDomainPayload
         self.data.read_str(offs)
     }
     
-    pub fn write_u32(&self, offs: usize, val: u32)
+    pub fn write_u8(&mut self, offs: usize, val: u8)
+    {
+        self.data.write_u8(offs, val);
+    }
+    
+    pub fn write_u16(&mut self, offs: usize, val: u16)
+    {
+        self.data.write_u16(offs, val);
+    }
+    
+    pub fn write_u32(&mut self, offs: usize, val: u32)
     {
         self.data.write_u32(offs, val);
     }
     
+    pub fn write_u64(&mut self, offs: usize, val: u64)
+    {
+        self.data.write_u64(offs, val);
+    }
+    
+    pub fn get_data_len(&self) -> usize
+    {
+        self.data.get_data_len()
+    }
+    
+    pub fn set_data_len(&mut self, len: usize)
+    {
+        self.data.set_data_len(len);
+    }
+    
     pub fn print(&self)
     {
         println!("Domain Payload:");
@@ -424,42 +687,90 @@ impl HIPCDomainPayload
 
 pub struct HIPCDataPayload
 {
+    header_size: u8, // 16 for CMIF, 4 for TIPC responses, 0 for TIPC requests
     magic: u32,
     version: u32,
     command: u32, // also error
     token: u32,
-    //data: Vec<u8>,
-    data_ptr: u64,
-    data_len: u16
+    data: Vec<u8>,
 }
 
 impl HIPCDataPayload
 {
-    pub fn unpack(buf: u64, data_size: u16) -> HIPCDataPayload
+    pub fn unpack<M: HIPCMem>(mem: &M, buf: u64, data_size: u16) -> HIPCDataPayload
     {
-        let mut buf_data = buf + 16;
-        /*let mut data: Vec<u8> = Vec::with_capacity((data_size-0x10) as usize);
-        for i in 0..(data_size-0x10)
+        let data_len = (data_size as usize).saturating_sub(16);
+        
+        HIPCDataPayload
         {
-            data.push(peek8(buf_data));
-            buf_data += 1;
-        }*/
+            header_size: 16,
+            magic: mem.read32(buf),
+            version: mem.read32(buf + 4),
+            command: mem.read32(buf + 8),
+            token: mem.read32(buf + 12),
+            data: hipc_peek_bytes(mem, buf + 16, data_len)
+        }
+    }
+    
+    pub fn unpack_tipc<M: HIPCMem>(mem: &M, buf: u64, data_size: u16, cmd: Option<u32>) -> HIPCDataPayload
+    {
+        // Requests carry the command in the packet type, responses
+        // start with the result
+        let mut command = 0;
+        let mut header_size = 0;
+        if let Some(cmd_id) = cmd {
+            command = cmd_id;
+        }
+        else if data_size >= 4 {
+            command = mem.read32(buf);
+            header_size = 4;
+        }
         
         HIPCDataPayload
         {
-            magic: peek32(buf),
-            version: peek32(buf + 4),
-            command: peek32(buf + 8),
-            token: peek32(buf + 12),
-            data_ptr: buf + 16,
-            data_len: (data_size - 16)
-            //data: data
+            header_size: header_size,
+            magic: 0,
+            version: 0,
+            command: command,
+            token: 0,
+            data: hipc_peek_bytes(mem, buf + header_size as u64, (data_size - header_size as u16) as usize)
+        }
+    }
+    
+    pub fn new_response(is_tipc: bool, result: u32) -> HIPCDataPayload
+    {
+        HIPCDataPayload
+        {
+            header_size: if is_tipc { 4 } else { 16 },
+            magic: if is_tipc { 0 } else { MAGIC_SFCO },
+            version: 0,
+            command: result,
+            token: 0,
+            data: Vec::new()
+        }
+    }
+    
+    pub fn pack_into(&self, out: &mut Vec<u8>)
+    {
+        match self.header_size
+        {
+            16 => {
+                hipc_push_u32(out, self.magic);
+                hipc_push_u32(out, self.version);
+                hipc_push_u32(out, self.command);
+                hipc_push_u32(out, self.token);
+            },
+            4 => {
+                hipc_push_u32(out, self.command);
+            },
+            _ => {}
         }
+        out.extend_from_slice(&self.data);
     }
     
     pub fn packed_size(&self) -> u64
     {
-        16 + self.data_len as u64 //self.data.len() as u64
+        (self.header_size as u64) + self.data.len() as u64
     }
     
     pub fn get_cmd_id(&self) -> u32
@@ -467,62 +778,98 @@ impl HIPCDataPayload
         self.command
     }
     
-    pub fn read_u8(&self, offs: usize) -> u8
+    pub fn set_cmd_id(&mut self, command: u32)
     {
-        peek8(self.data_ptr + (offs as u64))
-        //self.data[offs]
+        self.command = command;
     }
     
-    pub fn read_u16(&self, offs: usize) -> u16
+    pub fn get_data_len(&self) -> usize
+    {
+        self.data.len()
+    }
+    
+    pub fn set_data_len(&mut self, len: usize)
+    {
+        self.data.resize(len, 0);
+    }
+    
+    fn read_bytes<const N: usize>(&self, offs: usize) -> [u8; N]
     {
-        /*let mut bytes: [u8; 2] = [0;2];
-        for i in offs..offs+2
+        let mut bytes: [u8; N] = [0; N];
+        for i in 0..N
         {
-            bytes[i] = self.data[i];
+            if offs + i < self.data.len() {
+                bytes[i] = self.data[offs + i];
+            }
         }
-        u16::from_le_bytes(bytes)*/
-        peek16(self.data_ptr + (offs as u64))
+        return bytes;
     }
     
-    pub fn read_u32(&self, offs: usize) -> u32
+    fn write_bytes(&mut self, offs: usize, bytes: &[u8])
     {
-        /*let mut bytes: [u8; 4] = [0;4];
-        for i in offs..offs+4
-        {
-            bytes[i] = self.data[i];
+        // Writing past the end grows the raw data
+        if offs + bytes.len() > self.data.len() {
+            self.data.resize(offs + bytes.len(), 0);
         }
-        u32::from_le_bytes(bytes)*/
-        peek32(self.data_ptr + (offs as u64))
+        self.data[offs..offs + bytes.len()].copy_from_slice(bytes);
+    }
+    
+    pub fn read_u8(&self, offs: usize) -> u8
+    {
+        u8::from_le_bytes(self.read_bytes::<1>(offs))
+    }
+    
+    pub fn read_u16(&self, offs: usize) -> u16
+    {
+        u16::from_le_bytes(self.read_bytes::<2>(offs))
+    }
+    
+    pub fn read_u32(&self, offs: usize) -> u32
+    {
+        u32::from_le_bytes(self.read_bytes::<4>(offs))
     }
     
     pub fn read_u64(&self, offs: usize) -> u64
     {
-        /*let mut bytes: [u8; 8] = [0;8];
-        for i in offs..offs+8
-        {
-            bytes[i] = self.data[i];
-        }
-        u64::from_le_bytes(bytes)*/
-        peek64(self.data_ptr + (offs as u64))
+        u64::from_le_bytes(self.read_bytes::<8>(offs))
     }
     
     pub fn read_str(&self, offs: usize) -> String
     {
+        if offs >= self.data.len() {
+            return String::from("");
+        }
+        
         let mut s_len = 0;
-        for i in offs..(self.data_len as usize)
+        for i in offs..self.data.len()
         {
-            if peek8(self.data_ptr + i as u64) == 0
+            if self.data[i] == 0
             {
                 break;
             }
             s_len += 1;
         }
-        unsafe { String::from(str_from_null_terminated_utf8_u64ptr_unchecked_len(self.data_ptr + (offs as u64), s_len)) }
+        String::from(str_from_null_terminated_utf8_unchecked(&self.data[offs..offs+s_len]))
     }
     
-    pub fn write_u32(&self, offs: usize, val: u32)
+    pub fn write_u8(&mut self, offs: usize, val: u8)
+    {
+        self.write_bytes(offs, &val.to_le_bytes());
+    }
+    
+    pub fn write_u16(&mut self, offs: usize, val: u16)
     {
-        poke32(self.data_ptr + (offs as u64), val);
+        self.write_bytes(offs, &val.to_le_bytes());
+    }
+    
+    pub fn write_u32(&mut self, offs: usize, val: u32)
+    {
+        self.write_bytes(offs, &val.to_le_bytes());
+    }
+    
+    pub fn write_u64(&mut self, offs: usize, val: u64)
+    {
+        self.write_bytes(offs, &val.to_le_bytes());
     }
     
     pub fn print(&self)
@@ -532,43 +879,56 @@ impl HIPCDataPayload
         println!("  Version: {}", self.version);
         println!("  Command/Error: {:x}", self.command);
         println!("  Token: {:x}", self.token);
-        //hexdump_vec("Data Buf", &self.data);
-        // TODO data hexdump
+        hexdump_vec("  Data Buf", &self.data);
     }
 }
 
 pub struct HIPCHandleDesc
 {
     send_pid: bool,
-    num_copy: u8,
-    num_move: u8,
-    pid: u8,
+    pid: u64,
+    pad: u32,
     copy_handles: Vec<u32>,
     move_handles: Vec<u32>
 }
 
 impl HIPCHandleDesc
 {
-    pub fn unpack(buf: u64) -> HIPCHandleDesc
+    pub fn new() -> HIPCHandleDesc
+    {
+        HIPCHandleDesc
+        {
+            send_pid: false,
+            pid: 0,
+            pad: 0,
+            copy_handles: Vec::new(),
+            move_handles: Vec::new()
+        }
+    }
+    
+    pub fn unpack<M: HIPCMem>(mem: &M, buf: u64) -> HIPCHandleDesc
     {
-        let word0 = peek32(buf);
+        let word0 = mem.read32(buf);
         
         let send_pid = (word0 & 1) != 0;
         let num_copy = ((word0 >> 1) & 0xF) as u8;
         let num_move = ((word0 >> 5) & 0xF) as u8;
-        
-        let pid = (if send_pid { peek32(buf + 4) } else { 0 } & 0xFF) as u8;
+        let pad = word0 >> 9;
         
         let mut buf_inc = buf + 4;
+        
+        // PID placeholder is a full u64
+        let mut pid: u64 = 0;
         if send_pid
         {
-            buf_inc += 4;
+            pid = mem.read64(buf_inc);
+            buf_inc += 8;
         }
 
         let mut copy_handles: Vec<u32> = Vec::with_capacity(num_copy as usize);
         for i in 0..num_copy
         {
-            let handle = peek32(buf_inc);
+            let handle = mem.read32(buf_inc);
             buf_inc += 4;
             copy_handles.push(handle);
         }
@@ -576,7 +936,7 @@ impl HIPCHandleDesc
         let mut move_handles: Vec<u32> = Vec::with_capacity(num_move as usize);
         for i in 0..num_move
         {
-            let handle = peek32(buf_inc);
+            let handle = mem.read32(buf_inc);
             buf_inc += 4;
             move_handles.push(handle);
         }
@@ -584,22 +944,46 @@ impl HIPCHandleDesc
         HIPCHandleDesc
         {
             send_pid: send_pid,
-            num_copy: num_copy,
-            num_move: num_move,
             pid: pid,
+            pad: pad,
             copy_handles: copy_handles,
             move_handles: move_handles
         }
     }
     
+    pub fn pack_into(&self, out: &mut Vec<u8>)
+    {
+        let word0 = (if self.send_pid { 1 } else { 0 })
+                    | (((self.copy_handles.len() as u32) & 0xF) << 1)
+                    | (((self.move_handles.len() as u32) & 0xF) << 5)
+                    | (self.pad << 9);
+        
+        hipc_push_u32(out, word0);
+        if self.send_pid
+        {
+            hipc_push_u32(out, (self.pid & 0xFFFFFFFF) as u32);
+            hipc_push_u32(out, (self.pid >> 32) as u32);
+        }
+        
+        for handle in &self.copy_handles
+        {
+            hipc_push_u32(out, *handle);
+        }
+        
+        for handle in &self.move_handles
+        {
+            hipc_push_u32(out, *handle);
+        }
+    }
+    
     pub fn packed_size(&self) -> u64
     {
         let mut ret_size = 4;
         if self.send_pid {
-            ret_size += 4;
+            ret_size += 8;
         }
-        ret_size += (4 * self.num_copy);
-        ret_size += (4 * self.num_move);
+        ret_size += (4 * self.copy_handles.len());
+        ret_size += (4 * self.move_handles.len());
 
         return ret_size as u64;
     }
@@ -617,22 +1001,50 @@ impl HIPCHandleDesc
         return None;
     }
     
-    pub fn print(&self)
+    pub fn set_handle(&mut self, idx: usize, handle: u32) -> bool
     {
-        println!("Handle Desc:");
-        if self.send_pid
+        if idx < self.copy_handles.len()
+        {
+            self.copy_handles[idx] = handle;
+            return true;
+        }
+        else if idx - self.copy_handles.len() < self.move_handles.len()
+        {
+            self.move_handles[idx - self.copy_handles.len()] = handle;
+            return true;
+        }
+        return false;
+    }
+    
+    pub fn remove_handle(&mut self, idx: usize) -> Option<u32>
+    {
+        if idx < self.copy_handles.len()
+        {
+            return Some(self.copy_handles.remove(idx));
+        }
+        else if idx - self.copy_handles.len() < self.move_handles.len()
+        {
+            return Some(self.move_handles.remove(idx - self.copy_handles.len()));
+        }
+        return None;
+    }
+    
+    pub fn print(&self)
+    {
+        println!("Handle Desc:");
+        if self.send_pid
         {
             println!("  PID: {}", self.pid);
         }
-        println!("  Copied ({}):", self.num_copy);
-        for i in 0..(self.num_copy as usize)
+        println!("  Copied ({}):", self.copy_handles.len());
+        for handle in &self.copy_handles
         {
-            println!("    {:x}", self.copy_handles[i]);
+            println!("    {:x}", handle);
         }
-        println!("  Moved  ({}):", self.num_move);
-        for i in 0..(self.num_move as usize)
+        println!("  Moved  ({}):", self.move_handles.len());
+        for handle in &self.move_handles
         {
-            println!("    {:x}", self.move_handles[i]);
+            println!("    {:x}", handle);
         }
     }
 }
@@ -648,10 +1060,21 @@ pub struct HIPCStaticDesc
 
 impl HIPCStaticDesc
 {
-    pub fn unpack(buf: u64) -> HIPCStaticDesc
+    pub fn new(index: u16, addr: u64, size: u16) -> HIPCStaticDesc
+    {
+        HIPCStaticDesc
+        {
+            buf: 0,
+            index: index,
+            addr: addr,
+            size: size
+        }
+    }
+    
+    pub fn unpack<M: HIPCMem>(mem: &M, buf: u64) -> HIPCStaticDesc
     {
-        let word0 = peek32(buf);
-        let word1 = peek32(buf+4);
+        let word0 = mem.read32(buf);
+        let word1 = mem.read32(buf+4);
         
         let index5to0 = (word0 & 0x3F) as u16;
         let addr38to36 = ((word0 >> 6) & 0x7) as u64;
@@ -672,7 +1095,7 @@ impl HIPCStaticDesc
         }
     }
     
-    pub fn pack(&self)
+    fn pack_words(&self) -> (u32, u32)
     {
         let index5to0 = (self.index & 0x3F) as u32;
         let addr38to36 = ((self.addr >> 36) & 0x7) as u32;
@@ -682,8 +1105,28 @@ impl HIPCStaticDesc
 
         let word0 = ((self.size as u32) << 16) | (addr38to36 << 6) | (index11to9 << 9) | (addr35to32 << 12) | (index5to0);
         
-        poke32(self.buf, word0);
-        poke32(self.buf, addr31to0);
+        return (word0, addr31to0);
+    }
+    
+    pub fn pack(&self)
+    {
+        self.pack_with(&mut HIPCMemTarget);
+    }
+    
+    pub fn pack_with<M: HIPCMem>(&self, mem: &mut M)
+    {
+        let words = self.pack_words();
+        
+        mem.write32(self.buf, words.0);
+        mem.write32(self.buf + 4, words.1);
+    }
+    
+    pub fn pack_into(&self, out: &mut Vec<u8>)
+    {
+        let words = self.pack_words();
+        
+        hipc_push_u32(out, words.0);
+        hipc_push_u32(out, words.1);
     }
     
     pub const fn packed_size(&self) -> u64
@@ -725,24 +1168,61 @@ impl HIPCStaticDesc
     }
 }
 
+// Low two bits of word2 on A/B/W descriptors, tells the kernel how the
+// buffer may be mapped into the server
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HIPCBufferMode
+{
+    Normal = 0,
+    NonSecure = 1,
+    Invalid = 2,
+    NonDevice = 3
+}
+
+impl HIPCBufferMode
+{
+    pub fn from_u8(val: u8) -> HIPCBufferMode
+    {
+        match val & 3
+        {
+            0 => HIPCBufferMode::Normal,
+            1 => HIPCBufferMode::NonSecure,
+            2 => HIPCBufferMode::Invalid,
+            _ => HIPCBufferMode::NonDevice
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
 pub struct HIPCSendRecvExchDesc
 {
     pub addr: u64,
     pub size: u64,
-    pub flags: u8
+    pub mode: HIPCBufferMode
 }
 
 impl HIPCSendRecvExchDesc
 {
-    pub fn unpack(buf: u64) -> HIPCSendRecvExchDesc
+    pub fn new(addr: u64, size: u64, mode: HIPCBufferMode) -> HIPCSendRecvExchDesc
+    {
+        HIPCSendRecvExchDesc
+        {
+            addr: addr,
+            size: size,
+            mode: mode
+        }
+    }
+    
+    pub fn unpack<M: HIPCMem>(mem: &M, buf: u64) -> HIPCSendRecvExchDesc
     {
-        let word0 = peek32(buf);
-        let word1 = peek32(buf+4);
-        let word2 = peek32(buf+8);
+        let word0 = mem.read32(buf);
+        let word1 = mem.read32(buf+4);
+        let word2 = mem.read32(buf+8);
         
         let size31to0 = word0 as u64;
         let addr31to0 = word1 as u64;
-        let flags = (word2 & 3)  as u8;
+        let mode = HIPCBufferMode::from_u8((word2 & 3) as u8);
         let addr38to36 = ((word2 >> 2) & 7) as u64;
         let size35to32 = ((word2 >> 24) & 0xF) as u64;
         let addr35to32 = ((word2 >> 28) & 0xF) as u64;
@@ -754,10 +1234,25 @@ impl HIPCSendRecvExchDesc
         {
             addr: addr,
             size: size,
-            flags: flags
+            mode: mode
         }
     }
     
+    pub fn pack_into(&self, out: &mut Vec<u8>)
+    {
+        let size31to0 = (self.size & 0xFFFFFFFF) as u32;
+        let addr31to0 = (self.addr & 0xFFFFFFFF) as u32;
+        let addr38to36 = ((self.addr >> 36) & 7) as u32;
+        let size35to32 = ((self.size >> 32) & 0xF) as u32;
+        let addr35to32 = ((self.addr >> 32) & 0xF) as u32;
+        
+        let word2 = (self.mode as u32) | (addr38to36 << 2) | (size35to32 << 24) | (addr35to32 << 28);
+        
+        hipc_push_u32(out, size31to0);
+        hipc_push_u32(out, addr31to0);
+        hipc_push_u32(out, word2);
+    }
+    
     pub const fn packed_size(&self) -> u64
     {
         12
@@ -802,39 +1297,208 @@ impl HIPCSendRecvExchDesc
     }
 }
 
+// Receive list (C) descriptor, where the kernel copies the server's X
+// descriptor data for this side
+#[derive(Copy, Clone)]
+pub struct HIPCRecvListDesc
+{
+    pub addr: u64,
+    pub size: u16
+}
+
+impl HIPCRecvListDesc
+{
+    pub fn new(addr: u64, size: u16) -> HIPCRecvListDesc
+    {
+        HIPCRecvListDesc
+        {
+            addr: addr,
+            size: size
+        }
+    }
+    
+    pub fn unpack<M: HIPCMem>(mem: &M, buf: u64) -> HIPCRecvListDesc
+    {
+        let word0 = mem.read32(buf);
+        let word1 = mem.read32(buf+4);
+        
+        let addr31to0 = word0 as u64;
+        let addr47to32 = (word1 & 0xFFFF) as u64;
+        let size = ((word1 >> 16) & 0xFFFF) as u16;
+        
+        HIPCRecvListDesc
+        {
+            addr: addr31to0 | (addr47to32 << 32),
+            size: size
+        }
+    }
+    
+    pub fn pack_into(&self, out: &mut Vec<u8>)
+    {
+        let addr31to0 = (self.addr & 0xFFFFFFFF) as u32;
+        let addr47to32 = ((self.addr >> 32) & 0xFFFF) as u32;
+        
+        hipc_push_u32(out, addr31to0);
+        hipc_push_u32(out, addr47to32 | ((self.size as u32) << 16));
+    }
+    
+    pub const fn packed_size(&self) -> u64
+    {
+        8
+    }
+    
+    pub fn read_u8(&self, offs: usize) -> u8
+    {
+        peek8(translate_el1_stage12(self.addr + (offs as u64)))
+    }
+    
+    pub fn read_u32(&self, offs: usize) -> u32
+    {
+        peek32(translate_el1_stage12(self.addr + (offs as u64)))
+    }
+    
+    pub fn read_u64(&self, offs: usize) -> u64
+    {
+        peek64(translate_el1_stage12(self.addr + (offs as u64)))
+    }
+    
+    pub fn write_u32(&self, offs: usize, val: u32)
+    {
+        if offs + 4 > self.size as usize {
+            return;
+        }
+        poke32(translate_el1_stage12(self.addr + (offs as u64)), val);
+    }
+    
+    pub fn write_u64(&self, offs: usize, val: u64)
+    {
+        if offs + 8 > self.size as usize {
+            return;
+        }
+        poke64(translate_el1_stage12(self.addr + (offs as u64)), val);
+    }
+    
+    pub fn read_str_at(&self, offs: u64) -> String
+    {
+        if (offs as u16) > self.size {
+            return String::from("");
+        }
+        return String::from(kstr_len!(self.addr + offs, self.size - offs as u16));
+    }
+    
+    pub fn put_str_at(&self, offs: u64, strval: String)
+    {
+        let bytes = strval.into_bytes();
+        if offs + (bytes.len() as u64) >= self.size as u64 {
+            return;
+        }
+        let addr_el2 = translate_el1_stage12(self.addr);
+        for i in 0..bytes.len()
+        {
+            poke8(addr_el2 + offs + (i as u64), bytes[i]);
+        }
+        poke8(addr_el2 + offs + bytes.len() as u64, 0);
+    }
+    
+    pub fn get_addr_el2(&self) -> u64
+    {
+        return translate_el1_stage12(self.addr);
+    }
+}
+
+// How the receive list is laid out, from the 4-bit field in word1
+#[derive(Copy, Clone, PartialEq)]
+pub enum HIPCRecvListMode
+{
+    None,
+    MessageBuffer, // X data lands inline in the message buffer
+    Single, // one C descriptor, shared by every X descriptor
+    Multiple(u8) // one C descriptor per X descriptor
+}
+
+impl HIPCRecvListMode
+{
+    pub fn from_flags(flags: u8) -> HIPCRecvListMode
+    {
+        match flags & 0xF
+        {
+            0 => HIPCRecvListMode::None,
+            1 => HIPCRecvListMode::MessageBuffer,
+            2 => HIPCRecvListMode::Single,
+            n => HIPCRecvListMode::Multiple(n - 2)
+        }
+    }
+    
+    pub fn to_flags(&self) -> u8
+    {
+        match self
+        {
+            HIPCRecvListMode::None => 0,
+            HIPCRecvListMode::MessageBuffer => 1,
+            HIPCRecvListMode::Single => 2,
+            HIPCRecvListMode::Multiple(n) => (*n).wrapping_add(2) & 0xF
+        }
+    }
+    
+    pub fn num_descs(&self) -> usize
+    {
+        match self
+        {
+            HIPCRecvListMode::None | HIPCRecvListMode::MessageBuffer => 0,
+            HIPCRecvListMode::Single => 1,
+            HIPCRecvListMode::Multiple(n) => *n as usize
+        }
+    }
+}
+
 pub struct HIPCPacket
 {
+    cmd_buf: u64,
+    is_tipc: bool,
+    
     pkt_type: u16,
-    num_static: u8,
-    num_send: u8,
-    num_recv: u8,
-    num_exch: u8,
-    data_size: u16,
     recv_static_flags: u8,
     unk1: u8,
-    unk2: u16,
-    enable_handle: bool,
+    recv_list_offs: u16,
     handle_desc: Option<HIPCHandleDesc>,
     
-    static_desc_start: u64,
-    send_desc_start: u64,
-    recv_desc_start: u64,
-    exch_desc_start: u64,
-    
-    /*static_descs: Vec<HIPCStaticDesc>,
+    static_descs: Vec<HIPCStaticDesc>,
     send_descs: Vec<HIPCSendRecvExchDesc>,
     recv_descs: Vec<HIPCSendRecvExchDesc>,
-    exch_descs: Vec<HIPCSendRecvExchDesc>,*/
+    exch_descs: Vec<HIPCSendRecvExchDesc>,
     
+    // Alignment padding in front of the payload and whatever trails it
+    // (pointer buffer sizes, out objects), kept so packing round-trips
+    data_pad: Vec<u8>,
     data_payload: HIPCPayload,
+    data_tail: Vec<u8>,
+    
+    recv_list: Vec<HIPCRecvListDesc>,
 }
 
 impl HIPCPacket
 {
     pub fn unpack(cmd_buf: u64) -> HIPCPacket
     {
-        let word0 = peek32(cmd_buf);
-        let word1 = peek32(cmd_buf + 4);
+        HIPCPacket::unpack_from(&HIPCMemTarget, cmd_buf)
+    }
+    
+    pub fn unpack_as(cmd_buf: u64, is_tipc: bool) -> HIPCPacket
+    {
+        HIPCPacket::unpack_from_as(&HIPCMemTarget, cmd_buf, is_tipc)
+    }
+    
+    pub fn unpack_from<M: HIPCMem>(mem: &M, cmd_buf: u64) -> HIPCPacket
+    {
+        // Responses don't say which protocol they are, see hipc_get_response
+        let pkt_type = (mem.read32(cmd_buf) & 0xFFFF) as u16;
+        HIPCPacket::unpack_from_as(mem, cmd_buf, pkt_type >= PKT_TYPE_TIPC_CLOSE)
+    }
+    
+    pub fn unpack_from_as<M: HIPCMem>(mem: &M, cmd_buf: u64, is_tipc: bool) -> HIPCPacket
+    {
+        let word0 = mem.read32(cmd_buf);
+        let word1 = mem.read32(cmd_buf + 4);
 
         let pkt_type = (word0 & 0xFFFF) as u16;
         let num_static = ((word0 >> 16) & 0xF) as u8;
@@ -844,8 +1508,8 @@ impl HIPCPacket
 
         let data_size = (word1 & 0x3FF) as u16;
         let recv_static_flags = ((word1 >> 10) & 0xF) as u8;
-        let unk1 = ((word1 >> 14) & 0x7F) as u8;
-        let unk2 = ((word1 >> 21) & 0x3FF) as u16;
+        let unk1 = ((word1 >> 14) & 0x3F) as u8;
+        let recv_list_offs = ((word1 >> 20) & 0x7FF) as u16;
         let enable_handle = ((word1 & bit!(31)) != 0);
         
         let mut read_ptr = cmd_buf + 8;
@@ -854,65 +1518,169 @@ impl HIPCPacket
         let mut handle_desc: Option<HIPCHandleDesc> = None;
         if enable_handle
         {
-            let unpack_handledesc: HIPCHandleDesc = HIPCHandleDesc::unpack(read_ptr);
+            let unpack_handledesc: HIPCHandleDesc = HIPCHandleDesc::unpack(mem, read_ptr);
             read_ptr += unpack_handledesc.packed_size();
             
             handle_desc = Some(unpack_handledesc);
         }
         
         // Unpack Static descriptors
-        let static_desc_start = read_ptr;
-        read_ptr += 8 * num_static as u64;
+        let mut static_descs: Vec<HIPCStaticDesc> = Vec::with_capacity(num_static as usize);
+        for i in 0..num_static
+        {
+            let desc = HIPCStaticDesc::unpack(mem, read_ptr);
+            read_ptr += desc.packed_size();
+            static_descs.push(desc);
+        }
         
         // Unpack Send descriptors
-        let send_desc_start = read_ptr;
-        read_ptr += 12 * num_send as u64;
+        let mut send_descs: Vec<HIPCSendRecvExchDesc> = Vec::with_capacity(num_send as usize);
+        for i in 0..num_send
+        {
+            let desc = HIPCSendRecvExchDesc::unpack(mem, read_ptr);
+            read_ptr += desc.packed_size();
+            send_descs.push(desc);
+        }
         
         // Unpack Recv descriptors
-        let recv_desc_start = read_ptr;
-        read_ptr += 12 * num_recv as u64;
+        let mut recv_descs: Vec<HIPCSendRecvExchDesc> = Vec::with_capacity(num_recv as usize);
+        for i in 0..num_recv
+        {
+            let desc = HIPCSendRecvExchDesc::unpack(mem, read_ptr);
+            read_ptr += desc.packed_size();
+            recv_descs.push(desc);
+        }
         
         // Unpack Exchange descriptors
-        let exch_desc_start = read_ptr;
-        read_ptr += 12 * num_exch as u64;
+        let mut exch_descs: Vec<HIPCSendRecvExchDesc> = Vec::with_capacity(num_exch as usize);
+        for i in 0..num_exch
+        {
+            let desc = HIPCSendRecvExchDesc::unpack(mem, read_ptr);
+            read_ptr += desc.packed_size();
+            exch_descs.push(desc);
+        }
+        
+        // Unpack data payload
+        let data_start = read_ptr;
+        let data_end = data_start + (data_size as u64) * 4;
+        let payload_start = ((data_start + 0xF) & !0xF); // align to 0x10
         
+        let mut data_pad: Vec<u8> = Vec::new();
+        let mut data_tail: Vec<u8> = Vec::new();
         let hipc_payload: HIPCPayload;
-
-        read_ptr = ((read_ptr + 0xF) & !0xF); // align to 0x10
-        let magic = peek32(read_ptr);
-        if magic == MAGIC_SFCI || magic == MAGIC_SFCO
+        
+        let magic = mem.read32(payload_start);
+        let is_session = (magic == MAGIC_SFCI || magic == MAGIC_SFCO);
+        let payload_min = if is_session { 0x10 } else { 0x20 };
+        if is_tipc
         {
-            let payload = HIPCDataPayload::unpack(read_ptr, data_size * 4);
-            read_ptr += payload.packed_size();
+            // TIPC has no alignment padding, SFCI header or domains
+            let mut cmd: Option<u32> = None;
+            if pkt_type >= PKT_TYPE_TIPC_CMD_BASE {
+                cmd = Some((pkt_type - PKT_TYPE_TIPC_CMD_BASE) as u32);
+            }
             
-            hipc_payload = HIPCPayload::Session(payload);
+            hipc_payload = HIPCPayload::Tipc(HIPCDataPayload::unpack_tipc(mem, data_start, (data_end - data_start) as u16, cmd));
         }
-        else
+        else if payload_start + payload_min <= data_end
         {
-            let payload = HIPCDomainPayload::unpack(read_ptr, data_size * 4);
-            read_ptr += payload.packed_size();
+            let payload_size = (data_end - payload_start) as u16;
+            let payload_end: u64;
+            
+            data_pad = hipc_peek_bytes(mem, data_start, (payload_start - data_start) as usize);
+            
+            if is_session
+            {
+                let payload = HIPCDataPayload::unpack(mem, payload_start, payload_size);
+                payload_end = payload_start + payload.packed_size();
+                
+                hipc_payload = HIPCPayload::Session(payload);
+            }
+            else
+            {
+                let payload = HIPCDomainPayload::unpack(mem, payload_start, payload_size);
+                payload_end = payload_start + payload.packed_size();
+                
+                hipc_payload = HIPCPayload::Domain(payload);
+            }
             
-            hipc_payload = HIPCPayload::Domain(payload);
+            data_tail = hipc_peek_bytes(mem, payload_end, (data_end - payload_end) as usize);
+        }
+        else
+        {
+            // Too small for a payload header (ie, session close)
+            data_tail = hipc_peek_bytes(mem, data_start, (data_end - data_start) as usize);
+            hipc_payload = HIPCPayload::None();
+        }
+        
+        // Unpack C descriptors (receive list)
+        let num_recv_list = HIPCRecvListMode::from_flags(recv_static_flags).num_descs();
+        
+        let mut recv_list_ptr = data_end;
+        if recv_list_offs != 0 {
+            recv_list_ptr = cmd_buf + (recv_list_offs as u64) * 4;
+        }
+        
+        let mut recv_list: Vec<HIPCRecvListDesc> = Vec::with_capacity(num_recv_list);
+        for i in 0..num_recv_list
+        {
+            let desc = HIPCRecvListDesc::unpack(mem, recv_list_ptr);
+            recv_list_ptr += desc.packed_size();
+            recv_list.push(desc);
         }
 
         HIPCPacket
         {
+            cmd_buf: cmd_buf,
+            is_tipc: is_tipc,
             pkt_type: pkt_type,
-            num_static: num_static,
-            num_send: num_send,
-            num_recv: num_recv,
-            num_exch: num_exch,
-            data_size: data_size,
             recv_static_flags: recv_static_flags,
             unk1: unk1,
-            unk2: unk2,
-            enable_handle: enable_handle,
+            recv_list_offs: recv_list_offs,
             handle_desc: handle_desc,
-            static_desc_start: static_desc_start,
-            send_desc_start: send_desc_start,
-            recv_desc_start: recv_desc_start,
-            exch_desc_start: exch_desc_start,
+            static_descs: static_descs,
+            send_descs: send_descs,
+            recv_descs: recv_descs,
+            exch_descs: exch_descs,
+            data_pad: data_pad,
             data_payload: hipc_payload,
+            data_tail: data_tail,
+            recv_list: recv_list,
+        }
+    }
+    
+    // Blank reply to `req`, written over the same TLS buffer once packed
+    pub fn new_response(req: &HIPCPacket, result: u32) -> HIPCPacket
+    {
+        HIPCPacket
+        {
+            cmd_buf: req.cmd_buf,
+            is_tipc: req.is_tipc,
+            pkt_type: if req.is_tipc { req.pkt_type } else { 0 },
+            recv_static_flags: 0,
+            unk1: 0,
+            recv_list_offs: 0,
+            handle_desc: None,
+            static_descs: Vec::new(),
+            send_descs: Vec::new(),
+            recv_descs: Vec::new(),
+            exch_descs: Vec::new(),
+            data_pad: Vec::new(),
+            data_payload: if req.is_tipc { HIPCPayload::Tipc(HIPCDataPayload::new_response(true, result)) } else { HIPCPayload::Session(HIPCDataPayload::new_response(false, result)) },
+            data_tail: Vec::new(),
+            recv_list: Vec::new(),
+        }
+    }
+    
+    // Responses keep their result where requests keep the command ID
+    pub fn set_result(&mut self, result: u32)
+    {
+        match &mut self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.set_cmd_id(result);
+            },
+            _ => {}
         }
     }
     
@@ -920,11 +1688,11 @@ impl HIPCPacket
     {
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
-                false
-            },
             HIPCPayload::Domain(domain) => {
                 true
+            },
+            _ => {
+                false
             }
         }
     }
@@ -933,11 +1701,14 @@ impl HIPCPacket
     {
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
                 session.get_cmd_id()
             },
             HIPCPayload::Domain(domain) => {
                 domain.get_cmd_id()
+            },
+            HIPCPayload::None() => {
+                0
             }
         }
     }
@@ -946,11 +1717,11 @@ impl HIPCPacket
     {
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
-                0
-            },
             HIPCPayload::Domain(domain) => {
                 domain.get_domain_cmd()
+            },
+            _ => {
+                0
             }
         }
     }
@@ -959,11 +1730,11 @@ impl HIPCPacket
     {
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
-                0
-            },
             HIPCPayload::Domain(domain) => {
                 domain.get_domain_id()
+            },
+            _ => {
+                0
             }
         }
     }
@@ -972,37 +1743,70 @@ impl HIPCPacket
     {
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
-                None
-            },
             HIPCPayload::Domain(domain) => {
                 domain.get_domain_obj(idx)
+            },
+            _ => {
+                None
             }
         }
     }
     
-    pub fn read_u8(&self, offs: usize) -> u8
+    pub fn push_domain_obj(&mut self, obj: u32) -> bool
     {
-        match &self.data_payload
+        match &mut self.data_payload
         {
-            HIPCPayload::Session(session) => {
-                session.read_u8(offs)
-            },
             HIPCPayload::Domain(domain) => {
-                domain.read_u8(offs)
+                domain.push_domain_obj(obj);
+                true
+            },
+            _ => {
+                false
             }
         }
     }
     
-    pub fn read_u16(&self, offs: usize) -> u16
+    pub fn remove_domain_obj(&mut self, idx: usize) -> Option<u32>
     {
-        match &self.data_payload
+        match &mut self.data_payload
         {
-            HIPCPayload::Session(session) => {
-                session.read_u16(offs)
+            HIPCPayload::Domain(domain) => {
+                domain.remove_domain_obj(idx)
+            },
+            _ => {
+                None
+            }
+        }
+    }
+    
+    pub fn read_u8(&self, offs: usize) -> u8
+    {
+        match &self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.read_u8(offs)
+            },
+            HIPCPayload::Domain(domain) => {
+                domain.read_u8(offs)
+            },
+            HIPCPayload::None() => {
+                0
+            }
+        }
+    }
+    
+    pub fn read_u16(&self, offs: usize) -> u16
+    {
+        match &self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.read_u16(offs)
             },
             HIPCPayload::Domain(domain) => {
                 domain.read_u16(offs)
+            },
+            HIPCPayload::None() => {
+                0
             }
         }
     }
@@ -1011,11 +1815,14 @@ impl HIPCPacket
     {
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
                 session.read_u32(offs)
             },
             HIPCPayload::Domain(domain) => {
                 domain.read_u32(offs)
+            },
+            HIPCPayload::None() => {
+                0
             }
         }
     }
@@ -1024,11 +1831,14 @@ impl HIPCPacket
     {
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
                 session.read_u64(offs)
             },
             HIPCPayload::Domain(domain) => {
                 domain.read_u64(offs)
+            },
+            HIPCPayload::None() => {
+                0
             }
         }
     }
@@ -1037,28 +1847,104 @@ impl HIPCPacket
     {
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
                 session.read_str(offs)
             },
             HIPCPayload::Domain(domain) => {
                 domain.read_str(offs)
+            },
+            HIPCPayload::None() => {
+                String::from("")
             }
         }
     }
     
-    pub fn write_u32(&self, offs: usize, val: u32)
+    pub fn write_u8(&mut self, offs: usize, val: u8)
     {
-        match &self.data_payload
+        match &mut self.data_payload
         {
-            HIPCPayload::Session(session) => {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.write_u8(offs, val);
+            },
+            HIPCPayload::Domain(domain) => {
+                domain.write_u8(offs, val);
+            },
+            HIPCPayload::None() => {}
+        }
+    }
+    
+    pub fn write_u16(&mut self, offs: usize, val: u16)
+    {
+        match &mut self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.write_u16(offs, val);
+            },
+            HIPCPayload::Domain(domain) => {
+                domain.write_u16(offs, val);
+            },
+            HIPCPayload::None() => {}
+        }
+    }
+    
+    pub fn write_u32(&mut self, offs: usize, val: u32)
+    {
+        match &mut self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
                 session.write_u32(offs, val);
             },
             HIPCPayload::Domain(domain) => {
                 domain.write_u32(offs, val);
+            },
+            HIPCPayload::None() => {}
+        }
+    }
+    
+    pub fn write_u64(&mut self, offs: usize, val: u64)
+    {
+        match &mut self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.write_u64(offs, val);
+            },
+            HIPCPayload::Domain(domain) => {
+                domain.write_u64(offs, val);
+            },
+            HIPCPayload::None() => {}
+        }
+    }
+    
+    pub fn get_data_len(&self) -> usize
+    {
+        match &self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.get_data_len()
+            },
+            HIPCPayload::Domain(domain) => {
+                domain.get_data_len()
+            },
+            HIPCPayload::None() => {
+                0
             }
         }
     }
     
+    pub fn set_data_len(&mut self, len: usize)
+    {
+        match &mut self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.set_data_len(len);
+            },
+            HIPCPayload::Domain(domain) => {
+                domain.set_data_len(len);
+            },
+            HIPCPayload::None() => {}
+        }
+    }
+    
     pub fn get_handle(&self, idx: usize) -> Option<u32>
     {
         if let Some(desc) = &self.handle_desc {
@@ -1067,36 +1953,129 @@ impl HIPCPacket
         return None;
     }
     
-    pub fn get_static(&self, idx: usize) -> Option<HIPCStaticDesc>
+    pub fn set_handle(&mut self, idx: usize, handle: u32) -> bool
     {
-        if idx < self.num_static as usize {
-            return Some(HIPCStaticDesc::unpack(self.static_desc_start + (idx*8) as u64));
+        if let Some(desc) = &mut self.handle_desc {
+            return desc.set_handle(idx, handle);
         }
-        return None;
+        return false;
     }
     
-    pub fn get_send(&self, idx: usize) -> Option<HIPCSendRecvExchDesc>
+    pub fn get_num_handles(&self) -> usize
     {
-        if idx < self.num_send as usize {
-            return Some(HIPCSendRecvExchDesc::unpack(self.send_desc_start + (idx*12) as u64));
+        if let Some(desc) = &self.handle_desc {
+            return desc.copy_handles.len() + desc.move_handles.len();
+        }
+        return 0;
+    }
+    
+    pub fn push_copy_handle(&mut self, handle: u32)
+    {
+        self.handle_desc.get_or_insert_with(HIPCHandleDesc::new).copy_handles.push(handle);
+    }
+    
+    pub fn push_move_handle(&mut self, handle: u32)
+    {
+        self.handle_desc.get_or_insert_with(HIPCHandleDesc::new).move_handles.push(handle);
+    }
+    
+    pub fn remove_handle(&mut self, idx: usize) -> Option<u32>
+    {
+        if let Some(desc) = &mut self.handle_desc {
+            return desc.remove_handle(idx);
         }
         return None;
     }
     
-    pub fn get_recv(&self, idx: usize) -> Option<HIPCSendRecvExchDesc>
+    pub fn get_pid(&self) -> Option<u64>
     {
-        if idx < self.num_recv as usize {
-            return Some(HIPCSendRecvExchDesc::unpack(self.recv_desc_start + (idx*12) as u64));
+        if let Some(desc) = &self.handle_desc {
+            if desc.send_pid {
+                return Some(desc.pid);
+            }
         }
         return None;
     }
     
+    pub fn set_pid(&mut self, pid: Option<u64>)
+    {
+        let desc = self.handle_desc.get_or_insert_with(HIPCHandleDesc::new);
+        desc.send_pid = pid.is_some();
+        desc.pid = pid.unwrap_or(0);
+    }
+    
+    pub fn get_static(&self, idx: usize) -> Option<HIPCStaticDesc>
+    {
+        self.static_descs.get(idx).copied()
+    }
+    
+    pub fn get_send(&self, idx: usize) -> Option<HIPCSendRecvExchDesc>
+    {
+        self.send_descs.get(idx).copied()
+    }
+    
+    pub fn get_recv(&self, idx: usize) -> Option<HIPCSendRecvExchDesc>
+    {
+        self.recv_descs.get(idx).copied()
+    }
+    
     pub fn get_exch(&self, idx: usize) -> Option<HIPCSendRecvExchDesc>
     {
-        if idx < self.num_exch as usize {
-            return Some(HIPCSendRecvExchDesc::unpack(self.exch_desc_start + (idx*12) as u64));
+        self.exch_descs.get(idx).copied()
+    }
+    
+    pub fn get_recv_list_mode(&self) -> HIPCRecvListMode
+    {
+        HIPCRecvListMode::from_flags(self.recv_static_flags)
+    }
+    
+    // Keeps the descriptor count in line with the new mode
+    pub fn set_recv_list_mode(&mut self, mode: HIPCRecvListMode)
+    {
+        self.recv_static_flags = mode.to_flags();
+        self.recv_list.resize(mode.num_descs(), HIPCRecvListDesc::new(0, 0));
+    }
+    
+    pub fn get_recv_list(&self, idx: usize) -> Option<HIPCRecvListDesc>
+    {
+        self.recv_list.get(idx).copied()
+    }
+    
+    pub fn get_recv_list_mut(&mut self) -> &mut Vec<HIPCRecvListDesc>
+    {
+        &mut self.recv_list
+    }
+    
+    // Buffer the `idx`th out pointer of a request gets written to, which is
+    // the same one for every pointer in single mode
+    pub fn get_out_pointer(&self, idx: usize) -> Option<HIPCRecvListDesc>
+    {
+        match self.get_recv_list_mode()
+        {
+            HIPCRecvListMode::Single => self.get_recv_list(0),
+            HIPCRecvListMode::Multiple(_) => self.get_recv_list(idx),
+            _ => None
         }
-        return None;
+    }
+    
+    pub fn get_statics_mut(&mut self) -> &mut Vec<HIPCStaticDesc>
+    {
+        &mut self.static_descs
+    }
+    
+    pub fn get_sends_mut(&mut self) -> &mut Vec<HIPCSendRecvExchDesc>
+    {
+        &mut self.send_descs
+    }
+    
+    pub fn get_recvs_mut(&mut self) -> &mut Vec<HIPCSendRecvExchDesc>
+    {
+        &mut self.recv_descs
+    }
+    
+    pub fn get_exchs_mut(&mut self) -> &mut Vec<HIPCSendRecvExchDesc>
+    {
+        &mut self.exch_descs
     }
     
     pub fn hook_first_handle(&self, session_handle: u32, handler: HClientSessionHandler) -> bool
@@ -1171,39 +2150,394 @@ impl HIPCPacket
         self.pkt_type
     }
     
-    pub fn pack(&self)
+    pub fn is_tipc(&self) -> bool
     {
+        self.is_tipc
+    }
+    
+    pub fn is_request(&self) -> bool
+    {
+        if self.is_tipc {
+            return self.pkt_type >= PKT_TYPE_TIPC_CMD_BASE;
+        }
         
+        match self.pkt_type
+        {
+            PKT_TYPE_LEGACYREQEST | PKT_TYPE_REQUEST | PKT_TYPE_REQUESTWITHCONTEXT => true,
+            _ => false
+        }
+    }
+    
+    pub fn is_control(&self) -> bool
+    {
+        if self.is_tipc {
+            return false;
+        }
+        
+        match self.pkt_type
+        {
+            PKT_TYPE_LEGACYCONTROL | PKT_TYPE_CONTROL | PKT_TYPE_CONTROLWITHCONTEXT => true,
+            _ => false
+        }
+    }
+    
+    pub fn is_close(&self) -> bool
+    {
+        if self.is_tipc {
+            return self.pkt_type == PKT_TYPE_TIPC_CLOSE;
+        }
+        return self.pkt_type == PKT_TYPE_CLOSE;
+    }
+    
+    pub fn set_type(&mut self, pkt_type: u16)
+    {
+        self.pkt_type = pkt_type;
+    }
+    
+    pub fn get_cmd_buf(&self) -> u64
+    {
+        self.cmd_buf
+    }
+    
+    pub fn pack_bytes(&self) -> Option<Vec<u8>>
+    {
+        if self.static_descs.len() > 0xF || self.send_descs.len() > 0xF
+           || self.recv_descs.len() > 0xF || self.exch_descs.len() > 0xF {
+            return None;
+        }
+        
+        let mut out: Vec<u8> = Vec::with_capacity(HIPC_MAX_SIZE);
+        
+        // Header gets filled in once sizes are known
+        hipc_push_u32(&mut out, 0);
+        hipc_push_u32(&mut out, 0);
+        
+        if let Some(desc) = &self.handle_desc
+        {
+            if desc.copy_handles.len() > 0xF || desc.move_handles.len() > 0xF {
+                return None;
+            }
+            desc.pack_into(&mut out);
+        }
+        
+        for desc in &self.static_descs
+        {
+            desc.pack_into(&mut out);
+        }
+        
+        for desc in &self.send_descs
+        {
+            desc.pack_into(&mut out);
+        }
+        
+        for desc in &self.recv_descs
+        {
+            desc.pack_into(&mut out);
+        }
+        
+        for desc in &self.exch_descs
+        {
+            desc.pack_into(&mut out);
+        }
+        
+        // Pack data payload, keeping the original padding bytes where possible
+        let data_start = out.len();
+        match &self.data_payload
+        {
+            HIPCPayload::None() | HIPCPayload::Tipc(_) => {},
+            _ => {
+                let pad_len = ((data_start + 0xF) & !0xF) - data_start;
+                for i in 0..pad_len
+                {
+                    out.push(if i < self.data_pad.len() { self.data_pad[i] } else { 0 });
+                }
+            }
+        }
+        
+        match &self.data_payload
+        {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
+                session.pack_into(&mut out);
+            },
+            HIPCPayload::Domain(domain) => {
+                domain.pack_into(&mut out);
+            },
+            HIPCPayload::None() => {}
+        }
+        
+        out.extend_from_slice(&self.data_tail);
+        while (out.len() - data_start) % 4 != 0
+        {
+            out.push(0);
+        }
+        
+        let data_size = (out.len() - data_start) / 4;
+        if data_size > 0x3FF {
+            return None;
+        }
+        
+        // Pack C descriptors (receive list)
+        let mut recv_list_offs = self.recv_list_offs as usize;
+        if recv_list_offs != 0
+        {
+            if recv_list_offs * 4 < out.len() {
+                recv_list_offs = out.len() / 4;
+            }
+            out.resize(recv_list_offs * 4, 0);
+        }
+        
+        for desc in &self.recv_list
+        {
+            desc.pack_into(&mut out);
+        }
+        
+        if out.len() > HIPC_MAX_SIZE || recv_list_offs > 0x7FF {
+            return None;
+        }
+        
+        let word0 = (self.pkt_type as u32)
+                    | ((self.static_descs.len() as u32) << 16)
+                    | ((self.send_descs.len() as u32) << 20)
+                    | ((self.recv_descs.len() as u32) << 24)
+                    | ((self.exch_descs.len() as u32) << 28);
+        let word1 = (data_size as u32)
+                    | (((self.recv_static_flags & 0xF) as u32) << 10)
+                    | (((self.unk1 & 0x3F) as u32) << 14)
+                    | ((recv_list_offs as u32) << 20)
+                    | (if self.handle_desc.is_some() { bit!(31) } else { 0 });
+        
+        out[0..4].copy_from_slice(&word0.to_le_bytes());
+        out[4..8].copy_from_slice(&word1.to_le_bytes());
+        
+        return Some(out);
+    }
+    
+    pub fn pack(&self) -> bool
+    {
+        self.pack_to(self.cmd_buf)
+    }
+    
+    pub fn pack_to(&self, cmd_buf: u64) -> bool
+    {
+        self.pack_with(&mut HIPCMemTarget, cmd_buf)
+    }
+    
+    pub fn pack_with<M: HIPCMem>(&self, mem: &mut M, cmd_buf: u64) -> bool
+    {
+        if let Some(bytes) = self.pack_bytes()
+        {
+            for i in (0..bytes.len()).step_by(4)
+            {
+                let word = u32::from_le_bytes([bytes[i], bytes[i+1], bytes[i+2], bytes[i+3]]);
+                mem.write32(cmd_buf + i as u64, word);
+            }
+            return true;
+        }
+        
+        println_core!("HIPCPacket: packed message too large, not written!");
+        return false;
     }
     
     pub fn print(&self)
     {
         println!("HIPCPacket:");
-        println!("  Type: {}", self.pkt_type);
-        println!("  Num Static: {}", self.num_static);
-        println!("  Num Send: {}", self.num_send);
-        println!("  Num Recv: {}", self.num_recv);
-        println!("  Data Size: {}", self.data_size);
+        println!("  Type: {}{}", self.pkt_type, if self.is_tipc { " (TIPC)" } else { "" });
+        println!("  Num Static: {}", self.static_descs.len());
+        println!("  Num Send: {}", self.send_descs.len());
+        println!("  Num Recv: {}", self.recv_descs.len());
+        println!("  Num Exch: {}", self.exch_descs.len());
         println!("  RecvStatic Flags: {}", self.recv_static_flags);
-        println!("  Enable Handle: {}", self.enable_handle);
+        println!("  Enable Handle: {}", self.handle_desc.is_some());
 
         if let Some(desc) = &self.handle_desc {
             desc.print();
         }
+        
+        for (i, desc) in self.static_descs.iter().enumerate()
+        {
+            println!("  X[{}]: {:x} size {:x} idx {}", i, desc.addr, desc.size, desc.index);
+        }
+        for (label, descs) in [("A", &self.send_descs), ("B", &self.recv_descs), ("W", &self.exch_descs)].iter()
+        {
+            for (i, desc) in descs.iter().enumerate()
+            {
+                println!("  {}[{}]: {:x} size {:x} {:?}", label, i, desc.addr, desc.size, desc.mode);
+            }
+        }
+        for (i, desc) in self.recv_list.iter().enumerate()
+        {
+            println!("  C[{}]: {:x} size {:x}", i, desc.addr, desc.size);
+        }
 
         match &self.data_payload
         {
-            HIPCPayload::Session(session) => {
+            HIPCPayload::Session(session) | HIPCPayload::Tipc(session) => {
                 session.print();
             },
             HIPCPayload::Domain(domain) => {
                 domain.print();
-            }
+            },
+            HIPCPayload::None() => {}
         }
     }
 }
 
 pub fn hipc_get_packet() -> HIPCPacket
 {
-    HIPCPacket::unpack(translate_el1_stage12(get_tls_el0()))
+    HIPCPacket::unpack(translate_el1_stage12(vsvc_get_tls()))
+}
+
+// Response types are 0 for both CMIF and TIPC, so use the request's protocol
+pub fn hipc_get_response(req: &HIPCPacket) -> HIPCPacket
+{
+    HIPCPacket::unpack_as(translate_el1_stage12(vsvc_get_tls()), req.is_tipc())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::hos::hipcmem::HIPCMemSlice;
+
+    const TLS: u64 = 0x1000;
+
+    fn words_to_bytes(words: &[u32]) -> Vec<u8>
+    {
+        let mut out: Vec<u8> = Vec::new();
+        for word in words
+        {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        return out;
+    }
+
+    fn round_trip(words: &[u32]) -> (HIPCPacket, Vec<u8>)
+    {
+        let mut buf = words_to_bytes(words);
+        let pkt = HIPCPacket::unpack_from(&HIPCMemSlice::new(TLS, &mut buf), TLS);
+        let packed = pkt.pack_bytes().expect("packet should fit");
+        assert_eq!(packed, words_to_bytes(words));
+        return (pkt, packed);
+    }
+
+    // Request with a PID, a copy handle, X/A descriptors and one C
+    // descriptor after the data. Stale bytes in the alignment padding
+    const CMIF_REQUEST: &[u32] = &[
+        0x00110004, 0x80000808,                         // type 4, 1 X, 1 A, 8 data words, single C, handles
+        0x00000003, 0x00000000, 0x00000000, 0x0000C0DE, // PID placeholder, copy handle
+        0x00203001, 0x45678000,                         // X[0] index 1, size 0x20, addr 0x345678000
+        0x00000100, 0x12340000, 0x00000000,             // A[0] size 0x100, addr 0x12340000
+        0xDEADBEEF,                                     // padding
+        MAGIC_SFCI, 0x00000001, 0x00000011, 0x00000000, // SFCI v1, cmd 17
+        0x89ABCDEF, 0x01234567, 0x00000020,             // u64 arg, X[0] pointer buffer size
+        0x12345000, 0x00200000,                         // C[0] addr 0x12345000, size 0x20
+    ];
+
+    // Response with a move handle and a u32 out value
+    const CMIF_RESPONSE: &[u32] = &[
+        0x00000000, 0x80000008,
+        0x00000020, 0x00230104,                         // 1 move handle
+        MAGIC_SFCO, 0x00000000, 0x00000000, 0x00000000,
+        0x00000010, 0x00000000, 0x00000000, 0x00000000,
+    ];
+
+    // Domain message to object 0xF001 with an input object and a
+    // pointer buffer size trailing the objects
+    const CMIF_DOMAIN: &[u32] = &[
+        0x00000004, 0x00000010,
+        0xAAAAAAAA, 0xBBBBBBBB,                         // padding
+        0x00180101, 0x0000F001, 0x00000000, 0x00000000, // send message, 1 object, 0x18 data bytes
+        MAGIC_SFCI, 0x00000000, 0x00000010, 0x00000000,
+        0x00000001, 0x00000000,
+        0x0000F002,                                     // input object
+        0x00000040, 0x00000000, 0x00000000,
+    ];
+
+    #[test]
+    fn round_trip_request()
+    {
+        let (pkt, packed) = round_trip(CMIF_REQUEST);
+
+        assert_eq!(pkt.get_pid(), Some(0));
+        assert_eq!(pkt.get_cmd_id(), 17);
+        assert_eq!(pkt.read_u64(0), 0x0123456789ABCDEF);
+        assert_eq!(pkt.data_pad, vec![0xEF, 0xBE, 0xAD, 0xDE]);
+        assert!(pkt.data_tail.is_empty());
+
+        let desc = pkt.get_static(0).unwrap();
+        assert_eq!((desc.index, desc.addr, desc.size), (1, 0x345678000, 0x20));
+        assert_eq!(&packed[0x1C..0x20], &0x45678000u32.to_le_bytes());
+
+        let recv = pkt.get_recv_list(0).unwrap();
+        assert_eq!((recv.addr, recv.size), (0x12345000, 0x20));
+    }
+
+    #[test]
+    fn round_trip_response()
+    {
+        let (pkt, _) = round_trip(CMIF_RESPONSE);
+
+        assert_eq!(pkt.get_type(), PKT_TYPE_INVALID);
+        assert_eq!(pkt.get_handle(0), Some(0x00230104));
+        assert_eq!(pkt.read_u32(0), 0x10);
+        assert!(pkt.data_pad.is_empty());
+    }
+
+    #[test]
+    fn round_trip_domain()
+    {
+        let (pkt, _) = round_trip(CMIF_DOMAIN);
+
+        assert!(pkt.is_domain());
+        assert_eq!(pkt.get_domain_id(), 0xF001);
+        assert_eq!(pkt.get_domain_obj(0), Some(0xF002));
+        assert_eq!(pkt.get_cmd_id(), 0x10);
+        assert_eq!(pkt.data_pad, vec![0xAA, 0xAA, 0xAA, 0xAA, 0xBB, 0xBB, 0xBB, 0xBB]);
+        assert_eq!(pkt.data_tail, words_to_bytes(&[0x40, 0, 0]));
+    }
+
+    #[test]
+    fn edited_data_keeps_padding()
+    {
+        let mut buf = words_to_bytes(CMIF_REQUEST);
+        let mut pkt = HIPCPacket::unpack_from(&HIPCMemSlice::new(TLS, &mut buf), TLS);
+        pkt.write_u64(0, 0x1122334455667788);
+
+        let packed = pkt.pack_bytes().unwrap();
+        assert_eq!(&packed[0x2C..0x30], &0xDEADBEEFu32.to_le_bytes());
+        assert_eq!(&packed[0x40..0x48], &0x1122334455667788u64.to_le_bytes());
+        assert_eq!(packed.len(), CMIF_REQUEST.len() * 4);
+    }
+
+    #[test]
+    fn pack_with_writes_handles()
+    {
+        let mut buf = words_to_bytes(CMIF_RESPONSE);
+        let mut pkt = HIPCPacket::unpack_from(&HIPCMemSlice::new(TLS, &mut buf), TLS);
+        pkt.push_copy_handle(0x00120034);
+
+        let mut out = vec![0xFFu8; 0x100];
+        assert!(pkt.pack_with(&mut HIPCMemSlice::new(TLS, &mut out), TLS));
+
+        let expected = pkt.pack_bytes().unwrap();
+        assert_eq!(&out[..expected.len()], &expected[..]);
+        assert_eq!(&out[0x08..0x14], &words_to_bytes(&[0x00000022, 0x00120034, 0x00230104])[..]);
+        assert_eq!(out[expected.len()], 0xFF);
+    }
+
+    #[test]
+    fn pack_with_rejects_too_many_handles()
+    {
+        let mut buf = words_to_bytes(CMIF_RESPONSE);
+        let mut pkt = HIPCPacket::unpack_from(&HIPCMemSlice::new(TLS, &mut buf), TLS);
+        for i in 0..0x10
+        {
+            pkt.push_copy_handle(i);
+        }
+        assert!(pkt.pack_bytes().is_none());
+
+        let mut out = vec![0xFFu8; 0x100];
+        assert!(!pkt.pack_with(&mut HIPCMemSlice::new(TLS, &mut out), TLS));
+        assert!(out.iter().all(|b| *b == 0xFF));
+    }
 }
@@ -0,0 +1,396 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use super::hipc::HIPCPacket;
+
+// Pseudo-handle for the current thread, always valid to copy
+const HIPCFUZZ_PSEUDO_HANDLE: u32 = 0xFFFF8001;
+
+// The mutation engine only touches the packet and the PRNG, so a seed
+// reproduces the exact same mutations on a captured packet
+pub struct HIPCFuzzRng
+{
+    state: u64
+}
+
+impl HIPCFuzzRng
+{
+    pub const fn new(seed: u64) -> HIPCFuzzRng
+    {
+        // xorshift gets stuck on 0
+        HIPCFuzzRng { state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed } }
+    }
+
+    // xorshift64*
+    pub fn next_u64(&mut self) -> u64
+    {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545F4914F6CDD1D);
+    }
+
+    pub fn next_u32(&mut self) -> u32
+    {
+        (self.next_u64() >> 32) as u32
+    }
+
+    pub fn next_below(&mut self, max: u64) -> u64
+    {
+        if max == 0 {
+            return 0;
+        }
+        return self.next_u64() % max;
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum HIPCMutationKind
+{
+    RawWord = 0,
+    StaticSize = 1,
+    SendSize = 2,
+    RecvSize = 3,
+    ExchSize = 4,
+    HandleAdd = 5,
+    HandleRemove = 6,
+    DataLen = 7
+}
+
+#[derive(Copy, Clone)]
+pub struct HIPCMutation
+{
+    pub kind: HIPCMutationKind,
+    pub index: u16, // raw data offset or descriptor index
+    pub old: u64,
+    pub new: u64
+}
+
+impl HIPCMutationKind
+{
+    pub fn from_u8(val: u8) -> Option<HIPCMutationKind>
+    {
+        match val
+        {
+            0 => Some(HIPCMutationKind::RawWord),
+            1 => Some(HIPCMutationKind::StaticSize),
+            2 => Some(HIPCMutationKind::SendSize),
+            3 => Some(HIPCMutationKind::RecvSize),
+            4 => Some(HIPCMutationKind::ExchSize),
+            5 => Some(HIPCMutationKind::HandleAdd),
+            6 => Some(HIPCMutationKind::HandleRemove),
+            7 => Some(HIPCMutationKind::DataLen),
+            _ => None
+        }
+    }
+}
+
+impl HIPCMutation
+{
+    pub fn describe(&self) -> String
+    {
+        match self.kind
+        {
+            HIPCMutationKind::RawWord => format!("raw[{:#x}] {:#x} -> {:#x}", self.index, self.old, self.new),
+            HIPCMutationKind::StaticSize => format!("X[{}].size {:#x} -> {:#x}", self.index, self.old, self.new),
+            HIPCMutationKind::SendSize => format!("A[{}].size {:#x} -> {:#x}", self.index, self.old, self.new),
+            HIPCMutationKind::RecvSize => format!("B[{}].size {:#x} -> {:#x}", self.index, self.old, self.new),
+            HIPCMutationKind::ExchSize => format!("W[{}].size {:#x} -> {:#x}", self.index, self.old, self.new),
+            HIPCMutationKind::HandleAdd => format!("handles {} -> {} (+{:x})", self.old, self.old + 1, self.new),
+            HIPCMutationKind::HandleRemove => format!("handles {} -> {} (-{:x})", self.old, self.old - 1, self.new),
+            HIPCMutationKind::DataLen => format!("raw size {:#x} -> {:#x}", self.old, self.new),
+        }
+    }
+
+    // kind u8, index u16, old u64, new u64, little endian
+    pub fn serialize_into(&self, out: &mut Vec<u8>)
+    {
+        out.push(self.kind as u8);
+        out.extend_from_slice(&self.index.to_le_bytes());
+        out.extend_from_slice(&self.old.to_le_bytes());
+        out.extend_from_slice(&self.new.to_le_bytes());
+    }
+}
+
+fn hipcfuzz_interesting(rng: &mut HIPCFuzzRng, old: u64, max: u64) -> u64
+{
+    let val = match rng.next_below(10)
+    {
+        0 => 0,
+        1 => 1,
+        2 => max,
+        3 => max >> 1,
+        4 => (max >> 1) + 1,
+        5 => old.wrapping_add(1),
+        6 => old.wrapping_sub(1),
+        7 => old ^ (1 << rng.next_below(64)),
+        8 => old.wrapping_mul(2).wrapping_add(0x1000),
+        _ => rng.next_u64()
+    };
+    return val & max;
+}
+
+fn hipcfuzz_mutate_raw(pkt: &mut HIPCPacket, rng: &mut HIPCFuzzRng) -> Option<HIPCMutation>
+{
+    let len = pkt.get_data_len();
+    if len < 4 {
+        return None;
+    }
+
+    let offs = (rng.next_below((len / 4) as u64) * 4) as usize;
+    let old = pkt.read_u32(offs);
+    let new = hipcfuzz_interesting(rng, old as u64, 0xFFFFFFFF) as u32;
+    pkt.write_u32(offs, new);
+
+    return Some(HIPCMutation { kind: HIPCMutationKind::RawWord, index: offs as u16, old: old as u64, new: new as u64 });
+}
+
+fn hipcfuzz_mutate_buffer(pkt: &mut HIPCPacket, rng: &mut HIPCFuzzRng) -> Option<HIPCMutation>
+{
+    let num_static = pkt.get_statics_mut().len();
+    let num_send = pkt.get_sends_mut().len();
+    let num_recv = pkt.get_recvs_mut().len();
+    let num_exch = pkt.get_exchs_mut().len();
+    let total = num_static + num_send + num_recv + num_exch;
+    if total == 0 {
+        return None;
+    }
+
+    let mut idx = rng.next_below(total as u64) as usize;
+    if idx < num_static
+    {
+        let desc = &mut pkt.get_statics_mut()[idx];
+        let old = desc.size as u64;
+        desc.size = hipcfuzz_interesting(rng, old, 0xFFFF) as u16;
+        return Some(HIPCMutation { kind: HIPCMutationKind::StaticSize, index: idx as u16, old: old, new: desc.size as u64 });
+    }
+    idx -= num_static;
+
+    let (kind, descs) = if idx < num_send {
+        (HIPCMutationKind::SendSize, pkt.get_sends_mut())
+    }
+    else if idx < num_send + num_recv {
+        idx -= num_send;
+        (HIPCMutationKind::RecvSize, pkt.get_recvs_mut())
+    }
+    else {
+        idx -= num_send + num_recv;
+        (HIPCMutationKind::ExchSize, pkt.get_exchs_mut())
+    };
+
+    // Sizes are 36 bits in the descriptor
+    let desc = &mut descs[idx];
+    let old = desc.size;
+    desc.size = hipcfuzz_interesting(rng, old, 0xFFFFFFFFF);
+    return Some(HIPCMutation { kind: kind, index: idx as u16, old: old, new: desc.size });
+}
+
+fn hipcfuzz_mutate_handles(pkt: &mut HIPCPacket, rng: &mut HIPCFuzzRng) -> Option<HIPCMutation>
+{
+    let num_handles = pkt.get_num_handles();
+    if num_handles > 0 && rng.next_below(2) == 0
+    {
+        let idx = rng.next_below(num_handles as u64) as usize;
+        let handle = pkt.remove_handle(idx).unwrap_or(0);
+        return Some(HIPCMutation { kind: HIPCMutationKind::HandleRemove, index: idx as u16, old: num_handles as u64, new: handle as u64 });
+    }
+
+    if num_handles >= 8 {
+        return None;
+    }
+
+    // Either a duplicate of an existing handle or the thread pseudo-handle
+    let mut handle = HIPCFUZZ_PSEUDO_HANDLE;
+    if num_handles > 0 && rng.next_below(2) == 0 {
+        handle = pkt.get_handle(rng.next_below(num_handles as u64) as usize).unwrap_or(HIPCFUZZ_PSEUDO_HANDLE);
+    }
+    pkt.push_copy_handle(handle);
+
+    return Some(HIPCMutation { kind: HIPCMutationKind::HandleAdd, index: num_handles as u16, old: num_handles as u64, new: handle as u64 });
+}
+
+fn hipcfuzz_mutate_len(pkt: &mut HIPCPacket, rng: &mut HIPCFuzzRng) -> Option<HIPCMutation>
+{
+    let old = pkt.get_data_len();
+    let delta = ((rng.next_below(4) + 1) * 4) as usize;
+
+    let new = if old >= delta && rng.next_below(2) == 0 { old - delta } else { old + delta };
+    pkt.set_data_len(new);
+
+    return Some(HIPCMutation { kind: HIPCMutationKind::DataLen, index: 0, old: old as u64, new: new as u64 });
+}
+
+// Applies 1..=max_mutations mutations, falling back to raw data when the
+// chosen kind doesn't apply to this packet
+pub fn hipcfuzz_mutate(pkt: &mut HIPCPacket, rng: &mut HIPCFuzzRng, max_mutations: u32) -> Vec<HIPCMutation>
+{
+    let mut mutations: Vec<HIPCMutation> = Vec::new();
+    let count = rng.next_below(max_mutations.max(1) as u64) + 1;
+
+    for _ in 0..count
+    {
+        let mutation = match rng.next_below(10)
+        {
+            0..=5 => hipcfuzz_mutate_raw(pkt, rng),
+            6 | 7 => hipcfuzz_mutate_buffer(pkt, rng),
+            8 => hipcfuzz_mutate_handles(pkt, rng),
+            _ => hipcfuzz_mutate_len(pkt, rng)
+        };
+
+        let mutation = match mutation {
+            Some(m) => Some(m),
+            None => hipcfuzz_mutate_raw(pkt, rng).or_else(|| hipcfuzz_mutate_len(pkt, rng))
+        };
+
+        if let Some(m) = mutation {
+            mutations.push(m);
+        }
+    }
+
+    return mutations;
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::hos::hipc::MAGIC_SFCI;
+    use crate::hos::hipcmem::HIPCMemSlice;
+
+    const TLS: u64 = 0x1000;
+
+    // PID, one copy handle, X/A descriptors, a C descriptor and stale
+    // alignment padding
+    const REQUEST: &[u32] = &[
+        0x00110004, 0x8000080A,
+        0x00000003, 0x00000000, 0x00000000, 0x0000C0DE,
+        0x00203001, 0x45678000,
+        0x00000100, 0x12340000, 0x00000001,
+        0xDEADBEEF,
+        MAGIC_SFCI, 0x00000001, 0x00000011, 0x0000CAFE,
+        0x89ABCDEF, 0x01234567, 0x00000020, 0x00000000, 0x00000000,
+        0x12345000, 0x00200000,
+    ];
+
+    fn unpack_request() -> HIPCPacket
+    {
+        let mut buf: Vec<u8> = REQUEST.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+        return HIPCPacket::unpack_from(&HIPCMemSlice::new(TLS, &mut buf), TLS);
+    }
+
+    fn fuzz(seed: u64, max_mutations: u32) -> (HIPCPacket, Vec<HIPCMutation>)
+    {
+        let mut pkt = unpack_request();
+        let mut rng = HIPCFuzzRng::new(seed);
+        let mutations = hipcfuzz_mutate(&mut pkt, &mut rng, max_mutations);
+        return (pkt, mutations);
+    }
+
+    fn serialize(mutations: &[HIPCMutation]) -> Vec<u8>
+    {
+        let mut out: Vec<u8> = Vec::new();
+        for m in mutations
+        {
+            m.serialize_into(&mut out);
+        }
+        return out;
+    }
+
+    #[test]
+    fn same_seed_same_mutations()
+    {
+        for seed in 0..64
+        {
+            let (pkt_a, muts_a) = fuzz(seed, 4);
+            let (pkt_b, muts_b) = fuzz(seed, 4);
+            assert_eq!(serialize(&muts_a), serialize(&muts_b));
+            assert_eq!(pkt_a.pack_bytes(), pkt_b.pack_bytes());
+        }
+
+        let runs: Vec<Vec<u8>> = (1..9).map(|seed| serialize(&fuzz(seed, 4).1)).collect();
+        assert!(runs.iter().any(|run| *run != runs[0]));
+    }
+
+    #[test]
+    fn zero_seed_isnt_stuck()
+    {
+        let mut rng = HIPCFuzzRng::new(0);
+        let first = rng.next_u64();
+        assert_ne!(first, 0);
+        assert_ne!(first, rng.next_u64());
+    }
+
+    #[test]
+    fn mutations_stay_in_bounds()
+    {
+        let orig_len = unpack_request().get_data_len();
+        for seed in 1..512
+        {
+            let (pkt, mutations) = fuzz(seed, 4);
+            assert!(!mutations.is_empty() && mutations.len() <= 4);
+
+            let mut len = orig_len;
+            let mut handles = 1;
+            for m in &mutations
+            {
+                match m.kind
+                {
+                    HIPCMutationKind::RawWord => {
+                        assert!((m.index as usize) + 4 <= len && m.index % 4 == 0, "seed {}: {}", seed, m.describe());
+                        assert!(m.new <= 0xFFFFFFFF);
+                    },
+                    HIPCMutationKind::StaticSize => assert!(m.index < 1 && m.new <= 0xFFFF),
+                    HIPCMutationKind::SendSize => assert!(m.index < 1 && m.new <= 0xFFFFFFFFF),
+                    HIPCMutationKind::RecvSize | HIPCMutationKind::ExchSize => panic!("seed {}: no B/W descriptors to mutate", seed),
+                    HIPCMutationKind::HandleAdd => { handles += 1; assert!(handles <= 8); },
+                    HIPCMutationKind::HandleRemove => { assert!(handles > 0); handles -= 1; },
+                    HIPCMutationKind::DataLen => {
+                        assert_eq!(m.old as usize, len);
+                        assert_eq!((m.new as i64 - m.old as i64).abs() % 4, 0);
+                        len = m.new as usize;
+                    }
+                }
+            }
+
+            assert_eq!(pkt.get_data_len(), len);
+            assert_eq!(pkt.get_num_handles(), handles);
+
+            // Still a message the kernel would accept the shape of
+            let packed = pkt.pack_bytes().expect("mutated packet should still pack");
+            assert_eq!(packed.len() % 4, 0);
+        }
+    }
+
+    #[test]
+    fn skipped_fields_untouched()
+    {
+        for seed in 1..512
+        {
+            let (pkt, _) = fuzz(seed, 8);
+            let packed = pkt.pack_bytes().unwrap();
+
+            // CMIF header and PID are never fuzzed. Handles come before the
+            // descriptors, so adding or removing one moves the payload
+            assert_eq!(pkt.get_cmd_id(), 0x11);
+            assert_eq!(pkt.get_pid(), Some(0));
+            let desc_end = 0x28 + pkt.get_num_handles() * 4;
+            let payload = (desc_end + 0xF) & !0xF;
+            assert_eq!(&packed[payload..payload+0x10], &[MAGIC_SFCI.to_le_bytes(), 1u32.to_le_bytes(), 0x11u32.to_le_bytes(), 0xCAFEu32.to_le_bytes()].concat()[..]);
+
+            // Only sizes change on buffers, never where they point
+            let x0 = pkt.get_static(0).unwrap();
+            let a0 = pkt.get_send(0).unwrap();
+            assert_eq!((x0.index, x0.addr), (1, 0x345678000));
+            assert_eq!((a0.addr, a0.mode as u8), (0x12340000, 1));
+
+            let c0 = pkt.get_recv_list(0).unwrap();
+            assert_eq!((c0.addr, c0.size), (0x12345000, 0x20));
+        }
+    }
+}
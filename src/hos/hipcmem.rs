@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use crate::util::*;
+
+// Everything the HIPC parsers touch goes through this, so command buffers
+// can be decoded from guest TLS or from a captured copy
+pub trait HIPCMem
+{
+    fn read8(&self, addr: u64) -> u8;
+    fn write8(&mut self, addr: u64, val: u8);
+
+    fn read32(&self, addr: u64) -> u32
+    {
+        (self.read8(addr) as u32)
+        | ((self.read8(addr + 1) as u32) << 8)
+        | ((self.read8(addr + 2) as u32) << 16)
+        | ((self.read8(addr + 3) as u32) << 24)
+    }
+
+    fn read64(&self, addr: u64) -> u64
+    {
+        (self.read32(addr) as u64) | ((self.read32(addr + 4) as u64) << 32)
+    }
+
+    fn write32(&mut self, addr: u64, val: u32)
+    {
+        let bytes = val.to_le_bytes();
+        for i in 0..4
+        {
+            self.write8(addr + i as u64, bytes[i]);
+        }
+    }
+}
+
+// EL2 addresses, ie translated TLS
+pub struct HIPCMemTarget;
+
+impl HIPCMem for HIPCMemTarget
+{
+    fn read8(&self, addr: u64) -> u8
+    {
+        peek8(addr)
+    }
+
+    fn write8(&mut self, addr: u64, val: u8)
+    {
+        poke8(addr, val);
+    }
+
+    fn read32(&self, addr: u64) -> u32
+    {
+        peek32(addr)
+    }
+
+    fn read64(&self, addr: u64) -> u64
+    {
+        peek64(addr)
+    }
+
+    fn write32(&mut self, addr: u64, val: u32)
+    {
+        poke32(addr, val);
+    }
+}
+
+// Byte buffer mapped at `base`, reads outside of it return 0 and writes
+// are dropped so truncated captures can't take down the parser
+pub struct HIPCMemSlice<'a>
+{
+    base: u64,
+    buf: &'a mut [u8]
+}
+
+impl<'a> HIPCMemSlice<'a>
+{
+    pub fn new(base: u64, buf: &'a mut [u8]) -> HIPCMemSlice<'a>
+    {
+        HIPCMemSlice
+        {
+            base: base,
+            buf: buf
+        }
+    }
+
+    fn offset(&self, addr: u64) -> Option<usize>
+    {
+        if addr < self.base {
+            return None;
+        }
+
+        let offs = (addr - self.base) as usize;
+        if offs >= self.buf.len() {
+            return None;
+        }
+        return Some(offs);
+    }
+
+    pub fn get_base(&self) -> u64
+    {
+        self.base
+    }
+
+    pub fn as_slice(&self) -> &[u8]
+    {
+        self.buf
+    }
+}
+
+impl<'a> HIPCMem for HIPCMemSlice<'a>
+{
+    fn read8(&self, addr: u64) -> u8
+    {
+        if let Some(offs) = self.offset(addr) {
+            return self.buf[offs];
+        }
+        return 0;
+    }
+
+    fn write8(&mut self, addr: u64, val: u8)
+    {
+        if let Some(offs) = self.offset(addr) {
+            self.buf[offs] = val;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use alloc::vec::Vec;
+    use crate::hos::hipc::{HIPCPacket, MAGIC_SFCI};
+
+    // TLS lives at the top of the thread's TLS region, not at 0
+    const TLS: u64 = 0x3A00_1000;
+
+    fn words_to_bytes(words: &[u32]) -> Vec<u8>
+    {
+        words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
+    }
+
+    // Request with two X descriptors, an A and a B
+    const REQUEST: &[u32] = &[
+        0x01120004, 0x00000006,
+        0x00101000, 0x10000000,                         // X[0] index 0, size 0x10
+        0x00200001, 0x10000100,                         // X[1] index 1, size 0x20
+        0x00000300, 0x20000000, 0x00000001,             // A[0] size 0x300, mode 1
+        0x00000400, 0x30000000, 0x00000000,             // B[0] size 0x400, ends 0x10 aligned
+        MAGIC_SFCI, 0x00000000, 0x00000008, 0x00000000,
+        0x00000003, 0x00000000,
+    ];
+
+    // Domain close for object 0xF003
+    const DOMAIN_CLOSE: &[u32] = &[
+        0x00000004, 0x0000000A,
+        0x00000000, 0x00000000,
+        0x00000002, 0x0000F003, 0x00000000, 0x00000000,
+        0x00000000, 0x00000000, 0x00000000, 0x00000000,
+    ];
+
+    #[test]
+    fn slice_reads_are_little_endian()
+    {
+        let mut buf = words_to_bytes(&[0x04030201, 0x08070605]);
+        let mem = HIPCMemSlice::new(TLS, &mut buf);
+
+        assert_eq!(mem.read8(TLS + 1), 0x02);
+        assert_eq!(mem.read32(TLS), 0x04030201);
+        assert_eq!(mem.read64(TLS), 0x0807060504030201);
+    }
+
+    #[test]
+    fn slice_out_of_bounds()
+    {
+        let mut buf = words_to_bytes(&[0xFFFFFFFF, 0xFFFFFFFF]);
+        let mut mem = HIPCMemSlice::new(TLS, &mut buf);
+
+        assert_eq!(mem.read8(TLS - 1), 0);
+        assert_eq!(mem.read8(TLS + 8), 0);
+        assert_eq!(mem.read32(TLS + 6), 0x0000FFFF); // straddles the end
+        assert_eq!(mem.read64(TLS + 0x100), 0);
+
+        mem.write32(TLS + 6, 0x12345678);
+        mem.write8(TLS - 1, 0x12);
+        assert_eq!(mem.as_slice(), &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x78, 0x56]);
+    }
+
+    #[test]
+    fn slice_descriptor_offsets()
+    {
+        let mut buf = words_to_bytes(REQUEST);
+        let pkt = HIPCPacket::unpack_from(&HIPCMemSlice::new(TLS, &mut buf), TLS);
+
+        let x0 = pkt.get_static(0).unwrap();
+        let x1 = pkt.get_static(1).unwrap();
+        assert_eq!((x0.index, x0.addr, x0.size), (0, 0x110000000, 0x10));
+        assert_eq!((x1.index, x1.addr, x1.size), (1, 0x10000100, 0x20));
+
+        let a0 = pkt.get_send(0).unwrap();
+        let b0 = pkt.get_recv(0).unwrap();
+        assert_eq!((a0.addr, a0.size, a0.mode as u8), (0x20000000, 0x300, 1));
+        assert_eq!((b0.addr, b0.size), (0x30000000, 0x400));
+
+        // No padding needed, the descriptors end 0x10 aligned
+        assert_eq!(pkt.get_cmd_id(), 8);
+        assert_eq!(pkt.read_u32(0), 3);
+    }
+
+    #[test]
+    fn slice_domain_header()
+    {
+        let mut buf = words_to_bytes(DOMAIN_CLOSE);
+        let pkt = HIPCPacket::unpack_from(&HIPCMemSlice::new(TLS, &mut buf), TLS);
+
+        assert!(pkt.is_domain());
+        assert_eq!(pkt.get_domain_cmd(), 2);
+        assert_eq!(pkt.get_domain_id(), 0xF003);
+        assert_eq!(pkt.get_domain_obj(0), None);
+    }
+
+    #[test]
+    fn slice_truncated_capture()
+    {
+        // Header claims more descriptors and data than were captured
+        let mut buf = words_to_bytes(&REQUEST[..6]);
+        let pkt = HIPCPacket::unpack_from(&HIPCMemSlice::new(TLS, &mut buf), TLS);
+
+        assert_eq!(pkt.get_static(1).unwrap().addr, 0x10000100);
+        assert_eq!(pkt.get_send(0).unwrap().size, 0);
+        assert_eq!(pkt.get_cmd_id(), 0);
+    }
+}
@@ -0,0 +1,91 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// Bucket n holds latencies below 2^n ns, which tops out around 2 seconds
+pub const HIPCSTAT_NUM_BUCKETS: usize = 32;
+
+#[derive(Copy, Clone)]
+pub struct HIPCLatencyStats
+{
+    pub requests: u64,
+    pub errors: u64,
+    pub min_ns: u64,
+    pub max_ns: u64,
+    pub total_ns: u64,
+    buckets: [u32; HIPCSTAT_NUM_BUCKETS]
+}
+
+impl HIPCLatencyStats
+{
+    pub const fn new() -> HIPCLatencyStats
+    {
+        HIPCLatencyStats
+        {
+            requests: 0,
+            errors: 0,
+            min_ns: u64::MAX,
+            max_ns: 0,
+            total_ns: 0,
+            buckets: [0; HIPCSTAT_NUM_BUCKETS]
+        }
+    }
+
+    pub fn record(&mut self, latency_ns: u64, is_error: bool)
+    {
+        self.requests += 1;
+        if is_error {
+            self.errors += 1;
+        }
+
+        self.min_ns = self.min_ns.min(latency_ns);
+        self.max_ns = self.max_ns.max(latency_ns);
+        self.total_ns += latency_ns;
+
+        let bucket = (64 - latency_ns.leading_zeros()) as usize;
+        self.buckets[bucket.min(HIPCSTAT_NUM_BUCKETS - 1)] += 1;
+    }
+
+    pub fn merge(&mut self, other: &HIPCLatencyStats)
+    {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.min_ns = self.min_ns.min(other.min_ns);
+        self.max_ns = self.max_ns.max(other.max_ns);
+        self.total_ns += other.total_ns;
+
+        for i in 0..HIPCSTAT_NUM_BUCKETS
+        {
+            self.buckets[i] += other.buckets[i];
+        }
+    }
+
+    pub fn get_min_ns(&self) -> u64
+    {
+        if self.requests == 0 { 0 } else { self.min_ns }
+    }
+
+    pub fn get_avg_ns(&self) -> u64
+    {
+        if self.requests == 0 { 0 } else { self.total_ns / self.requests }
+    }
+
+    // Only as precise as the buckets, so this is the upper bound of the
+    // bucket the 99th percentile falls into
+    pub fn get_p99_ns(&self) -> u64
+    {
+        let threshold = (self.requests * 99 + 99) / 100;
+        let mut seen = 0;
+
+        for i in 0..HIPCSTAT_NUM_BUCKETS
+        {
+            seen += self.buckets[i] as u64;
+            if seen >= threshold && seen != 0 {
+                return ((1u64 << i) - 1).min(self.max_ns);
+            }
+        }
+        return self.max_ns;
+    }
+}
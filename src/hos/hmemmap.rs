@@ -0,0 +1,476 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use crate::logger::*;
+use crate::util::*;
+use crate::vm::vmmu::ipaddr_to_paddr;
+use crate::vm::vsvc::{vsvc_get_pid_ttbr, vsvc_get_pid_name};
+
+pub const HMEM_PERM_R: u32 = (1 << 0);
+pub const HMEM_PERM_W: u32 = (1 << 1);
+pub const HMEM_PERM_X: u32 = (1 << 2);
+pub const HMEM_PERM_RW: u32 = HMEM_PERM_R | HMEM_PERM_W;
+
+const PT_ENTRY_ADDR_MASK: u64 = 0x00007FFFFFFFF000;
+const PT_ENTRY_TYPE_MASK: u64 = 0x3;
+const PT_ENTRY_TYPE_TABLE: u64 = 0x3; // a page at level 3
+const PT_ENTRY_AP_SHIFT: u64 = 6;
+const PT_ENTRY_UXN: u64 = (1 << 54);
+
+const TCR_T0SZ_MASK: u64 = 0x3F;
+
+// Names follow the kernel's MemoryState where there's one to follow
+#[derive(Copy, Clone, PartialEq)]
+pub enum HMemState
+{
+    Normal,
+    Stack, // MapMemory destination
+    Code,
+    AliasCode,
+    Shared,
+    Transfered,
+    CodeOut,
+    GeneratedCode,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum HMemOrigin
+{
+    Heap,
+    MapMemory(u64), // source address
+    SharedMemory(u32, Option<u32>), // handle, owner pid
+    TransferMemory(u32, Option<u32>),
+    ProcessCode(u64),
+    CodeMemory(u32, Option<u32>),
+}
+
+#[derive(Copy, Clone)]
+pub struct HMemRegion
+{
+    pub addr: u64,
+    pub size: u64,
+    pub perm: u32,
+    pub state: HMemState,
+    pub origin: HMemOrigin,
+
+    // Set on a source region while something else holds it (MapMemory,
+    // transfer memory, code memory), with what it was before
+    pub lent: Option<(u32, HMemState)>,
+}
+
+static mut HMEMMAP_REGIONS: BTreeMap<u32, BTreeMap<u64, HMemRegion>> = BTreeMap::new();
+static mut HMEMMAP_HEAP: BTreeMap<u32, (u64, u64)> = BTreeMap::new();
+
+// Creator of shared/transfer/code memory handles, by (pid, handle)
+static mut HMEMMAP_OWNERS: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+
+// Source ranges of transfer/code memory, given back when the handle closes
+static mut HMEMMAP_LENT: BTreeMap<(u32, u32), (u64, u64)> = BTreeMap::new();
+
+pub fn hmem_perm_str(perm: u32) -> String
+{
+    format!("{}{}{}", if (perm & HMEM_PERM_R) != 0 { 'r' } else { '-' },
+                      if (perm & HMEM_PERM_W) != 0 { 'w' } else { '-' },
+                      if (perm & HMEM_PERM_X) != 0 { 'x' } else { '-' })
+}
+
+impl HMemState
+{
+    pub fn to_str(&self) -> &'static str
+    {
+        match self {
+            HMemState::Normal => "Normal",
+            HMemState::Stack => "Stack",
+            HMemState::Code => "Code",
+            HMemState::AliasCode => "AliasCode",
+            HMemState::Shared => "Shared",
+            HMemState::Transfered => "Transfered",
+            HMemState::CodeOut => "CodeOut",
+            HMemState::GeneratedCode => "GeneratedCode",
+        }
+    }
+}
+
+impl HMemOrigin
+{
+    pub fn to_string(&self) -> String
+    {
+        let owner_str = |owner: &Option<u32>| -> String {
+            match owner {
+                Some(pid) => format!("`{}`", vsvc_get_pid_name(*pid)),
+                None => String::from("unknown owner")
+            }
+        };
+
+        match self {
+            HMemOrigin::Heap => String::from("heap"),
+            HMemOrigin::MapMemory(src) => format!("alias of {:x}", src),
+            HMemOrigin::SharedMemory(handle, owner) => format!("shared memory {:x} from {}", handle, owner_str(owner)),
+            HMemOrigin::TransferMemory(handle, owner) => format!("transfer memory {:x} from {}", handle, owner_str(owner)),
+            HMemOrigin::ProcessCode(src) => format!("code from {:x}", src),
+            HMemOrigin::CodeMemory(handle, owner) => format!("code memory {:x} from {}", handle, owner_str(owner)),
+        }
+    }
+}
+
+impl HMemRegion
+{
+    pub fn new(addr: u64, size: u64, perm: u32, state: HMemState, origin: HMemOrigin) -> Self
+    {
+        HMemRegion
+        {
+            addr: addr,
+            size: size,
+            perm: perm,
+            state: state,
+            origin: origin,
+            lent: None,
+        }
+    }
+
+    pub fn end(&self) -> u64
+    {
+        self.addr + self.size
+    }
+
+    fn can_merge(&self, next: &HMemRegion) -> bool
+    {
+        self.end() == next.addr && self.perm == next.perm && self.state == next.state
+        && self.origin == next.origin && self.lent == next.lent
+    }
+}
+
+// Makes sure no region straddles `at`
+fn hmemmap_split(map: &mut BTreeMap<u64, HMemRegion>, at: u64)
+{
+    let mut tail = match map.range_mut(..at).next_back() {
+        Some((_, region)) if region.end() > at => {
+            let tail = *region;
+            region.size = at - region.addr;
+            tail
+        },
+        _ => return
+    };
+
+    tail.size = tail.end() - at;
+    tail.addr = at;
+    map.insert(at, tail);
+}
+
+fn hmemmap_get_map(pid: u32) -> &'static mut BTreeMap<u64, HMemRegion>
+{
+    unsafe { HMEMMAP_REGIONS.entry(pid).or_insert(BTreeMap::new()) }
+}
+
+pub fn hmemmap_unmap(pid: u32, addr: u64, size: u64)
+{
+    let map = hmemmap_get_map(pid);
+    hmemmap_split(map, addr);
+    hmemmap_split(map, addr + size);
+
+    let keys: Vec<u64> = map.range(addr..addr+size).map(|(key, _)| *key).collect();
+    for key in keys
+    {
+        map.remove(&key);
+    }
+}
+
+pub fn hmemmap_map(pid: u32, region: HMemRegion)
+{
+    hmemmap_unmap(pid, region.addr, region.size);
+    hmemmap_get_map(pid).insert(region.addr, region);
+}
+
+// Applies `f` to the tracked parts of [addr, addr+size)
+pub fn hmemmap_update<F>(pid: u32, addr: u64, size: u64, f: F) where F: Fn(&mut HMemRegion)
+{
+    let map = hmemmap_get_map(pid);
+    hmemmap_split(map, addr);
+    hmemmap_split(map, addr + size);
+
+    for (_, region) in map.range_mut(addr..addr+size)
+    {
+        f(region);
+    }
+}
+
+pub fn hmemmap_set_perm(pid: u32, addr: u64, size: u64, perm: u32)
+{
+    hmemmap_update(pid, addr, size, |region| region.perm = perm);
+}
+
+pub fn hmemmap_lend(pid: u32, addr: u64, size: u64, perm: u32, state: Option<HMemState>)
+{
+    hmemmap_update(pid, addr, size, |region| {
+        if region.lent.is_none() {
+            region.lent = Some((region.perm, region.state));
+        }
+        region.perm = perm;
+        if let Some(state) = state {
+            region.state = state;
+        }
+    });
+}
+
+pub fn hmemmap_unlend(pid: u32, addr: u64, size: u64)
+{
+    hmemmap_update(pid, addr, size, |region| {
+        if let Some((perm, state)) = region.lent {
+            region.perm = perm;
+            region.state = state;
+        }
+        region.lent = None;
+    });
+}
+
+// Transfer and code memory handles, the source comes back once they close
+pub fn hmemmap_lend_handle(pid: u32, handle: u32, addr: u64, size: u64, perm: u32, state: Option<HMemState>)
+{
+    hmemmap_lend(pid, addr, size, perm, state);
+    hmemmap_set_owner(pid, handle, pid);
+    unsafe
+    {
+        HMEMMAP_LENT.insert((pid, handle), (addr, size));
+    }
+}
+
+pub fn hmemmap_set_heap(pid: u32, addr: u64, size: u64)
+{
+    unsafe
+    {
+        if let Some((old_addr, old_size)) = HMEMMAP_HEAP.get(&pid).cloned() {
+            hmemmap_unmap(pid, old_addr, old_size);
+        }
+        HMEMMAP_HEAP.insert(pid, (addr, size));
+    }
+
+    if size != 0 {
+        hmemmap_map(pid, HMemRegion::new(addr, size, HMEM_PERM_RW, HMemState::Normal, HMemOrigin::Heap));
+    }
+}
+
+pub fn hmemmap_set_owner(pid: u32, handle: u32, owner_pid: u32)
+{
+    unsafe
+    {
+        HMEMMAP_OWNERS.insert((pid, handle), owner_pid);
+    }
+}
+
+pub fn hmemmap_get_owner(pid: u32, handle: u32) -> Option<u32>
+{
+    unsafe { HMEMMAP_OWNERS.get(&(pid, handle)).cloned() }
+}
+
+pub fn hmemmap_close_handle(pid: u32, handle: u32)
+{
+    unsafe
+    {
+        HMEMMAP_OWNERS.remove(&(pid, handle));
+        if let Some((addr, size)) = HMEMMAP_LENT.remove(&(pid, handle)) {
+            hmemmap_unlend(pid, addr, size);
+        }
+    }
+}
+
+pub fn hmemmap_process_exited(pid: u32)
+{
+    unsafe
+    {
+        HMEMMAP_REGIONS.remove(&pid);
+        HMEMMAP_HEAP.remove(&pid);
+        HMEMMAP_OWNERS.retain(|key, _| key.0 != pid);
+        HMEMMAP_LENT.retain(|key, _| key.0 != pid);
+    }
+}
+
+// Size of the address space TTBR0 covers, from the guest's TCR_EL1. Only
+// the 4K granule layouts that start walking at level 1 are handled, which
+// covers the 32-, 36- and 39-bit address spaces
+fn hmemmap_va_bits() -> Option<u64>
+{
+    let va_bits = 64 - (sysreg_read!("tcr_el1") & TCR_T0SZ_MASK);
+    if va_bits < 31 || va_bits > 39 {
+        return None;
+    }
+    return Some(va_bits);
+}
+
+fn hmemmap_walk_level(table: u64, vaddr_base: u64, level: u64, entries: u64, out: &mut Vec<(u64, u64, u32)>)
+{
+    let granularity: u64 = 1 << (39 - (level * 9));
+
+    for i in 0..entries
+    {
+        let val = peek64(table + (i*8));
+        if (val & 1) == 0 {
+            continue;
+        }
+
+        let vaddr = vaddr_base + (i * granularity);
+        let val_addr = ipaddr_to_paddr(val & PT_ENTRY_ADDR_MASK);
+        let is_table = (val & PT_ENTRY_TYPE_MASK) == PT_ENTRY_TYPE_TABLE;
+        if is_table && level < 3 {
+            hmemmap_walk_level(val_addr, vaddr, level + 1, 0x1000/8, out);
+            continue;
+        }
+
+        // 0b01 is a block at levels 1 and 2, but reserved at level 3
+        if !is_table && level == 3 {
+            continue;
+        }
+
+        // Only EL0's view matters here
+        let mut perm = match (val >> PT_ENTRY_AP_SHIFT) & 3 {
+            1 => HMEM_PERM_RW,
+            3 => HMEM_PERM_R,
+            _ => 0
+        };
+        if perm != 0 && (val & PT_ENTRY_UXN) == 0 {
+            perm |= HMEM_PERM_X;
+        }
+
+        match out.last_mut() {
+            Some(last) if last.0 + last.1 == vaddr && last.2 == perm => last.1 += granularity,
+            _ => out.push((vaddr, granularity, perm))
+        };
+    }
+}
+
+// Merged (addr, size, EL0 perm) runs from the process's stage 1 tables
+pub fn hmemmap_walk_pt(pid: u32) -> Option<Vec<(u64, u64, u32)>>
+{
+    let ttbr = vsvc_get_pid_ttbr(pid);
+    if ttbr == 0 {
+        return None;
+    }
+
+    // Level 1 entries cover 1GiB each
+    let va_bits = hmemmap_va_bits()?;
+    let mut out: Vec<(u64, u64, u32)> = Vec::new();
+    hmemmap_walk_level(ipaddr_to_paddr(ttbr & PT_ENTRY_ADDR_MASK), 0, 1, 1 << (va_bits - 30), &mut out);
+    return Some(out);
+}
+
+// Physical address backing `vaddr` in `pid`, same walk as above
+pub fn hmemmap_translate(pid: u32, vaddr: u64) -> Option<u64>
+{
+    let ttbr = vsvc_get_pid_ttbr(pid);
+    let va_bits = hmemmap_va_bits()?;
+    if ttbr == 0 || (vaddr >> va_bits) != 0 {
+        return None;
+    }
+
+    let mut table = ipaddr_to_paddr(ttbr & PT_ENTRY_ADDR_MASK);
+    for level in 1..4
+    {
+        let shift = 39 - (level * 9);
+        let val = peek64(table + (((vaddr >> shift) & 0x1FF) * 8));
+        if (val & 1) == 0 {
+            return None;
+        }
+
+        let is_table = (val & PT_ENTRY_TYPE_MASK) == PT_ENTRY_TYPE_TABLE;
+        if is_table && level < 3 {
+            table = ipaddr_to_paddr(val & PT_ENTRY_ADDR_MASK);
+            continue;
+        }
+        if !is_table && level == 3 {
+            return None;
+        }
+        return Some(ipaddr_to_paddr((val & PT_ENTRY_ADDR_MASK) + (vaddr & ((1 << shift) - 1))));
+    }
+    return None;
+}
+
+// First page table disagreement inside a tracked region, if any
+fn hmemmap_check_region(region: &HMemRegion, pt: &Vec<(u64, u64, u32)>) -> Option<String>
+{
+    let mut addr = region.addr;
+    for (pt_addr, pt_size, pt_perm) in pt.iter()
+    {
+        if pt_addr + pt_size <= addr {
+            continue;
+        }
+        if *pt_addr >= region.end() {
+            break;
+        }
+        if *pt_addr > addr {
+            return Some(format!("{:x} not mapped", addr));
+        }
+        if *pt_perm != region.perm {
+            return Some(format!("{:x} is {}", core::cmp::max(*pt_addr, region.addr), hmem_perm_str(*pt_perm)));
+        }
+        addr = pt_addr + pt_size;
+        if addr >= region.end() {
+            return None;
+        }
+    }
+    return Some(format!("{:x} not mapped", addr));
+}
+
+pub fn hmemmap_print(pid: u32)
+{
+    let mut regions: Vec<HMemRegion> = Vec::new();
+    for region in hmemmap_get_map(pid).values()
+    {
+        match regions.last_mut() {
+            Some(last) if last.can_merge(region) => last.size += region.size,
+            _ => regions.push(*region)
+        };
+    }
+
+    let pt = hmemmap_walk_pt(pid);
+    if pt.is_none() {
+        println!("(no TTBR seen for pid {} yet, not checking page tables)", pid);
+    }
+
+    println!("PID {} ({}) memory map:", pid, vsvc_get_pid_name(pid));
+    println!("  {:16} {:16} {:4} {:13} {}", "start", "end", "perm", "state", "origin");
+
+    // Page table runs nobody told us about get printed in between
+    let mut pt_idx = 0;
+    let mut print_untracked = |until: u64, from: &mut u64| {
+        if let Some(pt) = &pt
+        {
+            while pt_idx < pt.len() && pt[pt_idx].0 < until
+            {
+                let (pt_addr, pt_size, pt_perm) = pt[pt_idx];
+                let start = core::cmp::max(pt_addr, *from);
+                let end = core::cmp::min(pt_addr + pt_size, until);
+                if start < end {
+                    println!("  {:016x} {:016x} {:4} {:13} untracked", start, end, hmem_perm_str(pt_perm), "?");
+                }
+                if pt_addr + pt_size > until {
+                    break;
+                }
+                pt_idx += 1;
+            }
+        }
+    };
+
+    let mut cursor = 0;
+    for region in regions.iter()
+    {
+        print_untracked(region.addr, &mut cursor);
+        cursor = region.end();
+
+        print!("  {:016x} {:016x} {:4} {:13} {}", region.addr, region.end(), hmem_perm_str(region.perm), region.state.to_str(), region.origin.to_string());
+        if let Some((perm, state)) = region.lent {
+            print!(" (lent out, was {} {})", hmem_perm_str(perm), state.to_str());
+        }
+        if let Some(pt) = &pt {
+            if let Some(mismatch) = hmemmap_check_region(region, pt) {
+                print!(" !! page tables: {}", mismatch);
+            }
+        }
+        println!("");
+    }
+    print_untracked(u64::MAX, &mut cursor);
+}
@@ -0,0 +1,374 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// Rules for rewriting memory sizes the guest asks for or gets told about,
+// so titles fit in what's left after the hypervisor's carve-out. Only needs
+// alloc, so it builds on the host as well
+
+use alloc::string::String;
+use super::hrules::{HRule, HRuleList, hrules_parse_u32, hrules_parse_u64, hrules_parse_options};
+
+// Heap sizes have to stay a multiple of this or svcSetHeapSize fails
+pub const MEMPOLICY_HEAP_ALIGN: u64 = 0x200000;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum MemPolicyKind
+{
+    Limit, // svcSetResourceLimitLimitValue, PhysicalMemory
+    TotalMem, // svcGetInfo/svcGetSystemInfo total memory sizes
+    Heap, // svcSetHeapSize
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum MemPolicyOp
+{
+    Set(u64),
+    Add(u64),
+    Sub(u64),
+    Cap(u64),
+}
+
+#[derive(Clone)]
+pub struct MemPolicyRule
+{
+    pub id: u32,
+    pub kind: MemPolicyKind,
+    pub op: MemPolicyOp,
+    pub process: Option<String>, // None matches everything
+    pub program_id: Option<u64>,
+    pub above: Option<u64>, // only touch values at least this big
+    pub pool: Option<u32>, // svcGetSystemInfo pool, None for any
+
+    pub hits: u64,
+}
+
+// Who made the SVC
+pub struct MemPolicyTarget<'a>
+{
+    pub process: &'a str,
+    pub program_id: u64,
+}
+
+pub struct MemPolicyEngine
+{
+    pub rules: HRuleList<MemPolicyRule>,
+}
+
+impl MemPolicyKind
+{
+    pub fn parse(val: &str) -> Option<MemPolicyKind>
+    {
+        match val {
+            "limit" => Some(MemPolicyKind::Limit),
+            "totalmem" => Some(MemPolicyKind::TotalMem),
+            "heap" => Some(MemPolicyKind::Heap),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            MemPolicyKind::Limit => "limit",
+            MemPolicyKind::TotalMem => "totalmem",
+            MemPolicyKind::Heap => "heap",
+        }
+    }
+}
+
+impl MemPolicyOp
+{
+    pub fn apply(&self, val: u64) -> u64
+    {
+        match *self {
+            MemPolicyOp::Set(new_val) => new_val,
+            MemPolicyOp::Add(delta) => val.saturating_add(delta),
+            MemPolicyOp::Sub(delta) => val.saturating_sub(delta),
+            MemPolicyOp::Cap(max) => core::cmp::min(val, max),
+        }
+    }
+
+    pub fn describe(&self) -> String
+    {
+        match *self {
+            MemPolicyOp::Set(val) => format!("set {:#x}", val),
+            MemPolicyOp::Add(val) => format!("add {:#x}", val),
+            MemPolicyOp::Sub(val) => format!("sub {:#x}", val),
+            MemPolicyOp::Cap(val) => format!("cap {:#x}", val),
+        }
+    }
+}
+
+impl MemPolicyRule
+{
+    // <name/program id/*> <limit/totalmem/heap> <set/add/sub/cap> <value> [above <value>] [pool <n>]
+    pub fn parse(args: &[String]) -> Result<MemPolicyRule, String>
+    {
+        if args.len() < 4 {
+            return Err(String::from("need a process, a kind, an operation and a value"));
+        }
+
+        let (process, program_id) = match args[0].as_str() {
+            "*" => (None, None),
+            // Program IDs are always written out in full, so `0x1` could still be a name
+            selector if selector.len() == 18 && selector.starts_with("0x") => (None, Some(hrules_parse_u64(selector)?)),
+            selector => (Some(String::from(selector)), None)
+        };
+
+        let kind = match MemPolicyKind::parse(&args[1]) {
+            Some(kind) => kind,
+            None => return Err(format!("unknown kind `{}`", args[1]))
+        };
+
+        let val = hrules_parse_u64(&args[3])?;
+        let op = match args[2].as_str() {
+            "set" => MemPolicyOp::Set(val),
+            "add" => MemPolicyOp::Add(val),
+            "sub" => MemPolicyOp::Sub(val),
+            "cap" => MemPolicyOp::Cap(val),
+            _ => return Err(format!("unknown operation `{}`", args[2]))
+        };
+
+        let mut rule = MemPolicyRule
+        {
+            id: 0,
+            kind: kind,
+            op: op,
+            process: process,
+            program_id: program_id,
+            above: None,
+            pool: None,
+            hits: 0,
+        };
+
+        hrules_parse_options(&args[4..], |key, val| {
+            match key {
+                "above" => rule.above = Some(hrules_parse_u64(val)?),
+                "pool" if kind == MemPolicyKind::TotalMem => rule.pool = Some(hrules_parse_u32(val)?),
+                _ => return Ok(false)
+            };
+            return Ok(true);
+        })?;
+
+        return Ok(rule);
+    }
+
+    pub fn matches(&self, kind: MemPolicyKind, target: &MemPolicyTarget, val: u64, pool: Option<u32>) -> bool
+    {
+        if kind != self.kind {
+            return false;
+        }
+        if let Some(process) = &self.process {
+            if process != target.process {
+                return false;
+            }
+        }
+        if let Some(program_id) = self.program_id {
+            if program_id != target.program_id {
+                return false;
+            }
+        }
+        if let Some(above) = self.above {
+            if val < above {
+                return false;
+            }
+        }
+        if self.pool.is_some() && pool.is_some() && self.pool != pool {
+            return false;
+        }
+        return true;
+    }
+
+    pub fn describe(&self) -> String
+    {
+        let who = match (&self.process, self.program_id) {
+            (Some(process), _) => format!("`{}`", process),
+            (None, Some(program_id)) => format!("{:016x}", program_id),
+            (None, None) => String::from("*"),
+        };
+
+        let mut out = format!("{} {} {}", who, self.kind.name(), self.op.describe());
+        if let Some(above) = self.above {
+            out += &format!(", above {:#x}", above);
+        }
+        if let Some(pool) = self.pool {
+            out += &format!(", pool {}", pool);
+        }
+        out += &format!(" ({} rewrites)", self.hits);
+        return out;
+    }
+}
+
+impl HRule for MemPolicyRule
+{
+    fn get_id(&self) -> u32
+    {
+        self.id
+    }
+
+    fn set_id(&mut self, id: u32)
+    {
+        self.id = id;
+    }
+}
+
+impl MemPolicyEngine
+{
+    pub const fn new() -> MemPolicyEngine
+    {
+        MemPolicyEngine
+        {
+            rules: HRuleList::new(),
+        }
+    }
+
+    pub fn has_kind(&self, kind: MemPolicyKind) -> bool
+    {
+        self.rules.iter().any(|rule| rule.kind == kind)
+    }
+
+    // First matching rule wins. Returns the rule's ID and the new value, or
+    // None if nothing matched or the value came out the same
+    pub fn rewrite(&mut self, kind: MemPolicyKind, target: &MemPolicyTarget, val: u64, pool: Option<u32>) -> Option<(u32, u64)>
+    {
+        let rule = self.rules.iter_mut().find(|rule| rule.matches(kind, target, val, pool))?;
+
+        let mut new_val = rule.op.apply(val);
+        if kind == MemPolicyKind::Heap {
+            new_val &= !(MEMPOLICY_HEAP_ALIGN - 1);
+        }
+        if new_val == val {
+            return None;
+        }
+
+        rule.hits += 1;
+        return Some((rule.id, new_val));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use alloc::vec::Vec;
+
+    const QLAUNCH: MemPolicyTarget = MemPolicyTarget { process: "qlaunch", program_id: 0x0100000000001000 };
+    const GAME: MemPolicyTarget = MemPolicyTarget { process: "Application", program_id: 0x0100000000010000 };
+
+    fn args(line: &str) -> Vec<String>
+    {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn engine(rules: &[&str]) -> MemPolicyEngine
+    {
+        let mut engine = MemPolicyEngine::new();
+        for rule in rules
+        {
+            engine.rules.add(MemPolicyRule::parse(&args(rule)).unwrap());
+        }
+        return engine;
+    }
+
+    fn parse_err(line: &str) -> String
+    {
+        match MemPolicyRule::parse(&args(line)) {
+            Ok(_) => panic!("`{}` should not parse", line),
+            Err(err) => err
+        }
+    }
+
+    #[test]
+    fn parse()
+    {
+        let rule = MemPolicyRule::parse(&args("0x0100000000010000 totalmem sub 0x4000000 pool 0")).unwrap();
+        assert_eq!((rule.process.is_none(), rule.program_id), (true, Some(0x0100000000010000)));
+        assert!(rule.kind == MemPolicyKind::TotalMem && rule.op == MemPolicyOp::Sub(0x4000000));
+        assert_eq!(rule.pool, Some(0));
+
+        // Short hex is a name, not a program ID
+        let rule = MemPolicyRule::parse(&args("0x1 heap cap 0x1000000")).unwrap();
+        assert_eq!((rule.process.as_deref(), rule.program_id), (Some("0x1"), None));
+
+        assert_eq!(parse_err("* heap cap"), "need a process, a kind, an operation and a value");
+        assert_eq!(parse_err("* stack cap 0x1000"), "unknown kind `stack`");
+        assert_eq!(parse_err("* heap halve 0x1000"), "unknown operation `halve`");
+        assert_eq!(parse_err("* heap cap 0x1000 above"), "`above` needs a value");
+        assert_eq!(parse_err("* heap cap 0x1000 pool 0"), "unknown option `pool`");
+        assert_eq!(parse_err("* totalmem cap 0x1000 pool 0x100000000"), "invalid number `0x100000000`");
+    }
+
+    #[test]
+    fn ops()
+    {
+        assert_eq!(MemPolicyOp::Set(5).apply(10), 5);
+        assert_eq!(MemPolicyOp::Add(5).apply(u64::MAX), u64::MAX);
+        assert_eq!(MemPolicyOp::Sub(20).apply(10), 0);
+        assert_eq!(MemPolicyOp::Cap(5).apply(10), 5);
+        assert_eq!(MemPolicyOp::Cap(50).apply(10), 10);
+    }
+
+    #[test]
+    fn matching()
+    {
+        let mut engine = engine(&["qlaunch limit sub 0x1000000", "0x0100000000010000 limit cap 0xC0000000"]);
+
+        assert_eq!(engine.rewrite(MemPolicyKind::Limit, &QLAUNCH, 0x8000000, None), Some((1, 0x7000000)));
+        assert_eq!(engine.rewrite(MemPolicyKind::Limit, &GAME, 0xCD500000, None), Some((2, 0xC0000000)));
+        assert_eq!(engine.rewrite(MemPolicyKind::Heap, &QLAUNCH, 0x8000000, None), None);
+
+        let other = MemPolicyTarget { process: "qlaunch2", program_id: 0x0100000000001000 };
+        assert_eq!(engine.rewrite(MemPolicyKind::Limit, &other, 0x8000000, None), None);
+
+        assert!(engine.has_kind(MemPolicyKind::Limit));
+        assert!(!engine.has_kind(MemPolicyKind::TotalMem));
+    }
+
+    #[test]
+    fn first_rule_wins()
+    {
+        let mut engine = engine(&["* limit cap 0x1000", "qlaunch limit set 0"]);
+        assert_eq!(engine.rewrite(MemPolicyKind::Limit, &QLAUNCH, 0x2000, None), Some((1, 0x1000)));
+
+        // A rule that changes nothing still stops the search, and isn't a hit
+        assert_eq!(engine.rewrite(MemPolicyKind::Limit, &QLAUNCH, 0x800, None), None);
+        assert_eq!((engine.rules[0].hits, engine.rules[1].hits), (1, 0));
+
+        engine.rules.remove(1);
+        assert_eq!(engine.rewrite(MemPolicyKind::Limit, &QLAUNCH, 0x800, None), Some((2, 0)));
+    }
+
+    #[test]
+    fn above_threshold()
+    {
+        let mut engine = engine(&["* limit sub 0x4000000 above 0x10000000"]);
+        assert_eq!(engine.rewrite(MemPolicyKind::Limit, &GAME, 0xFFFFFFF, None), None);
+        assert_eq!(engine.rewrite(MemPolicyKind::Limit, &GAME, 0x10000000, None), Some((1, 0xC000000)));
+    }
+
+    #[test]
+    fn pool_matching()
+    {
+        let mut engine = engine(&["* totalmem cap 0xC0000000 pool 0"]);
+        assert_eq!(engine.rewrite(MemPolicyKind::TotalMem, &GAME, 0xCD500000, Some(0)), Some((1, 0xC0000000)));
+        assert_eq!(engine.rewrite(MemPolicyKind::TotalMem, &GAME, 0xCD500000, Some(1)), None);
+
+        // svcGetInfo doesn't say which pool
+        assert_eq!(engine.rewrite(MemPolicyKind::TotalMem, &GAME, 0xCD500000, None), Some((1, 0xC0000000)));
+    }
+
+    #[test]
+    fn heap_stays_aligned()
+    {
+        let mut engine = engine(&["* heap sub 0x123456"]);
+        assert_eq!(engine.rewrite(MemPolicyKind::Heap, &GAME, 0x10000000, None), Some((1, 0xFE00000)));
+        assert_eq!(engine.rewrite(MemPolicyKind::Heap, &GAME, 0x200000, None), Some((1, 0)));
+
+        // Rounding back down to where it started isn't a rewrite
+        let mut engine = self::engine(&["* heap add 0x1000"]);
+        assert_eq!(engine.rewrite(MemPolicyKind::Heap, &GAME, 0x200000, None), None);
+    }
+}
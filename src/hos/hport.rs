@@ -8,6 +8,7 @@ use core::cmp::{Ordering, Eq};
 use crate::vm::vsvc::vsvc_get_curpid;
 use alloc::string::String;
 use super::hclientsession::HClientSession;
+use super::hserversession::HServerSession;
 
 pub struct HPort
 {
@@ -28,6 +29,20 @@ impl HPort
     
     pub fn create_session(&self) -> HClientSession
     {
-        HClientSession::new(self.pid, (vsvc_get_curpid() & 0xFF) as u8)
+        let mut session = HClientSession::new(self.pid, (vsvc_get_curpid() & 0xFF) as u8);
+        if let Some(name) = &self.name {
+            session.set_service(name);
+        }
+        return session;
+    }
+    
+    // Server end of a session accepted on this port
+    pub fn create_server_session(&self) -> HServerSession
+    {
+        let mut session = HServerSession::new(self.pid);
+        if let Some(name) = &self.name {
+            session.set_service(name);
+        }
+        return session;
     }
 }
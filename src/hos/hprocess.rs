@@ -0,0 +1,498 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use crate::logger::*;
+use crate::util::*;
+use crate::arm::mmu::translate_el1_stage12;
+use crate::arm::ticks::{get_ticks, ticks_to_ns};
+
+// Exited processes kept for `proc history`, oldest dropped first
+const HPROCESS_HISTORY_MAX: usize = 64;
+
+// The kernel creates the INI1 KIPs in order, handing out PIDs from 1
+const HPROCESS_KIP_PID_START: u32 = 1;
+
+pub const HPROCESS_PID_IDLE: u32 = 0xFF;
+pub const HPROCESS_PSEUDO_HANDLE: u32 = 0xFFFF8001;
+
+const INI1_HEADER_SIZE: usize = 0x10;
+const KIP1_HEADER_SIZE: usize = 0x100;
+
+// CreateProcessParameter flags
+pub const HPROCESS_FLAG_64BIT: u32 = (1 << 0);
+pub const HPROCESS_FLAG_ADDRSPACE_SHIFT: u32 = 1;
+pub const HPROCESS_FLAG_ADDRSPACE_MASK: u32 = (0x7 << 1);
+pub const HPROCESS_FLAG_DEBUG: u32 = (1 << 4);
+pub const HPROCESS_FLAG_ASLR: u32 = (1 << 5);
+pub const HPROCESS_FLAG_APPLICATION: u32 = (1 << 6);
+
+pub const HPROCESS_ADDRSPACE_32BIT: u32 = 0;
+pub const HPROCESS_ADDRSPACE_64BIT_OLD: u32 = 1;
+pub const HPROCESS_ADDRSPACE_32BIT_NO_ALIAS: u32 = 2;
+pub const HPROCESS_ADDRSPACE_64BIT: u32 = 3;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum HProcessExit
+{
+    Exited,
+    Terminated(u32), // by pid
+    Replaced, // PID got reused before we saw it go
+}
+
+#[derive(Clone)]
+pub struct HProcess
+{
+    pub pid: u32,
+    pub name: String,
+    pub program_id: u64,
+    pub version: u32,
+    pub flags: u32,
+    pub code_address: u64,
+    pub code_num_pages: u32,
+    pub system_resource_num_pages: u32,
+    pub is_kip: bool,
+
+    // Creator's handle to the process, for matching Start/TerminateProcess
+    pub creator_pid: Option<u32>,
+    pub handle: u32,
+
+    pub start_ticks: u64,
+    pub exit_ticks: u64,
+    pub exit: Option<HProcessExit>,
+    pub svc_count: u64,
+    pub last_break: Option<u64>,
+}
+
+static mut HPROCESS_RUNNING: BTreeMap<u32, HProcess> = BTreeMap::new();
+static mut HPROCESS_HISTORY: Vec<HProcess> = Vec::new();
+
+// Created but not yet matched to a PID, by (creator pid, handle)
+static mut HPROCESS_PENDING: BTreeMap<(u32, u32), HProcess> = BTreeMap::new();
+
+// Started before svcGetProcessId was called on their handle, by (creator
+// pid, handle). They're bound once it is
+static mut HPROCESS_STARTED: BTreeMap<(u32, u32), HProcess> = BTreeMap::new();
+
+// Process handles seen through svcGetProcessId, by (pid, handle)
+static mut HPROCESS_HANDLE_PIDS: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+
+fn hprocess_name_from_bytes(data: &[u8]) -> String
+{
+    let len = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+    return String::from_utf8_lossy(&data[..len]).into_owned();
+}
+
+impl HProcess
+{
+    fn new(pid: u32, name: String) -> Self
+    {
+        HProcess
+        {
+            pid: pid,
+            name: name,
+            program_id: 0,
+            version: 0,
+            flags: 0,
+            code_address: 0,
+            code_num_pages: 0,
+            system_resource_num_pages: 0,
+            is_kip: false,
+            creator_pid: None,
+            handle: 0,
+            start_ticks: get_ticks(),
+            exit_ticks: 0,
+            exit: None,
+            svc_count: 0,
+            last_break: None,
+        }
+    }
+
+    // Reads a 64-bit CreateProcessParameter from guest memory. Names are a
+    // 12-byte field, only NUL terminated if they're shorter than that
+    pub fn from_create_params(params: u64, creator_pid: u32, handle: u32) -> Self
+    {
+        let mut name_bytes: [u8; 12] = [0; 12];
+        for i in 0..12
+        {
+            name_bytes[i] = peek8(translate_el1_stage12(params + i as u64));
+        }
+
+        let mut process = HProcess::new(0, hprocess_name_from_bytes(&name_bytes));
+        process.version = peek32(translate_el1_stage12(params + 0x0C));
+        process.program_id = peek64(translate_el1_stage12(params + 0x10));
+        process.code_address = peek64(translate_el1_stage12(params + 0x18));
+        process.code_num_pages = peek32(translate_el1_stage12(params + 0x20));
+        process.flags = peek32(translate_el1_stage12(params + 0x24));
+        process.system_resource_num_pages = peek32(translate_el1_stage12(params + 0x2C));
+        process.creator_pid = Some(creator_pid);
+        process.handle = handle;
+        return process;
+    }
+
+    // KIP1 flags don't line up with CreateProcessParameter's, so they're
+    // translated over to keep one set of accessors
+    fn from_kip(pid: u32, kip: &[u8]) -> Self
+    {
+        let mut process = HProcess::new(pid, hprocess_name_from_bytes(&kip[0x4..0x10]));
+        process.program_id = u64::from_le_bytes([kip[0x10], kip[0x11], kip[0x12], kip[0x13], kip[0x14], kip[0x15], kip[0x16], kip[0x17]]);
+        process.version = u32::from_le_bytes([kip[0x18], kip[0x19], kip[0x1A], kip[0x1B]]);
+        process.is_kip = true;
+
+        let kip_flags = kip[0x1F];
+        if (kip_flags & (1 << 3)) != 0 {
+            process.flags |= HPROCESS_FLAG_64BIT;
+        }
+        if (kip_flags & (1 << 4)) != 0 {
+            process.flags |= HPROCESS_ADDRSPACE_64BIT << HPROCESS_FLAG_ADDRSPACE_SHIFT;
+        }
+        return process;
+    }
+
+    pub fn is_64bit(&self) -> bool
+    {
+        (self.flags & HPROCESS_FLAG_64BIT) != 0
+    }
+
+    pub fn get_addrspace_str(&self) -> &'static str
+    {
+        match (self.flags & HPROCESS_FLAG_ADDRSPACE_MASK) >> HPROCESS_FLAG_ADDRSPACE_SHIFT {
+            HPROCESS_ADDRSPACE_32BIT => "32-bit",
+            HPROCESS_ADDRSPACE_64BIT_OLD => "36-bit",
+            HPROCESS_ADDRSPACE_32BIT_NO_ALIAS => "32-bit no alias",
+            HPROCESS_ADDRSPACE_64BIT => "39-bit",
+            _ => "unknown"
+        }
+    }
+
+    pub fn get_lifetime_ns(&self) -> u64
+    {
+        let end_ticks = if self.exit.is_some() { self.exit_ticks } else { get_ticks() };
+        return ticks_to_ns(end_ticks.wrapping_sub(self.start_ticks));
+    }
+
+    pub fn get_exit_str(&self) -> String
+    {
+        match self.exit {
+            None => String::from("running"),
+            Some(HProcessExit::Exited) => String::from("exited"),
+            Some(HProcessExit::Terminated(by_pid)) => format!("terminated by `{}`", hprocess_get_name(by_pid).unwrap_or(format!("pid {}", by_pid))),
+            Some(HProcessExit::Replaced) => String::from("gone (pid reused)"),
+        }
+    }
+
+    fn print_row(&self)
+    {
+        let lifetime_ms = self.get_lifetime_ns() / 1000000;
+        let creator = match self.creator_pid {
+            Some(pid) => hprocess_get_name(pid).unwrap_or(format!("pid {}", pid)),
+            None => String::from("INI1")
+        };
+
+        print!("  {:3}: {:12} {:016x} v{:<8x} {:3} {:15} {:>8}.{:03}s {:>8} svcs, from {}",
+               self.pid, self.name, self.program_id, self.version, if self.is_64bit() { "a64" } else { "a32" }, self.get_addrspace_str(),
+               lifetime_ms / 1000, lifetime_ms % 1000, self.svc_count, creator);
+        if (self.flags & HPROCESS_FLAG_APPLICATION) != 0 {
+            print!(", application");
+        }
+        if let Some(reason) = self.last_break {
+            print!(", svcBreak({:#x})", reason);
+        }
+        if self.exit.is_some() {
+            print!(", {}", self.get_exit_str());
+        }
+        println!("");
+    }
+}
+
+fn hprocess_insert_running(process: HProcess)
+{
+    unsafe
+    {
+        if let Some(mut old) = HPROCESS_RUNNING.remove(&process.pid) {
+            old.exit = Some(HProcessExit::Replaced);
+            old.exit_ticks = get_ticks();
+            hprocess_push_history(old);
+        }
+        HPROCESS_RUNNING.insert(process.pid, process);
+    }
+}
+
+fn hprocess_push_history(process: HProcess)
+{
+    unsafe
+    {
+        if HPROCESS_HISTORY.len() >= HPROCESS_HISTORY_MAX {
+            HPROCESS_HISTORY.remove(0);
+        }
+        HPROCESS_HISTORY.push(process);
+    }
+}
+
+// Embedded in the kernel binary since 8.0.0
+fn hprocess_find_ini1(kern_data: &[u8]) -> Option<&[u8]>
+{
+    let mut offs = 0;
+    while offs + INI1_HEADER_SIZE <= kern_data.len()
+    {
+        if &kern_data[offs..offs+4] == b"INI1"
+        {
+            let size = u32::from_le_bytes([kern_data[offs+4], kern_data[offs+5], kern_data[offs+6], kern_data[offs+7]]) as usize;
+            if offs + size <= kern_data.len() && size > INI1_HEADER_SIZE && &kern_data[offs+INI1_HEADER_SIZE..offs+INI1_HEADER_SIZE+4] == b"KIP1" {
+                return Some(&kern_data[offs..offs+size]);
+            }
+        }
+        offs += 4;
+    }
+    return None;
+}
+
+pub fn hprocess_init(kern_data: &[u8])
+{
+    hprocess_insert_running(HProcess::new(HPROCESS_PID_IDLE, String::from("idle core")));
+
+    let ini1 = match hprocess_find_ini1(kern_data) {
+        Some(ini1) => ini1,
+        None => {
+            println!("hprocess: no INI1 in kernel, initial processes will stay unnamed");
+            return;
+        }
+    };
+
+    let num_processes = u32::from_le_bytes([ini1[8], ini1[9], ini1[10], ini1[11]]);
+    let mut offs = INI1_HEADER_SIZE;
+    for i in 0..num_processes
+    {
+        if offs + KIP1_HEADER_SIZE > ini1.len() || &ini1[offs..offs+4] != b"KIP1" {
+            println!("hprocess: INI1 KIP {} is malformed, stopping", i);
+            break;
+        }
+
+        let kip = &ini1[offs..offs+KIP1_HEADER_SIZE];
+        hprocess_insert_running(HProcess::from_kip(HPROCESS_KIP_PID_START + i, kip));
+
+        // Text, RO and data are stored back to back after the header
+        let mut kip_size = KIP1_HEADER_SIZE;
+        for seg in 0..3
+        {
+            let seg_offs = 0x20 + seg * 0x10 + 8;
+            kip_size += u32::from_le_bytes([kip[seg_offs], kip[seg_offs+1], kip[seg_offs+2], kip[seg_offs+3]]) as usize;
+        }
+        offs += kip_size;
+    }
+}
+
+pub fn hprocess_created(process: HProcess)
+{
+    if let Some(creator_pid) = process.creator_pid
+    {
+        unsafe
+        {
+            HPROCESS_PENDING.insert((creator_pid, process.handle), process);
+        }
+    }
+}
+
+fn hprocess_bind(mut process: HProcess, pid: u32)
+{
+    process.pid = pid;
+    process.start_ticks = get_ticks();
+    hprocess_insert_running(process);
+}
+
+// pm asks for the PID of what it created before starting it, so the handle
+// is normally bound by now
+pub fn hprocess_started(creator_pid: u32, handle: u32)
+{
+    unsafe
+    {
+        let process = match HPROCESS_PENDING.remove(&(creator_pid, handle)) {
+            Some(process) => process,
+            None => return
+        };
+
+        match HPROCESS_HANDLE_PIDS.get(&(creator_pid, handle)) {
+            Some(pid) => hprocess_bind(process, *pid),
+            None => { HPROCESS_STARTED.insert((creator_pid, handle), process); }
+        };
+    }
+}
+
+// svcGetProcessId on a handle from svcCreateProcess, which pm always does
+pub fn hprocess_bind_handle(creator_pid: u32, handle: u32, pid: u32)
+{
+    unsafe
+    {
+        HPROCESS_HANDLE_PIDS.insert((creator_pid, handle), pid);
+        
+        if let Some(process) = HPROCESS_STARTED.remove(&(creator_pid, handle)) {
+            hprocess_bind(process, pid);
+        }
+    }
+}
+
+pub fn hprocess_on_svc(pid: u32)
+{
+    unsafe
+    {
+        if let Some(process) = HPROCESS_RUNNING.get_mut(&pid) {
+            process.svc_count += 1;
+        }
+    }
+}
+
+pub fn hprocess_exited(pid: u32, exit: HProcessExit)
+{
+    unsafe
+    {
+        if let Some(mut process) = HPROCESS_RUNNING.remove(&pid) {
+            process.exit = Some(exit);
+            process.exit_ticks = get_ticks();
+            hprocess_push_history(process);
+        }
+    }
+}
+
+pub fn hprocess_note_break(pid: u32, reason: u64)
+{
+    unsafe
+    {
+        if let Some(process) = HPROCESS_RUNNING.get_mut(&pid) {
+            process.last_break = Some(reason);
+        }
+    }
+}
+
+// PID for a creator's handle, or None if it never got bound
+pub fn hprocess_find_handle(creator_pid: u32, handle: u32) -> Option<u32>
+{
+    unsafe
+    {
+        HPROCESS_RUNNING.values().find(|process| process.creator_pid == Some(creator_pid) && process.handle == handle).map(|process| process.pid)
+    }
+}
+
+// Which process `pid` means by `handle`, if we can tell
+pub fn hprocess_resolve_handle(pid: u32, handle: u32) -> Option<u32>
+{
+    if handle == HPROCESS_PSEUDO_HANDLE {
+        return Some(pid);
+    }
+
+    unsafe
+    {
+        if let Some(target) = HPROCESS_HANDLE_PIDS.get(&(pid, handle)) {
+            return Some(*target);
+        }
+    }
+    return hprocess_find_handle(pid, handle);
+}
+
+pub fn hprocess_close_handle(pid: u32, handle: u32)
+{
+    unsafe
+    {
+        HPROCESS_HANDLE_PIDS.remove(&(pid, handle));
+    }
+}
+
+// Terminated before it ever ran, nothing to put in history
+pub fn hprocess_forget_handle(creator_pid: u32, handle: u32)
+{
+    unsafe
+    {
+        HPROCESS_PENDING.remove(&(creator_pid, handle));
+        HPROCESS_STARTED.remove(&(creator_pid, handle));
+    }
+}
+
+pub fn hprocess_get(pid: u32) -> Option<HProcess>
+{
+    unsafe { HPROCESS_RUNNING.get(&pid).cloned() }
+}
+
+pub fn hprocess_get_name(pid: u32) -> Option<String>
+{
+    unsafe { HPROCESS_RUNNING.get(&pid).map(|process| process.name.clone()) }
+}
+
+// Unknown processes are assumed 64-bit, like everything in INI1
+pub fn hprocess_is_64bit(pid: u32) -> bool
+{
+    unsafe { HPROCESS_RUNNING.get(&pid).map(|process| process.is_64bit()).unwrap_or(true) }
+}
+
+pub fn hprocess_find_name(name: &String) -> Option<u32>
+{
+    unsafe { HPROCESS_RUNNING.values().find(|process| &process.name == name).map(|process| process.pid) }
+}
+
+pub fn hprocess_get_pids() -> Vec<u32>
+{
+    unsafe { HPROCESS_RUNNING.keys().cloned().collect() }
+}
+
+fn hprocess_print_header()
+{
+    println!("  {:>3}  {:12} {:16} {:9} {:3} {:15} {:>13}", "pid", "name", "title id", "version", "isa", "address space", "lifetime");
+}
+
+pub fn hprocess_print_list()
+{
+    println!("Running Processes:");
+    hprocess_print_header();
+    unsafe
+    {
+        for process in HPROCESS_RUNNING.values()
+        {
+            if process.pid == HPROCESS_PID_IDLE { continue; }
+
+            process.print_row();
+        }
+
+        if !HPROCESS_PENDING.is_empty() || !HPROCESS_STARTED.is_empty() {
+            println!("  ({} created but not running yet)", HPROCESS_PENDING.len() + HPROCESS_STARTED.len());
+        }
+    }
+    println!("");
+}
+
+pub fn hprocess_print_history()
+{
+    println!("Exited Processes (last {}):", HPROCESS_HISTORY_MAX);
+    hprocess_print_header();
+    unsafe
+    {
+        for process in HPROCESS_HISTORY.iter()
+        {
+            process.print_row();
+        }
+    }
+    println!("");
+}
+
+pub fn hprocess_print_info(pid: u32) -> bool
+{
+    let process = match hprocess_get(pid) {
+        Some(process) => process,
+        None => return false
+    };
+
+    println!("PID {} `{}`:", process.pid, process.name);
+    println!("  Title ID:        {:016x}", process.program_id);
+    println!("  Version:         {:x}", process.version);
+    println!("  Source:          {}", if process.is_kip { "INI1 KIP" } else { "svcCreateProcess" });
+    println!("  Flags:           {:08x} ({}, {} address space{}{})", process.flags, if process.is_64bit() { "64-bit" } else { "32-bit" }, process.get_addrspace_str(),
+             if (process.flags & HPROCESS_FLAG_ASLR) != 0 { ", ASLR" } else { "" }, if (process.flags & HPROCESS_FLAG_DEBUG) != 0 { ", debug" } else { "" });
+    if !process.is_kip {
+        println!("  Code:            {:016x}, {:#x} pages", process.code_address, process.code_num_pages);
+        println!("  System resource: {:#x} pages", process.system_resource_num_pages);
+    }
+    println!("  Lifetime:        {} ms, {} SVCs", process.get_lifetime_ns() / 1000000, process.svc_count);
+    return true;
+}
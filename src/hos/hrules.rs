@@ -0,0 +1,187 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// Pieces shared by the debug shell's rule engines (svcinject, mempolicy):
+// numbered rule lists and `<key> <value>` option parsing. Only needs alloc,
+// so it builds on the host as well
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+pub trait HRule
+{
+    fn get_id(&self) -> u32;
+    fn set_id(&mut self, id: u32);
+}
+
+// IDs are never reused, so a stale `remove` from the shell can't hit a
+// newer rule
+pub struct HRuleList<T>
+{
+    rules: Vec<T>,
+    next_id: u32,
+}
+
+impl<T> HRuleList<T>
+{
+    pub const fn new() -> HRuleList<T>
+    {
+        HRuleList
+        {
+            rules: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.rules.clear();
+    }
+}
+
+impl<T: HRule> HRuleList<T>
+{
+    pub fn add(&mut self, mut rule: T) -> u32
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        rule.set_id(id);
+        self.rules.push(rule);
+        return id;
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool
+    {
+        let len = self.rules.len();
+        self.rules.retain(|rule| rule.get_id() != id);
+        return self.rules.len() != len;
+    }
+}
+
+impl<T> Deref for HRuleList<T>
+{
+    type Target = [T];
+
+    fn deref(&self) -> &[T]
+    {
+        &self.rules
+    }
+}
+
+impl<T> DerefMut for HRuleList<T>
+{
+    fn deref_mut(&mut self) -> &mut [T]
+    {
+        &mut self.rules
+    }
+}
+
+pub fn hrules_parse_u64(val: &str) -> Result<u64, String>
+{
+    let parsed = if val.starts_with("0x") {
+        u64::from_str_radix(&val[2..], 16)
+    }
+    else {
+        val.parse::<u64>()
+    };
+
+    return parsed.map_err(|_| format!("invalid number `{}`", val));
+}
+
+pub fn hrules_parse_u32(val: &str) -> Result<u32, String>
+{
+    let parsed = hrules_parse_u64(val)?;
+    if parsed > u32::MAX as u64 {
+        return Err(format!("invalid number `{}`", val));
+    }
+    return Ok(parsed as u32);
+}
+
+// Feeds each `<key> <value>` pair to `apply`, which returns false for keys
+// it doesn't know
+pub fn hrules_parse_options<F>(args: &[String], mut apply: F) -> Result<(), String>
+    where F: FnMut(&str, &str) -> Result<bool, String>
+{
+    for pair in args.chunks(2)
+    {
+        if pair.len() < 2 {
+            return Err(format!("`{}` needs a value", pair[0]));
+        }
+        if !apply(&pair[0], &pair[1])? {
+            return Err(format!("unknown option `{}`", pair[0]));
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    struct TestRule
+    {
+        id: u32,
+    }
+
+    impl HRule for TestRule
+    {
+        fn get_id(&self) -> u32 { self.id }
+        fn set_id(&mut self, id: u32) { self.id = id; }
+    }
+
+    fn args(line: &str) -> Vec<String>
+    {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn ids_are_not_reused()
+    {
+        let mut list: HRuleList<TestRule> = HRuleList::new();
+        assert_eq!(list.add(TestRule { id: 0 }), 1);
+        assert_eq!(list.add(TestRule { id: 0 }), 2);
+
+        assert!(list.remove(1));
+        assert!(!list.remove(1));
+        assert_eq!(list.add(TestRule { id: 0 }), 3);
+
+        list.clear();
+        assert!(list.is_empty());
+        assert_eq!(list.add(TestRule { id: 0 }), 4);
+        assert_eq!(list[0].id, 4);
+    }
+
+    #[test]
+    fn numbers()
+    {
+        assert_eq!(hrules_parse_u64("0x1000"), Ok(0x1000));
+        assert_eq!(hrules_parse_u64("4096"), Ok(4096));
+        assert_eq!(hrules_parse_u64("0x"), Err(String::from("invalid number `0x`")));
+        assert_eq!(hrules_parse_u64("-1"), Err(String::from("invalid number `-1`")));
+        assert_eq!(hrules_parse_u32("0xFFFFFFFF"), Ok(0xFFFFFFFF));
+        assert_eq!(hrules_parse_u32("0x100000000"), Err(String::from("invalid number `0x100000000`")));
+    }
+
+    #[test]
+    fn options()
+    {
+        let mut seen: Vec<(String, String)> = Vec::new();
+        let ret = hrules_parse_options(&args("a 1 b 2"), |key, val| {
+            seen.push((String::from(key), String::from(val)));
+            return Ok(true);
+        });
+        assert_eq!(ret, Ok(()));
+        assert_eq!(seen.len(), 2);
+
+        assert_eq!(hrules_parse_options(&args(""), |_, _| Ok(false)), Ok(()));
+        assert_eq!(hrules_parse_options(&args("a 1 b"), |_, _| Ok(true)), Err(String::from("`b` needs a value")));
+        assert_eq!(hrules_parse_options(&args("c 1"), |_, _| Ok(false)), Err(String::from("unknown option `c`")));
+        assert_eq!(hrules_parse_options(&args("a x"), |_, val| hrules_parse_u32(val).map(|_| true)), Err(String::from("invalid number `x`")));
+    }
+}
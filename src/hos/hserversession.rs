@@ -0,0 +1,52 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use super::hipc::HIPCPacket;
+
+pub struct HServerSession
+{
+    pub server_pid: u8,
+    pub client_handle: Option<u32>, // svcCreateSession's other end, until it's moved to a client
+    pub pending: Option<HIPCPacket>, // request the server hasn't replied to yet
+    pub service: String,
+    pub interface: String
+}
+
+impl HServerSession
+{
+    pub fn new(server_pid: u8) -> Self
+    {
+        HServerSession
+        {
+            server_pid: server_pid,
+            client_handle: None,
+            pending: None,
+            service: String::new(),
+            interface: String::new()
+        }
+    }
+
+    pub fn set_service(&mut self, service: &String)
+    {
+        self.service = service.clone();
+    }
+
+    pub fn get_service(&self) -> String
+    {
+        self.service.clone()
+    }
+
+    pub fn set_interface(&mut self, interface: &str)
+    {
+        self.interface = String::from(interface);
+    }
+
+    pub fn get_interface(&self) -> String
+    {
+        self.interface.clone()
+    }
+}
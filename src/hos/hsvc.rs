@@ -47,3 +47,42 @@ pub async fn hsvc_sleep_thread_2(mut pre_ctx: [u64; 32], ns: u64) -> [u64; 32]
     // Restore last context
     return post_ctx;
 }
+
+// Borrows the current thread to send whatever is in its TLS command buffer
+// over `handle`, the caller restores state afterwards
+pub async fn hsvc_send_sync_request(mut pre_ctx: [u64; 32], handle: u32) -> [u64; 32]
+{
+    let svc_handler = vsvc_get_svc_addr(0x21);
+    
+    let mut req_ctx = pre_ctx.clone();
+    req_ctx[0] = handle as u64;
+    
+    req_ctx[30] = req_ctx[31]+4;
+    req_ctx[31] = svc_handler;
+    
+    // Wait for SVC to complete
+    let post_ctx = SvcWait::new(req_ctx).await;
+    
+    return post_ctx;
+}
+
+// The pre-hook sits right on the kernel's call into the SVC, so stepping
+// over it returns `result` without the SVC ever running
+pub fn hsvc_return_early(mut pre_ctx: [u64; 32], result: u32) -> [u64; 32]
+{
+    let mut ret_ctx = pre_ctx.clone();
+    ret_ctx[0] = result as u64;
+    ret_ctx[31] += 4;
+    
+    return ret_ctx;
+}
+
+// Sends the thread back through the pre-hook, so the SVC is handled as if
+// it was only being made now
+pub fn hsvc_restart(mut pre_ctx: [u64; 32]) -> [u64; 32]
+{
+    let mut ret_ctx = pre_ctx.clone();
+    ret_ctx[31] -= 4;
+    
+    return ret_ctx;
+}
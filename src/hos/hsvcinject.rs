@@ -0,0 +1,363 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// Rule parsing and matching for SVC fault injection. Only needs alloc and
+// svcsig, so it builds on the host as well
+
+use alloc::string::String;
+use super::svcsig::{svcsig_lookup, svcsig_lookup_name};
+use super::hrules::{HRule, HRuleList, hrules_parse_u32, hrules_parse_u64, hrules_parse_options};
+
+#[derive(Clone)]
+pub struct SvcInjectRule
+{
+    pub id: u32,
+    pub svc_id: u8,
+    pub result: u32,
+    pub pid: Option<u32>,
+    pub process: Option<String>,
+    pub target: Option<String>, // port/service name the SVC is aimed at
+    pub every: u32, // fail every nth matching call
+    pub percent: u32, // or fail with this probability
+    pub limit: Option<u64>, // stop after this many injections
+
+    pub calls: u64,
+    pub hits: u64,
+}
+
+// What's known about an SVC call at the pre-hook
+pub struct SvcInjectCall<'a>
+{
+    pub pid: u32,
+    pub process: &'a str,
+    pub svc_id: u8,
+    pub target: Option<&'a str>,
+}
+
+pub struct SvcInjectEngine
+{
+    pub rules: HRuleList<SvcInjectRule>,
+    rng: u64,
+}
+
+impl HRule for SvcInjectRule
+{
+    fn get_id(&self) -> u32
+    {
+        self.id
+    }
+
+    fn set_id(&mut self, id: u32)
+    {
+        self.id = id;
+    }
+}
+
+impl SvcInjectRule
+{
+    // <svc> <result> [pid <pid/name>] [target <name>] [every <n>] [prob <percent>] [limit <n>]
+    pub fn parse(args: &[String]) -> Result<SvcInjectRule, String>
+    {
+        if args.len() < 2 {
+            return Err(String::from("need at least an SVC and a result"));
+        }
+
+        let svc_name = args[0].trim_start_matches("svc");
+        let svc_id = match svcsig_lookup_name(svc_name) {
+            Some(sig) => sig.id,
+            None => match hrules_parse_u32(&args[0]) {
+                Ok(id) if id < 0x80 => id as u8,
+                _ => return Err(format!("unknown SVC `{}`", args[0]))
+            }
+        };
+
+        let mut rule = SvcInjectRule
+        {
+            id: 0,
+            svc_id: svc_id,
+            result: hrules_parse_u32(&args[1])?,
+            pid: None,
+            process: None,
+            target: None,
+            every: 1,
+            percent: 100,
+            limit: None,
+            calls: 0,
+            hits: 0,
+        };
+
+        hrules_parse_options(&args[2..], |key, val| {
+            match key {
+                "pid" => {
+                    match val.parse::<u32>() {
+                        Ok(pid) => rule.pid = Some(pid),
+                        Err(_) => rule.process = Some(String::from(val))
+                    };
+                },
+                "target" => rule.target = Some(String::from(val)),
+                "every" => rule.every = core::cmp::max(hrules_parse_u32(val)?, 1),
+                "prob" => rule.percent = core::cmp::min(hrules_parse_u32(val.trim_end_matches('%'))?, 100),
+                "limit" => rule.limit = Some(hrules_parse_u64(val)?),
+                _ => return Ok(false)
+            };
+            return Ok(true);
+        })?;
+
+        return Ok(rule);
+    }
+
+    pub fn matches(&self, call: &SvcInjectCall) -> bool
+    {
+        if call.svc_id != self.svc_id {
+            return false;
+        }
+        if let Some(pid) = self.pid {
+            if pid != call.pid {
+                return false;
+            }
+        }
+        if let Some(process) = &self.process {
+            if process != call.process {
+                return false;
+            }
+        }
+        if let Some(target) = &self.target {
+            if Some(target.as_str()) != call.target {
+                return false;
+            }
+        }
+        if let Some(limit) = self.limit {
+            if self.hits >= limit {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    pub fn describe(&self) -> String
+    {
+        let svc_name = match svcsig_lookup(self.svc_id) {
+            Some(sig) => String::from(sig.name),
+            None => format!("{:#x}", self.svc_id)
+        };
+
+        let mut out = format!("svc{} -> {:#x}", svc_name, self.result);
+        if let Some(pid) = self.pid {
+            out += &format!(", pid {}", pid);
+        }
+        if let Some(process) = &self.process {
+            out += &format!(", `{}`", process);
+        }
+        if let Some(target) = &self.target {
+            out += &format!(", target `{}`", target);
+        }
+        if self.every > 1 {
+            out += &format!(", every {} calls", self.every);
+        }
+        if self.percent < 100 {
+            out += &format!(", {}% chance", self.percent);
+        }
+        if let Some(limit) = self.limit {
+            out += &format!(", limit {}", limit);
+        }
+        out += &format!(" ({} calls, {} injected)", self.calls, self.hits);
+        return out;
+    }
+}
+
+impl SvcInjectEngine
+{
+    pub const fn new() -> SvcInjectEngine
+    {
+        SvcInjectEngine
+        {
+            rules: HRuleList::new(),
+            rng: 0x9E3779B97F4A7C15,
+        }
+    }
+
+    pub fn seed(&mut self, seed: u64)
+    {
+        // xorshift gets stuck on 0
+        self.rng = if seed == 0 { 0x9E3779B97F4A7C15 } else { seed };
+    }
+
+    fn next_percent(&mut self) -> u32
+    {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        return (self.rng.wrapping_mul(0x2545F4914F6CDD1D) % 100) as u32;
+    }
+
+    // First matching rule that fires wins. Every matching rule counts the
+    // call, so `every` stays in step no matter what else is configured
+    pub fn check(&mut self, call: &SvcInjectCall) -> Option<(u32, u32)>
+    {
+        let mut fired: Option<(u32, u32)> = None;
+        for i in 0..self.rules.len()
+        {
+            if !self.rules[i].matches(call) {
+                continue;
+            }
+
+            self.rules[i].calls += 1;
+            if fired.is_some() {
+                continue;
+            }
+
+            let (calls, every, percent) = (self.rules[i].calls, self.rules[i].every, self.rules[i].percent);
+            if (calls % every as u64) != 0 {
+                continue;
+            }
+            if percent < 100 && self.next_percent() >= percent {
+                continue;
+            }
+
+            self.rules[i].hits += 1;
+            fired = Some((self.rules[i].id, self.rules[i].result));
+        }
+
+        return fired;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn args(line: &str) -> Vec<String>
+    {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn call<'a>(pid: u32, process: &'a str, svc_id: u8, target: Option<&'a str>) -> SvcInjectCall<'a>
+    {
+        SvcInjectCall { pid: pid, process: process, svc_id: svc_id, target: target }
+    }
+
+    fn engine(rules: &[&str]) -> SvcInjectEngine
+    {
+        let mut engine = SvcInjectEngine::new();
+        for rule in rules
+        {
+            engine.rules.add(SvcInjectRule::parse(&args(rule)).unwrap());
+        }
+        return engine;
+    }
+
+    fn parse_err(line: &str) -> String
+    {
+        match SvcInjectRule::parse(&args(line)) {
+            Ok(_) => panic!("`{}` should not parse", line),
+            Err(err) => err
+        }
+    }
+
+    #[test]
+    fn parse_svc_names_and_ids()
+    {
+        assert_eq!(SvcInjectRule::parse(&args("svcMapMemory 0xd401")).unwrap().svc_id, 0x04);
+        assert_eq!(SvcInjectRule::parse(&args("connecttonamedport 0xd401")).unwrap().svc_id, 0x1F);
+        assert_eq!(SvcInjectRule::parse(&args("0x7F 1")).unwrap().svc_id, 0x7F);
+
+        let rule = SvcInjectRule::parse(&args("MapMemory 0xd401 pid 81 target bsd:u every 3 prob 10% limit 2")).unwrap();
+        assert_eq!(rule.result, 0xd401);
+        assert_eq!((rule.pid, rule.process.as_deref()), (Some(81), None));
+        assert_eq!(rule.target.as_deref(), Some("bsd:u"));
+        assert_eq!((rule.every, rule.percent, rule.limit), (3, 10, Some(2)));
+
+        // Clamped rather than rejected
+        let rule = SvcInjectRule::parse(&args("MapMemory 1 every 0 prob 250")).unwrap();
+        assert_eq!((rule.every, rule.percent), (1, 100));
+    }
+
+    #[test]
+    fn parse_errors()
+    {
+        assert_eq!(parse_err("MapMemory"), "need at least an SVC and a result");
+        assert_eq!(parse_err("NotAnSvc 0xd401"), "unknown SVC `NotAnSvc`");
+        assert_eq!(parse_err("0x80 0xd401"), "unknown SVC `0x80`");
+        assert_eq!(parse_err("MapMemory 0xzz"), "invalid number `0xzz`");
+        assert_eq!(parse_err("MapMemory 0xd401 every"), "`every` needs a value");
+        assert_eq!(parse_err("MapMemory 0xd401 every three"), "invalid number `three`");
+        assert_eq!(parse_err("MapMemory 0xd401 limit -1"), "invalid number `-1`");
+        assert_eq!(parse_err("MapMemory 0xd401 often 3"), "unknown option `often`");
+    }
+
+    #[test]
+    fn matching()
+    {
+        let mut engine = engine(&["MapMemory 0xd401 pid 81", "ConnectToNamedPort 0xe401 pid sm target bsd:u"]);
+
+        assert_eq!(engine.check(&call(81, "qlaunch", 0x04, None)), Some((1, 0xd401)));
+        assert_eq!(engine.check(&call(82, "qlaunch", 0x04, None)), None);
+        assert_eq!(engine.check(&call(81, "qlaunch", 0x05, None)), None);
+
+        assert_eq!(engine.check(&call(3, "sm", 0x1F, Some("bsd:u"))), Some((2, 0xe401)));
+        assert_eq!(engine.check(&call(3, "sm", 0x1F, Some("bsd:s"))), None);
+        assert_eq!(engine.check(&call(3, "sm", 0x1F, None)), None);
+        assert_eq!(engine.check(&call(3, "fs", 0x1F, Some("bsd:u"))), None);
+
+        assert!(engine.rules.remove(1));
+        assert!(!engine.rules.remove(1));
+        assert_eq!(engine.check(&call(81, "qlaunch", 0x04, None)), None);
+    }
+
+    #[test]
+    fn every_nth_call()
+    {
+        let mut engine = engine(&["MapMemory 0xd401 every 3"]);
+        let fired: Vec<bool> = (0..9).map(|_| engine.check(&call(1, "a", 0x04, None)).is_some()).collect();
+        assert_eq!(fired, [false, false, true, false, false, true, false, false, true]);
+        assert_eq!((engine.rules[0].calls, engine.rules[0].hits), (9, 3));
+    }
+
+    #[test]
+    fn every_counts_calls_another_rule_took()
+    {
+        let mut engine = engine(&["MapMemory 1 limit 1", "MapMemory 2 every 2"]);
+        assert_eq!(engine.check(&call(1, "a", 0x04, None)), Some((1, 1)));
+        assert_eq!(engine.check(&call(1, "a", 0x04, None)), Some((2, 2)));
+        assert_eq!(engine.check(&call(1, "a", 0x04, None)), None);
+        assert_eq!(engine.check(&call(1, "a", 0x04, None)), Some((2, 2)));
+    }
+
+    #[test]
+    fn limit_stops_injecting()
+    {
+        let mut engine = engine(&["MapMemory 0xd401 limit 2"]);
+        let hits = (0..5).filter(|_| engine.check(&call(1, "a", 0x04, None)).is_some()).count();
+        assert_eq!(hits, 2);
+
+        // Once spent, it stops matching and counting too
+        assert_eq!(engine.rules[0].calls, 2);
+    }
+
+    #[test]
+    fn probability()
+    {
+        let mut never = engine(&["MapMemory 1 prob 0"]);
+        assert!((0..1000).all(|_| never.check(&call(1, "a", 0x04, None)).is_none()));
+
+        let mut some = engine(&["MapMemory 1 prob 10"]);
+        some.seed(1234);
+        let hits = (0..10000).filter(|_| some.check(&call(1, "a", 0x04, None)).is_some()).count();
+        assert!(hits > 800 && hits < 1200, "{} hits", hits);
+
+        // Same seed, same calls fail
+        let mut a = engine(&["MapMemory 1 prob 50"]);
+        let mut b = engine(&["MapMemory 1 prob 50"]);
+        a.seed(7);
+        b.seed(7);
+        for _ in 0..100
+        {
+            assert_eq!(a.check(&call(1, "a", 0x04, None)), b.check(&call(1, "a", 0x04, None)));
+        }
+    }
+}
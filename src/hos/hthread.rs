@@ -0,0 +1,380 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use crate::logger::*;
+use crate::util::*;
+use crate::arm::mmu::try_translate_el1_stage12;
+use crate::arm::ticks::{get_ticks, ticks_to_ns};
+use crate::hos::svcsig::svcsig_lookup;
+
+pub const HTHREAD_PSEUDO_HANDLE: u32 = 0xFFFF8000;
+
+// KExceptionContext, where the kernel spills user registers on SVC entry
+const FRAME_SP: u64 = 0xF8;
+const FRAME_PC: u64 = 0x100;
+const FRAME_PSR: u64 = 0x108;
+const FRAME_TPIDR: u64 = 0x110;
+
+#[derive(Clone)]
+pub struct HThread
+{
+    pub id: u32, // ours, kernel thread IDs are only known if someone asks
+    pub pid: u32,
+    pub thread_ctx: u64,
+    pub kernel_tid: Option<u64>,
+
+    // Only known for threads we saw get created
+    pub handle: Option<u32>,
+    pub entry: u64,
+    pub arg: u64,
+    pub stack_top: u64,
+    pub started: bool,
+
+    pub priority: u32,
+    pub ideal_core: u32,
+    pub core_mask: u64,
+
+    pub tls: u64,
+    pub frame: u64,
+    pub last_svc: Option<u8>,
+    pub last_svc_ticks: u64,
+    pub last_args: [u64; 8],
+    pub svc_count: u64,
+}
+
+static mut HTHREAD_NEXT_ID: u32 = 1;
+
+// By thread context, once a thread makes its first SVC
+static mut HTHREAD_RUNNING: BTreeMap<u64, HThread> = BTreeMap::new();
+
+// Created but not seen yet, by (pid, handle)
+static mut HTHREAD_PENDING: BTreeMap<(u32, u32), HThread> = BTreeMap::new();
+
+impl HThread
+{
+    fn new(pid: u32) -> Self
+    {
+        let id = unsafe
+        {
+            let id = HTHREAD_NEXT_ID;
+            HTHREAD_NEXT_ID += 1;
+            id
+        };
+
+        HThread
+        {
+            id: id,
+            pid: pid,
+            thread_ctx: 0,
+            kernel_tid: None,
+            handle: None,
+            entry: 0,
+            arg: 0,
+            stack_top: 0,
+            started: false,
+            priority: 0,
+            ideal_core: 0,
+            core_mask: 0,
+            tls: 0,
+            frame: 0,
+            last_svc: None,
+            last_svc_ticks: 0,
+            last_args: [0; 8],
+            svc_count: 0,
+        }
+    }
+
+    fn get_last_svc_str(&self) -> String
+    {
+        match self.last_svc {
+            Some(svc_id) => match svcsig_lookup(svc_id) {
+                Some(sig) => String::from(sig.name),
+                None => format!("{:#x}", svc_id)
+            },
+            None => String::from("-")
+        }
+    }
+
+    fn print_row(&self)
+    {
+        let tid = match self.kernel_tid {
+            Some(tid) => format!("{}", tid),
+            None => String::from("-")
+        };
+
+        println!("  {:4} {:016x} {:>5} {:016x} {:3} {:3} {:4x} {:016x} {:>8} {}", self.id, self.thread_ctx, tid, self.entry,
+                 self.priority, self.ideal_core as i32, self.core_mask, self.tls, self.svc_count, self.get_last_svc_str());
+    }
+}
+
+fn hthread_read_frame(frame: u64, offs: u64) -> Option<u64>
+{
+    let addr_el2 = try_translate_el1_stage12(frame + offs)?;
+    return Some(peek64(addr_el2));
+}
+
+fn hthread_write_frame(frame: u64, offs: u64, val: u64) -> bool
+{
+    let addr_el2 = match try_translate_el1_stage12(frame + offs) {
+        Some(addr_el2) => addr_el2,
+        None => return false
+    };
+    poke64(addr_el2, val);
+    return true;
+}
+
+// Threads created after boot are matched up on their first SVC by which
+// started thread's stack the user SP falls under
+fn hthread_claim_pending(pid: u32, user_sp: u64) -> Option<HThread>
+{
+    unsafe
+    {
+        let mut best: Option<(u32, u32)> = None;
+        let mut best_dist = u64::MAX;
+        for (key, thread) in HTHREAD_PENDING.iter()
+        {
+            if key.0 != pid || !thread.started || user_sp > thread.stack_top {
+                continue;
+            }
+
+            let dist = thread.stack_top - user_sp;
+            if dist < best_dist {
+                best = Some(*key);
+                best_dist = dist;
+            }
+        }
+
+        return best.and_then(|key| HTHREAD_PENDING.remove(&key));
+    }
+}
+
+pub fn hthread_on_svc(pid: u32, thread_ctx: u64, frame: u64, tls: u64, svc_id: u8, ctx: &[u64])
+{
+    unsafe
+    {
+        let reused = match HTHREAD_RUNNING.get(&thread_ctx) {
+            Some(thread) => thread.pid != pid,
+            None => true
+        };
+
+        if reused
+        {
+            let user_sp = hthread_read_frame(frame, FRAME_SP).unwrap_or(u64::MAX);
+            let mut thread = match hthread_claim_pending(pid, user_sp) {
+                Some(thread) => thread,
+                None => HThread::new(pid)
+            };
+            thread.thread_ctx = thread_ctx;
+            HTHREAD_RUNNING.insert(thread_ctx, thread);
+        }
+
+        let thread = HTHREAD_RUNNING.get_mut(&thread_ctx).unwrap();
+        thread.tls = tls;
+        thread.frame = frame;
+        thread.last_svc = Some(svc_id);
+        thread.last_svc_ticks = get_ticks();
+        thread.last_args.copy_from_slice(&ctx[..8]);
+        thread.svc_count += 1;
+    }
+}
+
+pub fn hthread_created(pid: u32, handle: u32, entry: u64, arg: u64, stack_top: u64, priority: u32, ideal_core: u32)
+{
+    let mut thread = HThread::new(pid);
+    thread.handle = Some(handle);
+    thread.entry = entry;
+    thread.arg = arg;
+    thread.stack_top = stack_top;
+    thread.priority = priority;
+    thread.ideal_core = ideal_core;
+    if (ideal_core as i32) >= 0 {
+        thread.core_mask = 1 << ideal_core;
+    }
+
+    unsafe
+    {
+        HTHREAD_PENDING.insert((pid, handle), thread);
+    }
+}
+
+// Resolves a thread handle from `pid`'s point of view, `thread_ctx` being
+// the caller for the pseudo-handle
+fn hthread_get_handle_mut(pid: u32, thread_ctx: u64, handle: u32) -> Option<&'static mut HThread>
+{
+    unsafe
+    {
+        if handle == HTHREAD_PSEUDO_HANDLE {
+            return HTHREAD_RUNNING.get_mut(&thread_ctx);
+        }
+
+        if HTHREAD_PENDING.contains_key(&(pid, handle)) {
+            return HTHREAD_PENDING.get_mut(&(pid, handle));
+        }
+
+        return HTHREAD_RUNNING.values_mut().find(|thread| thread.pid == pid && thread.handle == Some(handle));
+    }
+}
+
+pub fn hthread_started(pid: u32, handle: u32)
+{
+    if let Some(thread) = hthread_get_handle_mut(pid, 0, handle) {
+        thread.started = true;
+    }
+}
+
+pub fn hthread_bind_tid(pid: u32, thread_ctx: u64, handle: u32, tid: u64)
+{
+    if let Some(thread) = hthread_get_handle_mut(pid, thread_ctx, handle) {
+        thread.kernel_tid = Some(tid);
+    }
+}
+
+pub fn hthread_set_priority(pid: u32, thread_ctx: u64, handle: u32, priority: u32)
+{
+    if let Some(thread) = hthread_get_handle_mut(pid, thread_ctx, handle) {
+        thread.priority = priority;
+    }
+}
+
+pub fn hthread_set_core_mask(pid: u32, thread_ctx: u64, handle: u32, ideal_core: u32, core_mask: u64)
+{
+    if let Some(thread) = hthread_get_handle_mut(pid, thread_ctx, handle) {
+        thread.ideal_core = ideal_core;
+        thread.core_mask = core_mask;
+    }
+}
+
+pub fn hthread_exited(thread_ctx: u64)
+{
+    unsafe
+    {
+        HTHREAD_RUNNING.remove(&thread_ctx);
+    }
+}
+
+pub fn hthread_process_exited(pid: u32)
+{
+    unsafe
+    {
+        HTHREAD_RUNNING.retain(|_, thread| thread.pid != pid);
+        HTHREAD_PENDING.retain(|key, _| key.0 != pid);
+    }
+}
+
+pub fn hthread_get(thread_ctx: u64) -> Option<HThread>
+{
+    unsafe { HTHREAD_RUNNING.get(&thread_ctx).cloned() }
+}
+
+pub fn hthread_find_id(id: u32) -> Option<HThread>
+{
+    unsafe { HTHREAD_RUNNING.values().find(|thread| thread.id == id).cloned() }
+}
+
+// TPIDRRO_EL0 is the only thing that tells threads apart outside of SVCs
+pub fn hthread_find_tls(pid: u32, tls: u64) -> Option<HThread>
+{
+    unsafe { HTHREAD_RUNNING.values().find(|thread| thread.pid == pid && thread.tls == tls).cloned() }
+}
+
+pub fn hthread_get_pid_threads(pid: u32) -> Vec<HThread>
+{
+    unsafe { HTHREAD_RUNNING.values().filter(|thread| thread.pid == pid).cloned().collect() }
+}
+
+// x0-x30, sp, pc and psr as the kernel last saved them
+pub fn hthread_read_frame_regs(frame: u64) -> Option<[u64; 34]>
+{
+    let mut regs: [u64; 34] = [0; 34];
+    for i in 0..31
+    {
+        regs[i] = hthread_read_frame(frame, (i * 8) as u64)?;
+    }
+    regs[31] = hthread_read_frame(frame, FRAME_SP)?;
+    regs[32] = hthread_read_frame(frame, FRAME_PC)?;
+    regs[33] = hthread_read_frame(frame, FRAME_PSR)? & 0xFFFFFFFF;
+    return Some(regs);
+}
+
+// Only has any effect while the thread is inside the kernel, which restores
+// user state from the frame on the way out
+pub fn hthread_write_frame_regs(frame: u64, regs: &[u64; 34]) -> bool
+{
+    for i in 0..31
+    {
+        if !hthread_write_frame(frame, (i * 8) as u64, regs[i]) {
+            return false;
+        }
+    }
+    return hthread_write_frame(frame, FRAME_SP, regs[31])
+        && hthread_write_frame(frame, FRAME_PC, regs[32])
+        && hthread_write_frame(frame, FRAME_PSR, regs[33]);
+}
+
+pub fn hthread_print_list(pid: u32)
+{
+    println!("  {:>4} {:16} {:>5} {:16} {:3} {:3} {:4} {:16} {:>8} {}", "id", "thread ctx", "tid", "entry", "pri", "core", "mask", "tls", "svcs", "last svc");
+    unsafe
+    {
+        for thread in HTHREAD_RUNNING.values()
+        {
+            if thread.pid == pid {
+                thread.print_row();
+            }
+        }
+
+        let pending = HTHREAD_PENDING.keys().filter(|key| key.0 == pid).count();
+        if pending != 0 {
+            println!("  ({} created but not seen yet)", pending);
+        }
+    }
+}
+
+pub fn hthread_print_info(id: u32) -> bool
+{
+    let thread = match unsafe { HTHREAD_RUNNING.values().find(|thread| thread.id == id) } {
+        Some(thread) => thread.clone(),
+        None => return false
+    };
+
+    println!("Thread {} (pid {}, thread ctx {:016x}):", thread.id, thread.pid, thread.thread_ctx);
+    if let Some(tid) = thread.kernel_tid {
+        println!("  Kernel TID: {}", tid);
+    }
+    if thread.handle.is_some() {
+        println!("  Entry:      {:016x}({:016x}), stack top {:016x}", thread.entry, thread.arg, thread.stack_top);
+    }
+    println!("  Priority:   {}, ideal core {}, core mask {:x}", thread.priority, thread.ideal_core as i32, thread.core_mask);
+    println!("  TLS:        {:016x}", thread.tls);
+    println!("  Last SVC:   {} {} ms ago, {} total", thread.get_last_svc_str(), ticks_to_ns(get_ticks() - thread.last_svc_ticks) / 1000000, thread.svc_count);
+    println!("  Last args:  {:016x} {:016x} {:016x} {:016x}", thread.last_args[0], thread.last_args[1], thread.last_args[2], thread.last_args[3]);
+    println!("              {:016x} {:016x} {:016x} {:016x}", thread.last_args[4], thread.last_args[5], thread.last_args[6], thread.last_args[7]);
+
+    // Whatever's in the frame now is the last context the kernel saved
+    if hthread_read_frame(thread.frame, 0).is_none() {
+        println!("  SVC frame at {:016x} isn't mapped", thread.frame);
+        return true;
+    }
+
+    println!("  Saved context ({:016x}):", thread.frame);
+    for i in 0..31
+    {
+        if (i % 4) == 0 {
+            print!("  ");
+        }
+        print!(" x{:<2} {:016x}", i, hthread_read_frame(thread.frame, i * 8).unwrap_or(0));
+        if (i % 4) == 3 || i == 30 {
+            println!("");
+        }
+    }
+    println!("   sp  {:016x} pc  {:016x} psr {:08x} tpidr {:016x}", hthread_read_frame(thread.frame, FRAME_SP).unwrap_or(0),
+             hthread_read_frame(thread.frame, FRAME_PC).unwrap_or(0), hthread_read_frame(thread.frame, FRAME_PSR).unwrap_or(0) & 0xFFFFFFFF,
+             hthread_read_frame(thread.frame, FRAME_TPIDR).unwrap_or(0));
+    return true;
+}
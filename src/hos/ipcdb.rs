@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use super::hipc::HIPCPacket;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum IpcArgDir
+{
+    In,
+    Out
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum IpcArgKind
+{
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    Bool,
+    Pid,
+    CopyHandle,
+    MoveHandle,
+    Object,
+    Static,
+    Send,
+    Recv,
+    Exch,
+    Pointer
+}
+
+pub struct IpcArgDesc
+{
+    pub dir: IpcArgDir,
+    pub kind: IpcArgKind,
+    pub name: &'static str, // interface short name for objects
+    pub offset: u16         // raw data offset, raw types only
+}
+
+pub struct IpcCmdDesc
+{
+    pub id: u32,
+    pub name: &'static str,
+    pub args: &'static [IpcArgDesc]
+}
+
+pub struct IpcInterfaceDesc
+{
+    pub name: &'static str,
+    pub short_name: &'static str,
+    pub cmds: &'static [IpcCmdDesc],
+    pub services: &'static [&'static str]
+}
+
+include!(concat!(env!("OUT_DIR"), "/ipcdb_gen.rs"));
+
+impl IpcInterfaceDesc
+{
+    pub fn get_cmd(&self, id: u32) -> Option<&'static IpcCmdDesc>
+    {
+        for cmd in self.cmds
+        {
+            if cmd.id == id {
+                return Some(cmd);
+            }
+        }
+        return None;
+    }
+}
+
+impl IpcCmdDesc
+{
+    pub fn get_out_object(&self) -> Option<&'static str>
+    {
+        for arg in self.args
+        {
+            if arg.dir == IpcArgDir::Out && arg.kind == IpcArgKind::Object {
+                return Some(arg.name);
+            }
+        }
+        return None;
+    }
+}
+
+// Accepts either the full name or the short name
+pub fn ipcdb_get_interface(name: &str) -> Option<&'static IpcInterfaceDesc>
+{
+    for iface in IPCDB_INTERFACES
+    {
+        if iface.name == name || iface.short_name == name {
+            return Some(iface);
+        }
+    }
+    return None;
+}
+
+pub fn ipcdb_get_service_interface(service: &str) -> Option<&'static IpcInterfaceDesc>
+{
+    for iface in IPCDB_INTERFACES
+    {
+        if iface.services.contains(&service) {
+            return Some(iface);
+        }
+    }
+    return None;
+}
+
+// Sessions opened through another interface don't carry a service name
+// of their own, so an explicit interface wins over the service's root
+pub fn ipcdb_lookup(service: &str, interface: &str) -> Option<&'static IpcInterfaceDesc>
+{
+    if !interface.is_empty() {
+        if let Some(iface) = ipcdb_get_interface(interface) {
+            return Some(iface);
+        }
+    }
+    return ipcdb_get_service_interface(service);
+}
+
+pub fn ipcdb_lookup_cmd(service: &str, interface: &str, cmd_id: u32) -> Option<&'static IpcCmdDesc>
+{
+    if let Some(iface) = ipcdb_lookup(service, interface) {
+        return iface.get_cmd(cmd_id);
+    }
+    return None;
+}
+
+pub fn ipcdb_cmd_name(service: &str, interface: &str, cmd_id: u32) -> String
+{
+    let iface_opt = ipcdb_lookup(service, interface);
+
+    let prefix = match iface_opt {
+        Some(iface) => String::from(iface.short_name),
+        None if !interface.is_empty() => String::from(interface),
+        None if !service.is_empty() => String::from(service),
+        None => String::from("unk")
+    };
+
+    if let Some(cmd) = iface_opt.and_then(|iface| iface.get_cmd(cmd_id)) {
+        return format!("{}::{}", prefix, cmd.name);
+    }
+    return format!("{}::Cmd{}", prefix, cmd_id);
+}
+
+// ie `setsys::GetSettingsItemValue(name=X(0x48), item_key=X(0x48), value=B(0x400))`
+pub fn ipcdb_format_request(service: &str, interface: &str, pkt: &HIPCPacket) -> String
+{
+    let cmd_id = pkt.get_cmd_id();
+    let mut out = ipcdb_cmd_name(service, interface, cmd_id);
+    out += "(";
+
+    let cmd = match ipcdb_lookup_cmd(service, interface, cmd_id) {
+        Some(cmd) => cmd,
+        None => {
+            out += "...)";
+            return out;
+        }
+    };
+
+    let mut num_static = 0;
+    let mut num_send = 0;
+    let mut num_recv = 0;
+    let mut num_exch = 0;
+    let mut num_out_pointer = 0;
+    let mut first = true;
+    for arg in cmd.args
+    {
+        // Out buffers are still described by the request
+        let is_buffer = match arg.kind {
+            IpcArgKind::Static | IpcArgKind::Send | IpcArgKind::Recv | IpcArgKind::Exch | IpcArgKind::Pointer => true,
+            _ => false
+        };
+        if arg.dir == IpcArgDir::Out && !is_buffer {
+            continue;
+        }
+
+        if !first {
+            out += ", ";
+        }
+        first = false;
+
+        let offs = arg.offset as usize;
+        let val = match arg.kind {
+            IpcArgKind::U8 => format!("{:#x}", pkt.read_u8(offs)),
+            IpcArgKind::U16 => format!("{:#x}", pkt.read_u16(offs)),
+            IpcArgKind::U32 => format!("{:#x}", pkt.read_u32(offs)),
+            IpcArgKind::U64 => format!("{:#x}", pkt.read_u64(offs)),
+            IpcArgKind::U128 => format!("{:016x}{:016x}", pkt.read_u64(offs + 8), pkt.read_u64(offs)),
+            IpcArgKind::Bool => format!("{}", pkt.read_u8(offs) != 0),
+            IpcArgKind::Pid => format!("{}", pkt.get_pid().unwrap_or(pkt.read_u64(offs))),
+            IpcArgKind::CopyHandle | IpcArgKind::MoveHandle => String::from("handle"),
+            IpcArgKind::Object => String::from("obj"),
+            IpcArgKind::Static | IpcArgKind::Pointer if arg.dir == IpcArgDir::Out => {
+                // Out pointers are receive list entries
+                num_out_pointer += 1;
+                match pkt.get_out_pointer(num_out_pointer - 1) {
+                    Some(desc) => format!("C({:#x})", desc.size),
+                    None => String::from("C(?)")
+                }
+            },
+            IpcArgKind::Static | IpcArgKind::Pointer => {
+                num_static += 1;
+                match pkt.get_static(num_static - 1) {
+                    Some(desc) => format!("X({:#x})", desc.size),
+                    None => String::from("X(?)")
+                }
+            },
+            IpcArgKind::Send => {
+                num_send += 1;
+                match pkt.get_send(num_send - 1) {
+                    Some(desc) => format!("A({:#x})", desc.size),
+                    None => String::from("A(?)")
+                }
+            },
+            IpcArgKind::Recv => {
+                num_recv += 1;
+                match pkt.get_recv(num_recv - 1) {
+                    Some(desc) => format!("B({:#x})", desc.size),
+                    None => String::from("B(?)")
+                }
+            },
+            IpcArgKind::Exch => {
+                num_exch += 1;
+                match pkt.get_exch(num_exch - 1) {
+                    Some(desc) => format!("W({:#x})", desc.size),
+                    None => String::from("W(?)")
+                }
+            }
+        };
+        out += &format!("{}={}", arg.name, val);
+    }
+    out += ")";
+
+    return out;
+}
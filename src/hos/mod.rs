@@ -5,12 +5,27 @@
  */
 
 pub mod hipc;
+pub mod hipcmem;
+pub mod hipcfuzz;
+pub mod hipcstat;
+pub mod ipcdb;
+pub mod result;
 pub mod kernel;
+pub mod firmware;
 pub mod smc;
 pub mod svc;
+pub mod svcsig;
 pub mod hhandle;
 pub mod hport;
+pub mod hprocess;
+pub mod hthread;
+pub mod hmemmap;
 pub mod hclientsession;
+pub mod hserversession;
 pub mod hdomainobj;
 pub mod hdomainsession;
 pub mod hsvc;
+pub mod hrules;
+pub mod hsvcinject;
+pub mod hmempolicy;
+pub mod hgdb;
@@ -0,0 +1,229 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// Also pulled into debug_client by path, so this only depends on
+// alloc's String and format!
+
+use alloc::string::String;
+
+pub const RESULT_MODULE_KERNEL: u32 = 1;
+pub const RESULT_MODULE_FS: u32 = 2;
+pub const RESULT_MODULE_OS: u32 = 3;
+pub const RESULT_MODULE_NCM: u32 = 5;
+pub const RESULT_MODULE_LR: u32 = 8;
+pub const RESULT_MODULE_LDR: u32 = 9;
+pub const RESULT_MODULE_SF: u32 = 10;
+pub const RESULT_MODULE_HIPC: u32 = 11;
+pub const RESULT_MODULE_DMNT: u32 = 15;
+pub const RESULT_MODULE_PM: u32 = 16;
+pub const RESULT_MODULE_SM: u32 = 21;
+pub const RESULT_MODULE_RO: u32 = 22;
+pub const RESULT_MODULE_SPL: u32 = 26;
+pub const RESULT_MODULE_SETTINGS: u32 = 105;
+
+pub const KERNEL_RESULT_TIMED_OUT: u32 = result_make(RESULT_MODULE_KERNEL, 117);
+pub const KERNEL_RESULT_CANCELLED: u32 = result_make(RESULT_MODULE_KERNEL, 118);
+pub const KERNEL_RESULT_CONNECTION_CLOSED: u32 = result_make(RESULT_MODULE_KERNEL, 123);
+
+static RESULT_MODULES: &[(u32, &str)] = &[
+    (RESULT_MODULE_KERNEL, "svc"),
+    (RESULT_MODULE_FS, "fs"),
+    (RESULT_MODULE_OS, "os"),
+    (RESULT_MODULE_NCM, "ncm"),
+    (RESULT_MODULE_LR, "lr"),
+    (RESULT_MODULE_LDR, "ldr"),
+    (RESULT_MODULE_SF, "sf"),
+    (RESULT_MODULE_HIPC, "hipc"),
+    (RESULT_MODULE_DMNT, "dmnt"),
+    (RESULT_MODULE_PM, "pm"),
+    (RESULT_MODULE_SM, "sm"),
+    (RESULT_MODULE_RO, "ro"),
+    (RESULT_MODULE_SPL, "spl"),
+    (RESULT_MODULE_SETTINGS, "settings"),
+];
+
+static RESULT_DESCS_KERNEL: &[(u32, &str)] = &[
+    (7, "OutOfSessions"),
+    (14, "InvalidArgument"),
+    (33, "NotImplemented"),
+    (54, "StopProcessingException"),
+    (57, "NoSynchronizationObject"),
+    (59, "TerminationRequested"),
+    (70, "NoEvent"),
+    (101, "InvalidSize"),
+    (102, "InvalidAddress"),
+    (103, "OutOfResource"),
+    (104, "OutOfMemory"),
+    (105, "OutOfHandles"),
+    (106, "InvalidCurrentMemory"),
+    (108, "InvalidNewMemoryPermission"),
+    (110, "InvalidMemoryRegion"),
+    (112, "InvalidPriority"),
+    (113, "InvalidCoreId"),
+    (114, "InvalidHandle"),
+    (115, "InvalidPointer"),
+    (116, "InvalidCombination"),
+    (117, "TimedOut"),
+    (118, "Cancelled"),
+    (119, "OutOfRange"),
+    (120, "InvalidEnumValue"),
+    (121, "NotFound"),
+    (122, "Busy"),
+    (123, "ConnectionClosed"),
+    (124, "NotHandled"),
+    (125, "InvalidState"),
+    (126, "ReservedUsed"),
+    (127, "NotSupported"),
+    (128, "Debug"),
+    (129, "NoThread"),
+    (130, "UnknownThread"),
+    (131, "PortClosed"),
+    (132, "LimitReached"),
+    (133, "InvalidMemoryPool"),
+    (258, "ReceiveListBroken"),
+    (259, "OutOfAddressSpace"),
+    (260, "MessageTooLarge"),
+    (517, "InvalidProcessId"),
+    (518, "InvalidThreadId"),
+    (519, "InvalidId"),
+    (520, "ProcessTerminated"),
+];
+
+static RESULT_DESCS_FS: &[(u32, &str)] = &[
+    (1, "PathNotFound"),
+    (2, "PathAlreadyExists"),
+    (7, "TargetLocked"),
+    (30, "UsableSpaceNotEnough"),
+];
+
+static RESULT_DESCS_LDR: &[(u32, &str)] = &[
+    (1, "ArgumentOverflow"),
+    (2, "ArgumentCountOverflow"),
+    (3, "MetaOverflow"),
+    (4, "InvalidMeta"),
+    (5, "InvalidNso"),
+    (6, "InvalidPath"),
+    (7, "TooManyProcesses"),
+    (8, "NotPinned"),
+    (9, "InvalidProgramId"),
+    (10, "InvalidVersion"),
+];
+
+static RESULT_DESCS_SF: &[(u32, &str)] = &[
+    (1, "NotSupported"),
+    (3, "PreconditionViolation"),
+    (202, "InvalidHeaderSize"),
+    (211, "InvalidInHeader"),
+    (221, "UnknownCommandId"),
+    (232, "InvalidOutRawSize"),
+    (235, "InvalidNumInObjects"),
+    (236, "InvalidNumOutObjects"),
+    (239, "InvalidInObject"),
+    (261, "TargetNotFound"),
+    (301, "OutOfDomainEntries"),
+];
+
+static RESULT_DESCS_HIPC: &[(u32, &str)] = &[
+    (102, "OutOfSessionMemory"),
+    (131, "OutOfSessions"),
+    (141, "PointerBufferTooSmall"),
+    (200, "OutOfDomains"),
+    (301, "SessionClosed"),
+    (402, "InvalidRequestSize"),
+    (403, "UnknownCommandType"),
+    (420, "InvalidCmifRequest"),
+    (491, "TargetNotDomain"),
+    (492, "DomainObjectNotFound"),
+];
+
+static RESULT_DESCS_PM: &[(u32, &str)] = &[
+    (1, "ProcessNotFound"),
+    (2, "AlreadyStarted"),
+    (3, "NotTerminated"),
+    (4, "DebugHookInUse"),
+    (5, "ApplicationRunning"),
+    (6, "InvalidSize"),
+];
+
+static RESULT_DESCS_SM: &[(u32, &str)] = &[
+    (1, "OutOfProcesses"),
+    (2, "InvalidClient"),
+    (3, "OutOfSessions"),
+    (4, "AlreadyRegistered"),
+    (5, "OutOfServices"),
+    (6, "InvalidServiceName"),
+    (7, "NotRegistered"),
+    (8, "NotAllowed"),
+    (9, "TooLargeAccessControl"),
+];
+
+pub const fn result_make(module: u32, desc: u32) -> u32
+{
+    (module & 0x1FF) | ((desc & 0x1FFF) << 9)
+}
+
+pub const fn result_module(rc: u32) -> u32
+{
+    rc & 0x1FF
+}
+
+pub const fn result_description(rc: u32) -> u32
+{
+    (rc >> 9) & 0x1FFF
+}
+
+fn result_table_lookup(table: &[(u32, &'static str)], val: u32) -> Option<&'static str>
+{
+    for (key, name) in table
+    {
+        if *key == val {
+            return Some(*name);
+        }
+    }
+    return None;
+}
+
+pub fn result_module_name(module: u32) -> Option<&'static str>
+{
+    result_table_lookup(RESULT_MODULES, module)
+}
+
+pub fn result_description_name(rc: u32) -> Option<&'static str>
+{
+    let table = match result_module(rc) {
+        RESULT_MODULE_KERNEL => RESULT_DESCS_KERNEL,
+        RESULT_MODULE_FS => RESULT_DESCS_FS,
+        RESULT_MODULE_LDR => RESULT_DESCS_LDR,
+        RESULT_MODULE_SF => RESULT_DESCS_SF,
+        RESULT_MODULE_HIPC => RESULT_DESCS_HIPC,
+        RESULT_MODULE_PM => RESULT_DESCS_PM,
+        RESULT_MODULE_SM => RESULT_DESCS_SM,
+        _ => return None
+    };
+    result_table_lookup(table, result_description(rc))
+}
+
+// Results every process sees constantly, not worth logging from SVC hooks
+pub fn result_is_routine(rc: u32) -> bool
+{
+    rc == KERNEL_RESULT_TIMED_OUT || rc == KERNEL_RESULT_CANCELLED || rc == KERNEL_RESULT_CONNECTION_CLOSED
+}
+
+// ie `svc:ConnectionClosed (0xf601)`, or `2124-0001 (0x27c)` for unknown modules
+pub fn result_format(rc: u32) -> String
+{
+    if rc == 0 {
+        return String::from("Success");
+    }
+
+    let module = result_module(rc);
+    let desc = result_description(rc);
+    match (result_module_name(module), result_description_name(rc)) {
+        (Some(module_name), Some(desc_name)) => format!("{}:{} ({:#x})", module_name, desc_name, rc),
+        (Some(module_name), None) => format!("{}:{} ({:#x})", module_name, desc, rc),
+        _ => format!("{:04}-{:04} ({:#x})", 2000 + module, desc, rc)
+    }
+}
@@ -0,0 +1,149 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// Also pulled into debug_client by path, so this only depends on
+// alloc's String and format!, and the generated table
+
+use alloc::string::String;
+use super::result::result_format;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SvcArgKind
+{
+    U32,
+    U64,
+    Handle,
+    Ptr,
+    Size,
+    StrPtr,
+    OutU32,
+    OutU64,
+    OutHandle,
+    OutPtr
+}
+
+// What x0 holds once the SVC returns
+#[derive(Copy, Clone, PartialEq)]
+pub enum SvcRet
+{
+    Result,
+    None, // void, or a plain value described by an out arg in x0
+    NoReturn
+}
+
+// `reg` is the register the kernel reads an in arg from, or writes an out
+// arg to. They're numbered independently, so an in and out arg can share one
+pub struct SvcArgDesc
+{
+    pub name: &'static str,
+    pub kind: SvcArgKind,
+    pub reg: u8
+}
+
+pub struct SvcSignature
+{
+    pub id: u8,
+    pub name: &'static str,
+    pub ret: SvcRet,
+    pub args: &'static [SvcArgDesc]
+}
+
+const fn svc_arg(name: &'static str, kind: SvcArgKind, reg: u8) -> SvcArgDesc
+{
+    SvcArgDesc { name: name, kind: kind, reg: reg }
+}
+
+impl SvcArgKind
+{
+    pub fn is_out(&self) -> bool
+    {
+        match self
+        {
+            SvcArgKind::OutU32 | SvcArgKind::OutU64 | SvcArgKind::OutHandle | SvcArgKind::OutPtr => true,
+            _ => false
+        }
+    }
+
+    pub fn format(&self, val: u64) -> String
+    {
+        match self
+        {
+            SvcArgKind::U32 | SvcArgKind::OutU32 => format!("{:#x}", val & 0xFFFFFFFF),
+            SvcArgKind::Handle | SvcArgKind::OutHandle => format!("h:{:x}", val & 0xFFFFFFFF),
+            _ => format!("{:#x}", val)
+        }
+    }
+}
+
+// AArch64 register layout, matching the kernel's SVC ABI. Generated from
+// svcdb.txt by build.rs (and debug_client's build.rs, which shares svcdb.rs)
+include!(concat!(env!("OUT_DIR"), "/svcsig_gen.rs"));
+
+pub fn svcsig_lookup(id: u8) -> Option<&'static SvcSignature>
+{
+    SVC_SIGNATURES.iter().find(|sig| sig.id == id)
+}
+
+pub fn svcsig_lookup_name(name: &str) -> Option<&'static SvcSignature>
+{
+    SVC_SIGNATURES.iter().find(|sig| sig.name.eq_ignore_ascii_case(name))
+}
+
+impl SvcSignature
+{
+    pub fn in_args(&self) -> impl Iterator<Item = &'static SvcArgDesc>
+    {
+        self.args.iter().filter(|arg| !arg.kind.is_out())
+    }
+
+    pub fn out_args(&self) -> impl Iterator<Item = &'static SvcArgDesc>
+    {
+        self.args.iter().filter(|arg| arg.kind.is_out())
+    }
+
+    // `ins` and `outs` are in the same order as the args, `text` stands in
+    // for the first string pointer if it could be read
+    pub fn format_call(&self, ins: &[u64], outs: &[u64], result: u32, text: Option<&str>) -> String
+    {
+        let mut out = format!("{}(", self.name);
+        for (i, (arg, val)) in self.in_args().zip(ins.iter()).enumerate()
+        {
+            if i != 0 {
+                out += ", ";
+            }
+            match (arg.kind, text) {
+                (SvcArgKind::StrPtr, Some(text)) => out += &format!("{}=\"{}\"", arg.name, text),
+                _ => out += &format!("{}={}", arg.name, arg.kind.format(*val))
+            }
+        }
+        out += ")";
+
+        match self.ret
+        {
+            SvcRet::NoReturn => return out,
+            SvcRet::Result => {
+                out += &format!(" -> {}", result_format(result));
+                if result != 0 {
+                    return out;
+                }
+            },
+            SvcRet::None => {}
+        }
+
+        let mut first = true;
+        for (arg, val) in self.out_args().zip(outs.iter())
+        {
+            out += if first { " [" } else { ", " };
+            out += &format!("{}={}", arg.name, arg.kind.format(*val));
+            first = false;
+        }
+        if !first {
+            out += "]";
+        }
+
+        return out;
+    }
+}
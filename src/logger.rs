@@ -394,6 +394,45 @@ pub fn log_cmd(data: &[u8])
     }
 }
 
+// Command packets have to fit in one 64-byte bulk transfer, so anything
+// bigger goes out as [1, len, id, flags, data...] pieces
+pub const LOG_CMD_CHUNK_FIRST: u8 = bit!(0);
+pub const LOG_CMD_CHUNK_LAST: u8 = bit!(1);
+pub const LOG_CMD_CHUNK_MAX: usize = 60;
+
+pub fn log_cmd_chunked(id: u8, data: &[u8])
+{
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() + ((data.len() / LOG_CMD_CHUNK_MAX) + 1) * 4);
+    
+    let mut offs = 0;
+    loop
+    {
+        let chunk_len = core::cmp::min(data.len() - offs, LOG_CMD_CHUNK_MAX);
+        
+        let mut flags = 0;
+        if offs == 0 {
+            flags |= LOG_CMD_CHUNK_FIRST;
+        }
+        if offs + chunk_len >= data.len() {
+            flags |= LOG_CMD_CHUNK_LAST;
+        }
+        
+        out.push(1);
+        out.push((chunk_len + 2) as u8);
+        out.push(id);
+        out.push(flags);
+        out.extend_from_slice(&data[offs..offs+chunk_len]);
+        
+        offs += chunk_len;
+        if offs >= data.len() {
+            break;
+        }
+    }
+    
+    // Single log_cmd so the pieces can't interleave with this core's other packets
+    log_cmd(&out);
+}
+
 pub fn log_unsafe(data: &str)
 {
     //log_uarta_raw(data.as_bytes());
@@ -65,6 +65,7 @@ use usbd::debug::*;
 use logger::*;
 use alloc::vec::Vec;
 use hos::kernel::KERNEL_START;
+use hos::firmware::{firmware_init, firmware_get};
 use task::*;
 use task::executor::*;
 use task::sleep::*;
@@ -74,6 +75,8 @@ use exception_handler::*;
 use vm::vsysreg::*;
 use crate::vm::vsmc::vsmc_get_warm_entrypoint;
 use modules::ipc::ipc_init;
+use modules::ipcstat::ipcstat_send_telemetry;
+use modules::svcprof::svcprof_send_telemetry;
 
 global_asm!(include_str!("start.s"));
 
@@ -193,7 +196,7 @@ pub extern "C" fn main_cold()
     
     // Set up guest vMMIO, vSVC allocations
     vmmio_init();
-    vsvc_init();
+    vsvc_init(KERN_DATA);
     ipc_init();
     
     // Start IRQs for USB and tasking
@@ -249,6 +252,9 @@ pub extern "C" fn main_cold()
     //
     // Patching and hooking time...
     //
+    firmware_init(KERN_DATA);
+    let profile = firmware_get();
+    
     println!("Begin copy to {:016x}... {:x}", ipaddr_to_paddr(KERNEL_START), peek32(to_u64ptr!(&KERN_DATA[0])));
     memcpy32(ipaddr_to_paddr(KERNEL_START), to_u64ptr!(&KERN_DATA[0]), KERN_DATA.len());
     
@@ -296,13 +302,14 @@ pub extern "C" fn main_cold()
     }
     
     // TODO find a search pattern for these
-    poke32(ipaddr_to_paddr(KERNEL_START) + 0x800 + 0x280, 0xd4000002); // EL1 IRQ
-    poke32(ipaddr_to_paddr(KERNEL_START) + 0x800 + 0x280 - 4, 0xd69f03e0); // EL1 IRQ ERET
-    poke32(ipaddr_to_paddr(KERNEL_START) + 0x800 + 0x480, 0xd4000002); // EL0 IRQ
-    poke32(ipaddr_to_paddr(KERNEL_START) + 0x800 + 0x480 - 4, 0xd69f03e0); // EL0 IRQ ERET
+    let vbar = ipaddr_to_paddr(KERNEL_START) + profile.vbar_offset;
+    poke32(vbar + profile.irq_el1_offset, 0xd4000002); // EL1 IRQ
+    poke32(vbar + profile.irq_el1_offset - 4, 0xd69f03e0); // EL1 IRQ ERET
+    poke32(vbar + profile.irq_el0_offset, 0xd4000002); // EL0 IRQ
+    poke32(vbar + profile.irq_el0_offset - 4, 0xd69f03e0); // EL0 IRQ ERET
     
-    //poke32(ipaddr_to_paddr(KERNEL_START) + 0x800 + 0x584, 0xd4000002); // lowerel serror
-    //poke32(ipaddr_to_paddr(KERNEL_START) + 0x800 + 0x784, 0xd4000002); // lowerel serror
+    //poke32(vbar + 0x584, 0xd4000002); // lowerel serror
+    //poke32(vbar + 0x784, 0xd4000002); // lowerel serror
 
     // Finalize things
     dcache_flush(ipaddr_to_paddr(KERNEL_START), 0x10000000);
@@ -356,6 +363,16 @@ async fn blink_task()
         
         log_cmd(&[1, 5, 0xFE, tasking_time[0], tasking_time[1], tasking_time[2], tasking_time[3]]);
         
+        // IPC stats go out about once a second
+        if (i % 12) == 0 {
+            ipcstat_send_telemetry(12 * 80);
+        }
+
+        // SVC profile, offset from the IPC stats so the two don't bunch up
+        if (i % 12) == 6 {
+            svcprof_send_telemetry();
+        }
+        
         // Let debugger know we're on home screen
         /*if vsvc_is_qlaunch_started() {
             log_cmd(&[1, 1, 0xFF]);
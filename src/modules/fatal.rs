@@ -13,64 +13,36 @@ use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::prelude::v1::Box;
 use crate::task::svc_wait::SvcWait;
-use crate::vm::vsvc::vsvc_get_curpid_name;
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_curpid_name};
+use crate::modules::ipcfuzz::{ipcfuzz_notify_crash, IPCFUZZ_REASON_FATAL};
 use crate::hos::hdomainobj::HDomainObj;
 use crate::hos::hdomainsession::HDomainSession;
 use crate::modules::ipc::*;
 use crate::hos::hsvc::hsvc_sleep_thread;
 use crate::hos::hipc::{HObject, HObjectExtra, HExtraString};
 use crate::util::*;
+use crate::hos::result::result_format;
 
 pub fn fatal_init()
 {
-    ipc_register_handler(String::from("fatal:u"), handle_fatal_boxed);
+    ipc_register_cmd_hook("fatal:u", "", 0, Some(fatal_throw_pre), None);
+    ipc_register_cmd_hook("fatal:u", "", 1, Some(fatal_throw_pre), None);
+    ipc_register_cmd_hook("fatal:u", "", 2, Some(fatal_throw_pre), None);
 }
 
-async fn handle_fatal(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
+fn fatal_throw_pre(req: &mut IpcRequest)
 {
-    let pkt = hipc_get_packet();
+    let error = req.read_u32(0);
+    let policy = req.read_u32(4);
+    let tid = req.read_u64(8);
     
-    let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
-    let hsession = hipc_get_handle_clientsession(handle).unwrap();
-    
-    match pkt.get_cmd_id()
+    let cmd_name = match req.get_cmd_id()
     {
-        0 => // ThrowFatal
-        {
-            let error = pkt.read_u32(0);
-            let policy = pkt.read_u32(4);
-            let tid = pkt.read_u64(8);
-
-            println_core!("fatal::ThrowFatal(0x{:x}, 0x{:x}, 0x{:x}) from `{}`", error, policy, tid, vsvc_get_curpid_name());
-
-            return pre_ctx;
-        },
-        1 => // ThrowFatalWithPolicy
-        {
-            let error = pkt.read_u32(0);
-            let policy = pkt.read_u32(4);
-            let tid = pkt.read_u64(8);
-            
-            println_core!("fatal::ThrowFatalWithPolicy(0x{:x}, 0x{:x}, 0x{:x}) from `{}`", error, policy, tid, vsvc_get_curpid_name());
-            
-            return pre_ctx;
-        },
-        2 => // ThrowFatalWithCpuContext
-        {
-            let error = pkt.read_u32(0);
-            let policy = pkt.read_u32(4);
-            let tid = pkt.read_u64(8);
-            
-            println_core!("fatal::ThrowFatalWithCpuContext(0x{:x}, 0x{:x}, 0x{:x}) from `{}`", error, policy, tid, vsvc_get_curpid_name());
-
-            return pre_ctx;
-        }
-        _ => { return pre_ctx; }
-    }
-
-    return pre_ctx;
-}
+        0 => "ThrowFatal",
+        1 => "ThrowFatalWithPolicy",
+        _ => "ThrowFatalWithCpuContext"
+    };
 
-fn handle_fatal_boxed(mut pre_ctx: [u64; 32], hobj: HObject) -> Pin<Box<dyn Future<Output = [u64; 32]> + Send>> {
-    Box::pin(handle_fatal(pre_ctx, hobj))
+    println_core!("fatal::{}({}, 0x{:x}, 0x{:x}) from `{}`", cmd_name, result_format(error), policy, tid, vsvc_get_curpid_name());
+    ipcfuzz_notify_crash(vsvc_get_curpid(), IPCFUZZ_REASON_FATAL);
 }
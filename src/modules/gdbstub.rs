@@ -0,0 +1,684 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+// Hooks the RSP session from hos::hgdb up to the guest. Breakpoints are
+// `brk #0` patched into the process, and MDCR_EL2.TDE routes them (and
+// single steps) to us while a session is attached. Stopped threads either
+// spin on a `brk` or sleep at the start of their next SVC, so halting only
+// catches threads as they get to one of those.
+
+use alloc::vec::Vec;
+use alloc::string::String;
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crate::logger::*;
+use crate::util::*;
+use crate::arm::threading::*;
+use crate::arm::exceptions::{get_far_el2, get_spsr_el1};
+use crate::arm::cache::{dcache_flush, icache_invalidate};
+use crate::hos::hgdb::*;
+use crate::hos::hsvc::{hsvc_sleep_thread, hsvc_restart};
+use crate::hos::hthread::*;
+use crate::hos::hmemmap::hmemmap_translate;
+use crate::hos::hprocess::hprocess_is_64bit;
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_pid_list, vsvc_get_pid_name, vsvc_get_pid_ttbr};
+
+pub const LOG_CMD_GDB: u8 = 0x16;
+
+const GDBSTUB_BRK: u32 = 0xd4200000; // brk #0
+const GDBSTUB_PARK_NS: u64 = 1000000;
+
+// start.s keeps guest x29 past the end of the usual exception context
+const CTX_X29: usize = 35;
+
+const SPSR_SS: u64 = 1 << 21;
+const SPSR_IRQ_MASK: u64 = (1 << 7) | (1 << 6); // I, F
+const SPSR_NZCV: u64 = 0xF0000000;
+const MDCR_EL2_TDE: u64 = 1 << 8;
+const MDSCR_EL1_SS: u64 = 1 << 0;
+
+struct GdbStubBp
+{
+    insn: u32,
+    user: bool, // set by gdb, otherwise only there for threads to spin on
+    step_tid: Option<u32>, // catches a step that went into the kernel
+}
+
+#[derive(Copy, Clone)]
+enum GdbStubStepKind
+{
+    Thread(u32),
+    Reinsert, // let another thread past `pc`
+}
+
+#[derive(Copy, Clone)]
+struct GdbStubStep
+{
+    kind: GdbStubStepKind,
+    pc: u64,
+    irq_bits: u64, // what SPSR.I/F were before we masked them for the step
+}
+
+struct GdbStubTarget
+{
+    pid: Option<u32>,
+    halted: bool,
+    step_tid: Option<u32>,
+    bps: BTreeMap<u64, GdbStubBp>,
+
+    // Threads spinning on a `brk`, with the registers they'll get back
+    trapped: BTreeMap<u32, [u64; GDB_NUM_REGS]>,
+    // Resumed, but haven't come back around to the `brk` to pick them up yet
+    released: BTreeMap<u32, [u64; GDB_NUM_REGS]>,
+    // Sleeping at the start of an SVC, thread ID to SVC frame
+    svc_parked: BTreeMap<u32, u64>,
+}
+
+struct GdbStub
+{
+    session: GdbSession,
+    target: GdbStubTarget,
+}
+
+static GDBSTUB: spin::Mutex<GdbStub> = spin::Mutex::new(GdbStub { session: GdbSession::new(), target: GdbStubTarget::new() });
+static GDBSTUB_ACTIVE: AtomicBool = AtomicBool::new(false);
+static GDBSTUB_CORES_TDE: AtomicU32 = AtomicU32::new(0); // bit per core
+static mut GDBSTUB_CORE_STEP: [Option<GdbStubStep>; 4] = [None; 4];
+
+fn gdbstub_send(out: Vec<u8>)
+{
+    if !out.is_empty() {
+        log_cmd_chunked(LOG_CMD_GDB, &out);
+    }
+}
+
+// Swaps the instruction at `addr`, returns what was there
+fn gdbstub_patch(pid: u32, addr: u64, insn: u32) -> Option<u32>
+{
+    if (addr & 3) != 0 {
+        return None;
+    }
+
+    let paddr = hmemmap_translate(pid, addr)?;
+    let old = peek32(paddr);
+    poke32(paddr, insn);
+    dcache_flush(paddr, 4);
+    icache_invalidate(paddr, 4);
+    return Some(old);
+}
+
+fn gdbstub_regs_from_ctx(ctx: &[u64]) -> [u64; GDB_NUM_REGS]
+{
+    let mut regs: [u64; GDB_NUM_REGS] = [0; GDB_NUM_REGS];
+    regs[..29].copy_from_slice(&ctx[..29]);
+    regs[29] = ctx[CTX_X29];
+    regs[30] = ctx[30];
+    regs[GDB_REG_SP] = get_sp_el0();
+    regs[GDB_REG_PC] = ctx[31];
+    regs[GDB_REG_CPSR] = ctx[32] & 0xFFFFFFFF;
+    return regs;
+}
+
+// Returns the PC to go back to
+fn gdbstub_regs_to_ctx(ctx: &mut [u64], regs: &[u64; GDB_NUM_REGS]) -> u64
+{
+    ctx[..29].copy_from_slice(&regs[..29]);
+    ctx[CTX_X29] = regs[29];
+    ctx[30] = regs[30];
+    sysreg_write!("sp_el0", regs[GDB_REG_SP]);
+
+    // Only the flags are gdb's to change
+    ctx[32] = (ctx[32] & !SPSR_NZCV) | (regs[GDB_REG_CPSR] & SPSR_NZCV);
+    return regs[GDB_REG_PC];
+}
+
+// Runs exactly one EL0 instruction with interrupts held off, so the step
+// exception comes back on this core
+fn gdbstub_arm_step(ctx: &mut [u64], kind: GdbStubStepKind, pc: u64)
+{
+    unsafe
+    {
+        GDBSTUB_CORE_STEP[get_core() as usize] = Some(GdbStubStep
+        {
+            kind: kind,
+            pc: pc,
+            irq_bits: ctx[32] & SPSR_IRQ_MASK,
+        });
+    }
+
+    sysreg_or64!("mdscr_el1", MDSCR_EL1_SS);
+    isb();
+    ctx[32] |= SPSR_SS | SPSR_IRQ_MASK;
+}
+
+impl GdbStubTarget
+{
+    const fn new() -> GdbStubTarget
+    {
+        GdbStubTarget
+        {
+            pid: None,
+            halted: false,
+            step_tid: None,
+            bps: BTreeMap::new(),
+            trapped: BTreeMap::new(),
+            released: BTreeMap::new(),
+            svc_parked: BTreeMap::new(),
+        }
+    }
+
+    fn reset(&mut self)
+    {
+        *self = GdbStubTarget::new();
+        GDBSTUB_ACTIVE.store(false, Ordering::Relaxed);
+    }
+
+    fn is_parked_at(&self, addr: u64) -> bool
+    {
+        self.trapped.values().chain(self.released.values()).any(|regs| regs[GDB_REG_PC] == addr)
+    }
+
+    // Puts the instruction back once nothing needs the `brk` anymore
+    fn sync_bp(&mut self, pid: u32, addr: u64)
+    {
+        let unused = match self.bps.get(&addr) {
+            Some(bp) => !bp.user && bp.step_tid.is_none() && !self.is_parked_at(addr),
+            None => false
+        };
+
+        if unused {
+            let bp = self.bps.remove(&addr).unwrap();
+            gdbstub_patch(pid, addr, bp.insn);
+        }
+    }
+
+    // A `brk` for a stopped thread to spin on
+    fn pin(&mut self, pid: u32, addr: u64) -> bool
+    {
+        if self.bps.contains_key(&addr) {
+            return true;
+        }
+
+        match gdbstub_patch(pid, addr, GDBSTUB_BRK) {
+            Some(insn) => {
+                self.bps.insert(addr, GdbStubBp { insn: insn, user: false, step_tid: None });
+                return true;
+            },
+            None => return false
+        };
+    }
+
+    fn stop(&mut self, session: &mut GdbSession, tid: u32, regs: [u64; GDB_NUM_REGS]) -> Vec<u8>
+    {
+        self.trapped.insert(tid, regs);
+
+        // Something else already stopped the process, gdb hears about this
+        // one when it gets resumed and trips again
+        if self.halted {
+            return Vec::new();
+        }
+
+        self.halted = true;
+        self.step_tid = None;
+        return session.notify_stop(tid, GDB_SIGTRAP);
+    }
+}
+
+impl GdbTarget for GdbStubTarget
+{
+    fn get_processes(&mut self) -> Vec<(u32, String)>
+    {
+        vsvc_get_pid_list().into_iter()
+                           .filter(|pid| hprocess_is_64bit(*pid) && vsvc_get_pid_ttbr(*pid) != 0)
+                           .map(|pid| (pid, vsvc_get_pid_name(pid)))
+                           .collect()
+    }
+
+    fn get_threads(&mut self, pid: u32) -> Vec<u32>
+    {
+        hthread_get_pid_threads(pid).iter().map(|thread| thread.id).collect()
+    }
+
+    fn attach(&mut self, pid: u32) -> bool
+    {
+        if !self.get_processes().iter().any(|process| process.0 == pid) {
+            return false;
+        }
+
+        self.reset();
+        self.pid = Some(pid);
+        GDBSTUB_ACTIVE.store(true, Ordering::Relaxed);
+        println_core!("gdbstub: attached to `{}` (pid {})", vsvc_get_pid_name(pid), pid);
+        return true;
+    }
+
+    fn detach(&mut self, pid: u32)
+    {
+        // Anything still spinning runs off with the registers it last had
+        for (addr, bp) in self.bps.iter()
+        {
+            gdbstub_patch(pid, *addr, bp.insn);
+        }
+
+        self.reset();
+        println_core!("gdbstub: detached from `{}` (pid {})", vsvc_get_pid_name(pid), pid);
+    }
+
+    fn halt(&mut self, pid: u32) -> Option<u32>
+    {
+        self.halted = true;
+        self.step_tid = None;
+
+        if let Some(tid) = self.trapped.keys().next() {
+            return Some(*tid);
+        }
+        return self.get_threads(pid).first().copied();
+    }
+
+    fn resume(&mut self, _pid: u32, step: Option<u32>)
+    {
+        let trapped = core::mem::replace(&mut self.trapped, BTreeMap::new());
+        self.released.extend(trapped);
+        self.step_tid = step;
+        self.halted = false;
+    }
+
+    fn read_regs(&mut self, pid: u32, tid: u32) -> Option<[u64; GDB_NUM_REGS]>
+    {
+        if let Some(regs) = self.trapped.get(&tid).or(self.released.get(&tid)) {
+            return Some(*regs);
+        }
+
+        // Anything else has its registers from its last SVC
+        let thread = hthread_find_id(tid)?;
+        if thread.pid != pid {
+            return None;
+        }
+        return hthread_read_frame_regs(thread.frame);
+    }
+
+    fn write_regs(&mut self, _pid: u32, tid: u32, regs: &[u64; GDB_NUM_REGS]) -> bool
+    {
+        if let Some(trapped) = self.trapped.get_mut(&tid) {
+            *trapped = *regs;
+            return true;
+        }
+
+        let frame = match self.svc_parked.get(&tid) {
+            Some(frame) => *frame,
+            None => return false
+        };
+        let old_regs = match hthread_read_frame_regs(frame) {
+            Some(old_regs) => old_regs,
+            None => return false
+        };
+
+        let mut new_regs = *regs;
+        new_regs[GDB_REG_CPSR] = (old_regs[GDB_REG_CPSR] & !SPSR_NZCV) | (regs[GDB_REG_CPSR] & SPSR_NZCV);
+        return hthread_write_frame_regs(frame, &new_regs);
+    }
+
+    fn read_mem(&mut self, pid: u32, addr: u64, len: usize) -> Vec<u8>
+    {
+        let mut out: Vec<u8> = Vec::with_capacity(len);
+        while out.len() < len
+        {
+            let vaddr = addr + out.len() as u64;
+            let chunk = core::cmp::min(len - out.len(), 0x1000 - (vaddr & 0xFFF) as usize);
+            let paddr = match hmemmap_translate(pid, vaddr) {
+                Some(paddr) => paddr,
+                None => break
+            };
+
+            for i in 0..chunk
+            {
+                out.push(peek8(paddr + i as u64));
+            }
+        }
+
+        // gdb shouldn't see its own breakpoints, or ours
+        for (bp_addr, bp) in self.bps.range(addr.saturating_sub(3)..addr + out.len() as u64)
+        {
+            for (i, byte) in bp.insn.to_le_bytes().iter().enumerate()
+            {
+                let offs = (bp_addr + i as u64).wrapping_sub(addr);
+                if offs < out.len() as u64 {
+                    out[offs as usize] = *byte;
+                }
+            }
+        }
+        return out;
+    }
+
+    fn write_mem(&mut self, pid: u32, addr: u64, data: &[u8]) -> bool
+    {
+        let mut offs = 0;
+        while offs < data.len()
+        {
+            let vaddr = addr + offs as u64;
+            let chunk = core::cmp::min(data.len() - offs, 0x1000 - (vaddr & 0xFFF) as usize);
+            let paddr = match hmemmap_translate(pid, vaddr) {
+                Some(paddr) => paddr,
+                None => return false
+            };
+
+            for i in 0..chunk
+            {
+                poke8(paddr + i as u64, data[offs + i]);
+            }
+            dcache_flush(paddr, chunk);
+            icache_invalidate(paddr, chunk);
+            offs += chunk;
+        }
+
+        // Whatever got written over a breakpoint is what it puts back later
+        for (bp_addr, bp) in self.bps.range_mut(addr.saturating_sub(3)..addr + data.len() as u64)
+        {
+            if let Some(insn) = gdbstub_patch(pid, *bp_addr, GDBSTUB_BRK) {
+                bp.insn = insn;
+            }
+        }
+        return true;
+    }
+
+    fn insert_breakpoint(&mut self, pid: u32, addr: u64) -> bool
+    {
+        if let Some(bp) = self.bps.get_mut(&addr) {
+            bp.user = true;
+            return true;
+        }
+
+        match gdbstub_patch(pid, addr, GDBSTUB_BRK) {
+            Some(insn) => {
+                self.bps.insert(addr, GdbStubBp { insn: insn, user: true, step_tid: None });
+                return true;
+            },
+            None => return false
+        };
+    }
+
+    fn remove_breakpoint(&mut self, pid: u32, addr: u64) -> bool
+    {
+        match self.bps.get_mut(&addr) {
+            Some(bp) if bp.user => bp.user = false,
+            _ => return false
+        };
+
+        // Stays a `brk` underneath for as long as threads are parked on it
+        self.sync_bp(pid, addr);
+        return true;
+    }
+}
+
+// Whether gdbstub_sync_core has anything to do: a session is up, or cores
+// still have TDE set from one that went away. Cheap enough for every exception
+pub fn gdbstub_sync_pending() -> bool
+{
+    GDBSTUB_ACTIVE.load(Ordering::Relaxed) || GDBSTUB_CORES_TDE.load(Ordering::Relaxed) != 0
+}
+
+// Each core picks up TDE (and drops it again) on its next exception after
+// a session attaches or goes away
+pub fn gdbstub_sync_core()
+{
+    let core_bit = 1 << get_core();
+    let active = GDBSTUB_ACTIVE.load(Ordering::Relaxed);
+    let has_tde = (GDBSTUB_CORES_TDE.load(Ordering::Relaxed) & core_bit) != 0;
+    if has_tde == active {
+        return;
+    }
+
+    if active {
+        GDBSTUB_CORES_TDE.fetch_or(core_bit, Ordering::Relaxed);
+        sysreg_write!("oslar_el1", 0);
+        sysreg_or64!("mdcr_el2", MDCR_EL2_TDE);
+    }
+    else {
+        GDBSTUB_CORES_TDE.fetch_and(!core_bit, Ordering::Relaxed);
+        sysreg_and64!("mdscr_el1", !MDSCR_EL1_SS);
+        sysreg_and64!("mdcr_el2", !MDCR_EL2_TDE);
+    }
+    isb();
+}
+
+pub fn gdbstub_recv(data: &[u8])
+{
+    let out = {
+        let mut lock = GDBSTUB.lock();
+        let stub = &mut *lock;
+        stub.session.feed(&mut stub.target, data)
+    };
+    gdbstub_send(out);
+}
+
+pub fn gdbstub_process_exited(pid: u32)
+{
+    let out = {
+        let mut lock = GDBSTUB.lock();
+        let stub = &mut *lock;
+        if stub.target.pid != Some(pid) {
+            return;
+        }
+
+        stub.target.reset();
+        stub.session.notify_exit(pid, 0)
+    };
+    gdbstub_send(out);
+}
+
+// BRK from EL0. Returns where the thread goes next, or None if the `brk`
+// isn't one of ours
+pub fn gdbstub_handle_brk(ctx: &mut [u64]) -> Option<u64>
+{
+    // AArch64 EL0 only
+    if (ctx[32] & 0x1F) != 0 {
+        return None;
+    }
+
+    let pid = vsvc_get_curpid();
+    let pc = ctx[31];
+    let mut lock = GDBSTUB.lock();
+    let stub = &mut *lock;
+    let target = &mut stub.target;
+    if target.pid != Some(pid) || !target.bps.contains_key(&pc) {
+        return None;
+    }
+
+    // Threads that never made an SVC have nothing to be reported as, they
+    // just wait here until the breakpoint goes away
+    let tid = match hthread_find_tls(pid, get_tls_el0()) {
+        Some(thread) => thread.id,
+        None => return Some(pc)
+    };
+
+    if let Some(regs) = target.released.remove(&tid) {
+        let ret_pc = gdbstub_regs_to_ctx(ctx, &regs);
+        target.sync_bp(pid, pc);
+        if target.step_tid == Some(tid) {
+            gdbstub_arm_step(ctx, GdbStubStepKind::Thread(tid), ret_pc);
+        }
+        return Some(ret_pc);
+    }
+    if let Some(regs) = target.trapped.get(&tid) {
+        return Some(gdbstub_regs_to_ctx(ctx, regs));
+    }
+
+    let bp = target.bps.get_mut(&pc).unwrap();
+    if bp.step_tid == Some(tid) || bp.user {
+        if bp.step_tid == Some(tid) {
+            bp.step_tid = None;
+        }
+
+        let out = target.stop(&mut stub.session, tid, gdbstub_regs_from_ctx(ctx));
+        drop(lock);
+        gdbstub_send(out);
+        return Some(pc);
+    }
+
+    if target.halted {
+        target.trapped.insert(tid, gdbstub_regs_from_ctx(ctx));
+    }
+    else if bp.step_tid.is_some() {
+        // Someone else's step catcher, step this thread past it
+        gdbstub_patch(pid, pc, bp.insn);
+        gdbstub_arm_step(ctx, GdbStubStepKind::Reinsert, pc);
+    }
+
+    // Otherwise it's a leftover from a thread that was parked here, it goes
+    // away once the last one leaves
+    return Some(pc);
+}
+
+pub fn gdbstub_is_stepping() -> bool
+{
+    unsafe { GDBSTUB_CORE_STEP[get_core() as usize].is_some() }
+}
+
+// Software step we armed. Comes from EL0 once the instruction ran, or from
+// EL1 if the instruction went into the kernel instead
+pub fn gdbstub_handle_step(ctx: &mut [u64]) -> u64
+{
+    let step = match unsafe { GDBSTUB_CORE_STEP[get_core() as usize].take() } {
+        Some(step) => step,
+        None => return ctx[31]
+    };
+
+    sysreg_and64!("mdscr_el1", !MDSCR_EL1_SS);
+    isb();
+    ctx[32] &= !SPSR_SS;
+
+    let from_el0 = (ctx[32] & 0xF) == 0;
+    if from_el0 {
+        ctx[32] = (ctx[32] & !SPSR_IRQ_MASK) | step.irq_bits;
+    }
+    else {
+        // The kernel hasn't gotten around to saving EL0's PSTATE yet
+        sysreg_write!("spsr_el1", (get_spsr_el1() & !(SPSR_SS | SPSR_IRQ_MASK)) | step.irq_bits);
+    }
+
+    let pid = vsvc_get_curpid();
+    let mut lock = GDBSTUB.lock();
+    let stub = &mut *lock;
+    let target = &mut stub.target;
+    if target.pid != Some(pid) {
+        return ctx[31];
+    }
+
+    match step.kind {
+        GdbStubStepKind::Reinsert => {
+            if target.bps.contains_key(&step.pc) {
+                gdbstub_patch(pid, step.pc, GDBSTUB_BRK);
+            }
+        },
+        GdbStubStepKind::Thread(tid) if from_el0 => {
+            let pc = ctx[31];
+            target.pin(pid, pc);
+            let out = target.stop(&mut stub.session, tid, gdbstub_regs_from_ctx(ctx));
+            drop(lock);
+            gdbstub_send(out);
+        },
+        GdbStubStepKind::Thread(tid) => {
+            // SVCs and faults both come back to the next instruction, which
+            // is as good as a finished step
+            let pc = step.pc + 4;
+            if target.pin(pid, pc) {
+                target.bps.get_mut(&pc).unwrap().step_tid = Some(tid);
+            }
+        },
+    };
+    return ctx[31];
+}
+
+// Debug exceptions that aren't ours go to EL1 like they would without TDE
+pub fn gdbstub_reflect(ctx: &mut [u64]) -> u64
+{
+    let spsr = ctx[32];
+    let mut esr = ctx[34] & 0xFFFFFFFF;
+    let ec = (esr >> 26) & 0x3F;
+    let from_el0 = (spsr & 0xC) == 0;
+
+    let vector = if (spsr & 0x10) != 0 {
+        0x600
+    }
+    else if from_el0 {
+        0x400
+    }
+    else if (spsr & 1) != 0 {
+        0x200
+    }
+    else {
+        0x0
+    };
+
+    // Breakpoint, step and watchpoint ECs are one higher when taken from
+    // the same EL, BRK and BKPT are the same either way
+    if !from_el0 && ec != 0x3C && ec != 0x38 {
+        esr += 1 << 26;
+    }
+    if ec == 0x34 {
+        sysreg_write!("far_el1", get_far_el2());
+    }
+
+    sysreg_write!("elr_el1", ctx[31]);
+    sysreg_write!("spsr_el1", spsr);
+    sysreg_write!("esr_el1", esr);
+    ctx[32] = 0x3C5; // EL1h, DAIF masked
+
+    return sysreg_read!("vbar_el1") + vector;
+}
+
+// Thread ID to park at this SVC, if its process is stopped
+pub fn gdbstub_svc_park(thread_ctx: u64) -> Option<u32>
+{
+    if !GDBSTUB_ACTIVE.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let mut lock = GDBSTUB.lock();
+    if lock.target.pid != Some(vsvc_get_curpid()) || !lock.target.halted {
+        return None;
+    }
+
+    let thread = hthread_get(thread_ctx)?;
+    lock.target.svc_parked.insert(thread.id, thread.frame);
+    return Some(thread.id);
+}
+
+fn gdbstub_svc_unpark(tid: u32) -> bool
+{
+    let mut lock = GDBSTUB.lock();
+    let target = &mut lock.target;
+    if target.halted {
+        return false;
+    }
+
+    let frame = match target.svc_parked.remove(&tid) {
+        Some(frame) => frame,
+        None => return true
+    };
+
+    // Stepping out of an SVC stops wherever it returns to
+    if target.step_tid == Some(tid) {
+        let pid = target.pid.unwrap_or(0);
+        if let Some(pc) = hthread_read_frame_regs(frame).map(|regs| regs[GDB_REG_PC]) {
+            if target.pin(pid, pc) {
+                target.bps.get_mut(&pc).unwrap().step_tid = Some(tid);
+            }
+        }
+    }
+    return true;
+}
+
+// Sleeps in short bits for as long as the process stays stopped, then lets
+// the SVC through the usual handlers
+pub async fn gdbstub_park_task(mut pre_ctx: [u64; 32], tid: u32) -> [u64; 32]
+{
+    while !gdbstub_svc_unpark(tid)
+    {
+        pre_ctx = hsvc_sleep_thread(pre_ctx, GDBSTUB_PARK_NS).await;
+    }
+    return hsvc_restart(pre_ctx);
+}
@@ -0,0 +1,92 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::vm::vsvc::vsvc_get_curpid_name;
+use crate::modules::ipc::*;
+use crate::arm::mmu::translate_el0_stage12;
+use crate::hos::result::{result_make, RESULT_MODULE_KERNEL};
+use crate::util::*;
+
+const HTBDBG_DUMP_MAX: u64 = 0x1000;
+
+// Serviced entirely by the hypervisor, homebrew can use `htb:dbg` like
+// any other service
+pub fn htbdbg_init()
+{
+    ipc_register_emulated_service(String::from("htb:dbg"), handle_htbdbg);
+}
+
+fn htbdbg_version() -> u32
+{
+    let major = env!("CARGO_PKG_VERSION_MAJOR").parse::<u32>().unwrap_or(0);
+    let minor = env!("CARGO_PKG_VERSION_MINOR").parse::<u32>().unwrap_or(0);
+    let patch = env!("CARGO_PKG_VERSION_PATCH").parse::<u32>().unwrap_or(0);
+
+    return (major << 16) | (minor << 8) | patch;
+}
+
+fn htbdbg_dump_memory(addr: u64, size: u64) -> bool
+{
+    // The range may not be physically contiguous, so translate as we go
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for i in 0..size
+    {
+        let paddr = translate_el0_stage12(addr + i);
+        if paddr < 0x80000000 {
+            return false;
+        }
+
+        if i != 0 && (i % 16) == 0 {
+            lines.push(line);
+            line = String::new();
+        }
+        line += &format!(" {:02x}", peek8(paddr));
+    }
+    lines.push(line);
+
+    println_core!("htb:dbg dump of {:016x}-{:016x} from `{}`:", addr, addr + size, vsvc_get_curpid_name());
+    for (i, line) in lines.iter().enumerate()
+    {
+        println!("{:016x}:{}", addr + (i as u64) * 16, line);
+    }
+    return true;
+}
+
+fn handle_htbdbg(req: &IpcRequest, resp: &mut IpcResponse)
+{
+    match req.get_cmd_id()
+    {
+        0 => // Log
+        {
+            if let Some(desc) = req.get_static(0) {
+                println_core!("htb:dbg [{}] {}", vsvc_get_curpid_name(), desc.read_str());
+            }
+        },
+        1 => // GetVersion
+        {
+            resp.write_u32(0, htbdbg_version());
+        },
+        2 => // DumpMemory
+        {
+            let addr = req.read_u64(0);
+            let size = req.read_u64(8);
+
+            if size > HTBDBG_DUMP_MAX {
+                resp.set_result(result_make(RESULT_MODULE_KERNEL, 101)); // InvalidSize
+            }
+            else if !htbdbg_dump_memory(addr, size) {
+                resp.set_result(result_make(RESULT_MODULE_KERNEL, 102)); // InvalidAddress
+            }
+        },
+        _ =>
+        {
+            resp.set_result(result_make(RESULT_MODULE_KERNEL, 33)); // NotImplemented
+        }
+    }
+}
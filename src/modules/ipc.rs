@@ -5,16 +5,21 @@
  */
 
 use core::{future::Future, pin::Pin};
+use core::ops::{Deref, DerefMut};
 use crate::hos::{hport::HPort, hhandle::HHandle, hclientsession::HClientSession, hclientsession::HClientSessionHandler};
 use spin::mutex::Mutex;
 use crate::hos::hipc::{PKT_TYPE_INVALID, PKT_TYPE_LEGACYREQEST, PKT_TYPE_CLOSE, PKT_TYPE_LEGACYCONTROL, PKT_TYPE_REQUEST, PKT_TYPE_CONTROL, PKT_TYPE_REQUESTWITHCONTEXT, PKT_TYPE_CONTROLWITHCONTEXT, DOMAIN_CMD_SEND, DOMAIN_CMD_CLOSEOBJ};
-use crate::hos::hipc::{HObject, HObjectExtra, hipc_get_handle_clientsession, hipc_get_named_serverport, hipc_register_handle_clientsession, hipc_get_packet, hipc_close_handle, hipc_register_domain, hipc_remove_domain, hipc_get_domain_session};
+use crate::hos::hipc::{HObject, HObjectExtra, HIPCPacket, hipc_register_handle_serverport, hipc_get_handle_clientsession, hipc_get_named_serverport, hipc_register_handle_clientsession, hipc_get_packet, hipc_get_response, hipc_close_handle, hipc_register_domain, hipc_remove_domain, hipc_get_domain_session};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::collections::BTreeMap;
 use alloc::prelude::v1::Box;
 use crate::task::svc_wait::SvcWait;
-use crate::vm::vsvc::vsvc_get_curpid_name;
+use crate::hos::hsvc::hsvc_return_early;
+use crate::hos::result::{result_make, RESULT_MODULE_SF};
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_curpid_name};
+use crate::arm::ticks::get_ticks;
+use alloc::vec::Vec;
 use crate::hos::hdomainobj::HDomainObj;
 use crate::hos::hdomainsession::HDomainSession;
 use crate::modules::fsp::fsp_init;
@@ -23,8 +28,90 @@ use crate::modules::log::log_init;
 use crate::modules::set::set_init;
 use crate::modules::fatal::fatal_init;
 use crate::modules::erpt::erpt_init;
+use crate::modules::htbdbg::htbdbg_init;
+use crate::hos::ipcdb::{ipcdb_lookup, ipcdb_lookup_cmd};
+use crate::modules::ipcfuzz::{IpcFuzzCase, ipcfuzz_is_target, ipcfuzz_mutate_request, ipcfuzz_complete};
+use crate::modules::ipccap::{ipccap_is_armed, ipccap_capture_request};
+use crate::modules::ipctrace::{IpcTraceRecord, IPCTRACE_FLAG_DOMAIN, IPCTRACE_FLAG_TIPC, ipctrace_should_trace, ipctrace_emit};
+use crate::modules::ipcstat::{ipcstat_is_enabled, ipcstat_record};
 
 static mut IPC_MODULE_HANDLERS: BTreeMap<String, HClientSessionHandler> = BTreeMap::new();
+static mut IPC_CMD_HOOKS: BTreeMap<(String, String, u32), IpcCmdHook> = BTreeMap::new();
+static mut IPC_EMU_SERVICES: BTreeMap<String, IpcEmuHandler> = BTreeMap::new();
+static mut IPC_EMU_NEXT_HANDLE: u32 = 0;
+
+// Kernel handles never have bit 30 set (it's reserved), so handles to
+// hypervisor-emulated sessions can't collide with real ones
+pub const IPC_EMU_HANDLE_BASE: u32 = bit!(30);
+
+// Runs before the request reaches the server, changes need a pack()
+pub type IpcPreHook = fn(req: &mut IpcRequest);
+
+// Runs once the server has replied
+pub type IpcPostHook = fn(req: &IpcRequest, resp: &mut IpcResponse);
+
+// Answers a request to an emulated service, `resp` starts out as an empty
+// success response
+pub type IpcEmuHandler = fn(req: &IpcRequest, resp: &mut IpcResponse);
+
+#[derive(Copy, Clone)]
+pub struct IpcCmdHook
+{
+    pub pre: Option<IpcPreHook>,
+    pub post: Option<IpcPostHook>
+}
+
+pub struct IpcRequest
+{
+    pub handle: u32,
+    pub hobj: HObject,
+    pub service: String,
+    pub interface: String,
+    pub pkt: HIPCPacket
+}
+
+pub struct IpcResponse
+{
+    pub result: u32,
+    pub child: Option<HObject>, // hooked object returned by the command, if any
+    pub pkt: HIPCPacket
+}
+
+impl Deref for IpcRequest
+{
+    type Target = HIPCPacket;
+    
+    fn deref(&self) -> &HIPCPacket
+    {
+        &self.pkt
+    }
+}
+
+impl DerefMut for IpcRequest
+{
+    fn deref_mut(&mut self) -> &mut HIPCPacket
+    {
+        &mut self.pkt
+    }
+}
+
+impl Deref for IpcResponse
+{
+    type Target = HIPCPacket;
+    
+    fn deref(&self) -> &HIPCPacket
+    {
+        &self.pkt
+    }
+}
+
+impl DerefMut for IpcResponse
+{
+    fn deref_mut(&mut self) -> &mut HIPCPacket
+    {
+        &mut self.pkt
+    }
+}
 
 pub fn ipc_init()
 {
@@ -34,6 +121,7 @@ pub fn ipc_init()
     set_init();
     fatal_init();
     erpt_init();
+    htbdbg_init();
 }
 
 pub fn ipc_register_handler(service_name: String, handler: HClientSessionHandler)
@@ -44,6 +132,7 @@ pub fn ipc_register_handler(service_name: String, handler: HClientSessionHandler
     }
 }
 
+// Full session handlers take priority over command hooks
 pub fn ipc_get_handler(service_name: &String) -> Option<HClientSessionHandler>
 {
     unsafe
@@ -51,10 +140,254 @@ pub fn ipc_get_handler(service_name: &String) -> Option<HClientSessionHandler>
         if let Some(handler) = IPC_MODULE_HANDLERS.get(service_name) {
             return Some(*handler);
         }
+        if ipc_needs_dispatch(service_name, "", 0) {
+            return Some(ipc_hook_dispatch_boxed);
+        }
+        return None;
+    }
+}
+
+// An empty interface is the service's root object, an empty service
+// matches the interface under any service
+pub fn ipc_register_cmd_hook(service: &str, interface: &str, cmd_id: u32, pre: Option<IpcPreHook>, post: Option<IpcPostHook>)
+{
+    unsafe
+    {
+        IPC_CMD_HOOKS.insert((String::from(service), String::from(interface), cmd_id), IpcCmdHook { pre: pre, post: post });
+    }
+}
+
+pub fn ipc_get_cmd_hook(service: &str, interface: &str, cmd_id: u32) -> Option<IpcCmdHook>
+{
+    unsafe
+    {
+        if let Some(hook) = IPC_CMD_HOOKS.get(&(String::from(service), String::from(interface), cmd_id)) {
+            return Some(*hook);
+        }
+        if let Some(hook) = IPC_CMD_HOOKS.get(&(String::new(), String::from(interface), cmd_id)) {
+            return Some(*hook);
+        }
+        return None;
+    }
+}
+
+pub fn ipc_has_cmd_hooks(service: &str, interface: &str) -> bool
+{
+    unsafe
+    {
+        for (key, _) in &IPC_CMD_HOOKS
+        {
+            if (key.0 == service || key.0 == "") && key.1 == interface {
+                return true;
+            }
+        }
+        return false;
+    }
+}
+
+// Hooks on an interface only get reached if every object leading to it
+// goes through the dispatcher as well
+fn ipc_needs_dispatch(service: &str, interface: &str, depth: u32) -> bool
+{
+    if ipc_has_cmd_hooks(service, interface) {
+        return true;
+    }
+    
+    if depth >= 4 {
+        return false;
+    }
+    
+    if let Some(iface) = ipcdb_lookup(service, interface) {
+        for cmd in iface.cmds
+        {
+            if let Some(child) = cmd.get_out_object() {
+                if child != interface && ipc_needs_dispatch(service, child, depth + 1) {
+                    return true;
+                }
+            }
+        }
+    }
+    return false;
+}
+
+async fn ipc_hook_dispatch(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
+{
+    let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
+    let pkt = hipc_get_packet();
+    let cmd_id = pkt.get_cmd_id();
+    let service = hobj.get_service();
+    let interface = hobj.get_interface();
+    
+    let hook_opt = ipc_get_cmd_hook(&service, &interface, cmd_id);
+    
+    // Objects returned by this command get the dispatcher too, if anything
+    // is hooking their interface
+    let mut child_interface: Option<&'static str> = None;
+    if let Some(cmd) = ipcdb_lookup_cmd(&service, &interface, cmd_id) {
+        if let Some(child) = cmd.get_out_object() {
+            if ipc_needs_dispatch(&service, child, 0) {
+                child_interface = Some(child);
+            }
+        }
+    }
+    
+    if hook_opt.is_none() && child_interface.is_none() {
+        return pre_ctx;
+    }
+    
+    let mut req = IpcRequest
+    {
+        handle: handle,
+        hobj: hobj,
+        service: service,
+        interface: interface,
+        pkt: pkt
+    };
+    
+    if let Some(pre) = hook_opt.and_then(|hook| hook.pre) {
+        pre(&mut req);
+    }
+    
+    let post_opt = hook_opt.and_then(|hook| hook.post);
+    if post_opt.is_none() && child_interface.is_none() {
+        return pre_ctx;
+    }
+    
+    // Wait for SVC to complete
+    let post_ctx = SvcWait::new(pre_ctx).await;
+    let mut resp = IpcResponse
+    {
+        result: (post_ctx[0] & 0xFFFFFFFF) as u32,
+        child: None,
+        pkt: hipc_get_response(&req.pkt)
+    };
+    
+    if resp.result == 0 && resp.pkt.get_cmd_id() == 0 {
+        if let Some(child) = child_interface {
+            if resp.pkt.hook_first_handle(handle, ipc_hook_dispatch_boxed) {
+                resp.child = resp.pkt.get_first_handle_obj(handle);
+                if let Some(child_hobj) = &resp.child {
+                    child_hobj.set_interface(child);
+                }
+            }
+        }
+    }
+    
+    if let Some(post) = post_opt {
+        post(&req, &mut resp);
+    }
+    
+    return post_ctx;
+}
+
+fn ipc_hook_dispatch_boxed(mut pre_ctx: [u64; 32], hobj: HObject) -> Pin<Box<dyn Future<Output = [u64; 32]> + Send>> {
+    Box::pin(ipc_hook_dispatch(pre_ctx, hobj))
+}
+
+pub fn ipc_register_emulated_service(service_name: String, handler: IpcEmuHandler)
+{
+    unsafe
+    {
+        IPC_EMU_SERVICES.insert(service_name, handler);
+    }
+}
+
+pub fn ipc_get_emulated_service(service_name: &String) -> Option<IpcEmuHandler>
+{
+    unsafe
+    {
+        if let Some(handler) = IPC_EMU_SERVICES.get(service_name) {
+            return Some(*handler);
+        }
         return None;
     }
 }
 
+pub fn ipc_is_emulated_handle(handle: u32) -> bool
+{
+    // Pseudo-handles (0xFFFF8000...) also have bit 30 set
+    (handle & (bit!(31) | bit!(30))) == IPC_EMU_HANDLE_BASE
+}
+
+// Creates a session nothing but the hypervisor serves, the handle only
+// exists in our tables
+pub fn ipc_emu_open_session(service: &String, interface: &str) -> u32
+{
+    unsafe
+    {
+        let handle = IPC_EMU_HANDLE_BASE | (IPC_EMU_NEXT_HANDLE & 0x3FFFFFFF);
+        IPC_EMU_NEXT_HANDLE = IPC_EMU_NEXT_HANDLE.wrapping_add(1);
+        
+        let mut hsession = HClientSession::new(0xFF, (vsvc_get_curpid() & 0xFF) as u8);
+        hsession.set_service(service);
+        hsession.set_interface(interface);
+        hsession.set_handler(ipc_emu_dispatch_boxed);
+        
+        hipc_register_handle_clientsession(handle, Arc::new(Mutex::new(hsession)));
+        return handle;
+    }
+}
+
+async fn ipc_emu_dispatch(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
+{
+    let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
+    let pkt = hipc_get_packet();
+    let service = hobj.get_service();
+    let interface = hobj.get_interface();
+    
+    if pkt.is_close()
+    {
+        hipc_close_handle(handle);
+        return hsvc_return_early(pre_ctx, 0);
+    }
+    
+    let mut resp = IpcResponse
+    {
+        result: 0,
+        child: None,
+        pkt: HIPCPacket::new_response(&pkt, 0)
+    };
+    
+    if pkt.is_control()
+    {
+        match pkt.get_cmd_id()
+        {
+            2 | 4 => // CloneCurrentObject, CloneCurrentObjectEx
+            {
+                let clone_handle = ipc_emu_open_session(&service, &interface);
+                resp.push_move_handle(clone_handle);
+            },
+            3 => // QueryPointerBufferSize
+            {
+                resp.write_u16(0, 0);
+            },
+            _ => // Emulated sessions never become domains
+            {
+                resp.set_result(result_make(RESULT_MODULE_SF, 1));
+            }
+        }
+    }
+    else if let Some(handler) = ipc_get_emulated_service(&service)
+    {
+        let req = IpcRequest
+        {
+            handle: handle,
+            hobj: hobj,
+            service: service,
+            interface: interface,
+            pkt: pkt
+        };
+        handler(&req, &mut resp);
+    }
+    
+    resp.pack();
+    return hsvc_return_early(pre_ctx, 0);
+}
+
+fn ipc_emu_dispatch_boxed(mut pre_ctx: [u64; 32], hobj: HObject) -> Pin<Box<dyn Future<Output = [u64; 32]> + Send>> {
+    Box::pin(ipc_emu_dispatch(pre_ctx, hobj))
+}
+
 async fn handle_sm(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
 {
     let pkt = hipc_get_packet();
@@ -73,26 +406,41 @@ async fn handle_sm(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
         { 
             let name = pkt.read_str(0);
             
+            // sm doesn't know about emulated services, answer in its place
+            if ipc_get_emulated_service(&name).is_some()
+            {
+                let session_handle = ipc_emu_open_session(&name, "");
+                let mut resp = HIPCPacket::new_response(&pkt, 0);
+                resp.push_move_handle(session_handle);
+                resp.pack();
+                
+                return hsvc_return_early(pre_ctx, 0);
+            }
+            
             // Wait for SVC to complete
             let post_ctx = SvcWait::new(pre_ctx).await;
-            let resp = hipc_get_packet();
+            let resp = hipc_get_response(&pkt);
             
             /*if name == "clkrst" {
                 println_core!("sm::GetServiceHandle(`{}`) for `{}`", name, vsvc_get_curpid_name());
             }*/
-            if let Some(handler) = ipc_get_handler(&name) {
-                if let Some(handle) = resp.get_handle(0) {
-                    //println_core!("sm::GetServiceHandle(`{}`) -> {:x} for `{}`", name, handle, vsvc_get_curpid_name());
-                    
-                    // TODO: Copied handles may not actually belong to parent
-                    let mut service_hsession = sm_hsession.lock().new_from_parent();
-                    
-                    // Set handler
+            if let Some(handle) = resp.get_handle(0) {
+                //println_core!("sm::GetServiceHandle(`{}`) -> {:x} for `{}`", name, handle, vsvc_get_curpid_name());
+                
+                // TODO: Copied handles may not actually belong to parent
+                let mut service_hsession = sm_hsession.lock().new_from_parent();
+                
+                // Track every service session so they can be named later,
+                // even if nothing is hooking them
+                service_hsession.set_service(&name);
+                
+                // Set handler
+                if let Some(handler) = ipc_get_handler(&name) {
                     service_hsession.set_handler(handler);
-                    
-                    // Link new HClientSession to HOS handle
-                    hipc_register_handle_clientsession(handle, Arc::new(Mutex::new(service_hsession)));
                 }
+                
+                // Link new HClientSession to HOS handle
+                hipc_register_handle_clientsession(handle, Arc::new(Mutex::new(service_hsession)));
             }
             
             return post_ctx;
@@ -101,6 +449,19 @@ async fn handle_sm(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
         { 
             let name = pkt.read_str(0);
             //println_core!("sm::RegisterService(`{}`) from `{}`", name, vsvc_get_curpid_name());
+            
+            // The server gets its port back, sessions accepted on it are
+            // named after the service
+            let post_ctx = SvcWait::new(pre_ctx).await;
+            let resp = hipc_get_response(&pkt);
+            if (post_ctx[0] & 0xFFFFFFFF) == 0 && resp.get_cmd_id() == 0 {
+                if let Some(port_handle) = resp.get_handle(0) {
+                    let hport = HPort::from_curpid(Some(name));
+                    hipc_register_handle_serverport(port_handle, Arc::new(Mutex::new(hport)));
+                }
+            }
+            
+            return post_ctx;
         },
         3 => // UnregisterService
         { 
@@ -171,11 +532,13 @@ pub async fn ipc_handle_syncrequest_control(mut pre_ctx: [u64; 32]) -> [u64; 32]
         {
             let obj = pkt.read_u32(0);
             let mut handler_opt: Option<HClientSessionHandler> = None;
+            let mut interface = String::new();
             if let Some(mut hsession) = hipc_get_domain_session(HDomainObj::from_curpid(handle, obj))
             {
                 let hsession_locked = hsession.lock();
 
                 handler_opt = hsession_locked.get_handler();
+                interface = hsession_locked.get_interface();
             }
 
             // If there's a handler, copy it to new session handle
@@ -193,6 +556,7 @@ pub async fn ipc_handle_syncrequest_control(mut pre_ctx: [u64; 32]) -> [u64; 32]
                 
                     service_hsession.set_handler(handler);
                     service_hsession.set_extra(hsession.lock().get_extra());
+                    service_hsession.set_interface(&interface);
                 
                     // Link new HClientSession to HOS handle
                     hipc_register_handle_clientsession(session_handle, Arc::new(Mutex::new(service_hsession)));
@@ -219,6 +583,7 @@ pub async fn ipc_handle_syncrequest_control(mut pre_ctx: [u64; 32]) -> [u64; 32]
                 }
                 
                 service_hsession.set_extra(hsession.lock().get_extra());
+                service_hsession.set_interface(&hsession.lock().get_interface());
             
                 // Link new HClientSession to HOS handle
                 hipc_register_handle_clientsession(session_handle, Arc::new(Mutex::new(service_hsession)));
@@ -228,7 +593,17 @@ pub async fn ipc_handle_syncrequest_control(mut pre_ctx: [u64; 32]) -> [u64; 32]
         },
         3 => // QueryPointerBufferSize
         {
-            //println_core!("QueryPointerBufferSize");
+            if let Some(hsession) = hipc_get_handle_clientsession(handle)
+            {
+                // Wait for SVC to complete
+                let post_ctx = SvcWait::new(pre_ctx).await;
+                let resp = hipc_get_response(&pkt);
+                
+                if (post_ctx[0] & 0xFFFFFFFF) == 0 && resp.get_cmd_id() == 0 {
+                    hsession.lock().set_pointer_buffer_size(resp.read_u16(0));
+                }
+                return post_ctx;
+            }
         },
         _ => {}
     }
@@ -236,6 +611,103 @@ pub async fn ipc_handle_syncrequest_control(mut pre_ctx: [u64; 32]) -> [u64; 32]
 }
 
 pub async fn ipc_handle_syncrequest(mut pre_ctx: [u64; 32]) -> [u64; 32]
+{
+    let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
+    let pid = vsvc_get_curpid();
+    
+    let mut service = String::new();
+    let mut interface = String::new();
+    let mut server_pid = 0;
+    if let Some(hsession) = hipc_get_handle_clientsession(handle) {
+        service = hsession.lock().get_service();
+        interface = hsession.lock().get_interface();
+        server_pid = hsession.lock().parent_port_pid as u32;
+    }
+    
+    if ipccap_is_armed(&service) {
+        ipccap_capture_request(&hipc_get_packet(), pid, handle, &service, &interface);
+    }
+    
+    let tracing = ipctrace_should_trace(pid, &service);
+    
+    // Emulated services never reach a real server, nothing to crash
+    let fuzzing = !ipc_is_emulated_handle(handle) && ipcfuzz_is_target(pid, &service);
+    let stats = ipcstat_is_enabled();
+    if !tracing && !fuzzing && !stats {
+        return ipc_dispatch_syncrequest(pre_ctx).await;
+    }
+    
+    let mut pkt = hipc_get_packet();
+    if pkt.is_domain() {
+        if let Some(hsession) = hipc_get_domain_session(HDomainObj::from_curpid(handle, pkt.get_domain_id())) {
+            interface = hsession.lock().get_interface();
+        }
+    }
+    
+    let mut fuzz_case: Option<IpcFuzzCase> = None;
+    if fuzzing {
+        fuzz_case = ipcfuzz_mutate_request(&mut pkt, pid, server_pid, &service, &interface);
+    }
+    let payload = pkt.pack_bytes().unwrap_or(Vec::new());
+    
+    let start_ticks = get_ticks();
+    let checkpoint = SvcWait::checkpoint();
+    let mut post_ctx = ipc_dispatch_syncrequest(pre_ctx).await;
+    
+    // Handlers that don't care about the response return early,
+    // but the trace still needs it
+    if !SvcWait::waited_since(checkpoint) {
+        post_ctx = SvcWait::new(post_ctx).await;
+    }
+    let end_ticks = get_ticks();
+    
+    let svc_result = (post_ctx[0] & 0xFFFFFFFF) as u32;
+    let mut ipc_result = 0;
+    if svc_result == 0 && !pkt.is_close() {
+        ipc_result = hipc_get_response(&pkt).get_cmd_id();
+    }
+    
+    if let Some(case) = fuzz_case {
+        ipcfuzz_complete(case, svc_result, ipc_result);
+    }
+    
+    if stats && !pkt.is_close() {
+        ipcstat_record(handle, &service, &interface, pkt.get_cmd_id(), start_ticks, end_ticks, svc_result != 0 || ipc_result != 0);
+    }
+    
+    if !tracing {
+        return post_ctx;
+    }
+    
+    let mut flags = 0;
+    if pkt.is_domain() {
+        flags |= IPCTRACE_FLAG_DOMAIN;
+    }
+    if pkt.is_tipc() {
+        flags |= IPCTRACE_FLAG_TIPC;
+    }
+    
+    let record = IpcTraceRecord
+    {
+        pid: pid,
+        handle: handle,
+        domain_obj: pkt.get_domain_id(),
+        cmd_id: pkt.get_cmd_id(),
+        pkt_type: pkt.get_type(),
+        flags: flags,
+        svc_result: svc_result,
+        ipc_result: ipc_result,
+        latency: end_ticks - start_ticks,
+        service: service,
+        interface: interface,
+        payload: payload,
+    };
+    ipctrace_emit(&record);
+    
+    return post_ctx;
+}
+
+async fn ipc_dispatch_syncrequest(mut pre_ctx: [u64; 32]) -> [u64; 32]
 {
     let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
     
@@ -244,78 +716,75 @@ pub async fn ipc_handle_syncrequest(mut pre_ctx: [u64; 32]) -> [u64; 32]
     }*/
         
     let pkt = hipc_get_packet();
-    match pkt.get_type()
+    if pkt.is_request()
     {
-        PKT_TYPE_LEGACYREQEST | PKT_TYPE_REQUEST | PKT_TYPE_REQUESTWITHCONTEXT =>
+        if pkt.is_domain()
         {
-            if pkt.is_domain()
-            {
-                let obj = pkt.get_domain_id();
+            let obj = pkt.get_domain_id();
 
-                match pkt.get_domain_cmd()
+            match pkt.get_domain_cmd()
+            {
+                1 => // Request
                 {
-                    1 => // Request
+                    let mut handler_opt: Option<HClientSessionHandler> = None;
+                    let mut hobj: HObject = HObject::None();
+                    if let Some(mut hsession) = hipc_get_domain_session(HDomainObj::from_curpid(handle, obj))
                     {
-                        let mut handler_opt: Option<HClientSessionHandler> = None;
-                        let mut hobj: HObject = HObject::None();
-                        if let Some(mut hsession) = hipc_get_domain_session(HDomainObj::from_curpid(handle, obj))
-                        {
-                            hobj = HObject::DomainSession(hsession.clone());
-                            let hsession_locked = hsession.lock();
-
-                            handler_opt = hsession_locked.get_handler();
-                        }
-
-                        // If there's a handler, let it take over
-                        if let Some(handler) = handler_opt
-                        {
-                            return handler(pre_ctx, hobj).await;
-                        }
-                    },
-                    2 => // Delete
+                        hobj = HObject::DomainSession(hsession.clone());
+                        let hsession_locked = hsession.lock();
+
+                        handler_opt = hsession_locked.get_handler();
+                    }
+
+                    // If there's a handler, let it take over
+                    if let Some(handler) = handler_opt
                     {
+                        return handler(pre_ctx, hobj).await;
+                    }
+                },
+                2 => // Delete
+                {
+                    let post_ctx = SvcWait::new(pre_ctx).await;
+                    if post_ctx[0] == 0 {
                         hipc_remove_domain(HDomainObj::from_curpid(handle, obj));
-                        return pre_ctx;
-                    },
-                    _ => { return pre_ctx; }
-                }
+                    }
+                    return post_ctx;
+                },
+                _ => { return pre_ctx; }
             }
-            else
+        }
+        else
+        {
+            // Get port struct
+            let mut handler_opt: Option<HClientSessionHandler> = None;
+            let mut hobj: HObject = HObject::None();
+            if let Some(mut hsession) = hipc_get_handle_clientsession(handle)
             {
-                // Get port struct
-                let mut handler_opt: Option<HClientSessionHandler> = None;
-                let mut hobj: HObject = HObject::None();
-                if let Some(mut hsession) = hipc_get_handle_clientsession(handle)
-                {
-                    hobj = HObject::ClientSession(hsession.clone());
-                    let hsession_locked = hsession.lock();
+                hobj = HObject::ClientSession(hsession.clone());
+                let hsession_locked = hsession.lock();
 
-                    //println_core!("svcSendSyncRequest from `{}` to handle {:x}", vsvc_get_curpid_name(), handle);
-                    //println!("          `{}` -> `{}`", vsvc_get_curpid_name(), vsvc_get_pid_name(hsession_locked.parent_port_pid as u32));
-                    
-                    handler_opt = hsession_locked.get_handler();
-                }
+                //println_core!("svcSendSyncRequest from `{}` to handle {:x}", vsvc_get_curpid_name(), handle);
+                //println!("          `{}` -> `{}`", vsvc_get_curpid_name(), vsvc_get_pid_name(hsession_locked.parent_port_pid as u32));
                 
-                // If there's a handler, let it take over
-                if let Some(handler) = handler_opt
-                {
-                    return handler(pre_ctx, hobj).await;
-                }
+                handler_opt = hsession_locked.get_handler();
             }
-        },
-        PKT_TYPE_CLOSE =>
-        {
-            if pkt.is_domain()
+            
+            // If there's a handler, let it take over
+            if let Some(handler) = handler_opt
             {
-                //TODO?
+                return handler(pre_ctx, hobj).await;
             }
-            hipc_close_handle(handle);
-        }
-        PKT_TYPE_LEGACYCONTROL | PKT_TYPE_CONTROL | PKT_TYPE_CONTROLWITHCONTEXT =>
-        {
-            return ipc_handle_syncrequest_control(pre_ctx).await;
         }
-        _ => { return pre_ctx; }
+    }
+    else if pkt.is_close()
+    {
+        // The session is torn down even if the server never replies, and
+        // this takes every domain object hosted on it along with it
+        hipc_close_handle(handle);
+    }
+    else if pkt.is_control()
+    {
+        return ipc_handle_syncrequest_control(pre_ctx).await;
     }
     
 
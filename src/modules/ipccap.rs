@@ -0,0 +1,551 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use crate::logger::*;
+use crate::util::*;
+use crate::arm::mmu::translate_el1_stage12;
+use crate::hos::hipc::{HIPCPacket, HIPC_MAX_SIZE, hipc_find_pid_session};
+use crate::hos::hipcmem::HIPCMemSlice;
+use crate::hos::hsvc::hsvc_send_sync_request;
+use crate::hos::ipcdb::ipcdb_cmd_name;
+use crate::hos::result::result_format;
+use crate::vm::vsvc::{vsvc_get_pid_name, vsvc_get_svc_addr, vsvc_get_tls};
+
+pub const LOG_CMD_IPCCAP: u8 = 0x12;
+
+pub const IPCCAP_PKT_CAPTURE: u8 = 0;
+pub const IPCCAP_PKT_REPLAY: u8 = 1;
+
+pub const IPCCAP_CMD_ANY: u32 = 0xFFFFFFFF;
+
+pub const IPCCAP_BUF_SEND: u8 = 0;
+pub const IPCCAP_BUF_RECV: u8 = 1;
+pub const IPCCAP_BUF_EXCH: u8 = 2;
+
+// Buffers past this are left out of the capture entirely
+const IPCCAP_BUF_MAX: u64 = 0x1000;
+
+static mut IPCCAP_ARMED: Option<(String, u32)> = None;
+static mut IPCCAP_NEXT_SLOT: u32 = 0;
+static mut IPCCAP_SLOTS: BTreeMap<u32, IpcCapture> = BTreeMap::new();
+static mut IPCCAP_REPLAYS: BTreeMap<u32, IpcReplay> = BTreeMap::new();
+static mut IPCCAP_UPLOAD: Option<(u32, Vec<u8>)> = None;
+
+#[derive(Clone)]
+pub struct IpcCapBuffer
+{
+    pub kind: u8,
+    pub index: u8,
+    pub addr: u64,
+    pub size: u64,
+    pub data: Vec<u8>, // empty for B buffers, only the range gets saved
+}
+
+#[derive(Clone)]
+pub struct IpcCapture
+{
+    pub pid: u32,
+    pub handle: u32,
+    pub tls: u64,
+    pub service: String,
+    pub interface: String,
+    pub cmd_buf: Vec<u8>,
+    pub bufs: Vec<IpcCapBuffer>,
+}
+
+#[derive(Copy, Clone)]
+pub struct IpcReplay
+{
+    pub slot: u32,
+    pub tls: Option<u64>,
+    pub handle: Option<u32>,
+}
+
+fn ipccap_read_guest(addr: u64, size: u64) -> Option<Vec<u8>>
+{
+    let mut data: Vec<u8> = Vec::with_capacity(size as usize);
+    for i in 0..size
+    {
+        let paddr = translate_el1_stage12(addr + i);
+        if paddr < 0x80000000 {
+            return None;
+        }
+        data.push(peek8(paddr));
+    }
+    return Some(data);
+}
+
+fn ipccap_write_guest(addr: u64, data: &[u8]) -> bool
+{
+    for i in 0..data.len()
+    {
+        let paddr = translate_el1_stage12(addr + i as u64);
+        if paddr < 0x80000000 {
+            return false;
+        }
+        poke8(paddr, data[i]);
+    }
+    return true;
+}
+
+fn ipccap_read_u32(data: &[u8], offs: usize) -> u32
+{
+    u32::from_le_bytes([data[offs], data[offs+1], data[offs+2], data[offs+3]])
+}
+
+fn ipccap_read_u64(data: &[u8], offs: usize) -> u64
+{
+    (ipccap_read_u32(data, offs) as u64) | ((ipccap_read_u32(data, offs+4) as u64) << 32)
+}
+
+impl IpcCapture
+{
+    pub fn get_packet(&self) -> HIPCPacket
+    {
+        let mut cmd_buf = self.cmd_buf.clone();
+        let mem = HIPCMemSlice::new(0, &mut cmd_buf);
+        return HIPCPacket::unpack_from(&mem, 0);
+    }
+
+    // Little endian, mirrored by debug_client's ipc_cap.rs. Uploads from
+    // the host come back in the same format.
+    pub fn serialize(&self, slot: u32) -> Vec<u8>
+    {
+        let service = self.service.as_bytes();
+        let service_len = core::cmp::min(service.len(), 0xFF);
+        let interface = self.interface.as_bytes();
+        let interface_len = core::cmp::min(interface.len(), 0xFF);
+
+        let mut out: Vec<u8> = Vec::new();
+        out.push(IPCCAP_PKT_CAPTURE);
+        out.extend_from_slice(&slot.to_le_bytes());
+        out.extend_from_slice(&self.pid.to_le_bytes());
+        out.extend_from_slice(&self.handle.to_le_bytes());
+        out.extend_from_slice(&self.tls.to_le_bytes());
+        out.push(service_len as u8);
+        out.extend_from_slice(&service[..service_len]);
+        out.push(interface_len as u8);
+        out.extend_from_slice(&interface[..interface_len]);
+        out.extend_from_slice(&self.cmd_buf[..HIPC_MAX_SIZE]);
+        out.push(self.bufs.len() as u8);
+        for buf in &self.bufs
+        {
+            out.push(buf.kind);
+            out.push(buf.index);
+            out.extend_from_slice(&buf.addr.to_le_bytes());
+            out.extend_from_slice(&buf.size.to_le_bytes());
+            out.extend_from_slice(&(buf.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&buf.data);
+        }
+
+        return out;
+    }
+
+    pub fn parse(data: &[u8]) -> Option<(u32, IpcCapture)>
+    {
+        if data.len() < 22 || data[0] != IPCCAP_PKT_CAPTURE {
+            return None;
+        }
+
+        let slot = ipccap_read_u32(data, 1);
+        let pid = ipccap_read_u32(data, 5);
+        let handle = ipccap_read_u32(data, 9);
+        let tls = ipccap_read_u64(data, 13);
+
+        let mut offs = 21;
+        let service_len = data[offs] as usize;
+        offs += 1;
+        if data.len() < offs + service_len + 1 {
+            return None;
+        }
+        let service = String::from_utf8_lossy(&data[offs..offs+service_len]).into_owned();
+        offs += service_len;
+
+        let interface_len = data[offs] as usize;
+        offs += 1;
+        if data.len() < offs + interface_len + HIPC_MAX_SIZE + 1 {
+            return None;
+        }
+        let interface = String::from_utf8_lossy(&data[offs..offs+interface_len]).into_owned();
+        offs += interface_len;
+
+        let cmd_buf = data[offs..offs+HIPC_MAX_SIZE].to_vec();
+        offs += HIPC_MAX_SIZE;
+
+        let num_bufs = data[offs] as usize;
+        offs += 1;
+        let mut bufs: Vec<IpcCapBuffer> = Vec::new();
+        for _ in 0..num_bufs
+        {
+            if data.len() < offs + 22 {
+                return None;
+            }
+            let data_len = ipccap_read_u32(data, offs + 18) as usize;
+            if data.len() < offs + 22 + data_len {
+                return None;
+            }
+
+            bufs.push(IpcCapBuffer
+            {
+                kind: data[offs],
+                index: data[offs+1],
+                addr: ipccap_read_u64(data, offs + 2),
+                size: ipccap_read_u64(data, offs + 10),
+                data: data[offs+22..offs+22+data_len].to_vec(),
+            });
+            offs += 22 + data_len;
+        }
+
+        let capture = IpcCapture
+        {
+            pid: pid,
+            handle: handle,
+            tls: tls,
+            service: service,
+            interface: interface,
+            cmd_buf: cmd_buf,
+            bufs: bufs,
+        };
+        return Some((slot, capture));
+    }
+
+    pub fn print(&self, slot: u32)
+    {
+        let pkt = self.get_packet();
+        println!("  slot {}: `{}` (pid {}, tls {:x}) handle {:x} {}, {} buffers", slot, vsvc_get_pid_name(self.pid), self.pid, self.tls,
+                 self.handle, ipcdb_cmd_name(&self.service, &self.interface, pkt.get_cmd_id()), self.bufs.len());
+    }
+}
+
+pub fn ipccap_arm(service: &String, cmd_id: u32)
+{
+    unsafe
+    {
+        IPCCAP_ARMED = Some((service.clone(), cmd_id));
+    }
+}
+
+pub fn ipccap_disarm()
+{
+    unsafe
+    {
+        IPCCAP_ARMED = None;
+    }
+}
+
+pub fn ipccap_is_armed(service: &String) -> bool
+{
+    unsafe
+    {
+        match &IPCCAP_ARMED {
+            Some((armed, _)) => armed == service,
+            None => false
+        }
+    }
+}
+
+// Snapshots the request about to be sent from the current thread, once per arm
+pub fn ipccap_capture_request(pkt: &HIPCPacket, pid: u32, handle: u32, service: &String, interface: &String)
+{
+    unsafe
+    {
+        let cmd_id = match &IPCCAP_ARMED {
+            Some((armed, cmd_id)) if armed == service => *cmd_id,
+            _ => return
+        };
+        if !pkt.is_request() || (cmd_id != IPCCAP_CMD_ANY && pkt.get_cmd_id() != cmd_id) {
+            return;
+        }
+        IPCCAP_ARMED = None;
+    }
+
+    let mut cmd_buf: Vec<u8> = Vec::with_capacity(HIPC_MAX_SIZE);
+    for i in 0..HIPC_MAX_SIZE
+    {
+        cmd_buf.push(peek8(pkt.get_cmd_buf() + i as u64));
+    }
+
+    let mut bufs: Vec<IpcCapBuffer> = Vec::new();
+    let mut idx = 0;
+    while let Some(desc) = pkt.get_send(idx)
+    {
+        if desc.size <= IPCCAP_BUF_MAX {
+            if let Some(data) = ipccap_read_guest(desc.addr, desc.size) {
+                bufs.push(IpcCapBuffer { kind: IPCCAP_BUF_SEND, index: idx as u8, addr: desc.addr, size: desc.size, data: data });
+            }
+        }
+        idx += 1;
+    }
+
+    idx = 0;
+    while let Some(desc) = pkt.get_recv(idx)
+    {
+        bufs.push(IpcCapBuffer { kind: IPCCAP_BUF_RECV, index: idx as u8, addr: desc.addr, size: desc.size, data: Vec::new() });
+        idx += 1;
+    }
+
+    idx = 0;
+    while let Some(desc) = pkt.get_exch(idx)
+    {
+        if desc.size <= IPCCAP_BUF_MAX {
+            if let Some(data) = ipccap_read_guest(desc.addr, desc.size) {
+                bufs.push(IpcCapBuffer { kind: IPCCAP_BUF_EXCH, index: idx as u8, addr: desc.addr, size: desc.size, data: data });
+            }
+        }
+        idx += 1;
+    }
+
+    let capture = IpcCapture
+    {
+        pid: pid,
+        handle: handle,
+        tls: vsvc_get_tls(),
+        service: service.clone(),
+        interface: interface.clone(),
+        cmd_buf: cmd_buf,
+        bufs: bufs,
+    };
+
+    unsafe
+    {
+        let slot = IPCCAP_NEXT_SLOT;
+        IPCCAP_NEXT_SLOT += 1;
+
+        println_core!("ipccap: captured request into slot {}", slot);
+        capture.print(slot);
+        IPCCAP_SLOTS.insert(slot, capture);
+    }
+}
+
+pub fn ipccap_print_slots()
+{
+    unsafe
+    {
+        match &IPCCAP_ARMED {
+            Some((service, IPCCAP_CMD_ANY)) => println!("IPC capture armed for `{}`", service),
+            Some((service, cmd_id)) => println!("IPC capture armed for {}", ipcdb_cmd_name(service, "", *cmd_id)),
+            None => println!("IPC capture not armed")
+        };
+
+        for (slot, capture) in IPCCAP_SLOTS.iter()
+        {
+            capture.print(*slot);
+        }
+        for (pid, replay) in IPCCAP_REPLAYS.iter()
+        {
+            println!("  pending replay of slot {} in `{}` (pid {})", replay.slot, vsvc_get_pid_name(*pid), pid);
+        }
+    }
+}
+
+pub fn ipccap_drop(slot: u32) -> bool
+{
+    unsafe { IPCCAP_SLOTS.remove(&slot).is_some() }
+}
+
+pub fn ipccap_download(slot: u32) -> bool
+{
+    unsafe
+    {
+        if let Some(capture) = IPCCAP_SLOTS.get(&slot) {
+            log_cmd_chunked(LOG_CMD_IPCCAP, &capture.serialize(slot));
+            return true;
+        }
+        return false;
+    }
+}
+
+// Host uploads arrive as hex over several shell commands
+pub fn ipccap_upload_begin(slot: u32)
+{
+    unsafe
+    {
+        IPCCAP_UPLOAD = Some((slot, Vec::new()));
+    }
+}
+
+pub fn ipccap_upload_data(hex: &str) -> bool
+{
+    unsafe
+    {
+        let upload = match IPCCAP_UPLOAD.as_mut() {
+            Some((_, upload)) => upload,
+            None => return false
+        };
+
+        if hex.len() % 2 != 0 {
+            return false;
+        }
+        for i in (0..hex.len()).step_by(2)
+        {
+            match u8::from_str_radix(&hex[i..i+2], 16) {
+                Ok(val) => upload.push(val),
+                Err(_) => return false
+            };
+        }
+        return true;
+    }
+}
+
+pub fn ipccap_upload_end() -> Option<u32>
+{
+    unsafe
+    {
+        let (slot, upload) = IPCCAP_UPLOAD.take()?;
+        let (_, capture) = IpcCapture::parse(&upload)?;
+
+        IPCCAP_SLOTS.insert(slot, capture);
+        if slot >= IPCCAP_NEXT_SLOT {
+            IPCCAP_NEXT_SLOT = slot + 1;
+        }
+        return Some(slot);
+    }
+}
+
+// The replay rides on the next SVC a thread of `pid` makes
+pub fn ipccap_replay(slot: u32, pid: u32, tls: Option<u64>, handle: Option<u32>) -> bool
+{
+    unsafe
+    {
+        if !IPCCAP_SLOTS.contains_key(&slot) {
+            return false;
+        }
+
+        IPCCAP_REPLAYS.insert(pid, IpcReplay { slot: slot, tls: tls, handle: handle });
+        return true;
+    }
+}
+
+pub fn ipccap_take_replay(pid: u32, tls: u64) -> Option<IpcReplay>
+{
+    unsafe
+    {
+        if IPCCAP_REPLAYS.is_empty() {
+            return None;
+        }
+
+        let replay = *IPCCAP_REPLAYS.get(&pid)?;
+        if replay.tls.is_some() && replay.tls != Some(tls) {
+            return None;
+        }
+
+        // SendSyncRequest hasn't been seen yet, so there's nothing to call
+        if vsvc_get_svc_addr(0x21) == 0 {
+            return None;
+        }
+
+        IPCCAP_REPLAYS.remove(&pid);
+        return Some(replay);
+    }
+}
+
+fn ipccap_report(slot: u32, pid: u32, handle: u32, svc_result: u32, ipc_result: u32, response: &[u8])
+{
+    println_core!("ipccap: replay of slot {} in `{}` -> {} / {}", slot, vsvc_get_pid_name(pid), result_format(svc_result), result_format(ipc_result));
+
+    let mut out: Vec<u8> = Vec::new();
+    out.push(IPCCAP_PKT_REPLAY);
+    out.extend_from_slice(&slot.to_le_bytes());
+    out.extend_from_slice(&pid.to_le_bytes());
+    out.extend_from_slice(&handle.to_le_bytes());
+    out.extend_from_slice(&svc_result.to_le_bytes());
+    out.extend_from_slice(&ipc_result.to_le_bytes());
+    out.extend_from_slice(response);
+    log_cmd_chunked(LOG_CMD_IPCCAP, &out);
+}
+
+pub async fn ipccap_replay_task(mut pre_ctx: [u64; 32], pid: u32, replay: IpcReplay) -> [u64; 32]
+{
+    let capture = match unsafe { IPCCAP_SLOTS.get(&replay.slot) } {
+        Some(capture) => capture.clone(),
+        None => return pre_ctx
+    };
+
+    let mut resume_ctx = pre_ctx.clone();
+
+    // Re-run the SVC hook afterwards, so the SVC we borrowed the thread
+    // from still goes through its own handler
+    resume_ctx[31] -= 4;
+
+    let handle = match replay.handle {
+        Some(handle) => handle,
+        None => {
+            match hipc_find_pid_session(pid, &capture.service, &capture.interface) {
+                Some(handle) => handle,
+                None if pid == capture.pid => capture.handle,
+                None => {
+                    println_core!("ipccap: `{}` has no session to `{}`, specify a handle", vsvc_get_pid_name(pid), capture.service);
+                    return resume_ctx;
+                }
+            }
+        }
+    };
+
+    // Stash everything the request is about to clobber
+    let cmd_buf = translate_el1_stage12(vsvc_get_tls());
+    let mut saved_cmd_buf: Vec<u8> = Vec::with_capacity(HIPC_MAX_SIZE);
+    for i in 0..HIPC_MAX_SIZE
+    {
+        saved_cmd_buf.push(peek8(cmd_buf + i as u64));
+    }
+
+    let mut saved_bufs: Vec<(u64, Vec<u8>)> = Vec::new();
+    for buf in &capture.bufs
+    {
+        if buf.size > IPCCAP_BUF_MAX {
+            continue;
+        }
+
+        match ipccap_read_guest(buf.addr, buf.size) {
+            Some(saved) => {
+                saved_bufs.push((buf.addr, saved));
+                if buf.kind != IPCCAP_BUF_RECV {
+                    ipccap_write_guest(buf.addr, &buf.data);
+                }
+            },
+            None => {
+                println_core!("ipccap: buffer {:x} isn't mapped in `{}`, server will see a fault", buf.addr, vsvc_get_pid_name(pid));
+            }
+        };
+    }
+
+    for i in 0..HIPC_MAX_SIZE
+    {
+        poke8(cmd_buf + i as u64, capture.cmd_buf[i]);
+    }
+
+    let post_ctx = hsvc_send_sync_request(pre_ctx, handle).await;
+
+    let svc_result = (post_ctx[0] & 0xFFFFFFFF) as u32;
+    let mut response: Vec<u8> = Vec::with_capacity(HIPC_MAX_SIZE);
+    for i in 0..HIPC_MAX_SIZE
+    {
+        response.push(peek8(cmd_buf + i as u64));
+    }
+
+    let mut ipc_result = 0;
+    if svc_result == 0 {
+        let req = capture.get_packet();
+        let mem = HIPCMemSlice::new(0, &mut response);
+        ipc_result = HIPCPacket::unpack_from_as(&mem, 0, req.is_tipc()).get_cmd_id();
+    }
+
+    for (addr, saved) in &saved_bufs
+    {
+        ipccap_write_guest(*addr, saved);
+    }
+    for i in 0..HIPC_MAX_SIZE
+    {
+        poke8(cmd_buf + i as u64, saved_cmd_buf[i]);
+    }
+
+    ipccap_report(replay.slot, pid, handle, svc_result, ipc_result, &response);
+
+    return resume_ctx;
+}
@@ -0,0 +1,318 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::{BTreeMap, BTreeSet};
+use crate::logger::*;
+use crate::hos::hipc::HIPCPacket;
+use crate::hos::hipcfuzz::*;
+use crate::hos::ipcdb::ipcdb_cmd_name;
+use crate::hos::result::{result_format, KERNEL_RESULT_CONNECTION_CLOSED};
+use crate::vm::vsvc::vsvc_get_pid_name;
+
+pub const LOG_CMD_IPCFUZZ: u8 = 0x11;
+
+pub const IPCFUZZ_CMD_ANY: u32 = 0xFFFFFFFF;
+
+pub const IPCFUZZ_REASON_CLOSED: u8 = 0;
+pub const IPCFUZZ_REASON_FATAL: u8 = 1;
+pub const IPCFUZZ_REASON_BREAK: u8 = 2;
+
+const IPCFUZZ_HISTORY_MAX: usize = 32;
+
+static mut IPCFUZZ_ENABLED: bool = false;
+static mut IPCFUZZ_SEED: u64 = 0;
+static mut IPCFUZZ_RNG: HIPCFuzzRng = HIPCFuzzRng::new(0);
+static mut IPCFUZZ_MAX_MUTATIONS: u32 = 2;
+static mut IPCFUZZ_SEQ: u32 = 0;
+static mut IPCFUZZ_NUM_CRASHES: u32 = 0;
+static mut IPCFUZZ_TARGETS: BTreeSet<(String, u32)> = BTreeSet::new();
+static mut IPCFUZZ_PIDS: BTreeSet<u32> = BTreeSet::new();
+
+// Last case sent to each server, crashes get blamed on it
+static mut IPCFUZZ_LAST_CASE: BTreeMap<u32, IpcFuzzCase> = BTreeMap::new();
+static mut IPCFUZZ_HISTORY: Vec<IpcFuzzCase> = Vec::new();
+
+#[derive(Clone)]
+pub struct IpcFuzzCase
+{
+    pub seq: u32,
+    pub client_pid: u32,
+    pub server_pid: u32,
+    pub cmd_id: u32,
+    pub svc_result: u32,
+    pub ipc_result: u32,
+    pub service: String,
+    pub interface: String,
+    pub mutations: Vec<HIPCMutation>,
+    pub payload: Vec<u8>, // mutated request as sent
+}
+
+impl IpcFuzzCase
+{
+    // Little endian, mirrored by debug_client's ipc_fuzz.rs
+    pub fn serialize(&self, reason: u8) -> Vec<u8>
+    {
+        let service = self.service.as_bytes();
+        let service_len = core::cmp::min(service.len(), 0xFF);
+        let interface = self.interface.as_bytes();
+        let interface_len = core::cmp::min(interface.len(), 0xFF);
+        let num_mutations = core::cmp::min(self.mutations.len(), 0xFF);
+        let payload_len = core::cmp::min(self.payload.len(), 0xFFFF);
+
+        let mut out: Vec<u8> = Vec::new();
+        out.extend_from_slice(&self.seq.to_le_bytes());
+        out.extend_from_slice(&unsafe { IPCFUZZ_SEED }.to_le_bytes());
+        out.extend_from_slice(&self.client_pid.to_le_bytes());
+        out.extend_from_slice(&self.server_pid.to_le_bytes());
+        out.extend_from_slice(&self.cmd_id.to_le_bytes());
+        out.extend_from_slice(&self.svc_result.to_le_bytes());
+        out.extend_from_slice(&self.ipc_result.to_le_bytes());
+        out.push(reason);
+        out.push(service_len as u8);
+        out.extend_from_slice(&service[..service_len]);
+        out.push(interface_len as u8);
+        out.extend_from_slice(&interface[..interface_len]);
+        out.push(num_mutations as u8);
+        for mutation in &self.mutations[..num_mutations]
+        {
+            mutation.serialize_into(&mut out);
+        }
+        out.extend_from_slice(&(payload_len as u16).to_le_bytes());
+        out.extend_from_slice(&self.payload[..payload_len]);
+
+        return out;
+    }
+
+    pub fn print(&self)
+    {
+        println!("  #{} `{}` -> `{}` {}: {} / {}", self.seq, vsvc_get_pid_name(self.client_pid), vsvc_get_pid_name(self.server_pid),
+                 ipcdb_cmd_name(&self.service, &self.interface, self.cmd_id), result_format(self.svc_result), result_format(self.ipc_result));
+        for mutation in &self.mutations
+        {
+            println!("      {}", mutation.describe());
+        }
+    }
+}
+
+pub fn ipcfuzz_set_enabled(enabled: bool)
+{
+    unsafe
+    {
+        IPCFUZZ_ENABLED = enabled;
+    }
+}
+
+// Also restarts the sequence, so a run can be repeated exactly
+pub fn ipcfuzz_set_seed(seed: u64)
+{
+    unsafe
+    {
+        IPCFUZZ_SEED = seed;
+        IPCFUZZ_RNG = HIPCFuzzRng::new(seed);
+        IPCFUZZ_SEQ = 0;
+    }
+}
+
+pub fn ipcfuzz_set_max_mutations(max: u32)
+{
+    unsafe
+    {
+        IPCFUZZ_MAX_MUTATIONS = max;
+    }
+}
+
+pub fn ipcfuzz_target_service(service: &String, cmd_id: u32, add: bool)
+{
+    unsafe
+    {
+        if add {
+            IPCFUZZ_TARGETS.insert((service.clone(), cmd_id));
+        }
+        else {
+            IPCFUZZ_TARGETS.remove(&(service.clone(), cmd_id));
+        }
+    }
+}
+
+pub fn ipcfuzz_target_pid(pid: u32, add: bool)
+{
+    unsafe
+    {
+        if add {
+            IPCFUZZ_PIDS.insert(pid);
+        }
+        else {
+            IPCFUZZ_PIDS.remove(&pid);
+        }
+    }
+}
+
+pub fn ipcfuzz_clear()
+{
+    unsafe
+    {
+        IPCFUZZ_TARGETS.clear();
+        IPCFUZZ_PIDS.clear();
+        IPCFUZZ_LAST_CASE.clear();
+        IPCFUZZ_HISTORY.clear();
+    }
+}
+
+// Unlike tracing, nothing gets fuzzed until a target is picked
+pub fn ipcfuzz_should_fuzz(pid: u32, service: &String, cmd_id: u32) -> bool
+{
+    unsafe
+    {
+        if !IPCFUZZ_ENABLED {
+            return false;
+        }
+
+        return IPCFUZZ_PIDS.contains(&pid)
+               || IPCFUZZ_TARGETS.contains(&(service.clone(), cmd_id))
+               || IPCFUZZ_TARGETS.contains(&(service.clone(), IPCFUZZ_CMD_ANY));
+    }
+}
+
+// Coarse check before the packet is parsed, `ipcfuzz_should_fuzz` has the
+// final say once the command ID is known
+pub fn ipcfuzz_is_target(pid: u32, service: &String) -> bool
+{
+    unsafe
+    {
+        if !IPCFUZZ_ENABLED {
+            return false;
+        }
+
+        return IPCFUZZ_PIDS.contains(&pid)
+               || IPCFUZZ_TARGETS.iter().any(|(target, _)| target == service);
+    }
+}
+
+// Mutates `pkt` and writes it back over the request
+pub fn ipcfuzz_mutate_request(pkt: &mut HIPCPacket, client_pid: u32, server_pid: u32, service: &String, interface: &String) -> Option<IpcFuzzCase>
+{
+    if !pkt.is_request() || !ipcfuzz_should_fuzz(client_pid, service, pkt.get_cmd_id()) {
+        return None;
+    }
+
+    unsafe
+    {
+        let mutations = hipcfuzz_mutate(pkt, &mut IPCFUZZ_RNG, IPCFUZZ_MAX_MUTATIONS);
+        let payload = pkt.pack_bytes()?;
+        if !pkt.pack() {
+            return None;
+        }
+
+        let case = IpcFuzzCase
+        {
+            seq: IPCFUZZ_SEQ,
+            client_pid: client_pid,
+            server_pid: server_pid,
+            cmd_id: pkt.get_cmd_id(),
+            svc_result: 0,
+            ipc_result: 0,
+            service: service.clone(),
+            interface: interface.clone(),
+            mutations: mutations,
+            payload: payload,
+        };
+        IPCFUZZ_SEQ += 1;
+
+        return Some(case);
+    }
+}
+
+fn ipcfuzz_report(case: &IpcFuzzCase, reason: u8)
+{
+    unsafe
+    {
+        IPCFUZZ_NUM_CRASHES += 1;
+    }
+
+    let reason_str = match reason
+    {
+        IPCFUZZ_REASON_FATAL => "fatal",
+        IPCFUZZ_REASON_BREAK => "svcBreak",
+        _ => "session closed"
+    };
+    println_core!("ipcfuzz: `{}` crashed ({}) after case:", vsvc_get_pid_name(case.server_pid), reason_str);
+    case.print();
+
+    log_cmd_chunked(LOG_CMD_IPCFUZZ, &case.serialize(reason));
+}
+
+pub fn ipcfuzz_complete(mut case: IpcFuzzCase, svc_result: u32, ipc_result: u32)
+{
+    case.svc_result = svc_result;
+    case.ipc_result = ipc_result;
+
+    // A server dying mid-request takes the session with it
+    if svc_result == KERNEL_RESULT_CONNECTION_CLOSED {
+        ipcfuzz_report(&case, IPCFUZZ_REASON_CLOSED);
+    }
+
+    unsafe
+    {
+        if IPCFUZZ_HISTORY.len() >= IPCFUZZ_HISTORY_MAX {
+            IPCFUZZ_HISTORY.remove(0);
+        }
+        IPCFUZZ_HISTORY.push(case.clone());
+        IPCFUZZ_LAST_CASE.insert(case.server_pid, case);
+    }
+}
+
+// `pid` hit fatal or svcBreak, blame it on the last case it was sent
+pub fn ipcfuzz_notify_crash(pid: u32, reason: u8)
+{
+    unsafe
+    {
+        if let Some(case) = IPCFUZZ_LAST_CASE.remove(&pid) {
+            ipcfuzz_report(&case, reason);
+        }
+    }
+}
+
+pub fn ipcfuzz_print_status()
+{
+    unsafe
+    {
+        println!("IPC fuzzing: {}, seed {:#x}, up to {} mutations", if IPCFUZZ_ENABLED { "on" } else { "off" }, IPCFUZZ_SEED, IPCFUZZ_MAX_MUTATIONS);
+        println!("  {} cases sent, {} crashes", IPCFUZZ_SEQ, IPCFUZZ_NUM_CRASHES);
+
+        print!("  Targets:");
+        if IPCFUZZ_TARGETS.is_empty() && IPCFUZZ_PIDS.is_empty() {
+            print!(" (none)");
+        }
+        for (service, cmd_id) in IPCFUZZ_TARGETS.iter()
+        {
+            if *cmd_id == IPCFUZZ_CMD_ANY {
+                print!(" {}", service);
+            }
+            else {
+                print!(" {}", ipcdb_cmd_name(service, "", *cmd_id));
+            }
+        }
+        for pid in IPCFUZZ_PIDS.iter()
+        {
+            print!(" pid {} ({})", pid, vsvc_get_pid_name(*pid));
+        }
+        println!("");
+    }
+}
+
+pub fn ipcfuzz_print_history()
+{
+    unsafe
+    {
+        println!("Last {} fuzz cases:", IPCFUZZ_HISTORY.len());
+        for case in IPCFUZZ_HISTORY.iter()
+        {
+            case.print();
+        }
+    }
+}
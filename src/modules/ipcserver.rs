@@ -0,0 +1,211 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::collections::BTreeMap;
+use spin::mutex::Mutex;
+use crate::util::*;
+use crate::arm::mmu::translate_el1_stage12;
+use crate::hos::hipc::{HObject, HIPCPacket, hipc_get_handle_serverport, hipc_get_handle_serversession, hipc_register_handle_serversession, hipc_find_serversession_by_client};
+use crate::hos::hserversession::HServerSession;
+use crate::hos::ipcdb::{ipcdb_lookup_cmd, ipcdb_format_request};
+use crate::hos::result::result_format;
+use crate::modules::ipc::{IpcCmdHook, IpcPreHook, IpcPostHook, IpcRequest, IpcResponse};
+use crate::task::svc_wait::SvcWait;
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_curpid_name, vsvc_get_tls};
+
+static mut IPCSERVER_HOOKS: BTreeMap<(String, String, u32), IpcCmdHook> = BTreeMap::new();
+static mut IPCSERVER_LOGGING: bool = false;
+
+// Same matching rules as ipc_register_cmd_hook, but `pre` runs when the
+// server receives the request and `post` right before its reply goes out,
+// so every client is covered no matter when its session was opened
+pub fn ipc_register_server_hook(service: &str, interface: &str, cmd_id: u32, recv: Option<IpcPreHook>, reply: Option<IpcPostHook>)
+{
+    unsafe
+    {
+        IPCSERVER_HOOKS.insert((String::from(service), String::from(interface), cmd_id), IpcCmdHook { pre: recv, post: reply });
+    }
+}
+
+pub fn ipc_get_server_hook(service: &str, interface: &str, cmd_id: u32) -> Option<IpcCmdHook>
+{
+    unsafe
+    {
+        if let Some(hook) = IPCSERVER_HOOKS.get(&(String::from(service), String::from(interface), cmd_id)) {
+            return Some(*hook);
+        }
+        if let Some(hook) = IPCSERVER_HOOKS.get(&(String::new(), String::from(interface), cmd_id)) {
+            return Some(*hook);
+        }
+        return None;
+    }
+}
+
+pub fn ipcserver_set_logging(enabled: bool)
+{
+    unsafe
+    {
+        IPCSERVER_LOGGING = enabled;
+    }
+}
+
+// ReplyAndReceive blocks for as long as the server is idle, so only wait on
+// it if there's something to do with the result
+fn ipcserver_active() -> bool
+{
+    unsafe { IPCSERVER_LOGGING || !IPCSERVER_HOOKS.is_empty() }
+}
+
+pub fn ipcserver_accept_session(port_handle: u32, session_handle: u32)
+{
+    if let Some(hport) = hipc_get_handle_serverport(port_handle)
+    {
+        let hsession = hport.lock().create_server_session();
+        hipc_register_handle_serversession(session_handle, Arc::new(Mutex::new(hsession)));
+    }
+}
+
+// Sessions made with svcCreateSession are named once the server moves the
+// client end out as an object
+pub fn ipcserver_create_session(server_handle: u32, client_handle: u32)
+{
+    let mut hsession = HServerSession::new((vsvc_get_curpid() & 0xFF) as u8);
+    hsession.client_handle = Some(client_handle);
+    hipc_register_handle_serversession(server_handle, Arc::new(Mutex::new(hsession)));
+}
+
+fn ipcserver_track_out_object(req: &IpcRequest, resp: &IpcResponse)
+{
+    if resp.result != 0 {
+        return;
+    }
+
+    let child = match ipcdb_lookup_cmd(&req.service, &req.interface, req.get_cmd_id()).and_then(|cmd| cmd.get_out_object()) {
+        Some(child) => child,
+        None => return
+    };
+
+    if let Some(client_handle) = resp.get_handle(0) {
+        if let Some(hsession) = hipc_find_serversession_by_client(client_handle) {
+            let mut hsession_locked = hsession.lock();
+            hsession_locked.set_service(&req.service);
+            hsession_locked.set_interface(child);
+            hsession_locked.client_handle = None;
+        }
+    }
+}
+
+fn ipcserver_process_reply(msg_buf: u64, reply_handle: u32)
+{
+    let hsession = match hipc_get_handle_serversession(reply_handle) {
+        Some(hsession) => hsession,
+        None => return
+    };
+
+    let (pkt, service, interface) = {
+        let mut hsession_locked = hsession.lock();
+        match hsession_locked.pending.take() {
+            Some(pkt) => (pkt, hsession_locked.get_service(), hsession_locked.get_interface()),
+            None => return
+        }
+    };
+
+    let req = IpcRequest
+    {
+        handle: reply_handle,
+        hobj: HObject::ServerSession(hsession.clone()),
+        service: service,
+        interface: interface,
+        pkt: pkt
+    };
+
+    let resp_pkt = HIPCPacket::unpack_as(msg_buf, req.is_tipc());
+    let mut resp = IpcResponse
+    {
+        result: resp_pkt.get_cmd_id(),
+        child: None,
+        pkt: resp_pkt
+    };
+
+    if let Some(reply) = ipc_get_server_hook(&req.service, &req.interface, req.get_cmd_id()).and_then(|hook| hook.post) {
+        reply(&req, &mut resp);
+    }
+
+    if unsafe { IPCSERVER_LOGGING } {
+        println_core!("ipcserver: `{}` replied to {} -> {}", vsvc_get_curpid_name(), ipcdb_format_request(&req.service, &req.interface, &req.pkt), result_format(resp.result));
+    }
+
+    ipcserver_track_out_object(&req, &resp);
+}
+
+fn ipcserver_process_request(msg_buf: u64, handle: u32)
+{
+    let hsession = match hipc_get_handle_serversession(handle) {
+        Some(hsession) => hsession,
+        None => return
+    };
+
+    let pkt = HIPCPacket::unpack(msg_buf);
+    if !pkt.is_request() {
+        return;
+    }
+
+    let mut req = IpcRequest
+    {
+        handle: handle,
+        hobj: HObject::ServerSession(hsession.clone()),
+        service: hsession.lock().get_service(),
+        interface: hsession.lock().get_interface(),
+        pkt: pkt
+    };
+
+    if unsafe { IPCSERVER_LOGGING } {
+        println_core!("ipcserver: `{}` received {}", vsvc_get_curpid_name(), ipcdb_format_request(&req.service, &req.interface, &req.pkt));
+    }
+
+    if let Some(recv) = ipc_get_server_hook(&req.service, &req.interface, req.get_cmd_id()).and_then(|hook| hook.pre) {
+        recv(&mut req);
+    }
+
+    // Kept around so the reply can be matched up with it
+    hsession.lock().pending = Some(req.pkt);
+}
+
+// ReplyAndReceiveWithUserBuffer has the message buffer in x1/x2, shifting
+// the rest of the arguments over
+pub async fn ipcserver_handle_replyandreceive(mut pre_ctx: [u64; 32], user_buffer: bool) -> [u64; 32]
+{
+    let arg = if user_buffer { 2 } else { 0 };
+    let handles_ptr = pre_ctx[1 + arg];
+    let num_handles = (pre_ctx[2 + arg] & 0xFFFFFFFF) as u32;
+    let reply_handle = (pre_ctx[3 + arg] & 0xFFFFFFFF) as u32;
+
+    if !ipcserver_active() {
+        return pre_ctx;
+    }
+
+    let msg_buf = if user_buffer { translate_el1_stage12(pre_ctx[1]) } else { translate_el1_stage12(vsvc_get_tls()) };
+
+    if reply_handle != 0 {
+        ipcserver_process_reply(msg_buf, reply_handle);
+    }
+
+    // Wait for SVC to complete
+    let post_ctx = SvcWait::new(pre_ctx).await;
+
+    let result = (post_ctx[0] & 0xFFFFFFFF) as u32;
+    let index = (post_ctx[1] & 0xFFFFFFFF) as u32;
+    if result != 0 || index >= num_handles {
+        return post_ctx;
+    }
+
+    let handle = peek32(translate_el1_stage12(handles_ptr + (index as u64) * 4));
+    ipcserver_process_request(msg_buf, handle);
+
+    return post_ctx;
+}
@@ -0,0 +1,180 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use crate::logger::*;
+use crate::arm::ticks::ticks_to_ns;
+use crate::hos::hipc::{hipc_get_handle_clientsession, hipc_get_pid_clientsessions};
+use crate::hos::hipcstat::HIPCLatencyStats;
+use crate::hos::ipcdb::ipcdb_cmd_name;
+use crate::vm::vsvc::vsvc_get_pid_name;
+
+pub const LOG_CMD_IPCSTAT: u8 = 0x13;
+
+// Services sent per telemetry packet, busiest first
+const IPCSTAT_TELEMETRY_MAX: usize = 8;
+
+static mut IPCSTAT_ENABLED: bool = false;
+static mut IPCSTAT_CMDS: BTreeMap<(String, String, u32), HIPCLatencyStats> = BTreeMap::new();
+
+// Per-service counters since the last telemetry packet
+static mut IPCSTAT_INTERVAL: BTreeMap<String, HIPCLatencyStats> = BTreeMap::new();
+
+pub fn ipcstat_set_enabled(enabled: bool)
+{
+    unsafe
+    {
+        IPCSTAT_ENABLED = enabled;
+    }
+}
+
+pub fn ipcstat_is_enabled() -> bool
+{
+    unsafe { IPCSTAT_ENABLED }
+}
+
+pub fn ipcstat_clear()
+{
+    unsafe
+    {
+        IPCSTAT_CMDS.clear();
+        IPCSTAT_INTERVAL.clear();
+    }
+}
+
+// `start_ticks`/`end_ticks` bracket the SVC, from pre-hook to post-hook
+pub fn ipcstat_record(handle: u32, service: &String, interface: &String, cmd_id: u32, start_ticks: u64, end_ticks: u64, is_error: bool)
+{
+    if !ipcstat_is_enabled() {
+        return;
+    }
+
+    let latency_ns = ticks_to_ns(end_ticks.wrapping_sub(start_ticks));
+
+    if let Some(hsession) = hipc_get_handle_clientsession(handle) {
+        hsession.lock().stats.record(latency_ns, is_error);
+    }
+
+    unsafe
+    {
+        IPCSTAT_CMDS.entry((service.clone(), interface.clone(), cmd_id)).or_insert(HIPCLatencyStats::new()).record(latency_ns, is_error);
+        IPCSTAT_INTERVAL.entry(service.clone()).or_insert(HIPCLatencyStats::new()).record(latency_ns, is_error);
+    }
+}
+
+fn ipcstat_print_header(label: &str)
+{
+    println!("  {:40} {:>8} {:>6} {:>10} {:>10} {:>10} {:>10}", label, "reqs", "errs", "min ns", "avg ns", "p99 ns", "max ns");
+}
+
+fn ipcstat_print_row(label: &String, stats: &HIPCLatencyStats)
+{
+    println!("  {:40} {:>8} {:>6} {:>10} {:>10} {:>10} {:>10}", label, stats.requests, stats.errors,
+             stats.get_min_ns(), stats.get_avg_ns(), stats.get_p99_ns(), stats.max_ns);
+}
+
+fn ipcstat_by_service() -> Vec<(String, HIPCLatencyStats)>
+{
+    let mut services: BTreeMap<String, HIPCLatencyStats> = BTreeMap::new();
+
+    unsafe
+    {
+        for ((service, _, _), stats) in IPCSTAT_CMDS.iter()
+        {
+            services.entry(service.clone()).or_insert(HIPCLatencyStats::new()).merge(stats);
+        }
+    }
+
+    let mut sorted: Vec<(String, HIPCLatencyStats)> = services.into_iter().collect();
+    sorted.sort_by(|a, b| b.1.requests.cmp(&a.1.requests));
+    return sorted;
+}
+
+pub fn ipcstat_print_summary()
+{
+    println!("IPC stats: {}", if ipcstat_is_enabled() { "on" } else { "off" });
+    ipcstat_print_header("service");
+    for (service, stats) in ipcstat_by_service().iter()
+    {
+        ipcstat_print_row(service, stats);
+    }
+}
+
+pub fn ipcstat_print_service(service: &String) -> bool
+{
+    let mut cmds: Vec<(String, HIPCLatencyStats)> = Vec::new();
+
+    unsafe
+    {
+        for ((cmd_service, interface, cmd_id), stats) in IPCSTAT_CMDS.iter()
+        {
+            if cmd_service == service {
+                cmds.push((ipcdb_cmd_name(cmd_service, interface, *cmd_id), *stats));
+            }
+        }
+    }
+
+    if cmds.is_empty() {
+        return false;
+    }
+
+    cmds.sort_by(|a, b| b.1.requests.cmp(&a.1.requests));
+    println!("IPC stats for `{}`:", service);
+    ipcstat_print_header("command");
+    for (name, stats) in cmds.iter()
+    {
+        ipcstat_print_row(name, stats);
+    }
+    return true;
+}
+
+pub fn ipcstat_print_pid(pid: u32)
+{
+    println!("IPC stats for sessions of pid {} ({}):", pid, vsvc_get_pid_name(pid));
+    ipcstat_print_header("session");
+    for (handle, hsession) in hipc_get_pid_clientsessions(pid).iter()
+    {
+        let session = hsession.lock();
+        if session.stats.requests == 0 {
+            continue;
+        }
+        ipcstat_print_row(&format!("{:08x} `{}` {}", handle, session.get_service(), session.get_interface()), &session.stats);
+    }
+}
+
+// interval_ms u32, count u8, then per service: service_len u8, service,
+// requests u32, errors u32, avg_ns u32, max_ns u32. Mirrored by
+// debug_client's ipc_stat.rs
+pub fn ipcstat_send_telemetry(interval_ms: u32)
+{
+    if !ipcstat_is_enabled() {
+        return;
+    }
+
+    let mut services: Vec<(String, HIPCLatencyStats)> = unsafe { core::mem::take(&mut IPCSTAT_INTERVAL) }.into_iter().collect();
+    services.sort_by(|a, b| b.1.requests.cmp(&a.1.requests));
+    services.truncate(IPCSTAT_TELEMETRY_MAX);
+
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(&interval_ms.to_le_bytes());
+    out.push(services.len() as u8);
+    for (service, stats) in services.iter()
+    {
+        let service = service.as_bytes();
+        let service_len = core::cmp::min(service.len(), 0xFF);
+
+        out.push(service_len as u8);
+        out.extend_from_slice(&service[..service_len]);
+        out.extend_from_slice(&(stats.requests.min(u32::MAX as u64) as u32).to_le_bytes());
+        out.extend_from_slice(&(stats.errors.min(u32::MAX as u64) as u32).to_le_bytes());
+        out.extend_from_slice(&(stats.get_avg_ns().min(u32::MAX as u64) as u32).to_le_bytes());
+        out.extend_from_slice(&(stats.max_ns.min(u32::MAX as u64) as u32).to_le_bytes());
+    }
+
+    log_cmd_chunked(LOG_CMD_IPCSTAT, &out);
+}
@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::BTreeSet;
+use crate::logger::*;
+use crate::vm::vsvc::vsvc_get_pid_name;
+
+pub const LOG_CMD_IPCTRACE: u8 = 0x10;
+
+pub const IPCTRACE_FLAG_DOMAIN: u8 = bit!(0);
+pub const IPCTRACE_FLAG_TIPC: u8 = bit!(1);
+
+static mut IPCTRACE_ENABLED: bool = false;
+static mut IPCTRACE_SERVICES: BTreeSet<String> = BTreeSet::new();
+static mut IPCTRACE_PIDS: BTreeSet<u32> = BTreeSet::new();
+
+pub struct IpcTraceRecord
+{
+    pub pid: u32,
+    pub handle: u32,
+    pub domain_obj: u32,
+    pub cmd_id: u32,
+    pub pkt_type: u16,
+    pub flags: u8,
+    pub svc_result: u32,
+    pub ipc_result: u32,
+    pub latency: u64,
+    pub service: String,
+    pub interface: String,
+    pub payload: Vec<u8>,
+}
+
+impl IpcTraceRecord
+{
+    // Little endian, mirrored by debug_client's ipc_trace.rs
+    pub fn serialize(&self) -> Vec<u8>
+    {
+        let service = self.service.as_bytes();
+        let service_len = core::cmp::min(service.len(), 0xFF);
+        let interface = self.interface.as_bytes();
+        let interface_len = core::cmp::min(interface.len(), 0xFF);
+        let payload_len = core::cmp::min(self.payload.len(), 0xFFFF);
+
+        let mut out: Vec<u8> = Vec::with_capacity(37 + service_len + interface_len + payload_len);
+        out.extend_from_slice(&self.pid.to_le_bytes());
+        out.extend_from_slice(&self.handle.to_le_bytes());
+        out.extend_from_slice(&self.domain_obj.to_le_bytes());
+        out.extend_from_slice(&self.cmd_id.to_le_bytes());
+        out.extend_from_slice(&self.pkt_type.to_le_bytes());
+        out.push(self.flags);
+        out.push(service_len as u8);
+        out.extend_from_slice(&self.svc_result.to_le_bytes());
+        out.extend_from_slice(&self.ipc_result.to_le_bytes());
+        out.extend_from_slice(&self.latency.to_le_bytes());
+        out.extend_from_slice(&service[..service_len]);
+        out.push(interface_len as u8);
+        out.extend_from_slice(&interface[..interface_len]);
+        out.extend_from_slice(&(payload_len as u16).to_le_bytes());
+        out.extend_from_slice(&self.payload[..payload_len]);
+
+        return out;
+    }
+}
+
+pub fn ipctrace_set_enabled(enabled: bool)
+{
+    unsafe
+    {
+        IPCTRACE_ENABLED = enabled;
+    }
+}
+
+pub fn ipctrace_is_enabled() -> bool
+{
+    unsafe { IPCTRACE_ENABLED }
+}
+
+pub fn ipctrace_filter_service(service: &String, add: bool)
+{
+    unsafe
+    {
+        if add {
+            IPCTRACE_SERVICES.insert(service.clone());
+        }
+        else {
+            IPCTRACE_SERVICES.remove(service);
+        }
+    }
+}
+
+pub fn ipctrace_filter_pid(pid: u32, add: bool)
+{
+    unsafe
+    {
+        if add {
+            IPCTRACE_PIDS.insert(pid);
+        }
+        else {
+            IPCTRACE_PIDS.remove(&pid);
+        }
+    }
+}
+
+pub fn ipctrace_filter_clear()
+{
+    unsafe
+    {
+        IPCTRACE_SERVICES.clear();
+        IPCTRACE_PIDS.clear();
+    }
+}
+
+// Empty filter lists match everything
+pub fn ipctrace_should_trace(pid: u32, service: &String) -> bool
+{
+    unsafe
+    {
+        if !IPCTRACE_ENABLED {
+            return false;
+        }
+
+        if !IPCTRACE_PIDS.is_empty() && !IPCTRACE_PIDS.contains(&pid) {
+            return false;
+        }
+
+        if !IPCTRACE_SERVICES.is_empty() && !IPCTRACE_SERVICES.contains(service) {
+            return false;
+        }
+
+        return true;
+    }
+}
+
+pub fn ipctrace_emit(record: &IpcTraceRecord)
+{
+    log_cmd_chunked(LOG_CMD_IPCTRACE, &record.serialize());
+}
+
+pub fn ipctrace_print_status()
+{
+    unsafe
+    {
+        println!("IPC trace: {}", if IPCTRACE_ENABLED { "on" } else { "off" });
+
+        print!("  Services:");
+        if IPCTRACE_SERVICES.is_empty() {
+            print!(" (all)");
+        }
+        for service in IPCTRACE_SERVICES.iter()
+        {
+            print!(" {}", service);
+        }
+        println!("");
+
+        print!("  PIDs:");
+        if IPCTRACE_PIDS.is_empty() {
+            print!(" (all)");
+        }
+        for pid in IPCTRACE_PIDS.iter()
+        {
+            print!(" {} ({})", pid, vsvc_get_pid_name(*pid));
+        }
+        println!("");
+    }
+}
@@ -0,0 +1,80 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use crate::logger::*;
+use crate::hos::hmempolicy::{MemPolicyEngine, MemPolicyRule, MemPolicyTarget, MemPolicyKind};
+use crate::hos::hprocess::hprocess_get;
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_pid_name};
+
+static mut MEMPOLICY_ENGINE: MemPolicyEngine = MemPolicyEngine::new();
+
+// Cheap check so handlers can skip waiting on SVCs nobody wants rewritten
+pub fn mempolicy_has(kind: MemPolicyKind) -> bool
+{
+    unsafe { MEMPOLICY_ENGINE.has_kind(kind) }
+}
+
+// Runs `val` through the rules for the calling process. `what` is only for
+// the log line
+pub fn mempolicy_rewrite(kind: MemPolicyKind, what: &str, val: u64, pool: Option<u32>) -> u64
+{
+    if !mempolicy_has(kind) {
+        return val;
+    }
+
+    let pid = vsvc_get_curpid();
+    let process = vsvc_get_pid_name(pid);
+    let target = MemPolicyTarget
+    {
+        process: &process,
+        program_id: hprocess_get(pid).map(|hprocess| hprocess.program_id).unwrap_or(0),
+    };
+
+    match unsafe { MEMPOLICY_ENGINE.rewrite(kind, &target, val, pool) } {
+        Some((rule_id, new_val)) => {
+            println_core!("mempolicy: rule {} rewrote {} {:#x} -> {:#x} for `{}` (pid {})", rule_id, what, val, new_val, process, pid);
+            return new_val;
+        },
+        None => return val
+    };
+}
+
+pub fn mempolicy_add(args: &[String]) -> Result<u32, String>
+{
+    let rule = MemPolicyRule::parse(args)?;
+    unsafe { Ok(MEMPOLICY_ENGINE.rules.add(rule)) }
+}
+
+pub fn mempolicy_remove(id: u32) -> bool
+{
+    unsafe { MEMPOLICY_ENGINE.rules.remove(id) }
+}
+
+pub fn mempolicy_clear()
+{
+    unsafe
+    {
+        MEMPOLICY_ENGINE.rules.clear();
+    }
+}
+
+pub fn mempolicy_print_rules()
+{
+    unsafe
+    {
+        if MEMPOLICY_ENGINE.rules.is_empty() {
+            println!("No memory policy rules");
+            return;
+        }
+
+        println!("Memory policy rules:");
+        for rule in MEMPOLICY_ENGINE.rules.iter()
+        {
+            println!("  {}: {}", rule.id, rule.describe());
+        }
+    }
+}
@@ -5,9 +5,20 @@
  */
 
 pub mod ipc;
+pub mod ipctrace;
+pub mod ipcfuzz;
+pub mod ipccap;
+pub mod ipcserver;
+pub mod ipcstat;
+pub mod svctrace;
+pub mod svcinject;
+pub mod svcprof;
+pub mod mempolicy;
+pub mod gdbstub;
 pub mod fsp;
 pub mod pcv;
 pub mod log;
 pub mod set;
 pub mod fatal;
 pub mod erpt;
+pub mod htbdbg;
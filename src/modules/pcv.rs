@@ -22,82 +22,40 @@ use crate::hos::hipc::{HObject, HObjectExtra, HExtraString};
 
 pub fn pcv_init()
 {
-    ipc_register_handler(String::from("clkrst"), handle_clkrst_boxed);
-    ipc_register_handler(String::from("clkrst:i"), handle_clkrst_boxed);
+    ipc_register_cmd_hook("clkrst", "", 0, None, Some(clkrst_open_session_post));
+    ipc_register_cmd_hook("clkrst:i", "", 0, None, Some(clkrst_open_session_post));
+    ipc_register_cmd_hook("", "IClkrstSession", 7, Some(clksession_set_clock_rate_pre), None);
 }
 
-async fn handle_clksession(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
+fn clksession_set_clock_rate_pre(req: &mut IpcRequest)
 {
-    let pkt = hipc_get_packet();
-    let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
     let mut dev = 0xFFFFFFFF;
-    let extra = hobj.get_extra();
+    let extra = req.hobj.get_extra();
     match extra
     {
         HObjectExtra::U32(a) => { dev = a.val; }
         _ => {}
     }
     
-    if pkt.get_cmd_id() == 7
-    {
-        let mut hz = pkt.read_u32(0);
-        //println_core!("clkrst::iClkrstSession::SetClockRate(dev_id={:x}, hz={}) from `{}`", dev, hz, vsvc_get_curpid_name());
-        
-        // CPU clocks
-        if dev == 0x40000001 {
-            hz = 1785 * 1000000;
-            //println_core!("clkrst: Overclocking to 1.785GHz!");
-            pkt.write_u32(0, hz);
-        }
-
-        return pre_ctx;
-    }
-    else
-    {
-        //println_core!("clkrst::iClkrstSession::Cmd{}(dev_id={:x}) from `{}`", pkt.get_cmd_id(), dev, vsvc_get_curpid_name());
-    }
+    let mut hz = req.read_u32(0);
+    //println_core!("clkrst::iClkrstSession::SetClockRate(dev_id={:x}, hz={}) from `{}`", dev, hz, vsvc_get_curpid_name());
     
-    return pre_ctx;
-}
-
-fn handle_clksession_boxed(mut pre_ctx: [u64; 32], hobj: HObject) -> Pin<Box<dyn Future<Output = [u64; 32]> + Send>> {
-    Box::pin(handle_clksession(pre_ctx, hobj))
+    // CPU clocks
+    if dev == 0x40000001 {
+        hz = 1785 * 1000000;
+        //println_core!("clkrst: Overclocking to 1.785GHz!");
+        req.write_u32(0, hz);
+        req.pack();
+    }
 }
 
-async fn handle_clkrst(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
+fn clkrst_open_session_post(req: &IpcRequest, resp: &mut IpcResponse)
 {
-    let pkt = hipc_get_packet();
+    let dev = req.read_u32(0);
     
-    let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
-    let hsession = hipc_get_handle_clientsession(handle).unwrap();
-    
-    match pkt.get_cmd_id()
-    {
-        0 => // OpenSession
-        {
-            let dev = pkt.read_u32(0);
-            
-            // Wait for SVC to complete
-            let post_ctx = SvcWait::new(pre_ctx).await;
-            let resp = hipc_get_packet();
-    
-            // Try to hook first handle/domain if it exists
-            if (resp.hook_first_handle(handle, handle_clksession_boxed))
-            {
-                if let Some(resp_hobj) = resp.get_first_handle_obj(handle) {
-                    resp_hobj.set_extra_u32(dev);
-                }
-                //println_core!("clkrst::OpenSession({:08x}) from `{}`", dev, vsvc_get_curpid_name());
-            }
-
-            return post_ctx;
-        },
-        _ => { return pre_ctx; }
+    // The dispatcher already hooked the IClkrstSession, just tag its device
+    if let Some(child) = &resp.child {
+        child.set_extra_u32(dev);
+        //println_core!("clkrst::OpenSession({:08x}) from `{}`", dev, vsvc_get_curpid_name());
     }
-
-    return pre_ctx;
-}
-
-fn handle_clkrst_boxed(mut pre_ctx: [u64; 32], hobj: HObject) -> Pin<Box<dyn Future<Output = [u64; 32]> + Send>> {
-    Box::pin(handle_clkrst(pre_ctx, hobj))
 }
@@ -10,87 +10,60 @@ use alloc::string::String;
 use alloc::prelude::v1::Box;
 use crate::task::svc_wait::SvcWait;
 use crate::vm::vsvc::vsvc_get_curpid_name;
-use crate::modules::ipc::ipc_register_handler;
+use crate::modules::ipc::{ipc_register_cmd_hook, IpcRequest, IpcResponse};
 use crate::hos::hipc::{HObject};
 use crate::util::*;
 
 pub fn set_init()
 {
-    ipc_register_handler(String::from("set:sys"), handle_setsys_boxed);
+    ipc_register_cmd_hook("set:sys", "", 3, None, Some(setsys_get_firmware_version_post));
+    ipc_register_cmd_hook("set:sys", "", 4, None, Some(setsys_get_firmware_version_post));
+    ipc_register_cmd_hook("set:sys", "", 38, None, Some(setsys_get_settings_item_value_post));
+    ipc_register_cmd_hook("set:sys", "", 62, Some(setsys_get_debug_mode_flag_pre), None);
 }
 
-async fn handle_setsys(mut pre_ctx: [u64; 32], hobj: HObject) -> [u64; 32]
+fn setsys_get_firmware_version_post(req: &IpcRequest, resp: &mut IpcResponse)
 {
-    let pkt = hipc_get_packet();
-    
-    let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
-    let hsession = hipc_get_handle_clientsession(handle).unwrap();
+    let curpid_name = vsvc_get_curpid_name();
+    if curpid_name != "qlaunch" && curpid_name != "maintenance" {
+        return;
+    }
     
-    match pkt.get_cmd_id()
+    if let Some(mut desc) = resp.get_static(0)
     {
-        3 | 4 => // GetSystemVersion
-        {
-            let curpid_name = vsvc_get_curpid_name();
-            if curpid_name != "qlaunch" && curpid_name != "maintenance" {
-                return pre_ctx;
-            }
-            
-            // Wait for SVC to complete
-            let post_ctx = SvcWait::new(pre_ctx).await;
-            let resp = hipc_get_packet();
-            
-            if let Some(mut desc) = resp.get_static(0)
-            {
-                let version = desc.read_str_at(0x68);
-                let new_version = format!("HTB {}", version);
+        let version = desc.read_str_at(0x68);
+        let new_version = format!("HTB {}", version);
 
-                desc.put_str_at(0x68, new_version);
-                desc.pack();
-            }
-            
-            return post_ctx;
-        },
-        38 => // GetSettingsItemValue
-        {
-            let mut setting_id = format!("");
-            if let Some(desc) = pkt.get_static(0) {
-                if let Some(desc_2) = pkt.get_static(1) {
-                    setting_id = format!("{}!{}", desc.read_str(), desc_2.read_str());
-                }
-            }
-            
-            let recv_desc = pkt.get_recv(0);
-            
-            // Wait for SVC to complete
-            let post_ctx = SvcWait::new(pre_ctx).await;
-            let resp = hipc_get_packet();
-            
-            if let Some(desc) = recv_desc
-            {
-                let result_str = if desc.is_ascii() { desc.read_str(0) } else { format!("{:016x}", peek64(desc.get_addr_el2())) };
-                //println_core!("setsys::GetSettingsItemValue(`{}`) -> `{}` from `{}`", setting_id, result_str, vsvc_get_curpid_name());
-                
-                if setting_id == "am.debug!force_disable_continuous_recording" {
-                    poke8(desc.get_addr_el2(), 1);
-                }
-                else if setting_id == "am.debug!dev_function" {
-                    poke8(desc.get_addr_el2(), 1);
-                }
-            }
+        desc.put_str_at(0x68, new_version);
+        desc.pack();
+    }
+}
 
-            return post_ctx;
-        },
-        62 => // GetDebugModeFlag
-        {
-            pkt.write_u32(0, 1);
-            return pre_ctx;
+fn setsys_get_settings_item_value_post(req: &IpcRequest, resp: &mut IpcResponse)
+{
+    let mut setting_id = format!("");
+    if let Some(desc) = req.get_static(0) {
+        if let Some(desc_2) = req.get_static(1) {
+            setting_id = format!("{}!{}", desc.read_str(), desc_2.read_str());
+        }
+    }
+    
+    if let Some(desc) = req.get_recv(0)
+    {
+        let result_str = if desc.is_ascii() { desc.read_str(0) } else { format!("{:016x}", peek64(desc.get_addr_el2())) };
+        //println_core!("setsys::GetSettingsItemValue(`{}`) -> `{}` from `{}`", setting_id, result_str, vsvc_get_curpid_name());
+        
+        if setting_id == "am.debug!force_disable_continuous_recording" {
+            poke8(desc.get_addr_el2(), 1);
+        }
+        else if setting_id == "am.debug!dev_function" {
+            poke8(desc.get_addr_el2(), 1);
         }
-        _ => { return pre_ctx; }
     }
-
-    return pre_ctx;
 }
 
-fn handle_setsys_boxed(mut pre_ctx: [u64; 32], hobj: HObject) -> Pin<Box<dyn Future<Output = [u64; 32]> + Send>> {
-    Box::pin(handle_setsys(pre_ctx, hobj))
+fn setsys_get_debug_mode_flag_pre(req: &mut IpcRequest)
+{
+    req.write_u32(0, 1);
+    req.pack();
 }
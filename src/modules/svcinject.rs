@@ -0,0 +1,109 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use crate::logger::*;
+use crate::util::*;
+use crate::arm::ticks::get_ticks;
+use crate::hos::hipc::hipc_get_handle_clientsession;
+use crate::hos::hsvc::hsvc_return_early;
+use crate::hos::hsvcinject::{SvcInjectEngine, SvcInjectRule, SvcInjectCall};
+use crate::hos::result::result_format;
+use crate::hos::svcsig::svcsig_lookup;
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_pid_name};
+
+static mut SVCINJECT_ENGINE: SvcInjectEngine = SvcInjectEngine::new();
+
+// Port or service name a call is aimed at, for `target` rules
+fn svcinject_get_target(svc_id: u8, ctx: &[u64]) -> Option<String>
+{
+    let session_handle = match svc_id {
+        0x1F | 0x71 => return Some(String::from(kstr!(ctx[1]))), // ConnectToNamedPort, ManageNamedPort
+        0x21 => ctx[0], // SendSyncRequest
+        0x22 => ctx[2], // SendSyncRequestWithUserBuffer
+        _ => return None
+    };
+
+    return hipc_get_handle_clientsession((session_handle & 0xFFFFFFFF) as u32).map(|hsession| hsession.lock().get_service());
+}
+
+// Result to fail the SVC with, if a rule fires
+pub fn svcinject_pre(iss: u32, ctx: &[u64]) -> Option<u32>
+{
+    if unsafe { SVCINJECT_ENGINE.rules.is_empty() } {
+        return None;
+    }
+
+    let svc_id = (iss & 0xFF) as u8;
+    let pid = vsvc_get_curpid();
+    let process = vsvc_get_pid_name(pid);
+    let target = svcinject_get_target(svc_id, ctx);
+
+    let call = SvcInjectCall
+    {
+        pid: pid,
+        process: &process,
+        svc_id: svc_id,
+        target: target.as_deref(),
+    };
+
+    let (rule_id, result) = unsafe { SVCINJECT_ENGINE.check(&call) }?;
+
+    let svc_name = svcsig_lookup(svc_id).map(|sig| sig.name).unwrap_or("?");
+    match &target {
+        Some(target) => println_core!("svcinject: rule {} failed svc{}(`{}`) in `{}` (pid {}) with {}", rule_id, svc_name, target, process, pid, result_format(result)),
+        None => println_core!("svcinject: rule {} failed svc{} in `{}` (pid {}) with {}", rule_id, svc_name, process, pid, result_format(result))
+    };
+    return Some(result);
+}
+
+pub async fn svcinject_task(pre_ctx: [u64; 32], result: u32) -> [u64; 32]
+{
+    return hsvc_return_early(pre_ctx, result);
+}
+
+pub fn svcinject_add(args: &[String]) -> Result<u32, String>
+{
+    let rule = SvcInjectRule::parse(args)?;
+
+    unsafe
+    {
+        if SVCINJECT_ENGINE.rules.is_empty() {
+            SVCINJECT_ENGINE.seed(get_ticks());
+        }
+        return Ok(SVCINJECT_ENGINE.rules.add(rule));
+    }
+}
+
+pub fn svcinject_remove(id: u32) -> bool
+{
+    unsafe { SVCINJECT_ENGINE.rules.remove(id) }
+}
+
+pub fn svcinject_clear()
+{
+    unsafe
+    {
+        SVCINJECT_ENGINE.rules.clear();
+    }
+}
+
+pub fn svcinject_print_rules()
+{
+    unsafe
+    {
+        if SVCINJECT_ENGINE.rules.is_empty() {
+            println!("No SVC injection rules");
+            return;
+        }
+
+        println!("SVC injection rules:");
+        for rule in SVCINJECT_ENGINE.rules.iter()
+        {
+            println!("  {}: {}", rule.id, rule.describe());
+        }
+    }
+}
@@ -0,0 +1,338 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use crate::logger::*;
+use crate::arm::threading::get_core;
+use crate::arm::ticks::ticks_to_ns;
+use crate::hos::svcsig::svcsig_lookup;
+use crate::hos::firmware::firmware_get_thread_ctx;
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_pid_name};
+
+pub const LOG_CMD_SVCPROF: u8 = 0x15;
+
+// (pid, SVC) pairs sent per telemetry packet, most total time first
+const SVCPROF_TELEMETRY_MAX: usize = 16;
+
+const SVCPROF_CORES: usize = 4;
+const SVCPROF_SLOTS: usize = 512; // per core, power of two
+const SVCPROF_INFLIGHT_SLOTS: usize = 512; // power of two
+
+// Every core only ever writes its own table, so recording is a handful of
+// relaxed atomics. Readers on other cores may see an entry mid-update,
+// which is fine for a profile.
+struct SvcProfEntry
+{
+    key: AtomicU32, // 0 if free, otherwise SVCPROF_KEY_USED | pid << 8 | svc
+    calls: AtomicU64,
+    total_ticks: AtomicU64,
+    max_ticks: AtomicU64,
+}
+
+// Pre-hook tick stamps by thread. A thread can block in one SVC and come out
+// on another core, so these are shared and claimed with a CAS
+struct SvcProfInflight
+{
+    thread_ctx: AtomicU64,
+    ticks: AtomicU64,
+}
+
+const SVCPROF_KEY_USED: u32 = 1 << 16;
+
+const SVCPROF_ENTRY_INIT: SvcProfEntry = SvcProfEntry { key: AtomicU32::new(0), calls: AtomicU64::new(0), total_ticks: AtomicU64::new(0), max_ticks: AtomicU64::new(0) };
+const SVCPROF_TABLE_INIT: [SvcProfEntry; SVCPROF_SLOTS] = [SVCPROF_ENTRY_INIT; SVCPROF_SLOTS];
+const SVCPROF_INFLIGHT_INIT: SvcProfInflight = SvcProfInflight { thread_ctx: AtomicU64::new(0), ticks: AtomicU64::new(0) };
+const SVCPROF_PIDS_INIT: [AtomicU64; 4] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+static SVCPROF_TABLES: [[SvcProfEntry; SVCPROF_SLOTS]; SVCPROF_CORES] = [SVCPROF_TABLE_INIT; SVCPROF_CORES];
+static SVCPROF_INFLIGHT: [SvcProfInflight; SVCPROF_INFLIGHT_SLOTS] = [SVCPROF_INFLIGHT_INIT; SVCPROF_INFLIGHT_SLOTS];
+static SVCPROF_DROPPED: [AtomicU64; SVCPROF_CORES] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+// PIDs each core still has to zero before its next record. Until it does,
+// readers skip that core's entries for them.
+static SVCPROF_RESET_PIDS: [[AtomicU64; 4]; SVCPROF_CORES] = [SVCPROF_PIDS_INIT; SVCPROF_CORES];
+
+#[derive(Copy, Clone, Default)]
+pub struct SvcProfStats
+{
+    pub calls: u64,
+    pub total_ticks: u64,
+    pub max_ticks: u64,
+}
+
+impl SvcProfStats
+{
+    fn merge(&mut self, other: &SvcProfStats)
+    {
+        self.calls += other.calls;
+        self.total_ticks += other.total_ticks;
+        self.max_ticks = core::cmp::max(self.max_ticks, other.max_ticks);
+    }
+
+    pub fn get_total_ns(&self) -> u64
+    {
+        ticks_to_ns(self.total_ticks)
+    }
+
+    pub fn get_avg_ns(&self) -> u64
+    {
+        if self.calls == 0 {
+            return 0;
+        }
+        return ticks_to_ns(self.total_ticks / self.calls);
+    }
+
+    pub fn get_max_ns(&self) -> u64
+    {
+        ticks_to_ns(self.max_ticks)
+    }
+}
+
+fn svcprof_hash(val: u64) -> usize
+{
+    (val.wrapping_mul(0x9E3779B97F4A7C15) >> 40) as usize
+}
+
+fn svcprof_reset_pending(core: usize, pid: u32) -> bool
+{
+    let word = SVCPROF_RESET_PIDS[core][((pid >> 6) & 3) as usize].load(Ordering::Relaxed);
+    return (word & (1 << (pid & 0x3F))) != 0;
+}
+
+// Zeroes whatever another core asked this one to forget. Keys stay put so
+// probe chains don't break.
+fn svcprof_apply_resets(core: usize)
+{
+    for word_idx in 0..4
+    {
+        let word = SVCPROF_RESET_PIDS[core][word_idx].load(Ordering::Acquire);
+        if word == 0 {
+            continue;
+        }
+
+        for entry in SVCPROF_TABLES[core].iter()
+        {
+            let key = entry.key.load(Ordering::Relaxed);
+            let pid = (key >> 8) & 0xFF;
+            if key == 0 || (pid >> 6) as usize != word_idx || (word & (1 << (pid & 0x3F))) == 0 {
+                continue;
+            }
+
+            entry.calls.store(0, Ordering::Relaxed);
+            entry.total_ticks.store(0, Ordering::Relaxed);
+            entry.max_ticks.store(0, Ordering::Relaxed);
+        }
+        SVCPROF_RESET_PIDS[core][word_idx].fetch_and(!word, Ordering::Release);
+    }
+}
+
+// Called first thing from the SVC pre-hook HVCs
+pub fn svcprof_pre(ctx: &[u64], ticks: u64)
+{
+    let thread_ctx = firmware_get_thread_ctx(ctx);
+    let slot = &SVCPROF_INFLIGHT[svcprof_hash(thread_ctx) & (SVCPROF_INFLIGHT_SLOTS - 1)];
+
+    // Colliding threads just lose a sample
+    slot.ticks.store(ticks, Ordering::Relaxed);
+    slot.thread_ctx.store(thread_ctx, Ordering::Release);
+}
+
+// Called first thing from the SVC post-hook HVCs
+pub fn svcprof_post(iss: u32, ctx: &[u64], ticks: u64)
+{
+    let thread_ctx = firmware_get_thread_ctx(ctx);
+    let slot = &SVCPROF_INFLIGHT[svcprof_hash(thread_ctx) & (SVCPROF_INFLIGHT_SLOTS - 1)];
+    if slot.thread_ctx.load(Ordering::Acquire) != thread_ctx {
+        return;
+    }
+
+    let start_ticks = slot.ticks.load(Ordering::Relaxed);
+    if slot.thread_ctx.compare_exchange(thread_ctx, 0, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+        return;
+    }
+
+    let core = get_core() as usize;
+    let pid = vsvc_get_curpid() & 0xFF;
+    let latency = ticks.wrapping_sub(start_ticks);
+    let key = SVCPROF_KEY_USED | (pid << 8) | (iss & 0xFF);
+
+    svcprof_apply_resets(core);
+
+    let table = &SVCPROF_TABLES[core];
+    let mut idx = svcprof_hash(key as u64);
+    for _ in 0..SVCPROF_SLOTS
+    {
+        let entry = &table[idx & (SVCPROF_SLOTS - 1)];
+        let entry_key = entry.key.load(Ordering::Relaxed);
+        if entry_key == 0 {
+            entry.key.store(key, Ordering::Release);
+        }
+        else if entry_key != key {
+            idx += 1;
+            continue;
+        }
+
+        entry.calls.fetch_add(1, Ordering::Relaxed);
+        entry.total_ticks.fetch_add(latency, Ordering::Relaxed);
+        if latency > entry.max_ticks.load(Ordering::Relaxed) {
+            entry.max_ticks.store(latency, Ordering::Relaxed);
+        }
+        return;
+    }
+
+    SVCPROF_DROPPED[core].fetch_add(1, Ordering::Relaxed);
+}
+
+// Every core's table folded into one, by (pid, SVC)
+pub fn svcprof_collect(pid: Option<u32>) -> BTreeMap<(u32, u8), SvcProfStats>
+{
+    let mut merged: BTreeMap<(u32, u8), SvcProfStats> = BTreeMap::new();
+    for core in 0..SVCPROF_CORES
+    {
+        for entry in SVCPROF_TABLES[core].iter()
+        {
+            let key = entry.key.load(Ordering::Acquire);
+            if key == 0 {
+                continue;
+            }
+
+            let entry_pid = (key >> 8) & 0xFF;
+            if pid.is_some() && pid != Some(entry_pid) {
+                continue;
+            }
+            if svcprof_reset_pending(core, entry_pid) {
+                continue;
+            }
+
+            let stats = SvcProfStats
+            {
+                calls: entry.calls.load(Ordering::Relaxed),
+                total_ticks: entry.total_ticks.load(Ordering::Relaxed),
+                max_ticks: entry.max_ticks.load(Ordering::Relaxed),
+            };
+            if stats.calls == 0 {
+                continue;
+            }
+            merged.entry((entry_pid, (key & 0xFF) as u8)).or_insert(SvcProfStats::default()).merge(&stats);
+        }
+    }
+    return merged;
+}
+
+pub fn svcprof_reset(pid: Option<u32>)
+{
+    for core in 0..SVCPROF_CORES
+    {
+        match pid {
+            Some(pid) => { SVCPROF_RESET_PIDS[core][((pid >> 6) & 3) as usize].fetch_or(1 << (pid & 0x3F), Ordering::Release); },
+            None => {
+                for word in SVCPROF_RESET_PIDS[core].iter()
+                {
+                    word.store(u64::MAX, Ordering::Release);
+                }
+                SVCPROF_DROPPED[core].store(0, Ordering::Relaxed);
+            }
+        };
+    }
+}
+
+fn svcprof_svc_name(svc_id: u8) -> String
+{
+    match svcsig_lookup(svc_id) {
+        Some(sig) => String::from(sig.name),
+        None => format!("{:#x}", svc_id)
+    }
+}
+
+fn svcprof_print_header(label: &str)
+{
+    println!("  {:36} {:>10} {:>12} {:>10} {:>10}", label, "calls", "total us", "avg ns", "max ns");
+}
+
+fn svcprof_print_row(label: &String, stats: &SvcProfStats)
+{
+    println!("  {:36} {:>10} {:>12} {:>10} {:>10}", label, stats.calls, stats.get_total_ns() / 1000, stats.get_avg_ns(), stats.get_max_ns());
+}
+
+// No pid: per process totals, then the busiest (pid, SVC) pairs. With a
+// pid: every SVC that process made.
+pub fn svcprof_print(pid: Option<u32>)
+{
+    let stats = svcprof_collect(pid);
+    let mut sorted: Vec<(&(u32, u8), &SvcProfStats)> = stats.iter().collect();
+    sorted.sort_by(|a, b| b.1.total_ticks.cmp(&a.1.total_ticks));
+
+    if let Some(pid) = pid {
+        println!("SVC profile for pid {} ({}):", pid, vsvc_get_pid_name(pid));
+        svcprof_print_header("svc");
+        for ((_, svc_id), stats) in sorted.iter()
+        {
+            svcprof_print_row(&svcprof_svc_name(*svc_id), stats);
+        }
+        return;
+    }
+
+    let mut processes: BTreeMap<u32, SvcProfStats> = BTreeMap::new();
+    for ((pid, _), stats) in stats.iter()
+    {
+        processes.entry(*pid).or_insert(SvcProfStats::default()).merge(stats);
+    }
+    let mut processes: Vec<(u32, SvcProfStats)> = processes.into_iter().collect();
+    processes.sort_by(|a, b| b.1.total_ticks.cmp(&a.1.total_ticks));
+
+    let dropped: u64 = SVCPROF_DROPPED.iter().map(|count| count.load(Ordering::Relaxed)).sum();
+    println!("SVC profile ({} samples dropped, tables full):", dropped);
+    svcprof_print_header("process");
+    for (pid, stats) in processes.iter()
+    {
+        svcprof_print_row(&format!("{} ({})", pid, vsvc_get_pid_name(*pid)), stats);
+    }
+
+    println!("");
+    svcprof_print_header("process/svc");
+    for ((pid, svc_id), stats) in sorted.iter().take(20)
+    {
+        svcprof_print_row(&format!("{} {}", vsvc_get_pid_name(*pid), svcprof_svc_name(*svc_id)), stats);
+    }
+}
+
+// count u8, then per (pid, SVC): pid u8, svc u8, name_len u8, process name,
+// calls u32, total_us u32, avg_ns u32, max_ns u32. Totals are since the last
+// reset. Mirrored by debug_client's svc_prof.rs
+pub fn svcprof_send_telemetry()
+{
+    let stats = svcprof_collect(None);
+    if stats.is_empty() {
+        return;
+    }
+
+    let mut sorted: Vec<(&(u32, u8), &SvcProfStats)> = stats.iter().collect();
+    sorted.sort_by(|a, b| b.1.total_ticks.cmp(&a.1.total_ticks));
+    sorted.truncate(SVCPROF_TELEMETRY_MAX);
+
+    let mut out: Vec<u8> = Vec::new();
+    out.push(sorted.len() as u8);
+    for ((pid, svc_id), stats) in sorted.iter()
+    {
+        let name = vsvc_get_pid_name(*pid);
+        let name = name.as_bytes();
+        let name_len = core::cmp::min(name.len(), 0xFF);
+
+        out.push(*pid as u8);
+        out.push(*svc_id);
+        out.push(name_len as u8);
+        out.extend_from_slice(&name[..name_len]);
+        out.extend_from_slice(&(stats.calls.min(u32::MAX as u64) as u32).to_le_bytes());
+        out.extend_from_slice(&((stats.get_total_ns() / 1000).min(u32::MAX as u64) as u32).to_le_bytes());
+        out.extend_from_slice(&(stats.get_avg_ns().min(u32::MAX as u64) as u32).to_le_bytes());
+        out.extend_from_slice(&(stats.get_max_ns().min(u32::MAX as u64) as u32).to_le_bytes());
+    }
+
+    log_cmd_chunked(LOG_CMD_SVCPROF, &out);
+}
@@ -0,0 +1,308 @@
+/*
+ * Copyright (c) 2015-2021, SALT.
+ * This file is part of HashtagBlessedII and is distributed under the 3-clause BSD license.
+ * See LICENSE.md for terms of use.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::{BTreeMap, BTreeSet};
+use crate::logger::*;
+use crate::util::peek8;
+use crate::arm::mmu::translate_el1_stage12;
+use crate::arm::ticks::get_ticks;
+use crate::hos::svcsig::{SvcArgKind, SvcRet, svcsig_lookup};
+use crate::hos::result::result_is_routine;
+use crate::vm::vsvc::{vsvc_get_curpid, vsvc_get_pid_name};
+
+pub const LOG_CMD_SVCTRACE: u8 = 0x14;
+
+const SVCTRACE_STR_MAX: usize = 64;
+
+static mut SVCTRACE_ENABLED: bool = false;
+static mut SVCTRACE_ERRORS_ONLY: bool = false;
+static mut SVCTRACE_PIDS: BTreeSet<u32> = BTreeSet::new();
+static mut SVCTRACE_NAMES: BTreeSet<String> = BTreeSet::new();
+static mut SVCTRACE_SVCS: BTreeSet<u8> = BTreeSet::new();
+
+// Calls waiting on their post-hook, by thread context
+static mut SVCTRACE_PENDING: BTreeMap<u64, SvcTraceRecord> = BTreeMap::new();
+
+pub struct SvcTraceRecord
+{
+    pub pid: u32,
+    pub svc_id: u8,
+    pub result: u32,
+    pub start_ticks: u64,
+    pub latency: u64,
+    pub ins: Vec<u64>,
+    pub outs: Vec<u64>,
+    pub text: Vec<u8>, // first string pointer arg, if any
+}
+
+impl SvcTraceRecord
+{
+    // Little endian, mirrored by debug_client's svc_trace.rs. Only the
+    // registers the signature uses are sent
+    pub fn serialize(&self) -> Vec<u8>
+    {
+        let text_len = core::cmp::min(self.text.len(), 0xFF);
+
+        let mut out: Vec<u8> = Vec::with_capacity(20 + (self.ins.len() + self.outs.len()) * 8 + text_len);
+        out.extend_from_slice(&self.pid.to_le_bytes());
+        out.push(self.svc_id);
+        out.push(self.ins.len() as u8);
+        out.push(self.outs.len() as u8);
+        out.push(text_len as u8);
+        out.extend_from_slice(&self.result.to_le_bytes());
+        out.extend_from_slice(&self.latency.to_le_bytes());
+        for val in self.ins.iter().chain(self.outs.iter())
+        {
+            out.extend_from_slice(&val.to_le_bytes());
+        }
+        out.extend_from_slice(&self.text[..text_len]);
+
+        return out;
+    }
+}
+
+pub fn svctrace_set_enabled(enabled: bool)
+{
+    unsafe
+    {
+        SVCTRACE_ENABLED = enabled;
+        SVCTRACE_PENDING.clear();
+    }
+}
+
+pub fn svctrace_set_errors_only(errors_only: bool)
+{
+    unsafe
+    {
+        SVCTRACE_ERRORS_ONLY = errors_only;
+    }
+}
+
+pub fn svctrace_filter_pid(pid: u32, add: bool)
+{
+    unsafe
+    {
+        if add {
+            SVCTRACE_PIDS.insert(pid);
+        }
+        else {
+            SVCTRACE_PIDS.remove(&pid);
+        }
+    }
+}
+
+// Unlike PIDs, names still match after the process restarts
+pub fn svctrace_filter_name(name: &String, add: bool)
+{
+    unsafe
+    {
+        if add {
+            SVCTRACE_NAMES.insert(name.clone());
+        }
+        else {
+            SVCTRACE_NAMES.remove(name);
+        }
+    }
+}
+
+pub fn svctrace_filter_svc(svc_id: u8, add: bool)
+{
+    unsafe
+    {
+        if add {
+            SVCTRACE_SVCS.insert(svc_id);
+        }
+        else {
+            SVCTRACE_SVCS.remove(&svc_id);
+        }
+    }
+}
+
+pub fn svctrace_filter_clear()
+{
+    unsafe
+    {
+        SVCTRACE_PIDS.clear();
+        SVCTRACE_NAMES.clear();
+        SVCTRACE_SVCS.clear();
+        SVCTRACE_ERRORS_ONLY = false;
+    }
+}
+
+// Empty filter lists match everything, PIDs and names are one list
+pub fn svctrace_should_trace(pid: u32, svc_id: u8) -> bool
+{
+    unsafe
+    {
+        if !SVCTRACE_ENABLED {
+            return false;
+        }
+
+        if !SVCTRACE_SVCS.is_empty() && !SVCTRACE_SVCS.contains(&svc_id) {
+            return false;
+        }
+
+        if SVCTRACE_PIDS.is_empty() && SVCTRACE_NAMES.is_empty() {
+            return true;
+        }
+
+        return SVCTRACE_PIDS.contains(&pid) || SVCTRACE_NAMES.contains(&vsvc_get_pid_name(pid));
+    }
+}
+
+fn svctrace_read_str(addr: u64, max: usize) -> Vec<u8>
+{
+    let mut out: Vec<u8> = Vec::new();
+    for i in 0..max
+    {
+        let addr_el2 = translate_el1_stage12(addr + i as u64);
+        if addr_el2 < 0x80000000 {
+            break;
+        }
+
+        let val = peek8(addr_el2);
+        if val == 0 {
+            break;
+        }
+        out.push(val);
+    }
+    return out;
+}
+
+fn svctrace_emit(record: &SvcTraceRecord)
+{
+    log_cmd_chunked(LOG_CMD_SVCTRACE, &record.serialize());
+}
+
+pub fn svctrace_pre(iss: u32, thread_ctx: u64, ctx: &[u64])
+{
+    let svc_id = (iss & 0xFF) as u8;
+    let pid = vsvc_get_curpid();
+    if !svctrace_should_trace(pid, svc_id) {
+        return;
+    }
+
+    let sig = match svcsig_lookup(svc_id) {
+        Some(sig) => sig,
+        None => return
+    };
+
+    let ins: Vec<u64> = sig.in_args().map(|arg| ctx[arg.reg as usize]).collect();
+
+    // Strings with a length have it as the next arg, otherwise they're
+    // NUL terminated
+    let mut text: Vec<u8> = Vec::new();
+    let mut in_args = sig.in_args().peekable();
+    while let Some(arg) = in_args.next()
+    {
+        if arg.kind != SvcArgKind::StrPtr {
+            continue;
+        }
+
+        let mut len = SVCTRACE_STR_MAX;
+        if let Some(next) = in_args.peek() {
+            if next.kind == SvcArgKind::Size {
+                len = core::cmp::min(ctx[next.reg as usize] as usize, SVCTRACE_STR_MAX);
+            }
+        }
+        text = svctrace_read_str(ctx[arg.reg as usize], len);
+        break;
+    }
+
+    let record = SvcTraceRecord
+    {
+        pid: pid,
+        svc_id: svc_id,
+        result: 0,
+        start_ticks: get_ticks(),
+        latency: 0,
+        ins: ins,
+        outs: Vec::new(),
+        text: text,
+    };
+
+    // Nothing comes back from these, so they go out right away
+    if sig.ret == SvcRet::NoReturn {
+        if unsafe { !SVCTRACE_ERRORS_ONLY } {
+            svctrace_emit(&record);
+        }
+        return;
+    }
+
+    unsafe
+    {
+        SVCTRACE_PENDING.insert(thread_ctx, record);
+    }
+}
+
+pub fn svctrace_post(iss: u32, thread_ctx: u64, ctx: &[u64])
+{
+    let svc_id = (iss & 0xFF) as u8;
+
+    let mut record = unsafe
+    {
+        // Injected SVCs get their own post-hook, leave the outer call pending
+        match SVCTRACE_PENDING.get(&thread_ctx) {
+            Some(pending) if pending.svc_id == svc_id => SVCTRACE_PENDING.remove(&thread_ctx).unwrap(),
+            _ => return
+        }
+    };
+
+    let sig = match svcsig_lookup(svc_id) {
+        Some(sig) => sig,
+        None => return
+    };
+
+    if sig.ret == SvcRet::Result {
+        record.result = (ctx[0] & 0xFFFFFFFF) as u32;
+    }
+
+    // Timeouts and closed sessions would drown out the failures worth seeing
+    if unsafe { SVCTRACE_ERRORS_ONLY } && (record.result == 0 || result_is_routine(record.result)) {
+        return;
+    }
+
+    record.latency = get_ticks() - record.start_ticks;
+    record.outs = sig.out_args().map(|arg| ctx[arg.reg as usize]).collect();
+    svctrace_emit(&record);
+}
+
+pub fn svctrace_print_status()
+{
+    unsafe
+    {
+        println!("SVC trace: {}{}", if SVCTRACE_ENABLED { "on" } else { "off" }, if SVCTRACE_ERRORS_ONLY { ", errors only" } else { "" });
+
+        print!("  Processes:");
+        if SVCTRACE_PIDS.is_empty() && SVCTRACE_NAMES.is_empty() {
+            print!(" (all)");
+        }
+        for pid in SVCTRACE_PIDS.iter()
+        {
+            print!(" {} ({})", pid, vsvc_get_pid_name(*pid));
+        }
+        for name in SVCTRACE_NAMES.iter()
+        {
+            print!(" `{}`", name);
+        }
+        println!("");
+
+        print!("  SVCs:");
+        if SVCTRACE_SVCS.is_empty() {
+            print!(" (all)");
+        }
+        for svc_id in SVCTRACE_SVCS.iter()
+        {
+            match svcsig_lookup(*svc_id) {
+                Some(sig) => print!(" {}", sig.name),
+                None => print!(" {:#x}", svc_id)
+            };
+        }
+        println!("");
+    }
+}
@@ -195,6 +195,7 @@ exception_print:
     stp	x21, x22, [sp, #0x100] // 32,33
     mrs	x22, esr_el2
     str x22, [sp, #0x110] // 34
+    str x29, [sp, #0x118] // guest x29, 35
 
     sub sp, sp, #0x10
     str lr, [sp]
@@ -234,6 +235,7 @@ exception_print:
     ldp x26, x27, [sp, #0xD0]
     ldr x28, [sp, #0xE0]
     ldr x30, [sp, #0xF0]
+    ldr x29, [sp, #0x118]
     add sp, sp, #0x120
     eret
 
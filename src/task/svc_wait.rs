@@ -13,6 +13,7 @@ use crate::arm::threading::*;
 
 static mut SVCWAIT_CTX: [[u64; 32]; 4] = [[0; 32]; 4];
 static mut SVCWAIT_CTX_SET: [bool; 4] = [false; 4];
+static mut SVCWAIT_COMPLETED: [u64; 4] = [0; 4];
 
 pub struct SvcWait 
 {
@@ -28,6 +29,7 @@ impl Future for SvcWait
         {
             unsafe
             {
+                SVCWAIT_COMPLETED[get_core() as usize] += 1;
                 Poll::Ready(SVCWAIT_CTX[get_core() as usize])
             }
         } 
@@ -56,6 +58,16 @@ impl SvcWait
         }
     }
     
+    // A task that never awaits runs start to finish on one core, so if the
+    // core and completion count match, no SvcWait happened in between
+    pub fn checkpoint() -> (u8, u64) {
+        unsafe { (get_core(), SVCWAIT_COMPLETED[get_core() as usize]) }
+    }
+    
+    pub fn waited_since(checkpoint: (u8, u64)) -> bool {
+        return SvcWait::checkpoint() != checkpoint;
+    }
+    
     pub fn is_waiting() -> bool {
         unsafe { SVCWAIT_CTX_SET[get_core() as usize] }
     }
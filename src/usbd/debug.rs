@@ -21,6 +21,24 @@ use alloc::collections::vec_deque::VecDeque;
 use crate::vm::vsvc::*;
 use crate::vm::vmmu::ipaddr_to_paddr;
 use crate::util::peek64;
+use crate::modules::ipctrace::*;
+use crate::modules::ipcfuzz::*;
+use crate::modules::ipccap::*;
+use crate::modules::ipcserver::ipcserver_set_logging;
+use crate::modules::ipcstat::*;
+use crate::modules::svctrace::*;
+use crate::modules::svcinject::*;
+use crate::modules::svcprof::*;
+use crate::modules::mempolicy::*;
+use crate::modules::gdbstub::{gdbstub_recv, LOG_CMD_GDB};
+use crate::hos::ipcdb::*;
+use crate::hos::result::result_format;
+use crate::hos::svcsig::{svcsig_lookup_name, SVC_SIGNATURES};
+use crate::hos::hipc::hipc_print_pid_handles;
+use crate::hos::hprocess::{hprocess_print_list, hprocess_print_history, hprocess_print_info};
+use crate::hos::hthread::{hthread_print_list, hthread_print_info};
+use crate::hos::hmemmap::hmemmap_print;
+use crate::hos::hrules::hrules_parse_u64;
 
 pub const TTB_ENTRY_ATTR_MASK: u64 = 0xFFF0000000000000;
 pub const TTB_ENTRY_ATTR_SHIFT: usize = (52);
@@ -232,19 +250,26 @@ pub fn debug_process_cmd()
             println!("");
             println!("Valid operations:");
             println!(" - list: Lists all processes");
+            println!(" - history: Lists recently exited processes");
+            println!(" - info <pid/name>: Show a process's creation parameters");
         }
         else
         {
             match args[0].as_str() {
                 "list" => {
-                    println!("Running Processes:");
-                    for pid in vsvc_get_pid_list()
-                    {
-                        if pid == 0xFF { continue; }
-
-                        println!("  {:3}: {}", pid, vsvc_get_pid_name(pid));
+                    hprocess_print_list();
+                },
+                "history" => {
+                    hprocess_print_history();
+                },
+                "info" if args.len() >= 2 => {
+                    let pid = match args[1].parse::<u32>() {
+                        Ok(pid) => pid,
+                        Err(_) => vsvc_get_process_pid(&args[1])
+                    };
+                    if !hprocess_print_info(pid) {
+                        println!("No running process `{}`", args[1]);
                     }
-                    println!("");
                 },
                 _ => {
                     println!("Unknown operation `{}`", args[0]);
@@ -279,12 +304,638 @@ pub fn debug_process_cmd()
             
         }
     }
+    else if (command == "vmmap")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: vmmap <pid/name>");
+        }
+        else
+        {
+            let pid = match args[0].parse::<u32>() {
+                Ok(pid) => pid,
+                Err(_) => vsvc_get_process_pid(&args[0])
+            };
+            hmemmap_print(pid);
+        }
+    }
+    else if (command == "handles")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: handles <pid/name>");
+        }
+        else
+        {
+            let pid = match args[0].parse::<u32>() {
+                Ok(pid) => pid,
+                Err(_) => vsvc_get_process_pid(&args[0])
+            };
+            println!("PID {} ({}) tracked handles:", pid, vsvc_get_pid_name(pid));
+            hipc_print_pid_handles(pid);
+        }
+    }
+    else if (command == "thread")
+    {
+        if (args.len() < 2)
+        {
+            println!("Usage: thread <operation>");
+            println!("");
+            println!("Valid operations:");
+            println!(" - list <pid/name>: Lists a process's threads");
+            println!(" - info <id>: Show a thread's last SVC and saved registers");
+        }
+        else
+        {
+            match args[0].as_str() {
+                "list" => {
+                    let pid = match args[1].parse::<u32>() {
+                        Ok(pid) => pid,
+                        Err(_) => vsvc_get_process_pid(&args[1])
+                    };
+                    println!("PID {} ({}) threads:", pid, vsvc_get_pid_name(pid));
+                    hthread_print_list(pid);
+                },
+                "info" => {
+                    match args[1].parse::<u32>() {
+                        Ok(id) => {
+                            if !hthread_print_info(id) {
+                                println!("No thread {}", id);
+                            }
+                        },
+                        Err(_) => println!("Invalid thread id `{}`", args[1])
+                    };
+                },
+                _ => {
+                    println!("Unknown operation `{}`", args[0]);
+                }
+            };
+        }
+    }
+    else if (command == "ipctrace")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: ipctrace <operation>");
+            println!("");
+            println!("Valid operations:");
+            println!(" - on/off: Enable or disable IPC tracing");
+            println!(" - status: Show tracing state and filters");
+            println!(" - service <name>: Only trace the given service (repeatable)");
+            println!(" - pid <pid/name>: Only trace the given process (repeatable)");
+            println!(" - unservice <name>, unpid <pid/name>: Remove a filter");
+            println!(" - clear: Remove all filters");
+        }
+        else
+        {
+            match args[0].as_str() {
+                "on" => {
+                    ipctrace_set_enabled(true);
+                    ipctrace_print_status();
+                },
+                "off" => {
+                    ipctrace_set_enabled(false);
+                    ipctrace_print_status();
+                },
+                "status" => {
+                    ipctrace_print_status();
+                },
+                "service" | "unservice" if args.len() >= 2 => {
+                    ipctrace_filter_service(&args[1], args[0] == "service");
+                    ipctrace_print_status();
+                },
+                "pid" | "unpid" if args.len() >= 2 => {
+                    let pid = match args[1].parse::<u32>() {
+                        Ok(pid) => pid,
+                        Err(_) => vsvc_get_process_pid(&args[1])
+                    };
+                    ipctrace_filter_pid(pid, args[0] == "pid");
+                    ipctrace_print_status();
+                },
+                "clear" => {
+                    ipctrace_filter_clear();
+                    ipctrace_print_status();
+                },
+                _ => {
+                    println!("Unknown operation `{}`", args[0]);
+                }
+            };
+        }
+    }
+    else if (command == "ipcfuzz")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: ipcfuzz <operation>");
+            println!("");
+            println!("Valid operations:");
+            println!(" - on/off: Enable or disable IPC fuzzing");
+            println!(" - status: Show fuzzing state and targets");
+            println!(" - history: Show the most recent fuzz cases");
+            println!(" - seed <n>: Reseed and restart the case sequence");
+            println!(" - mutations <n>: Maximum mutations per request");
+            println!(" - service <name> [cmd]: Fuzz a service, or one of its commands (repeatable)");
+            println!(" - pid <pid/name>: Fuzz all requests from a process (repeatable)");
+            println!(" - unservice <name> [cmd], unpid <pid/name>: Remove a target");
+            println!(" - clear: Remove all targets and history");
+        }
+        else
+        {
+            match args[0].as_str() {
+                "on" => {
+                    ipcfuzz_set_enabled(true);
+                    ipcfuzz_print_status();
+                },
+                "off" => {
+                    ipcfuzz_set_enabled(false);
+                    ipcfuzz_print_status();
+                },
+                "status" => {
+                    ipcfuzz_print_status();
+                },
+                "history" => {
+                    ipcfuzz_print_history();
+                },
+                "seed" if args.len() >= 2 => {
+                    match hrules_parse_u64(args[1]) {
+                        Ok(seed) => {
+                            ipcfuzz_set_seed(seed);
+                            ipcfuzz_print_status();
+                        },
+                        Err(_) => {
+                            println!("Invalid seed `{}`", args[1]);
+                        }
+                    };
+                },
+                "mutations" if args.len() >= 2 => {
+                    match args[1].parse::<u32>() {
+                        Ok(max) => {
+                            ipcfuzz_set_max_mutations(max);
+                            ipcfuzz_print_status();
+                        },
+                        Err(_) => {
+                            println!("Invalid mutation count `{}`", args[1]);
+                        }
+                    };
+                },
+                "service" | "unservice" if args.len() >= 2 => {
+                    let cmd_id = match args.get(2) {
+                        Some(cmd) => cmd.parse::<u32>().ok(),
+                        None => Some(IPCFUZZ_CMD_ANY)
+                    };
+                    match cmd_id {
+                        Some(cmd_id) => {
+                            ipcfuzz_target_service(&args[1], cmd_id, args[0] == "service");
+                            ipcfuzz_print_status();
+                        },
+                        None => {
+                            println!("Invalid command ID `{}`", args[2]);
+                        }
+                    };
+                },
+                "pid" | "unpid" if args.len() >= 2 => {
+                    let pid = match args[1].parse::<u32>() {
+                        Ok(pid) => pid,
+                        Err(_) => vsvc_get_process_pid(&args[1])
+                    };
+                    ipcfuzz_target_pid(pid, args[0] == "pid");
+                    ipcfuzz_print_status();
+                },
+                "clear" => {
+                    ipcfuzz_clear();
+                    ipcfuzz_print_status();
+                },
+                _ => {
+                    println!("Unknown operation `{}`", args[0]);
+                }
+            };
+        }
+    }
+    else if (command == "ipccap")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: ipccap <operation>");
+            println!("");
+            println!("Valid operations:");
+            println!(" - status: Show capture slots and pending replays");
+            println!(" - arm <service> [cmd]: Capture the next matching request");
+            println!(" - disarm: Cancel a pending capture");
+            println!(" - get <slot>: Send a captured request to the host");
+            println!(" - drop <slot>: Delete a capture slot");
+            println!(" - replay <slot> <pid/name> [handle] [tls]: Send a slot from the next SVC of a process/thread");
+            println!(" - begin <slot>, data <hex>, end: Upload a request from the host");
+        }
+        else
+        {
+            let slot = args.get(1).and_then(|arg| arg.parse::<u32>().ok());
+            match args[0].as_str() {
+                "status" => {
+                    ipccap_print_slots();
+                },
+                "arm" if args.len() >= 2 => {
+                    let cmd_id = match args.get(2) {
+                        Some(cmd) => cmd.parse::<u32>().ok(),
+                        None => Some(IPCCAP_CMD_ANY)
+                    };
+                    match cmd_id {
+                        Some(cmd_id) => {
+                            ipccap_arm(&args[1], cmd_id);
+                            ipccap_print_slots();
+                        },
+                        None => {
+                            println!("Invalid command ID `{}`", args[2]);
+                        }
+                    };
+                },
+                "disarm" => {
+                    ipccap_disarm();
+                    ipccap_print_slots();
+                },
+                "get" if slot.is_some() => {
+                    if !ipccap_download(slot.unwrap()) {
+                        println!("No capture in slot {}", args[1]);
+                    }
+                },
+                "drop" if slot.is_some() => {
+                    if !ipccap_drop(slot.unwrap()) {
+                        println!("No capture in slot {}", args[1]);
+                    }
+                },
+                "replay" if slot.is_some() && args.len() >= 3 => {
+                    let pid = match args[2].parse::<u32>() {
+                        Ok(pid) => pid,
+                        Err(_) => vsvc_get_process_pid(&args[2])
+                    };
+                    let handle = args.get(3).and_then(|arg| u32::from_str_radix(arg.trim_start_matches("0x"), 16).ok());
+                    let tls = args.get(4).and_then(|arg| u64::from_str_radix(arg.trim_start_matches("0x"), 16).ok());
+                    if ipccap_replay(slot.unwrap(), pid, tls, handle) {
+                        println!("Replaying slot {} on the next SVC from `{}`", args[1], vsvc_get_pid_name(pid));
+                    }
+                    else {
+                        println!("No capture in slot {}", args[1]);
+                    }
+                },
+                "begin" if slot.is_some() => {
+                    ipccap_upload_begin(slot.unwrap());
+                },
+                "data" if args.len() >= 2 => {
+                    if !ipccap_upload_data(&args[1]) {
+                        println!("Bad upload data");
+                    }
+                },
+                "end" => {
+                    match ipccap_upload_end() {
+                        Some(slot) => println!("Uploaded request into slot {}", slot),
+                        None => println!("Upload failed")
+                    };
+                },
+                _ => {
+                    println!("Unknown operation `{}`", args[0]);
+                }
+            };
+        }
+    }
+    else if (command == "ipcserver")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: ipcserver <on/off>");
+            println!("");
+            println!("Logs requests as servers receive and reply to them");
+        }
+        else
+        {
+            match args[0].as_str() {
+                "on" => ipcserver_set_logging(true),
+                "off" => ipcserver_set_logging(false),
+                _ => println!("Unknown operation `{}`", args[0])
+            };
+        }
+    }
+    else if (command == "ipcstat")
+    {
+        if (args.len() < 1)
+        {
+            ipcstat_print_summary();
+        }
+        else
+        {
+            match args[0].as_str() {
+                "on" => ipcstat_set_enabled(true),
+                "off" => ipcstat_set_enabled(false),
+                "clear" => ipcstat_clear(),
+                "help" => {
+                    println!("Usage: ipcstat [operation/service/pid]");
+                    println!("");
+                    println!("Valid operations:");
+                    println!(" - on/off: Enable or disable IPC statistics");
+                    println!(" - clear: Reset all counters");
+                    println!(" - <service>: Per-command stats for a service");
+                    println!(" - <pid/name>: Per-session stats for a process");
+                },
+                _ => {
+                    if !ipcstat_print_service(&args[0])
+                    {
+                        let pid = match args[0].parse::<u32>() {
+                            Ok(pid) => pid,
+                            Err(_) => vsvc_get_process_pid(&args[0])
+                        };
+                        ipcstat_print_pid(pid);
+                    }
+                }
+            };
+        }
+    }
+    else if (command == "svctrace")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: svctrace <operation>");
+            println!("");
+            println!("Valid operations:");
+            println!(" - on/off: Enable or disable SVC tracing");
+            println!(" - status: Show tracing state and filters");
+            println!(" - pid <pid/name>: Only trace the given process (repeatable)");
+            println!(" - name <name>: Only trace processes with this name, across restarts");
+            println!(" - svc <name/id>: Only trace the given SVC (repeatable)");
+            println!(" - unpid, unname, unsvc: Remove a filter");
+            println!(" - errors <on/off>: Only trace calls that failed, other than timeouts/cancels/closed sessions");
+            println!(" - list: List known SVCs");
+            println!(" - clear: Remove all filters");
+        }
+        else
+        {
+            match args[0].as_str() {
+                "on" | "off" => {
+                    svctrace_set_enabled(args[0] == "on");
+                    svctrace_print_status();
+                },
+                "status" => {
+                    svctrace_print_status();
+                },
+                "pid" | "unpid" if args.len() >= 2 => {
+                    let pid = match args[1].parse::<u32>() {
+                        Ok(pid) => pid,
+                        Err(_) => vsvc_get_process_pid(&args[1])
+                    };
+                    svctrace_filter_pid(pid, args[0] == "pid");
+                    svctrace_print_status();
+                },
+                "name" | "unname" if args.len() >= 2 => {
+                    svctrace_filter_name(&args[1], args[0] == "name");
+                    svctrace_print_status();
+                },
+                "svc" | "unsvc" if args.len() >= 2 => {
+                    let svc_id = match svcsig_lookup_name(&args[1]) {
+                        Some(sig) => Some(sig.id),
+                        None => u8::from_str_radix(args[1].trim_start_matches("0x"), 16).ok()
+                    };
+                    match svc_id {
+                        Some(svc_id) => {
+                            svctrace_filter_svc(svc_id, args[0] == "svc");
+                            svctrace_print_status();
+                        },
+                        None => println!("Unknown SVC `{}`", args[1])
+                    };
+                },
+                "errors" if args.len() >= 2 => {
+                    svctrace_set_errors_only(args[1] == "on");
+                    svctrace_print_status();
+                },
+                "list" => {
+                    for sig in SVC_SIGNATURES
+                    {
+                        println!("  {:02x} {}", sig.id, sig.name);
+                    }
+                },
+                "clear" => {
+                    svctrace_filter_clear();
+                    svctrace_print_status();
+                },
+                _ => {
+                    println!("Unknown operation `{}`", args[0]);
+                }
+            };
+        }
+    }
+    else if (command == "svcinject")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: svcinject <operation>");
+            println!("");
+            println!("Valid operations:");
+            println!(" - add <svc> <result> [options]: Fail matching SVCs with `result`");
+            println!("     pid <pid/name>: Only calls from this process");
+            println!("     target <name>: Only calls to this port/service (ConnectToNamedPort, SendSyncRequest)");
+            println!("     every <n>: Only every nth matching call");
+            println!("     prob <percent>: Only with this probability");
+            println!("     limit <n>: Stop after n injections");
+            println!(" - list: Show rules and how often they fired");
+            println!(" - del <id>: Remove a rule");
+            println!(" - clear: Remove all rules");
+            println!("");
+            println!("e.g. `svcinject add MapMemory 0xd401 pid 5 every 3`");
+            println!("     `svcinject add ConnectToNamedPort 0xf201 target bsd:u prob 10`");
+        }
+        else
+        {
+            match args[0].as_str() {
+                "add" => {
+                    match svcinject_add(&args[1..]) {
+                        Ok(id) => {
+                            println!("Added rule {}", id);
+                            svcinject_print_rules();
+                        },
+                        Err(err) => println!("svcinject: {}", err)
+                    };
+                },
+                "list" => {
+                    svcinject_print_rules();
+                },
+                "del" if args.len() >= 2 => {
+                    match args[1].parse::<u32>() {
+                        Ok(id) if svcinject_remove(id) => println!("Removed rule {}", id),
+                        _ => println!("No rule `{}`", args[1])
+                    };
+                },
+                "clear" => {
+                    svcinject_clear();
+                    println!("Cleared all rules");
+                },
+                _ => {
+                    println!("Unknown operation `{}`", args[0]);
+                }
+            };
+        }
+    }
+    else if (command == "svcprof")
+    {
+        if (args.len() < 1)
+        {
+            svcprof_print(None);
+        }
+        else if (args[0] == "reset")
+        {
+            svcprof_reset(None);
+            println!("Reset all SVC profiles");
+        }
+        else if (args[0] == "help")
+        {
+            println!("Usage: svcprof [pid/name] [reset]");
+            println!("");
+            println!(" - no args: Per-process totals and the busiest SVCs");
+            println!(" - <pid/name>: Per-SVC stats for a process");
+            println!(" - reset: Reset everything, or just the given process");
+        }
+        else
+        {
+            let pid = match args[0].parse::<u32>() {
+                Ok(pid) => pid,
+                Err(_) => vsvc_get_process_pid(&args[0])
+            };
+
+            if (args.len() >= 2 && args[1] == "reset")
+            {
+                svcprof_reset(Some(pid));
+                println!("Reset SVC profile for pid {} ({})", pid, vsvc_get_pid_name(pid));
+            }
+            else
+            {
+                svcprof_print(Some(pid));
+            }
+        }
+    }
+    else if (command == "mempolicy")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: mempolicy <operation>");
+            println!("");
+            println!("Valid operations:");
+            println!(" - add <name/program id/*> <kind> <set/add/sub/cap> <value> [options]: Rewrite memory sizes");
+            println!("     kind limit: PhysicalMemory in svcSetResourceLimitLimitValue, matched against the caller (usually pm)");
+            println!("     kind totalmem: Total memory sizes from svcGetInfo and svcGetSystemInfo");
+            println!("     kind heap: Requested size in svcSetHeapSize");
+            println!("     above <value>: Only values at least this big");
+            println!("     pool <n>: Only this svcGetSystemInfo pool (totalmem)");
+            println!(" - list: Show rules and how often they rewrote something");
+            println!(" - del <id>: Remove a rule");
+            println!(" - clear: Remove all rules");
+            println!("");
+            println!("e.g. `mempolicy add pm limit sub 0x4000000 above 0xcd500000`");
+            println!("     `mempolicy add * totalmem sub 0x4000000 pool 0`");
+        }
+        else
+        {
+            match args[0].as_str() {
+                "add" => {
+                    match mempolicy_add(&args[1..]) {
+                        Ok(id) => {
+                            println!("Added rule {}", id);
+                            mempolicy_print_rules();
+                        },
+                        Err(err) => println!("mempolicy: {}", err)
+                    };
+                },
+                "list" => {
+                    mempolicy_print_rules();
+                },
+                "del" if args.len() >= 2 => {
+                    match args[1].parse::<u32>() {
+                        Ok(id) if mempolicy_remove(id) => println!("Removed rule {}", id),
+                        _ => println!("No rule `{}`", args[1])
+                    };
+                },
+                "clear" => {
+                    mempolicy_clear();
+                    println!("Cleared all rules");
+                },
+                _ => {
+                    println!("Unknown operation `{}`", args[0]);
+                }
+            };
+        }
+    }
+    else if (command == "ipcdb")
+    {
+        if (args.len() < 1)
+        {
+            println!("Known interfaces:");
+            for iface in IPCDB_INTERFACES
+            {
+                print!("  {:16} {}", iface.short_name, iface.name);
+                for service in iface.services
+                {
+                    print!(" `{}`", service);
+                }
+                println!("");
+            }
+            println!("");
+        }
+        else
+        {
+            match ipcdb_lookup(&args[0], &args[0]) {
+                Some(iface) => {
+                    println!("{} ({}):", iface.name, iface.short_name);
+                    for cmd in iface.cmds
+                    {
+                        print!("  {:4}: {}(", cmd.id, cmd.name);
+                        for (i, arg) in cmd.args.iter().enumerate()
+                        {
+                            let dir = if arg.dir == IpcArgDir::In { "in" } else { "out" };
+                            print!("{}{} {}", if i != 0 { ", " } else { "" }, dir, arg.name);
+                        }
+                        println!(")");
+                    }
+                    println!("");
+                },
+                None => {
+                    println!("Unknown interface or service `{}`", args[0]);
+                }
+            };
+        }
+    }
+    else if (command == "result")
+    {
+        if (args.len() < 1)
+        {
+            println!("Usage: result <code>");
+        }
+        else
+        {
+            let code = args[0].trim_start_matches("0x");
+            match u32::from_str_radix(code, 16) {
+                Ok(rc) => {
+                    println!("{}", result_format(rc));
+                },
+                Err(_) => {
+                    println!("Invalid result code `{}`", args[0]);
+                }
+            };
+        }
+    }
     else if command == "help" || command == "?"
     {
         println!("Available Commands:");
         println!(" rcm - Reset to RCM mode");
         println!(" proc - Process commands");
+        println!(" thread - Thread commands");
+        println!(" vmmap - Show a process's tracked memory map");
         println!(" ttbr - Translation table register print");
+        println!(" handles - List tracked IPC handles of a process");
+        println!(" ipctrace - IPC request tracing");
+        println!(" ipcfuzz - IPC request fuzzing");
+        println!(" ipccap - Capture and replay IPC requests");
+        println!(" ipcserver - Server-side IPC request logging");
+        println!(" ipcstat - IPC request counts and latencies");
+        println!(" svctrace - SVC call tracing");
+        println!(" svcinject - Force SVCs to fail");
+        println!(" svcprof - Per-process SVC counts and latencies");
+        println!(" mempolicy - Rewrite memory limits and sizes per title");
+        println!(" ipcdb - IPC interface/command names");
+        println!(" result - Decode a result code");
         println!(" help, ? - Display help");
         println!("")
     }
@@ -304,19 +955,24 @@ pub fn debug_dispatch_bincmd()
     let debug = get_debug();    
     if (!debug.isactive) { return; }
 
-    let mut lock = debug.bincmd_buf.lock();
-    let mut bincmd_buf = lock.as_mut().unwrap();
-    let bincmd_size = bincmd_buf.len();
+    // Take the whole command so the next one starts from scratch
+    let bincmd: Vec<u8> = {
+        let mut lock = debug.bincmd_buf.lock();
+        let bincmd_buf = lock.as_mut().unwrap();
+        bincmd_buf.drain(..).collect()
+    };
+    let bincmd_size = bincmd.len();
     
     if bincmd_size <= 0 {
         return;
     }
     
-    let bincmd_cmd = bincmd_buf[0];
+    let bincmd_cmd = bincmd[0];
     match bincmd_cmd {
         0 => {},
         1 => {
         }
+        LOG_CMD_GDB => gdbstub_recv(&bincmd[1..]),
         _ => {
             println_core!("debug: Received unknown debug cmd {:x}, pkt len {:x}", bincmd_cmd, bincmd_size);
         }
@@ -477,16 +1133,21 @@ pub fn debug_if0_recvcomplete(usbd: &mut UsbDevice, epNum: u8)
         }
     }
     
-    // Parse binary command
+    // Parse binary command. The header comes in its own packet, except for
+    // gdb which sends [1, len, LOG_CMD_GDB, data...] in one
+    let mut pkt_start = 0;
     if pkt_data.read() == 1 && len >= 2 {
         let bincmd_len = pkt_data.offset(1).read();
         debug.bincmd_toread = bincmd_len;
-        return;
+        if len < 3 || pkt_data.offset(2).read() != LOG_CMD_GDB {
+            return;
+        }
+        pkt_start = 2;
     }
     
     // Convert the strings or whatever
     let mut is_escape = false;
-    for i in 0..(len as usize)
+    for i in pkt_start..(len as usize)
     {
         let val = pkt_data.offset(i as isize).read();
         
@@ -9,6 +9,7 @@ use crate::arm::threading::*;
 use crate::arm::mmu::translate_el1_stage12;
 use crate::vm::funcs::*;
 use crate::hos::svc::*;
+use crate::hos::result::*;
 use alloc::collections::btree_map::BTreeMap;
 use alloc::string::String;
 use crate::util::*;
@@ -21,36 +22,38 @@ use alloc::vec::Vec;
 use core::{future::Future, pin::Pin};
 use crate::hos::{hipc::*, hport::HPort, hhandle::HHandle, hclientsession::HClientSession, hclientsession::HClientSessionHandler};
 use spin::mutex::Mutex;
-use crate::modules::ipc::{ipc_handle_syncrequest, ipc_hook_namedport};
-use crate::hos::hsvc::hsvc_sleep_thread;
+use crate::modules::ipc::{ipc_handle_syncrequest, ipc_hook_namedport, ipc_is_emulated_handle};
+use crate::modules::ipcfuzz::{ipcfuzz_notify_crash, IPCFUZZ_REASON_BREAK};
+use crate::modules::ipccap::{ipccap_take_replay, ipccap_replay_task};
+use crate::modules::ipcserver::{ipcserver_handle_replyandreceive, ipcserver_accept_session, ipcserver_create_session};
+use crate::modules::svctrace::{svctrace_pre, svctrace_post};
+use crate::modules::svcinject::{svcinject_pre, svcinject_task};
+use crate::modules::mempolicy::{mempolicy_has, mempolicy_rewrite};
+use crate::modules::gdbstub::{gdbstub_svc_park, gdbstub_park_task, gdbstub_process_exited};
+use crate::hos::hmempolicy::MemPolicyKind;
+use crate::hos::hsvc::{hsvc_sleep_thread, hsvc_return_early};
+use crate::hos::firmware::{firmware_get_thread_ctx, firmware_get_svc_frame};
+use crate::hos::hprocess::*;
+use crate::hos::hthread::*;
+use crate::hos::hmemmap::*;
 use crate::io::smmu::smmu_active;
 
 use alloc::boxed::Box;
 use async_trait::async_trait;
 
-static mut LAST_CREATED: [Option<String>; 8] = [None, None, None, None, None, None, None, None];
-static mut RUNNING_PROCESS_NAME: BTreeMap<u32, String> = BTreeMap::new();
-static mut PROCESS_NAME_PID: BTreeMap<String, u32> = BTreeMap::new();
 static mut VSVC_QLAUNCH_STARTED: bool = false;
 static mut VSVC_TTBRS: BTreeMap<u32, u64> = BTreeMap::new();
-static mut VSVC_PROC_HANDLES: BTreeMap<u32, String> = BTreeMap::new();
 static mut VSVC_SVC_ADDR: [u64; 128] = [0; 128];
 
+// Where each 32-bit thread's current SVC was hooked, and whether a handler
+// sent it off into a 64-bit SVC body, by thread context
+static mut VSVC_ENTRY_PC_32: BTreeMap<u64, (u64, bool)> = BTreeMap::new();
+
 include!(concat!(env!("OUT_DIR"), "/vsvc_gen.rs"));
 
-// Key differences for SVC32 vs SVC64:
-// SleepThread, GetThreadCoreMask, SetThreadCoreMask, WaitSynchronization,
-// WaitProcessWideKeyAtomic, GetSystemTick, GetProcessId, GetThreadId, GetInfo, GetDebugFutureThreadInfo,
-// GetResourceLimitLimitValue, GetResourceLimitCurrentValue, WaitForAddress, SignalToAddress,
-// GetResourceLimitPeakValue, ReplyAndReceive, ReplyAndReceiveWithUserBuffer, ControlCodeMemory,
-// ReadWriteRegister, QueryIoMapping, CreateDeviceAddressSpace, MapDeviceAddressSpaceByForce,
-// MapDeviceAddressSpaceAligned, MapDeviceAddressSpace, UnmapDeviceAddressSpace,
-// InvalidateProcessDataCache, StoreProcessDataCache, FlushProcessDataCache,
-// DebugActiveProcess, ContinueDebugEvent, GetProcessList, GetThreadList, 
-// GetDebugThreadContext, SetDebugThreadContext, SetHardwareBreakPoint,
-// GetDebugThreadParam, CreatePort, SetProcessMemoryPermission, MapProcessMemory,
-// UnmapProcessMemory, QueryProcessMemory, MapProcessCodeMemory, UnmapProcessCodeMemory
-// StartProcess, GetProcessInfo, SetResourceLimitLimitValue, CallSecureMonitor
+// Handlers are picked up by build.rs and have to be named Svc<Name> after an
+// SVC in hos/svcdb.txt. They always get SVC64 register layouts, 32-bit
+// processes' SVCs are converted using the SVC32 layouts from svcdb.txt.
 
 #[async_trait]
 impl SvcHandler for SvcInvalid
@@ -102,29 +105,9 @@ pub fn vsvc_is_qlaunch_started() -> bool
     unsafe { return VSVC_QLAUNCH_STARTED; }
 }
 
-pub fn vsvc_init()
+pub fn vsvc_init(kern_data: &[u8])
 {
-    // 8.0.1, TODO parse this from KIPs?
-    unsafe
-    {
-        RUNNING_PROCESS_NAME.insert(1, String::from("FS"));
-        RUNNING_PROCESS_NAME.insert(2, String::from("Loader"));
-        RUNNING_PROCESS_NAME.insert(3, String::from("NCM"));
-        RUNNING_PROCESS_NAME.insert(4, String::from("ProcessMana"));
-        RUNNING_PROCESS_NAME.insert(5, String::from("sm"));
-        RUNNING_PROCESS_NAME.insert(6, String::from("spl"));
-        RUNNING_PROCESS_NAME.insert(7, String::from("boot"));
-        RUNNING_PROCESS_NAME.insert(0xFF, String::from("idle core"));
-        
-        PROCESS_NAME_PID.insert(String::from("FS"), 1);
-        PROCESS_NAME_PID.insert(String::from("Loader"), 2);
-        PROCESS_NAME_PID.insert(String::from("NCM"), 3);
-        PROCESS_NAME_PID.insert(String::from("ProcessMana"), 4);
-        PROCESS_NAME_PID.insert(String::from("sm"), 5);
-        PROCESS_NAME_PID.insert(String::from("spl"), 6);
-        PROCESS_NAME_PID.insert(String::from("boot"), 7);
-        PROCESS_NAME_PID.insert(String::from("kernel"), 0xFF);
-    }
+    hprocess_init(kern_data);
 }
 
 pub fn vsvc_get_svc_addr(idx: usize) -> u64
@@ -144,33 +127,33 @@ pub fn vsvc_get_curpid() -> u32
     }
 }
 
-pub fn vsvc_get_pid_list() -> Vec<u32>
+// TLS of the calling thread, 32-bit processes only get to use the low half
+pub fn vsvc_get_tls() -> u64
 {
-    unsafe
-    {
-        return RUNNING_PROCESS_NAME.keys().cloned().collect();
+    if hprocess_is_64bit(vsvc_get_curpid()) {
+        return get_tls_el0();
     }
+    return get_tls_el0() & 0xFFFFFFFF;
+}
+
+pub fn vsvc_get_pid_list() -> Vec<u32>
+{
+    return hprocess_get_pids();
 }
 
 pub fn vsvc_get_pid_name(pid: u32) -> String
 {
-    unsafe
-    {
-        match RUNNING_PROCESS_NAME.get(&pid) {
-           Some(name) => name.clone(),
-           None => format!("unknown pid {}", pid)
-        }
+    match hprocess_get_name(pid) {
+       Some(name) => name,
+       None => format!("unknown pid {}", pid)
     }
 }
 
 pub fn vsvc_get_process_pid(name: &String) -> u32
 {
-    unsafe
-    {
-        match PROCESS_NAME_PID.get(name) {
-           Some(pid) => *pid,
-           None => 0
-        }
+    match hprocess_find_name(name) {
+       Some(pid) => pid,
+       None => 0
     }
 }
 
@@ -183,9 +166,11 @@ pub fn vsvc_get_curpid_name() -> String
 pub fn vsvc_pre_handle(iss: u32, ctx: &mut [u64]) -> u64
 {
     //let svc = HorizonSvc::from_iss(iss);
-    let thread_ctx = peek64(translate_el1_stage12(ctx[18]));
+    let thread_ctx = firmware_get_thread_ctx(ctx);
     
-    //println_core!("SVC #{} {:x} {:x} from PID {} ({})", iss & 0xFF, peek64(translate_el1_stage12(ctx[18])), peek64(translate_el1_stage12(ctx[18]+8)), vsvc_get_curpid(), vsvc_get_curpid_name());
+    hprocess_on_svc(vsvc_get_curpid());
+    hthread_on_svc(vsvc_get_curpid(), thread_ctx, firmware_get_svc_frame(), vsvc_get_tls(), (iss & 0xFF) as u8, ctx);
+    svctrace_pre(iss, thread_ctx, ctx);
     
     unsafe
     {
@@ -194,7 +179,16 @@ pub fn vsvc_pre_handle(iss: u32, ctx: &mut [u64]) -> u64
     
     let mut pre_ctx: [u64; 32] = Default::default();
     pre_ctx.copy_from_slice(&ctx[..32]);
-    if _svc_gen_pre(iss, thread_ctx, pre_ctx) {
+    if let Some(tid) = gdbstub_svc_park(thread_ctx) {
+        task_run_svc(thread_ctx, gdbstub_park_task(pre_ctx, tid));
+    }
+    else if let Some(replay) = ipccap_take_replay(vsvc_get_curpid(), vsvc_get_tls()) {
+        task_run_svc(thread_ctx, ipccap_replay_task(pre_ctx, vsvc_get_curpid(), replay));
+    }
+    else if let Some(result) = svcinject_pre(iss, ctx) {
+        task_run_svc(thread_ctx, svcinject_task(pre_ctx, result));
+    }
+    else if _svc_gen_pre(iss, thread_ctx, pre_ctx) {
         return ctx[31];
     }
     
@@ -223,11 +217,13 @@ pub fn vsvc_pre_handle(iss: u32, ctx: &mut [u64]) -> u64
 
 pub fn vsvc_post_handle(iss: u32, ctx: &mut [u64]) -> u64
 {
-    let thread_ctx = peek64(translate_el1_stage12(ctx[18]));
+    let thread_ctx = firmware_get_thread_ctx(ctx);
+    
+    svctrace_post(iss, thread_ctx, ctx);
     
     let errcode = ctx[0] & 0xFFFFFFFF;
-    if (errcode != 0 && errcode != 0xea01 && errcode != 0xec01 && errcode != 0xf601 && (iss & 0xFF) != 0x7F && (iss & 0xFF) != 0x7) {
-        //println!("(core {}) SVC return 0x{:02x} -> {:08x}, pid {:02x} ({})", get_core(), iss & 0xFF, errcode, vsvc_get_curpid(), vsvc_get_curpid_name());
+    if (errcode != 0 && !result_is_routine(errcode as u32) && (iss & 0xFF) != 0x7F && (iss & 0xFF) != 0x7) {
+        //println!("(core {}) SVC return 0x{:02x} -> {}, pid {:02x} ({})", get_core(), iss & 0xFF, result_format(errcode as u32), vsvc_get_curpid(), vsvc_get_curpid_name());
     }
     
     let mut post_ctx: [u64; 32] = Default::default();
@@ -261,70 +257,55 @@ pub fn vsvc_post_handle(iss: u32, ctx: &mut [u64]) -> u64
     }
 }
 
+// Handlers only ever see SVC64 register layouts. Whatever they hand back
+// either re-enters the SVC at its hook (args) or leaves it (results), unless
+// it was pointed at some other SVC's 64-bit body, which is left alone.
+fn vsvc_ctx_to_32(svc_id: u8, thread_ctx: u64, entry_pc: u64, ret_ctx: &mut [u64; 32], done: bool)
+{
+    let redirected = ret_ctx[31] != entry_pc && !done;
+    if ret_ctx[31] == entry_pc {
+        svc_args_to_a32(svc_id, ret_ctx);
+    }
+    else if done {
+        svc_out_to_a32(svc_id, ret_ctx);
+    }
+    
+    unsafe
+    {
+        VSVC_ENTRY_PC_32.insert(thread_ctx, (entry_pc, redirected));
+    }
+}
+
 pub fn vsvc_pre_handle_32(iss: u32, ctx: &mut [u64]) -> u64
 {
-    let thread_ctx = peek64(translate_el1_stage12(ctx[18]));
+    let thread_ctx = firmware_get_thread_ctx(ctx);
+    let svc_id = (iss & 0xFF) as u8;
+    let entry_pc = ctx[31];
     
-    match HorizonSvc::from_iss(iss)
-    {
-        HorizonSvc::SleepThread(_) => { return ctx[31]; },
-        HorizonSvc::GetThreadCoreMask(_) => { return ctx[31]; },
-        HorizonSvc::SetThreadCoreMask(_) => { return ctx[31]; },
-        HorizonSvc::WaitSynchronization(_) => { return ctx[31]; },
-        HorizonSvc::WaitProcessWideKeyAtomic(_) => { return ctx[31]; },
-        HorizonSvc::GetSystemTick(_) => { return ctx[31]; },
-        HorizonSvc::GetProcessId(_) => { return ctx[31]; },
-        HorizonSvc::GetThreadId(_) => { return ctx[31]; },
-        HorizonSvc::GetInfo(_) => { return ctx[31]; },
-        HorizonSvc::GetDebugFutureThreadInfo(_) => { return ctx[31]; },
-        HorizonSvc::GetResourceLimitLimitValue(_) => { return ctx[31]; },
-        HorizonSvc::GetResourceLimitCurrentValue(_) => { return ctx[31]; },
-        HorizonSvc::WaitForAddress(_) => { return ctx[31]; },
-        HorizonSvc::SignalToAddress(_) => { return ctx[31]; },
-        HorizonSvc::GetResourceLimitPeakValue(_) => { return ctx[31]; },
-        HorizonSvc::ReplyAndReceive(_) => { return ctx[31]; },
-        HorizonSvc::ReplyAndReceiveWithUserBuffer(_) => { return ctx[31]; },
-        HorizonSvc::ControlCodeMemory(_) => { return ctx[31]; },
-        HorizonSvc::ReadWriteRegister(_) => { return ctx[31]; },
-        HorizonSvc::QueryIoMapping(_) => { return ctx[31]; },
-        HorizonSvc::CreateDeviceAddressSpace(_) => { return ctx[31]; },
-        HorizonSvc::MapDeviceAddressSpaceByForce(_) => { return ctx[31]; },
-        HorizonSvc::MapDeviceAddressSpaceAligned(_) => { return ctx[31]; },
-        HorizonSvc::MapDeviceAddressSpace(_) => { return ctx[31]; },
-        HorizonSvc::UnmapDeviceAddressSpace(_) => { return ctx[31]; },
-        HorizonSvc::InvalidateProcessDataCache(_) => { return ctx[31]; },
-        HorizonSvc::StoreProcessDataCache(_) => { return ctx[31]; },
-        HorizonSvc::FlushProcessDataCache(_) => { return ctx[31]; },
-        HorizonSvc::DebugActiveProcess(_) => { return ctx[31]; },
-        HorizonSvc::ContinueDebugEvent(_) => { return ctx[31]; },
-        HorizonSvc::GetProcessList(_) => { return ctx[31]; },
-        HorizonSvc::GetThreadList(_) => { return ctx[31]; },
-        HorizonSvc::GetDebugThreadContext(_) => { return ctx[31]; },
-        HorizonSvc::SetDebugThreadContext(_) => { return ctx[31]; },
-        HorizonSvc::SetHardwareBreakPoint(_) => { return ctx[31]; },
-        HorizonSvc::GetDebugThreadParam(_) => { return ctx[31]; },
-        HorizonSvc::CreatePort(_) => { return ctx[31]; },
-        HorizonSvc::SetProcessMemoryPermission(_) => { return ctx[31]; },
-        HorizonSvc::MapProcessMemory(_) => { return ctx[31]; },
-        HorizonSvc::UnmapProcessMemory(_) => { return ctx[31]; },
-        HorizonSvc::QueryProcessMemory(_) => { return ctx[31]; },
-        HorizonSvc::MapProcessCodeMemory(_) => { return ctx[31]; },
-        HorizonSvc::UnmapProcessCodeMemory(_) => { return ctx[31]; },
-        HorizonSvc::StartProcess(_) => { return ctx[31]; },
-        HorizonSvc::GetProcessInfo(_) => { return ctx[31]; },
-        HorizonSvc::SetResourceLimitLimitValue(_) => { return ctx[31]; },
-        HorizonSvc::CallSecureMonitor(_) => { return ctx[31]; },
-        _ => {},
-    }
-       
     let mut pre_ctx: [u64; 32] = Default::default();
     pre_ctx.copy_from_slice(&ctx[..32]);
-    if _svc_gen_pre(iss, thread_ctx, pre_ctx) {
+    svc_args_from_a32(svc_id, &mut pre_ctx);
+    
+    hprocess_on_svc(vsvc_get_curpid());
+    hthread_on_svc(vsvc_get_curpid(), thread_ctx, firmware_get_svc_frame(), vsvc_get_tls(), svc_id, &pre_ctx);
+    svctrace_pre(iss, thread_ctx, &pre_ctx);
+    
+    if let Some(tid) = gdbstub_svc_park(thread_ctx) {
+        task_run_svc(thread_ctx, gdbstub_park_task(pre_ctx, tid));
+    }
+    else if let Some(replay) = ipccap_take_replay(vsvc_get_curpid(), vsvc_get_tls()) {
+        task_run_svc(thread_ctx, ipccap_replay_task(pre_ctx, vsvc_get_curpid(), replay));
+    }
+    else if let Some(result) = svcinject_pre(iss, &pre_ctx) {
+        task_run_svc(thread_ctx, svcinject_task(pre_ctx, result));
+    }
+    else if _svc_gen_pre(iss, thread_ctx, pre_ctx) {
         return ctx[31];
     }
     
     // SVC handler returned early
-    if let Some(ret_ctx) = task_advance_svc_ctx(thread_ctx) {
+    if let Some(mut ret_ctx) = task_advance_svc_ctx(thread_ctx) {
+        vsvc_ctx_to_32(svc_id, thread_ctx, entry_pc, &mut ret_ctx, true);
         for i in 0..32 {
             ctx[i] = ret_ctx[i];
         }
@@ -335,32 +316,36 @@ pub fn vsvc_pre_handle_32(iss: u32, ctx: &mut [u64]) -> u64
     {
         // Otherwise, SVC handler is blocking for Future output
         
-        let ret_ctx = SvcWait::get_ctx();
+        let mut ret_ctx = SvcWait::get_ctx();
+        vsvc_ctx_to_32(svc_id, thread_ctx, entry_pc, &mut ret_ctx, false);
         for i in 0..32 {
             ctx[i] = ret_ctx[i];
         }
         
         return ctx[31];
     }
-
-    return ctx[31];
 }
 
 pub fn vsvc_post_handle_32(iss: u32, ctx: &mut [u64]) -> u64
 {
-    let thread_ctx = peek64(translate_el1_stage12(ctx[18]));
-    
-    let errcode = ctx[0] & 0xFFFFFFFF;
-    if (errcode != 0 && errcode != 0xea01 && errcode != 0xec01 && errcode != 0xf601 && (iss & 0xFF) != 0x7F && (iss & 0xFF) != 0x7) {
-        //println!("(core {}) SVC return 0x{:02x} -> {:08x}, pid {:02x} ({})", get_core(), iss & 0xFF, errcode, vsvc_get_curpid(), vsvc_get_curpid_name());
-    }
+    let thread_ctx = firmware_get_thread_ctx(ctx);
+    let svc_id = (iss & 0xFF) as u8;
+    let (entry_pc, redirected) = unsafe { VSVC_ENTRY_PC_32.get(&thread_ctx).copied().unwrap_or((0, false)) };
     
+    // A 64-bit SVC body already returns SVC64 layouts
     let mut post_ctx: [u64; 32] = Default::default();
     post_ctx.copy_from_slice(&ctx[..32]);
+    if !redirected {
+        svc_out_from_a32(svc_id, &mut post_ctx);
+    }
+    
+    svctrace_post(iss, thread_ctx, &post_ctx);
     SvcWait::populate_ctx(post_ctx);
     
     // async handler is complete
-    if let Some(ret_ctx) = task_advance_svc_ctx(thread_ctx) {
+    if let Some(mut ret_ctx) = task_advance_svc_ctx(thread_ctx) {
+        vsvc_ctx_to_32(svc_id, thread_ctx, entry_pc, &mut ret_ctx, true);
+        unsafe { VSVC_ENTRY_PC_32.remove(&thread_ctx); }
         for i in 0..32 {
             ctx[i] = ret_ctx[i];
         }
@@ -369,7 +354,8 @@ pub fn vsvc_post_handle_32(iss: u32, ctx: &mut [u64]) -> u64
     }
     else if SvcWait::is_waiting() // We have another wait
     {
-        let ret_ctx = SvcWait::get_ctx();
+        let mut ret_ctx = SvcWait::get_ctx();
+        vsvc_ctx_to_32(svc_id, thread_ctx, entry_pc, &mut ret_ctx, false);
         for i in 0..32 {
             ctx[i] = ret_ctx[i];
         }
@@ -382,6 +368,7 @@ pub fn vsvc_post_handle_32(iss: u32, ctx: &mut [u64]) -> u64
     else
     {
         // No handler, do nothing
+        unsafe { VSVC_ENTRY_PC_32.remove(&thread_ctx); }
         return ctx[31];
     }
 }
@@ -450,6 +437,8 @@ impl SvcHandler for SvcBreak
             val = peek32(translate_el1_stage12(pre_ctx[1]));
         }
         println_core!("process `{}` (pid {}) called svcBreak(0x{:x}, 0x{:x}, 0x{:x} -> 0x{:x})!", vsvc_get_curpid_name(), vsvc_get_curpid(), pre_ctx[0], pre_ctx[1], pre_ctx[2], val);
+        ipcfuzz_notify_crash(vsvc_get_curpid(), IPCFUZZ_REASON_BREAK);
+        hprocess_note_break(vsvc_get_curpid(), pre_ctx[0]);
 
         return pre_ctx;
     }
@@ -484,46 +473,50 @@ impl SvcHandler for SvcCreateProcess
 {
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
-        let proc_name = kstr!(pre_ctx[1]);
+        let params = pre_ctx[1];
 
         //
         // Wait for SVC to complete
         //
         let post_ctx = SvcWait::new(pre_ctx).await;
+        if (post_ctx[0] & 0xFFFFFFFF) != 0 {
+            return post_ctx;
+        }
         
         let process_handle = (post_ctx[1] & 0xFFFFFFFF) as u32;
+        let process = HProcess::from_create_params(params, vsvc_get_curpid(), process_handle);
         
-        println_core!("svcCreateProcess from `{}` -> {} (handle {:x})", vsvc_get_curpid_name(), proc_name, process_handle);
+        println_core!("svcCreateProcess from `{}` -> {} ({:016x}, handle {:x})", vsvc_get_curpid_name(), process.name, process.program_id, process_handle);
         unsafe
         {
-            LAST_CREATED[get_core() as usize] = Some(String::from(proc_name));
-            
-            if (proc_name == "overlayDisp") {
+            if (process.name == "overlayDisp") {
                 VSVC_QLAUNCH_STARTED = true;
             }
         }
+        hprocess_created(process);
 
         return post_ctx;
     }
 }
 
 #[async_trait]
-impl SvcHandler for SvcQueryMemory
+impl SvcHandler for SvcGetProcessId
 {
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
-        unsafe
-        {
-            if !RUNNING_PROCESS_NAME.contains_key(&vsvc_get_curpid())
-            {
-                let name = &LAST_CREATED[get_core() as usize];
-                if name.is_some() {
-                    RUNNING_PROCESS_NAME.insert(vsvc_get_curpid(), name.as_ref().unwrap().clone());
-                    PROCESS_NAME_PID.insert(name.as_ref().unwrap().clone(), vsvc_get_curpid());
-                }
-            }
+        let handle = (pre_ctx[1] & 0xFFFFFFFF) as u32;
+
+        //
+        // Wait for SVC to complete
+        //
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        // Creators always look up the PID of what they just made
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hprocess_bind_handle(vsvc_get_curpid(), handle, (post_ctx[1] & 0xFF) as u32);
         }
-        return pre_ctx;
+
+        return post_ctx;
     }
 }
 
@@ -532,20 +525,14 @@ impl SvcHandler for SvcExitProcess
 {
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
-        unsafe
-        {
-            let pid = vsvc_get_curpid();
-            if RUNNING_PROCESS_NAME.contains_key(&pid)
-            {
-                if let Some(name) = RUNNING_PROCESS_NAME.get(&pid) {
-                    println_core!("svcExitProcess -> {}", name);
-                   PROCESS_NAME_PID.remove(name);
-                }
-        
-                RUNNING_PROCESS_NAME.remove(&pid);
-            }
-            hipc_remove_pid_handles(pid);
-        }
+        let pid = vsvc_get_curpid();
+        println_core!("svcExitProcess -> {}", vsvc_get_pid_name(pid));
+        
+        hprocess_exited(pid, HProcessExit::Exited);
+        hthread_process_exited(pid);
+        hmemmap_process_exited(pid);
+        hipc_remove_pid_handles(pid);
+        gdbstub_process_exited(pid);
         return pre_ctx;
     }
 }
@@ -558,12 +545,7 @@ impl SvcHandler for SvcStartProcess
         let process_handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
         println_core!("svcStartProcess from {} for handle {:x}", vsvc_get_curpid_name(), process_handle);
         
-        unsafe
-        {
-            if let Some(proc_name) = &LAST_CREATED[get_core() as usize] {
-                VSVC_PROC_HANDLES.insert(process_handle, proc_name.clone());
-            }
-        }
+        hprocess_started(vsvc_get_curpid(), process_handle);
         
         return pre_ctx;
     }
@@ -577,41 +559,162 @@ impl SvcHandler for SvcTerminateProcess
         let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
         println_core!("svcTerminateProcess from {} for handle {:x}", vsvc_get_curpid_name(), handle);
         
-        unsafe
-        {
-            if let Some(proc_name) = VSVC_PROC_HANDLES.remove(&handle) 
-            {
-                println!("    -> Terminated process {}", proc_name);
-                if let Some(pid) = PROCESS_NAME_PID.remove(&proc_name) {
-                    RUNNING_PROCESS_NAME.remove(&pid);
-                    hipc_remove_pid_handles(pid);
-                }
-            }
+        //
+        // Wait for SVC to complete
+        //
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        if post_ctx[0] != 0 {
+            println_core!("    -> Failed, {}", result_format(post_ctx[0] as u32));
+            return post_ctx;
         }
         
-        // TODO check error?
+        match hprocess_find_handle(vsvc_get_curpid(), handle) {
+            Some(pid) => {
+                println!("    -> Terminated process {}", vsvc_get_pid_name(pid));
+                hprocess_exited(pid, HProcessExit::Terminated(vsvc_get_curpid()));
+                hthread_process_exited(pid);
+                hmemmap_process_exited(pid);
+                hipc_remove_pid_handles(pid);
+                gdbstub_process_exited(pid);
+            },
+            None => hprocess_forget_handle(vsvc_get_curpid(), handle)
+        };
 
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcCreateThread
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        //
+        // Wait for SVC to complete
+        //
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hthread_created(vsvc_get_curpid(), (post_ctx[1] & 0xFFFFFFFF) as u32, pre_ctx[1], pre_ctx[2], pre_ctx[3],
+                            (pre_ctx[4] & 0xFFFFFFFF) as u32, (pre_ctx[5] & 0xFFFFFFFF) as u32);
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcStartThread
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        hthread_started(vsvc_get_curpid(), (pre_ctx[0] & 0xFFFFFFFF) as u32);
         return pre_ctx;
     }
 }
 
 #[async_trait]
-impl SvcHandler for SvcReplyAndReceive
+impl SvcHandler for SvcExitThread
 {
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
-        /*let pkt = hipc_get_packet();
-        let error = pkt.get_cmd_id();
+        hthread_exited(firmware_get_thread_ctx(&pre_ctx));
+        return pre_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcSetThreadPriority
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
         
-        if error != 0 && error != 0x202 && error != 0xe02 && error != 0x402 && error != 0x408 && error != 0x1015 && error != 0xcc9d && error != 0x7d402 && error != 0x48c69 && error != 0x41a && error != 1 {
-            println_core!("svcReplyAndReceive from `{}` returning error {:x}", vsvc_get_curpid_name(), pkt.get_cmd_id());
-        }*/
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hthread_set_priority(vsvc_get_curpid(), firmware_get_thread_ctx(&pre_ctx), (pre_ctx[0] & 0xFFFFFFFF) as u32, (pre_ctx[1] & 0xFFFFFFFF) as u32);
+        }
         
-        /*if get_core() == 3 {
-            
-        }*/
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcSetThreadCoreMask
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
         
-        return pre_ctx;
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hthread_set_core_mask(vsvc_get_curpid(), firmware_get_thread_ctx(&pre_ctx), (pre_ctx[0] & 0xFFFFFFFF) as u32, (pre_ctx[1] & 0xFFFFFFFF) as u32, pre_ctx[2]);
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcGetThreadId
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hthread_bind_tid(vsvc_get_curpid(), firmware_get_thread_ctx(&pre_ctx), (pre_ctx[1] & 0xFFFFFFFF) as u32, post_ctx[1]);
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcCreateSession
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            ipcserver_create_session((post_ctx[1] & 0xFFFFFFFF) as u32, (post_ctx[2] & 0xFFFFFFFF) as u32);
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcAcceptSession
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let port_handle = (pre_ctx[1] & 0xFFFFFFFF) as u32;
+        
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            ipcserver_accept_session(port_handle, (post_ctx[1] & 0xFFFFFFFF) as u32);
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcReplyAndReceive
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        return ipcserver_handle_replyandreceive(pre_ctx, false).await;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcReplyAndReceiveWithUserBuffer
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        return ipcserver_handle_replyandreceive(pre_ctx, true).await;
     }
 }
 
@@ -630,9 +733,26 @@ impl SvcHandler for SvcCloseHandle
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
         let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
-        //hipc_close_handle(handle); // TODO error check?
+        
+        // The kernel has never heard of emulated session handles
+        if ipc_is_emulated_handle(handle) {
+            hipc_close_handle(handle);
+            return hsvc_return_early(pre_ctx, 0);
+        }
+        
+        //
+        // Wait for SVC to complete
+        //
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        // Handle values get reused, so only forget ones the kernel actually freed
+        if post_ctx[0] == 0 {
+            hipc_close_handle(handle);
+            hprocess_close_handle(vsvc_get_curpid(), handle);
+            hmemmap_close_handle(vsvc_get_curpid(), handle);
+        }
 
-        return pre_ctx;
+        return post_ctx;
     }
 }
 
@@ -660,24 +780,24 @@ impl SvcHandler for SvcGetSystemInfo
 {
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
-        /*let info_type = pre_ctx[1];
+        let info_type = pre_ctx[1];
         let info_subtype = pre_ctx[3];
-        
+
+        // TotalPhysicalMemorySize, subtype is the pool
+        if info_type != 0 || !mempolicy_has(MemPolicyKind::TotalMem) {
+            return pre_ctx;
+        }
+
         //
         // Wait for SVC to complete
         //
         let mut post_ctx = SvcWait::new(pre_ctx).await;
-        
-        //println_core!("svcGetSystemInfo({},{}) from `{}` -> {:x}", info_type, info_subtype, vsvc_get_curpid_name(), post_ctx[1]);
-        
-        // return less on TotalPhysicalMemorySize_Application to prevent app
-        // from allocating beyond end of RAM
-        if info_type == 0 && info_subtype == 0 {
-            post_ctx[1] -= 0x4000000;
+
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            post_ctx[1] = mempolicy_rewrite(MemPolicyKind::TotalMem, "svcGetSystemInfo(TotalPhysicalMemorySize)", post_ctx[1], Some(info_subtype as u32));
         }
 
-        return post_ctx;*/
-        return pre_ctx;
+        return post_ctx;
     }
 }
 
@@ -686,88 +806,310 @@ impl SvcHandler for SvcGetInfo
 {
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
-        /*let info_type = pre_ctx[1];
+        let info_type = pre_ctx[1];
         let info_subtype = pre_ctx[3];
-        
+
+        let what = match (info_type, info_subtype) {
+            (6, 0) => "svcGetInfo(TotalMemorySize)",
+            (21, 0) => "svcGetInfo(TotalNonSystemMemorySize)",
+            _ => return pre_ctx
+        };
+        if !mempolicy_has(MemPolicyKind::TotalMem) {
+            return pre_ctx;
+        }
+
         //
         // Wait for SVC to complete
         //
         let mut post_ctx = SvcWait::new(pre_ctx).await;
+
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            post_ctx[1] = mempolicy_rewrite(MemPolicyKind::TotalMem, what, post_ctx[1], None);
+        }
+
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcSetHeapSize
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        pre_ctx[1] = mempolicy_rewrite(MemPolicyKind::Heap, "svcSetHeapSize size", pre_ctx[1], None);
+        let size = pre_ctx[1];
         
-        //println_core!("svcGetInfo({},{}) from `{}` -> {:x}", info_type, info_subtype, vsvc_get_curpid_name(), post_ctx[1]);
+        //
+        // Wait for SVC to complete
+        //
+        let post_ctx = SvcWait::new(pre_ctx).await;
         
-        // Don't mess with applets/sysmodules
-        if vsvc_get_curpid() <= 128 {
-            return post_ctx;
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_set_heap(vsvc_get_curpid(), post_ctx[1], size);
         }
+
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcSetMemoryPermission
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
         
-        // return less on TotalPhysicalMemorySize_Application to prevent app
-        // from allocating beyond end of RAM
-        if info_type == 21 && info_subtype == 0 {
-            //post_ctx[1] -= 0x8000000;
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_set_perm(vsvc_get_curpid(), pre_ctx[0], pre_ctx[1], (pre_ctx[2] & 0xFFFFFFFF) as u32);
         }
-        else if info_type == 7 && info_subtype == 0 { // Pretend that the code executable is larger
-            //post_ctx[1] += 0x4000000;
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcMapMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let (dst, src, size) = (pre_ctx[0], pre_ctx[1], pre_ctx[2]);
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_lend(vsvc_get_curpid(), src, size, 0, None);
+            hmemmap_map(vsvc_get_curpid(), HMemRegion::new(dst, size, HMEM_PERM_RW, HMemState::Stack, HMemOrigin::MapMemory(src)));
         }
+        
+        return post_ctx;
+    }
+}
 
-        return post_ctx;*/
-        return pre_ctx;
+#[async_trait]
+impl SvcHandler for SvcUnmapMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let (dst, src, size) = (pre_ctx[0], pre_ctx[1], pre_ctx[2]);
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_unmap(vsvc_get_curpid(), dst, size);
+            hmemmap_unlend(vsvc_get_curpid(), src, size);
+        }
+        
+        return post_ctx;
     }
 }
 
 #[async_trait]
-impl SvcHandler for SvcSetHeapSize
+impl SvcHandler for SvcCreateSharedMemory
 {
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
-        return pre_ctx;
-        /*let size = pre_ctx[1];
+        let post_ctx = SvcWait::new(pre_ctx).await;
         
-        if vsvc_get_curpid() > 128 && pre_ctx[1] >= 0xc6800000 {
-            //pre_ctx[1] += 0x200000;
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_set_owner(vsvc_get_curpid(), (post_ctx[1] & 0xFFFFFFFF) as u32, vsvc_get_curpid());
         }
         
-        //
-        // Wait for SVC to complete
-        //
-        let mut post_ctx = SvcWait::new(pre_ctx).await;
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcMapSharedMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
+        let post_ctx = SvcWait::new(pre_ctx).await;
         
-        // Crash Bandicoot is c7e00000
-        // ARMS is ca800000
-        println_core!("svcSetHeapSize({:x}) from `{}` -> {:x},{:x}", size, vsvc_get_curpid_name(), post_ctx[1], post_ctx[0]);
+        // Handles that came in over IPC have no owner we know of
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            let origin = HMemOrigin::SharedMemory(handle, hmemmap_get_owner(vsvc_get_curpid(), handle));
+            hmemmap_map(vsvc_get_curpid(), HMemRegion::new(pre_ctx[1], pre_ctx[2], (pre_ctx[3] & 0xFFFFFFFF) as u32, HMemState::Shared, origin));
+        }
+        
+        return post_ctx;
+    }
+}
 
-        return post_ctx;*/
+#[async_trait]
+impl SvcHandler for SvcUnmapSharedMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_unmap(vsvc_get_curpid(), pre_ctx[1], pre_ctx[2]);
+        }
+        
+        return post_ctx;
     }
 }
 
 #[async_trait]
-impl SvcHandler for SvcSetResourceLimitLimitValue
+impl SvcHandler for SvcCreateTransferMemory
 {
     async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
     {
-        let resource = pre_ctx[1];
-        let mut val = pre_ctx[2];
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_lend_handle(vsvc_get_curpid(), (post_ctx[1] & 0xFFFFFFFF) as u32, pre_ctx[1], pre_ctx[2],
+                                (pre_ctx[3] & 0xFFFFFFFF) as u32, None);
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcMapTransferMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
+        let post_ctx = SvcWait::new(pre_ctx).await;
         
-        if resource == 0 && val >= 0xcd500000 {
-            //val -= 0x4000000;
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            let origin = HMemOrigin::TransferMemory(handle, hmemmap_get_owner(vsvc_get_curpid(), handle));
+            hmemmap_map(vsvc_get_curpid(), HMemRegion::new(pre_ctx[1], pre_ctx[2], (pre_ctx[3] & 0xFFFFFFFF) as u32, HMemState::Transfered, origin));
         }
-        else if resource == 0 && val == 0x1fb00000 {
-            //val -= 0x1000000;
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcUnmapTransferMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_unmap(vsvc_get_curpid(), pre_ctx[1], pre_ctx[2]);
         }
         
-        pre_ctx[2] = val;
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcCreateCodeMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
         
-        return pre_ctx;
-        /*
-        //
-        // Wait for SVC to complete
-        //
-        let mut post_ctx = SvcWait::new(pre_ctx).await;
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            hmemmap_lend_handle(vsvc_get_curpid(), (post_ctx[1] & 0xFFFFFFFF) as u32, pre_ctx[1], pre_ctx[2], 0, None);
+        }
         
-        //println_core!("svcSetResourceLimitLimitValue({:x}, {:x}) from `{}` -> {:x}", resource, val, vsvc_get_curpid_name(), post_ctx[0]);
+        return post_ctx;
+    }
+}
 
-        return post_ctx;*/
+#[async_trait]
+impl SvcHandler for SvcControlCodeMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let handle = (pre_ctx[0] & 0xFFFFFFFF) as u32;
+        let op = (pre_ctx[1] & 0xFFFFFFFF) as u32;
+        let (addr, size, perm) = (pre_ctx[2], pre_ctx[3], (pre_ctx[4] & 0xFFFFFFFF) as u32);
+        
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        if (post_ctx[0] & 0xFFFFFFFF) != 0 {
+            return post_ctx;
+        }
+        
+        let pid = vsvc_get_curpid();
+        let owner = hmemmap_get_owner(pid, handle);
+        let owner_pid = owner.unwrap_or(pid);
+        match op {
+            0 => hmemmap_map(pid, HMemRegion::new(addr, size, perm, HMemState::CodeOut, HMemOrigin::CodeMemory(handle, owner))),
+            1 => hmemmap_map(owner_pid, HMemRegion::new(addr, size, perm, HMemState::GeneratedCode, HMemOrigin::CodeMemory(handle, owner))),
+            2 => hmemmap_unmap(pid, addr, size),
+            3 => hmemmap_unmap(owner_pid, addr, size),
+            _ => {}
+        };
         
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcSetProcessMemoryPermission
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            if let Some(pid) = hprocess_resolve_handle(vsvc_get_curpid(), (pre_ctx[0] & 0xFFFFFFFF) as u32) {
+                hmemmap_set_perm(pid, pre_ctx[1], pre_ctx[2], (pre_ctx[3] & 0xFFFFFFFF) as u32);
+            }
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcMapProcessCodeMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let (dst, src, size) = (pre_ctx[1], pre_ctx[2], pre_ctx[3]);
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        // Code starts out inaccessible until SetProcessMemoryPermission
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            if let Some(pid) = hprocess_resolve_handle(vsvc_get_curpid(), (pre_ctx[0] & 0xFFFFFFFF) as u32) {
+                hmemmap_lend(pid, src, size, 0, Some(HMemState::AliasCode));
+                hmemmap_map(pid, HMemRegion::new(dst, size, 0, HMemState::Code, HMemOrigin::ProcessCode(src)));
+            }
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcUnmapProcessCodeMemory
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let (dst, src, size) = (pre_ctx[1], pre_ctx[2], pre_ctx[3]);
+        let post_ctx = SvcWait::new(pre_ctx).await;
+        
+        if (post_ctx[0] & 0xFFFFFFFF) == 0 {
+            if let Some(pid) = hprocess_resolve_handle(vsvc_get_curpid(), (pre_ctx[0] & 0xFFFFFFFF) as u32) {
+                hmemmap_unmap(pid, dst, size);
+                hmemmap_unlend(pid, src, size);
+            }
+        }
+        
+        return post_ctx;
+    }
+}
+
+#[async_trait]
+impl SvcHandler for SvcSetResourceLimitLimitValue
+{
+    async fn handle(&self, mut pre_ctx: [u64; 32]) -> [u64; 32]
+    {
+        let resource = pre_ctx[1];
+
+        // PhysicalMemory
+        if resource == 0 {
+            pre_ctx[2] = mempolicy_rewrite(MemPolicyKind::Limit, "svcSetResourceLimitLimitValue(PhysicalMemory)", pre_ctx[2], None);
+        }
+
+        return pre_ctx;
     }
 }
 
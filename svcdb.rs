@@ -0,0 +1,216 @@
+// svcdb.txt parsing, shared by build.rs and debug_client/build.rs
+
+use std::fs;
+use std::string::String;
+
+pub struct SvcDbArg {
+    pub is_out: bool,
+    pub kind: String,
+    pub name: String,
+    pub regs: Vec<usize>,
+}
+
+pub struct SvcDbEntry {
+    pub id: u8,
+    pub name: String,
+    pub ret: &'static str, // SvcRet variant
+    pub args: Vec<SvcDbArg>,
+    pub args_a32: Option<Vec<SvcDbArg>>,
+}
+
+const SVCDB_KEYWORDS: &[&str] = &["as", "break", "const", "continue", "crate", "else", "enum", "fn", "for", "if", "impl", "in", "let",
+                                  "loop", "match", "mod", "move", "mut", "ref", "return", "self", "static", "struct", "super",
+                                  "trait", "type", "unsafe", "use", "where", "while", "async", "await", "dyn"];
+
+fn svcdb_parse_arg(arg: &str, line_num: usize, is_a32: bool) -> SvcDbArg {
+    let split: Vec<&str> = arg.split(':').collect();
+    if split.len() != 4 {
+        panic!("svcdb.txt:{}: expected `<in|out>:<type>:<name>:<reg>`, got `{}`", line_num + 1, arg);
+    }
+    
+    let is_out = match split[0] {
+        "in" => false,
+        "out" => true,
+        _ => panic!("svcdb.txt:{}: bad argument direction `{}`", line_num + 1, split[0])
+    };
+    match split[1] {
+        "u32" | "u64" | "handle" | "ptr" | "size" | "str" => {},
+        _ => panic!("svcdb.txt:{}: unknown argument type `{}`", line_num + 1, split[1])
+    };
+    if SVCDB_KEYWORDS.contains(&split[2]) || !split[2].chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        panic!("svcdb.txt:{}: `{}` can't be used as a field name", line_num + 1, split[2]);
+    }
+    
+    let mut regs: Vec<usize> = Vec::new();
+    for reg in split[3].split('+') {
+        match reg.parse::<usize>() {
+            Ok(reg) if reg < 8 => regs.push(reg),
+            _ => panic!("svcdb.txt:{}: bad register `{}`", line_num + 1, reg)
+        };
+    }
+    
+    // Only a 32-bit register pair can hold a u64
+    let expected = if is_a32 && split[1] == "u64" { 2 } else { 1 };
+    if regs.len() != expected {
+        panic!("svcdb.txt:{}: `{}` needs {} register(s)", line_num + 1, arg, expected);
+    }
+    
+    return SvcDbArg { is_out: is_out, kind: String::from(split[1]), name: String::from(split[2]), regs: regs };
+}
+
+fn svcdb_parse_args(args: &[&str], line_num: usize, is_a32: bool) -> Vec<SvcDbArg> {
+    let parsed: Vec<SvcDbArg> = args.iter().map(|arg| svcdb_parse_arg(arg, line_num, is_a32)).collect();
+    
+    for (i, arg) in parsed.iter().enumerate() {
+        for other in &parsed[..i] {
+            if other.is_out != arg.is_out { continue; }
+            if other.name == arg.name {
+                panic!("svcdb.txt:{}: `{}` is listed twice", line_num + 1, arg.name);
+            }
+            if other.regs.iter().any(|reg| arg.regs.contains(reg)) {
+                panic!("svcdb.txt:{}: `{}` and `{}` share a register", line_num + 1, other.name, arg.name);
+            }
+        }
+    }
+    return parsed;
+}
+
+// x0 holds a result code unless the SVC is marked otherwise, or hands a
+// value back in it
+fn svcdb_parse_ret(rets: &[&str], args: &Vec<SvcDbArg>, line_num: usize) -> &'static str {
+    let out_x0 = args.iter().any(|arg| arg.is_out && arg.regs.contains(&0));
+    let ret = match rets {
+        [] if out_x0 => "None",
+        [] => "Result",
+        ["ret:void"] if !out_x0 => "None",
+        ["ret:noreturn"] if !out_x0 => "NoReturn",
+        [ret] if out_x0 => panic!("svcdb.txt:{}: `{}` clashes with an out arg in register 0", line_num + 1, ret),
+        _ => panic!("svcdb.txt:{}: expected at most one of `ret:void` or `ret:noreturn`", line_num + 1)
+    };
+    return ret;
+}
+
+fn svcdb_check_a32(svc: &SvcDbEntry, line_num: usize) {
+    match &svc.args_a32 {
+        Some(args_a32) => {
+            if args_a32.len() != svc.args.len() {
+                panic!("svcdb.txt:{}: a32 layout for {} doesn't have the same args", line_num + 1, svc.name);
+            }
+            for arg in &svc.args {
+                let arg_a32 = match args_a32.iter().find(|other| other.name == arg.name && other.is_out == arg.is_out) {
+                    Some(arg_a32) => arg_a32,
+                    None => panic!("svcdb.txt:{}: a32 layout for {} is missing `{}`", line_num + 1, svc.name, arg.name)
+                };
+                if svcdb_is_u32(&arg.kind) && arg_a32.kind == "u64" {
+                    panic!("svcdb.txt:{}: `{}` is 32-bit on A64 but not on A32", line_num + 1, arg.name);
+                }
+            }
+        },
+        None => {
+            if svc.args.iter().any(|arg| arg.kind == "u64") {
+                panic!("svcdb.txt:{}: {} has 64-bit args and needs an a32 layout", line_num + 1, svc.name);
+            }
+        }
+    }
+}
+
+pub fn svcdb_read(path: &str) -> Vec<SvcDbEntry> {
+    let mut svcs: Vec<SvcDbEntry> = Vec::new();
+    let mut last_line = 0;
+    
+    let text = fs::read_to_string(path).unwrap_or_else(|_| panic!("failed to open {}", path));
+    for (line_num, line) in text.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => &line
+        };
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        
+        let split: Vec<&str> = line.split_whitespace().collect();
+        match split[0] {
+            "svc" => {
+                if split.len() < 3 {
+                    panic!("svcdb.txt:{}: expected `svc <id> <name> [args]`", line_num + 1);
+                }
+                if let Some(last) = svcs.last() {
+                    svcdb_check_a32(last, last_line);
+                }
+                
+                let id = u8::from_str_radix(split[1].trim_start_matches("0x"), 16).unwrap_or_else(|_| panic!("svcdb.txt:{}: bad SVC id `{}`", line_num + 1, split[1]));
+                if id == 0 || id >= 0x80 {
+                    panic!("svcdb.txt:{}: SVC id {:#x} is out of range", line_num + 1, id);
+                }
+                if let Some(other) = svcs.iter().find(|other| other.id == id || other.name == split[2]) {
+                    panic!("svcdb.txt:{}: {:#x} {} clashes with {:#x} {}", line_num + 1, id, split[2], other.id, other.name);
+                }
+                if split[2] == "Invalid" || split[2] == "DefaultHandler" || !split[2].chars().all(|c| c.is_ascii_alphanumeric()) {
+                    panic!("svcdb.txt:{}: `{}` can't be used as an SVC name", line_num + 1, split[2]);
+                }
+                
+                let (rets, args): (Vec<&str>, Vec<&str>) = split[3..].iter().partition(|arg| arg.starts_with("ret:"));
+                let args = svcdb_parse_args(&args, line_num, false);
+                let ret = svcdb_parse_ret(&rets, &args, line_num);
+                
+                svcs.push(SvcDbEntry {
+                    id: id,
+                    name: String::from(split[2]),
+                    ret: ret,
+                    args: args,
+                    args_a32: None,
+                });
+                last_line = line_num;
+            },
+            "a32" => {
+                let svc = match svcs.last_mut() {
+                    Some(svc) if svc.args_a32.is_none() => svc,
+                    _ => panic!("svcdb.txt:{}: expected `a32` once, after an svc", line_num + 1)
+                };
+                svc.args_a32 = Some(svcdb_parse_args(&split[1..], line_num, true));
+            },
+            _ => {
+                panic!("svcdb.txt:{}: unknown directive `{}`", line_num + 1, split[0]);
+            }
+        }
+    }
+    
+    if let Some(last) = svcs.last() {
+        svcdb_check_a32(last, last_line);
+    }
+    
+    return svcs;
+}
+
+
+pub fn svcdb_is_u32(kind: &str) -> bool {
+    return kind == "u32" || kind == "handle";
+}
+
+fn svcsig_arg_kind(arg: &SvcDbArg) -> &'static str {
+    match (arg.is_out, arg.kind.as_str()) {
+        (false, "u32") => "U32",
+        (false, "u64") => "U64",
+        (false, "handle") => "Handle",
+        (false, "ptr") => "Ptr",
+        (false, "size") => "Size",
+        (false, "str") => "StrPtr",
+        (true, "u32") => "OutU32",
+        (true, "handle") => "OutHandle",
+        (true, "ptr") | (true, "str") => "OutPtr",
+        _ => "OutU64"
+    }
+}
+
+// SVC_SIGNATURES for src/hos/svcsig.rs, A64 layout only
+pub fn gen_svcsig(svcs: &Vec<SvcDbEntry>) -> String {
+    let mut output = String::new();
+    
+    output += "pub static SVC_SIGNATURES: &[SvcSignature] = &[\n";
+    for svc in svcs {
+        let args: Vec<String> = svc.args.iter().map(|arg| format!("svc_arg(\"{}\", SvcArgKind::{}, {})", arg.name, svcsig_arg_kind(arg), arg.regs[0])).collect();
+        output += &format!("    SvcSignature {{ id: 0x{:02X}, name: \"{}\", ret: SvcRet::{}, args: &[{}] }},\n", svc.id, svc.name, svc.ret, args.join(", "));
+    }
+    output += "];\n";
+    
+    return output;
+}